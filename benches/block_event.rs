@@ -0,0 +1,73 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    criterion::{black_box, criterion_group, criterion_main, Criterion},
+    solana_accountsdb_plugin_kafka::build_block_event,
+    solana_geyser_plugin_interface::geyser_plugin_interface::ReplicaBlockInfoV3,
+    solana_program::{epoch_schedule::EpochSchedule, pubkey::Pubkey},
+    solana_transaction_status::{Reward, RewardType},
+};
+
+/// Rewards of an epoch boundary block, paid out to 1,000 stake accounts.
+fn rewards() -> Vec<Reward> {
+    (0..1_000u64)
+        .map(|i| Reward {
+            pubkey: Pubkey::new_from_array([(i % 251) as u8; 32]).to_string(),
+            lamports: 1_000_000 + i as i64,
+            post_balance: 5_000_000_000 + i,
+            reward_type: Some(RewardType::Staking),
+            commission: Some(7),
+        })
+        .collect()
+}
+
+fn bench_build_block_event(c: &mut Criterion) {
+    let rewards = rewards();
+    let info = ReplicaBlockInfoV3 {
+        parent_slot: 249_999_999,
+        parent_blockhash: "4sGjMW1sUnHzSxGspuhpqLDx6wiyjNtZAMdL4VZHirAn",
+        slot: 250_000_000,
+        blockhash: "8qbHbw2BbbTHBW1sbeqakYXVKRQM8Ne7pLK7m6CVfeR",
+        rewards: &rewards,
+        block_time: Some(1_700_000_000),
+        block_height: Some(230_000_000),
+        executed_transaction_count: 3_000,
+        entry_count: 700,
+    };
+    let leader = Some(Pubkey::new_from_array([9; 32]));
+    let epoch_schedule = EpochSchedule::without_warmup();
+
+    c.bench_function("build_block_event/1000_rewards/borrowed", |b| {
+        b.iter(|| build_block_event(black_box(&info), leader, &epoch_schedule))
+    });
+    // Baseline of copying the notification's rewards before building, as an owned
+    // block info would.
+    c.bench_function("build_block_event/1000_rewards/cloned", |b| {
+        b.iter(|| {
+            let rewards = black_box(&info).rewards.to_vec();
+            build_block_event(
+                &ReplicaBlockInfoV3 {
+                    rewards: &rewards,
+                    ..info
+                },
+                leader,
+                &epoch_schedule,
+            )
+        })
+    });
+}
+
+criterion_group!(benches, bench_build_block_event);
+criterion_main!(benches);
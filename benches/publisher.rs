@@ -0,0 +1,159 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    criterion::{
+        criterion_group, criterion_main,
+        measurement::{Measurement, ValueFormatter},
+        BatchSize, Criterion, Throughput,
+    },
+    solana_accountsdb_plugin_kafka::{
+        Config, Partitioning, Publisher, SchemaCompat, UpdateAccountEvent,
+    },
+    std::{
+        alloc::{GlobalAlloc, Layout, System},
+        sync::atomic::{AtomicUsize, Ordering},
+    },
+};
+
+/// Counts allocations, for the `Allocations` measurement.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn account_event(data_len: usize) -> UpdateAccountEvent {
+    UpdateAccountEvent {
+        slot: 250_000_000,
+        pubkey: vec![1; 32],
+        lamports: 1_000_000,
+        owner: vec![2; 32],
+        data: vec![3; data_len],
+        write_version: 42,
+        ..Default::default()
+    }
+}
+
+/// Allocations made by the measured code, reported per event like times are.
+struct Allocations;
+
+impl Measurement for Allocations {
+    type Intermediate = usize;
+    type Value = usize;
+
+    fn start(&self) -> usize {
+        ALLOCATIONS.load(Ordering::Relaxed)
+    }
+
+    fn end(&self, start: usize) -> usize {
+        ALLOCATIONS.load(Ordering::Relaxed) - start
+    }
+
+    fn add(&self, v1: &usize, v2: &usize) -> usize {
+        v1 + v2
+    }
+
+    fn zero(&self) -> usize {
+        0
+    }
+
+    fn to_f64(&self, value: &usize) -> f64 {
+        *value as f64
+    }
+
+    fn formatter(&self) -> &dyn ValueFormatter {
+        self
+    }
+}
+
+impl ValueFormatter for Allocations {
+    fn scale_values(&self, _typical_value: f64, _values: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+
+    fn scale_throughputs(
+        &self,
+        _typical_value: f64,
+        _throughput: &Throughput,
+        _values: &mut [f64],
+    ) -> &'static str {
+        "allocs"
+    }
+
+    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+}
+
+/// Publish account updates of each size, wrapped or not, measured by `c`.
+fn update_account<M: Measurement + 'static>(c: &mut Criterion<M>, group: &str) {
+    let publisher = Publisher::dry_run(&Config::default());
+    for (name, data_len) in [("small", 128), ("large", 64 * 1024)] {
+        for wrap in [false, true] {
+            let id = format!("{group}/{name}/wrap={wrap}");
+            c.bench_function(&id, |b| {
+                b.iter_batched(
+                    || account_event(data_len),
+                    |event| {
+                        publisher
+                            .update_account(
+                                event,
+                                wrap,
+                                Partitioning::KeyHash,
+                                SchemaCompat::Latest,
+                                false,
+                                "bench",
+                            )
+                            .unwrap();
+                        publisher.take_recorded()
+                    },
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+}
+
+fn bench_update_account(c: &mut Criterion) {
+    update_account(c, "update_account");
+}
+
+fn bench_update_account_allocations(c: &mut Criterion<Allocations>) {
+    // The dry run publisher copies each message into its recording, so the counts include
+    // those copies on top of the publisher's own allocations. Wrapped events allocate no more
+    // than unwrapped ones, as the `MessageWrapper` isn't boxed (see build.rs).
+    update_account(c, "update_account_allocations");
+}
+
+criterion_group!(benches, bench_update_account);
+criterion_group! {
+    name = allocations;
+    // Counts don't vary between samples, which the density plots can't be drawn for.
+    config = Criterion::default().with_measurement(Allocations).without_plots();
+    targets = bench_update_account_allocations
+}
+criterion_main!(benches, allocations);
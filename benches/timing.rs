@@ -0,0 +1,66 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    criterion::{black_box, criterion_group, criterion_main, Criterion},
+    solana_accountsdb_plugin_kafka::{Config, ConfigFilter, KafkaPlugin},
+    solana_geyser_plugin_interface::geyser_plugin_interface::{
+        GeyserPlugin, ReplicaAccountInfoV3, ReplicaAccountInfoVersions,
+    },
+    solana_program::pubkey::Pubkey,
+};
+
+/// Account update callbacks with and without `timing_metrics`, the difference being the cost
+/// of timing filter evaluation, event building and sending.
+fn bench_timing_metrics(c: &mut Criterion) {
+    let owner = Pubkey::new_from_array([2; 32]);
+    let pubkey = Pubkey::new_from_array([1; 32]);
+    let data = vec![3; 128];
+    let info = ReplicaAccountInfoV3 {
+        pubkey: pubkey.as_ref(),
+        lamports: 1_000_000,
+        owner: owner.as_ref(),
+        executable: false,
+        rent_epoch: 0,
+        data: &data,
+        write_version: 42,
+        txn: None,
+    };
+    for timing_metrics in [false, true] {
+        let mut config = Config::default();
+        config.timing_metrics = timing_metrics;
+        config.filters = vec![ConfigFilter {
+            update_account_topic: Some("bench".to_owned()),
+            program_filters: vec![owner.to_string()],
+            ..Default::default()
+        }];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        let id = format!("update_account/timing_metrics={timing_metrics}");
+        c.bench_function(&id, |b| {
+            b.iter(|| {
+                plugin
+                    .update_account(
+                        ReplicaAccountInfoVersions::V0_0_3(black_box(&info)),
+                        250_000_000,
+                        false,
+                    )
+                    .unwrap();
+                plugin.take_recorded()
+            })
+        });
+    }
+}
+
+criterion_group!(benches, bench_timing_metrics);
+criterion_main!(benches);
@@ -14,6 +14,6 @@ set -x
 # Build/test all host crates
 rust_stable=$(rustc --version | awk '{print $2}')
 cargo +"$rust_stable" build
-cargo +"$rust_stable" test -- --nocapture
+cargo +"$rust_stable" test --all-features -- --nocapture
 
 exit 0
@@ -0,0 +1,229 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{LookupTable, StakeAccount, StakeStateKind},
+    solana_program::{
+        address_lookup_table::{self, state::AddressLookupTable},
+        stake::{self, state::StakeStateV2},
+    },
+};
+
+pub fn is_stake_account(owner: &[u8]) -> bool {
+    owner == stake::program::id().as_ref()
+}
+
+pub fn is_lookup_table_account(owner: &[u8]) -> bool {
+    owner == address_lookup_table::program::id().as_ref()
+}
+
+/// Decode the state of an account owned by the stake program.
+pub fn decode_stake_account(data: &[u8]) -> StakeAccount {
+    let state = match bincode::deserialize::<StakeStateV2>(data) {
+        Ok(state) => state,
+        Err(_error) => {
+            return StakeAccount {
+                decode_failed: true,
+                ..Default::default()
+            }
+        }
+    };
+
+    let mut account = StakeAccount {
+        state: match state {
+            StakeStateV2::Uninitialized => StakeStateKind::Uninitialized,
+            StakeStateV2::Initialized(_) => StakeStateKind::Initialized,
+            StakeStateV2::Stake(..) => StakeStateKind::Delegated,
+            StakeStateV2::RewardsPool => StakeStateKind::RewardsPool,
+        }
+        .into(),
+        ..Default::default()
+    };
+    if let Some(meta) = state.meta() {
+        account.rent_exempt_reserve = meta.rent_exempt_reserve;
+        account.staker = meta.authorized.staker.to_bytes().to_vec();
+        account.withdrawer = meta.authorized.withdrawer.to_bytes().to_vec();
+    }
+    if let Some(delegation) = state.delegation() {
+        account.voter_pubkey = delegation.voter_pubkey.to_bytes().to_vec();
+        account.stake = delegation.stake;
+        account.activation_epoch = delegation.activation_epoch;
+        account.deactivation_epoch = delegation.deactivation_epoch;
+    }
+    account
+}
+
+/// Decode an account owned by the address lookup table program.
+pub fn decode_lookup_table(data: &[u8]) -> LookupTable {
+    let table = match AddressLookupTable::deserialize(data) {
+        Ok(table) => table,
+        Err(_error) => {
+            return LookupTable {
+                decode_failed: true,
+                ..Default::default()
+            }
+        }
+    };
+    let meta = table.meta;
+    LookupTable {
+        authority: meta
+            .authority
+            .map(|authority| authority.to_bytes().to_vec())
+            .unwrap_or_default(),
+        deactivation_slot: meta.deactivation_slot,
+        last_extended_slot: meta.last_extended_slot,
+        last_extended_slot_start_index: meta.last_extended_slot_start_index.into(),
+        addresses: table
+            .addresses
+            .iter()
+            .map(|address| address.to_bytes().to_vec())
+            .collect(),
+        decode_failed: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        solana_program::{address_lookup_table::state::LookupTableMeta, pubkey::Pubkey},
+    };
+
+    /// Data of an initialized stake account, as the stake program lays it out: the variant,
+    /// `rent_exempt_reserve` 2_282_880, staker `[1; 32]`, withdrawer `[2; 32]` and a lockup
+    /// until 1_700_000_000 or epoch 700 with custodian `[7; 32]`, padded to 200 bytes.
+    const INITIALIZED_STAKE_ACCOUNT: &str = concat!(
+        "0100000080d522000000000001010101010101010101010101010101010101010101010101010101",
+        "01010101020202020202020202020202020202020202020202020202020202020202020200f15365",
+        "00000000bc0200000000000007070707070707070707070707070707070707070707070707070707",
+        "07070707000000000000000000000000000000000000000000000000000000000000000000000000",
+        "00000000000000000000000000000000000000000000000000000000000000000000000000000000",
+    );
+
+    /// The same meta without a lockup, delegating 5_000_000_000 lamports to `[3; 32]` from
+    /// epoch 600, with the legacy warmup rate 0.25, 123_456_789 credits observed and no flags.
+    const DELEGATED_STAKE_ACCOUNT: &str = concat!(
+        "0200000080d522000000000001010101010101010101010101010101010101010101010101010101",
+        "01010101020202020202020202020202020202020202020202020202020202020202020200000000",
+        "00000000000000000000000000000000000000000000000000000000000000000000000000000000",
+        "00000000030303030303030303030303030303030303030303030303030303030303030300f2052a",
+        "010000005802000000000000ffffffffffffffff000000000000d03f15cd5b070000000000000000",
+    );
+
+    const REWARDS_POOL_STAKE_ACCOUNT: &str = concat!(
+        "03000000000000000000000000000000000000000000000000000000000000000000000000000000",
+        "00000000000000000000000000000000000000000000000000000000000000000000000000000000",
+        "00000000000000000000000000000000000000000000000000000000000000000000000000000000",
+        "00000000000000000000000000000000000000000000000000000000000000000000000000000000",
+        "00000000000000000000000000000000000000000000000000000000000000000000000000000000",
+    );
+
+    fn stake_account_data(hex: &str) -> Vec<u8> {
+        let data: Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect();
+        assert_eq!(data.len(), StakeStateV2::size_of());
+        data
+    }
+
+    #[test]
+    fn test_initialized() {
+        let account = decode_stake_account(&stake_account_data(INITIALIZED_STAKE_ACCOUNT));
+        assert_eq!(account.state(), StakeStateKind::Initialized);
+        assert_eq!(account.rent_exempt_reserve, 2_282_880);
+        assert_eq!(account.staker, vec![1; 32]);
+        assert_eq!(account.withdrawer, vec![2; 32]);
+        assert!(account.voter_pubkey.is_empty());
+        assert!(!account.decode_failed);
+    }
+
+    #[test]
+    fn test_delegated() {
+        let account = decode_stake_account(&stake_account_data(DELEGATED_STAKE_ACCOUNT));
+        assert_eq!(account.state(), StakeStateKind::Delegated);
+        assert_eq!(account.voter_pubkey, vec![3; 32]);
+        assert_eq!(account.stake, 5_000_000_000);
+        assert_eq!(account.activation_epoch, 600);
+        assert_eq!(account.deactivation_epoch, u64::MAX);
+        assert_eq!(account.rent_exempt_reserve, 2_282_880);
+        assert_eq!(account.staker, vec![1; 32]);
+        assert_eq!(account.withdrawer, vec![2; 32]);
+        assert!(!account.decode_failed);
+    }
+
+    #[test]
+    fn test_rewards_pool_and_garbage() {
+        let account = decode_stake_account(&stake_account_data(REWARDS_POOL_STAKE_ACCOUNT));
+        assert_eq!(account.state(), StakeStateKind::RewardsPool);
+        assert!(account.staker.is_empty());
+        assert!(!account.decode_failed);
+
+        assert!(decode_stake_account(&[9, 0, 0, 0]).decode_failed);
+        assert!(decode_stake_account(&[]).decode_failed);
+    }
+
+    fn lookup_table(authority: Option<Pubkey>, addresses: &[Pubkey]) -> Vec<u8> {
+        AddressLookupTable {
+            meta: LookupTableMeta {
+                deactivation_slot: u64::MAX,
+                last_extended_slot: 250_000_000,
+                last_extended_slot_start_index: 3,
+                authority,
+                _padding: 0,
+            },
+            addresses: addresses.into(),
+        }
+        .serialize_for_tests()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_lookup_table() {
+        let addresses: Vec<Pubkey> = (1..=5).map(|i| Pubkey::new_from_array([i; 32])).collect();
+        let data = lookup_table(Some(Pubkey::new_from_array([9; 32])), &addresses);
+        let table = decode_lookup_table(&data);
+        assert!(!table.decode_failed);
+        assert_eq!(table.authority, vec![9; 32]);
+        assert_eq!(table.deactivation_slot, u64::MAX);
+        assert_eq!(table.last_extended_slot, 250_000_000);
+        assert_eq!(table.last_extended_slot_start_index, 3);
+        assert_eq!(
+            table.addresses,
+            (1..=5).map(|i| vec![i; 32]).collect::<Vec<_>>()
+        );
+
+        // Frozen tables have no authority, new ones no addresses.
+        let table = decode_lookup_table(&lookup_table(None, &[]));
+        assert!(!table.decode_failed);
+        assert!(table.authority.is_empty());
+        assert!(table.addresses.is_empty());
+    }
+
+    #[test]
+    fn test_lookup_table_corrupt() {
+        let data = lookup_table(None, &[Pubkey::new_from_array([1; 32])]);
+        // Cut off within the last address.
+        assert!(decode_lookup_table(&data[..data.len() - 1]).decode_failed);
+        // Cut off within the meta.
+        assert!(decode_lookup_table(&data[..20]).decode_failed);
+        // Uninitialized.
+        assert!(decode_lookup_table(&[0; 56]).decode_failed);
+        assert!(decode_lookup_table(&[]).decode_failed);
+        assert!(is_lookup_table_account(
+            address_lookup_table::program::id().as_ref()
+        ));
+        assert!(!is_lookup_table_account(stake::program::id().as_ref()));
+    }
+}
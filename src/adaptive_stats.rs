@@ -0,0 +1,287 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `kafka_stats_healthy_every`, exporting only some librdkafka statistics while the brokers are
+//! healthy and every one of them while they are not, so that a low `statistics.interval.ms`
+//! costs little at steady state. Health is judged from the exported statistics alone: any
+//! broker with `txerrs` or `req_timeouts` grown since the previous ones, or with more than
+//! `kafka_stats_outbuf_threshold` requests in its output buffer, switches to exporting every
+//! statistics right away, and `kafka_stats_cool_down_secs` of healthy ones switch back.
+
+use {
+    crate::instance_metrics::InstanceMetrics,
+    log::info,
+    prometheus::IntGauge,
+    rdkafka::statistics::Statistics,
+    std::{
+        collections::HashMap,
+        time::{Duration, Instant},
+    },
+};
+
+/// When statistics are exported in full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptiveStatsOptions {
+    /// Statistics exported at most once this many while healthy, at least 2.
+    pub healthy_every: u32,
+    /// `outbuf_cnt` of a broker above which it is unhealthy.
+    pub outbuf_threshold: i64,
+    /// Time statistics stay healthy before going back to exporting only some.
+    pub cool_down: Duration,
+}
+
+/// Whether every statistics are exported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsMode {
+    /// Every `healthy_every`-th.
+    Sampled,
+    /// Every one, since the brokers were last unhealthy.
+    Detailed,
+}
+
+/// Error counters of a broker, as librdkafka counts them since the producer started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct BrokerErrors {
+    txerrs: u64,
+    req_timeouts: u64,
+}
+
+#[derive(Debug)]
+pub struct AdaptiveStats {
+    options: AdaptiveStatsOptions,
+    mode: StatsMode,
+    /// Statistics to skip before exporting the next ones in `Sampled` mode.
+    to_skip: u32,
+    /// Start of the healthy run in `Detailed` mode.
+    healthy_since: Option<Instant>,
+    errors: HashMap<String, BrokerErrors>,
+    /// `kafka_stats_detailed` of the instance.
+    detailed: IntGauge,
+}
+
+impl AdaptiveStats {
+    /// Sampling statistics, the first of which are exported to compare the next against.
+    pub fn new(options: AdaptiveStatsOptions, metrics: &InstanceMetrics) -> Self {
+        let detailed = metrics.kafka_stats_detailed.clone();
+        detailed.set(0);
+        Self {
+            options,
+            mode: StatsMode::Sampled,
+            to_skip: 0,
+            healthy_since: None,
+            errors: HashMap::new(),
+            detailed,
+        }
+    }
+
+    #[cfg(test)]
+    fn mode(&self) -> StatsMode {
+        self.mode
+    }
+
+    /// Whether the statistics just received are exported.
+    pub fn sample(&mut self) -> bool {
+        match self.mode {
+            StatsMode::Detailed => true,
+            StatsMode::Sampled if self.to_skip == 0 => {
+                self.to_skip = self.options.healthy_every.saturating_sub(1);
+                true
+            }
+            StatsMode::Sampled => {
+                self.to_skip -= 1;
+                false
+            }
+        }
+    }
+
+    /// Judge the exported `statistics` received at `now`, switching mode as due.
+    pub fn observe(&mut self, statistics: &Statistics, now: Instant) -> StatsMode {
+        let healthy = self.is_healthy(statistics);
+        match (self.mode, healthy) {
+            (StatsMode::Sampled, true) => {}
+            (StatsMode::Sampled, false) => {
+                info!("Kafka brokers unhealthy, exporting every librdkafka statistics");
+                self.set_mode(StatsMode::Detailed);
+            }
+            (StatsMode::Detailed, false) => self.healthy_since = None,
+            (StatsMode::Detailed, true) => {
+                let since = *self.healthy_since.get_or_insert(now);
+                if now.saturating_duration_since(since) >= self.options.cool_down {
+                    info!("Kafka brokers healthy again, sampling librdkafka statistics");
+                    self.set_mode(StatsMode::Sampled);
+                }
+            }
+        }
+        self.mode
+    }
+
+    fn set_mode(&mut self, mode: StatsMode) {
+        self.mode = mode;
+        self.healthy_since = None;
+        // The next statistics after a switch back are a full `healthy_every` away.
+        self.to_skip = self.options.healthy_every.saturating_sub(1);
+        self.detailed.set((mode == StatsMode::Detailed).into());
+    }
+
+    /// Whether no broker has errors grown since its previous statistics or a deep output
+    /// buffer. Brokers seen for the first time have nothing to compare against.
+    fn is_healthy(&mut self, statistics: &Statistics) -> bool {
+        let mut healthy = true;
+        for (name, broker) in &statistics.brokers {
+            let errors = BrokerErrors {
+                txerrs: broker.txerrs,
+                req_timeouts: broker.req_timeouts,
+            };
+            if let Some(previous) = self.errors.insert(name.clone(), errors) {
+                // Counters restart with a re-created producer, which isn't a failure.
+                healthy &= errors.txerrs <= previous.txerrs
+                    && errors.req_timeouts <= previous.req_timeouts;
+            }
+            healthy &= broker.outbuf_cnt <= self.options.outbuf_threshold;
+        }
+        self.errors
+            .retain(|name, _| statistics.brokers.contains_key(name));
+        healthy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, rdkafka::statistics::Broker};
+
+    const OPTIONS: AdaptiveStatsOptions = AdaptiveStatsOptions {
+        healthy_every: 3,
+        outbuf_threshold: 10,
+        cool_down: Duration::from_secs(5),
+    };
+
+    /// Statistics of brokers `a` and `b`, the `txerrs`, `req_timeouts` and `outbuf_cnt` of `b`
+    /// as given.
+    fn statistics(txerrs: u64, req_timeouts: u64, outbuf_cnt: i64) -> Statistics {
+        let b = Broker {
+            txerrs,
+            req_timeouts,
+            outbuf_cnt,
+            ..Default::default()
+        };
+        Statistics {
+            brokers: [
+                ("a:9092/1".to_owned(), Broker::default()),
+                ("b:9092/2".to_owned(), b),
+            ]
+            .into(),
+            ..Default::default()
+        }
+    }
+
+    /// Feed `sequence` one second apart, returning which were exported and the mode after
+    /// each.
+    fn feed(stats: &mut AdaptiveStats, sequence: &[Statistics]) -> Vec<(bool, StatsMode)> {
+        let start = Instant::now();
+        sequence
+            .iter()
+            .enumerate()
+            .map(|(i, statistics)| {
+                let exported = stats.sample();
+                if exported {
+                    stats.observe(statistics, start + Duration::from_secs(i as u64));
+                }
+                (exported, stats.mode())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sampled_while_healthy() {
+        let mut stats = AdaptiveStats::new(OPTIONS, &InstanceMetrics::default());
+        let healthy = vec![statistics(1, 1, 10); 7];
+        let exported: Vec<bool> = feed(&mut stats, &healthy)
+            .into_iter()
+            .map(|(exported, mode)| {
+                assert_eq!(mode, StatsMode::Sampled);
+                exported
+            })
+            .collect();
+        assert_eq!(exported, [true, false, false, true, false, false, true]);
+    }
+
+    #[test]
+    fn test_transitions() {
+        use StatsMode::*;
+        let mut stats = AdaptiveStats::new(OPTIONS, &InstanceMetrics::default());
+        let sequence = [
+            statistics(0, 0, 0),
+            // Errors grown while skipped are caught by the next exported statistics.
+            statistics(2, 0, 0),
+            statistics(2, 0, 0),
+            statistics(2, 0, 0),
+            // Healthy from here, but not yet for the cool-down.
+            statistics(2, 0, 0),
+            statistics(2, 0, 0),
+            // A timeout restarts the cool-down.
+            statistics(2, 1, 0),
+            statistics(2, 1, 0),
+            statistics(2, 1, 0),
+            statistics(2, 1, 0),
+            statistics(2, 1, 0),
+            statistics(2, 1, 0),
+            statistics(2, 1, 0),
+            statistics(2, 1, 0),
+            // A deep output buffer alone is unhealthy.
+            statistics(2, 1, 11),
+            statistics(2, 1, 11),
+        ];
+        assert_eq!(
+            feed(&mut stats, &sequence),
+            [
+                (true, Sampled),
+                (false, Sampled),
+                (false, Sampled),
+                (true, Detailed),
+                (true, Detailed),
+                (true, Detailed),
+                (true, Detailed),
+                (true, Detailed),
+                (true, Detailed),
+                (true, Detailed),
+                (true, Detailed),
+                (true, Detailed),
+                // 5 seconds after the first healthy statistics of the run, at 7.
+                (true, Sampled),
+                (false, Sampled),
+                (false, Sampled),
+                (true, Detailed),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_restarted_counters_are_healthy() {
+        let mut stats = AdaptiveStats::new(
+            AdaptiveStatsOptions {
+                healthy_every: 2,
+                ..OPTIONS
+            },
+            &InstanceMetrics::default(),
+        );
+        let sequence = [
+            statistics(9, 9, 0),
+            statistics(0, 0, 0),
+            statistics(0, 0, 0),
+        ];
+        assert!(feed(&mut stats, &sequence)
+            .iter()
+            .all(|(_, mode)| *mode == StatsMode::Sampled));
+    }
+}
@@ -0,0 +1,60 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Replays captured notifications through a dry run plugin, printing every message it would
+//! produce as `topic key payload`, with key and payload hex encoded.
+
+use {
+    solana_accountsdb_plugin_kafka::{capture::CaptureReader, Config, KafkaPlugin},
+    std::{env, fs::File, io::BufReader, process::exit},
+};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        eprintln!("usage: {} <config.json> <capture>...", args[0]);
+        exit(2);
+    }
+
+    let config = Config::read_from(&args[1]).unwrap_or_else(|error| {
+        eprintln!("failed to read config {:?}: {error}", args[1]);
+        exit(1);
+    });
+    let plugin = KafkaPlugin::dry_run(&config).unwrap_or_else(|error| {
+        eprintln!("failed to load plugin: {error}");
+        exit(1);
+    });
+
+    for path in &args[2..] {
+        let reader = File::open(path)
+            .map(BufReader::new)
+            .and_then(CaptureReader::new)
+            .unwrap_or_else(|error| {
+                eprintln!("failed to open capture {path:?}: {error}");
+                exit(1);
+            });
+        for record in reader {
+            let record = record.unwrap_or_else(|error| {
+                eprintln!("failed to read capture {path:?}: {error}");
+                exit(1);
+            });
+            if let Err(error) = record.replay(&plugin) {
+                eprintln!("replay failed: {error}");
+            }
+            for message in plugin.take_recorded() {
+                println!("{message}");
+            }
+        }
+    }
+}
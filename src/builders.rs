@@ -0,0 +1,359 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Builders of realistic events for consumer tests, enabled by the `fixtures` feature.
+//! Events are built by the same code the plugin publishes with.
+
+use {
+    crate::{
+        build_account_event, build_transaction_event, InstanceMetrics, TransactionCaps,
+        TransactionEvent, UpdateAccountEvent,
+    },
+    solana_geyser_plugin_interface::geyser_plugin_interface::ReplicaAccountInfoV3,
+    solana_program::pubkey::Pubkey,
+    solana_sdk::{
+        message::SimpleAddressLoader,
+        signature::Signature,
+        transaction::{MessageHash, SanitizedTransaction, TransactionError, VersionedTransaction},
+    },
+    solana_transaction_status::TransactionStatusMeta,
+};
+
+/// Builds an `UpdateAccountEvent`, defaulting to an empty account owned by the system program.
+#[derive(Debug, Clone)]
+pub struct UpdateAccountEventBuilder {
+    pubkey: Pubkey,
+    slot: u64,
+    lamports: u64,
+    owner: Pubkey,
+    executable: bool,
+    rent_epoch: u64,
+    data: Vec<u8>,
+    write_version: u64,
+    txn_signature: Option<Signature>,
+}
+
+impl UpdateAccountEventBuilder {
+    pub fn new(pubkey: Pubkey) -> Self {
+        Self {
+            pubkey,
+            slot: 0,
+            lamports: 0,
+            owner: solana_program::system_program::id(),
+            executable: false,
+            rent_epoch: 0,
+            data: Vec::new(),
+            write_version: 0,
+            txn_signature: None,
+        }
+    }
+
+    pub fn slot(mut self, slot: u64) -> Self {
+        self.slot = slot;
+        self
+    }
+
+    pub fn lamports(mut self, lamports: u64) -> Self {
+        self.lamports = lamports;
+        self
+    }
+
+    pub fn owner(mut self, owner: Pubkey) -> Self {
+        self.owner = owner;
+        self
+    }
+
+    pub fn executable(mut self, executable: bool) -> Self {
+        self.executable = executable;
+        self
+    }
+
+    pub fn rent_epoch(mut self, rent_epoch: u64) -> Self {
+        self.rent_epoch = rent_epoch;
+        self
+    }
+
+    pub fn data(mut self, data: impl Into<Vec<u8>>) -> Self {
+        self.data = data.into();
+        self
+    }
+
+    pub fn write_version(mut self, write_version: u64) -> Self {
+        self.write_version = write_version;
+        self
+    }
+
+    /// Signature of the transaction causing the update.
+    pub fn txn_signature(mut self, signature: Signature) -> Self {
+        self.txn_signature = Some(signature);
+        self
+    }
+
+    pub fn build(self) -> UpdateAccountEvent {
+        let info = ReplicaAccountInfoV3 {
+            pubkey: self.pubkey.as_ref(),
+            lamports: self.lamports,
+            owner: self.owner.as_ref(),
+            executable: self.executable,
+            rent_epoch: self.rent_epoch,
+            data: &self.data,
+            write_version: self.write_version,
+            txn: None,
+        };
+        let mut event = build_account_event(self.slot, &info);
+        event.txn_signature = self.txn_signature.map(|v| v.as_ref().to_owned());
+        event
+    }
+}
+
+/// Builds a `TransactionEvent` from a signed transaction and its status.
+/// Addresses of V0 lookup tables are resolved from `loaded_addresses` of the status.
+#[derive(Debug, Clone)]
+pub struct TransactionEventBuilder {
+    transaction: VersionedTransaction,
+    meta: TransactionStatusMeta,
+    slot: u64,
+    index: usize,
+    is_vote: Option<bool>,
+    caps: TransactionCaps,
+}
+
+impl TransactionEventBuilder {
+    pub fn new(transaction: VersionedTransaction, meta: TransactionStatusMeta) -> Self {
+        Self {
+            transaction,
+            meta,
+            slot: 0,
+            index: 0,
+            is_vote: None,
+            caps: TransactionCaps::default(),
+        }
+    }
+
+    pub fn slot(mut self, slot: u64) -> Self {
+        self.slot = slot;
+        self
+    }
+
+    /// Position of the transaction in its block.
+    pub fn index(mut self, index: usize) -> Self {
+        self.index = index;
+        self
+    }
+
+    /// Defaults to whether the transaction is a simple vote transaction.
+    pub fn is_vote(mut self, is_vote: bool) -> Self {
+        self.is_vote = Some(is_vote);
+        self
+    }
+
+    /// Truncate inner instructions and log messages as a filter with these caps would.
+    pub fn caps(mut self, caps: TransactionCaps) -> Self {
+        self.caps = caps;
+        self
+    }
+
+    /// Fails if the transaction doesn't sanitize, as the validator would reject it.
+    pub fn build(self) -> Result<TransactionEvent, TransactionError> {
+        let transaction = SanitizedTransaction::try_create(
+            self.transaction,
+            MessageHash::Compute,
+            None,
+            SimpleAddressLoader::Enabled(self.meta.loaded_addresses.clone()),
+        )?;
+        // Balance length mismatches are counted in metrics of the builder's own.
+        Ok(build_transaction_event(
+            &InstanceMetrics::default(),
+            self.slot,
+            transaction.signature(),
+            self.is_vote
+                .unwrap_or_else(|| transaction.is_simple_vote_transaction()),
+            &transaction,
+            &self.meta,
+            self.index,
+            self.caps,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{message_wrapper::EventMessage, sanitized_message::MessagePayload, MessageWrapper},
+        prost::Message,
+        solana_sdk::{
+            hash::Hash,
+            instruction::CompiledInstruction,
+            message::{
+                v0::{self, LoadedAddresses, MessageAddressTableLookup},
+                MessageHeader, VersionedMessage,
+            },
+            signature::Signer,
+            signer::keypair::Keypair,
+            system_transaction,
+        },
+    };
+
+    fn round_trip<M: Message + Default>(message: &M) -> M {
+        M::decode(message.encode_to_vec().as_slice()).unwrap()
+    }
+
+    #[test]
+    fn test_account_event() {
+        let pubkey = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let signature = Signature::from([7; 64]);
+        let event = UpdateAccountEventBuilder::new(pubkey)
+            .slot(42)
+            .lamports(1_000)
+            .owner(owner)
+            .data(vec![1, 2, 3])
+            .write_version(9)
+            .txn_signature(signature)
+            .build();
+        assert_eq!(event.pubkey, pubkey.to_bytes());
+        assert_eq!(event.owner, owner.to_bytes());
+        assert_eq!(event.txn_signature, Some(signature.as_ref().to_vec()));
+        assert_eq!(round_trip(&event), event);
+
+        let default = UpdateAccountEventBuilder::new(pubkey).build();
+        assert_eq!(
+            default.owner,
+            solana_program::system_program::id().to_bytes()
+        );
+        assert_eq!(default.txn_signature, None);
+
+        let wrapper = MessageWrapper {
+            event_message: Some(EventMessage::Account(event)),
+            ..Default::default()
+        };
+        assert_eq!(round_trip(&wrapper), wrapper);
+    }
+
+    #[test]
+    fn test_legacy_transaction_event() {
+        let payer = Keypair::new();
+        let to = Pubkey::new_unique();
+        let transaction =
+            system_transaction::transfer(&payer, &to, 5_000, Hash::new_unique()).into();
+        let meta = TransactionStatusMeta {
+            fee: 5_000,
+            pre_balances: vec![20_000, 0, 1],
+            post_balances: vec![10_000, 5_000, 1],
+            log_messages: Some(vec![
+                "Program 11111111111111111111111111111111 success".into()
+            ]),
+            ..Default::default()
+        };
+        let event = TransactionEventBuilder::new(transaction, meta)
+            .slot(7)
+            .index(3)
+            .build()
+            .unwrap();
+        assert_eq!(event.slot, 7);
+        assert_eq!(event.index, 3);
+        assert!(!event.is_vote);
+        let status = event.transaction_status_meta.as_ref().unwrap();
+        assert_eq!(status.fee, 5_000);
+        assert!(status.rewards.is_empty());
+        let message = event
+            .transaction
+            .as_ref()
+            .unwrap()
+            .message
+            .as_ref()
+            .unwrap();
+        match message.message_payload.as_ref().unwrap() {
+            MessagePayload::Legacy(legacy) => assert_eq!(
+                legacy.message.as_ref().unwrap().account_keys[..2],
+                [payer.pubkey().to_bytes().to_vec(), to.to_bytes().to_vec()]
+            ),
+            MessagePayload::V0(_) => panic!("expected a legacy message"),
+        }
+        assert_eq!(round_trip(&event), event);
+    }
+
+    #[test]
+    fn test_v0_transaction_event() {
+        let payer = Keypair::new();
+        let table = Pubkey::new_unique();
+        let loaded = Pubkey::new_unique();
+        let message = v0::Message {
+            header: MessageHeader {
+                num_required_signatures: 1,
+                num_readonly_signed_accounts: 0,
+                num_readonly_unsigned_accounts: 1,
+            },
+            account_keys: vec![payer.pubkey(), solana_program::system_program::id()],
+            recent_blockhash: Hash::new_unique(),
+            instructions: vec![CompiledInstruction::new_from_raw_parts(
+                1,
+                vec![],
+                vec![0, 2],
+            )],
+            address_table_lookups: vec![MessageAddressTableLookup {
+                account_key: table,
+                writable_indexes: vec![0],
+                readonly_indexes: vec![],
+            }],
+        };
+        let transaction =
+            VersionedTransaction::try_new(VersionedMessage::V0(message), &[&payer]).unwrap();
+        let signature = transaction.signatures[0];
+        let meta = TransactionStatusMeta {
+            loaded_addresses: LoadedAddresses {
+                writable: vec![loaded],
+                readonly: vec![],
+            },
+            ..Default::default()
+        };
+        let event = TransactionEventBuilder::new(transaction.clone(), meta)
+            .is_vote(true)
+            .build()
+            .unwrap();
+        assert!(event.is_vote);
+        assert_eq!(event.signature, signature.as_ref());
+        let message = event
+            .transaction
+            .as_ref()
+            .unwrap()
+            .message
+            .as_ref()
+            .unwrap();
+        match message.message_payload.as_ref().unwrap() {
+            MessagePayload::V0(v0) => {
+                let addresses = v0.loaded_adresses.as_ref().unwrap();
+                assert_eq!(addresses.writable, vec![loaded.to_bytes().to_vec()]);
+                let lookups = &v0.message.as_ref().unwrap().address_table_lookup;
+                assert_eq!(lookups[0].account_key, table.to_bytes());
+            }
+            MessagePayload::Legacy(_) => panic!("expected a V0 message"),
+        }
+        let wrapper = MessageWrapper {
+            event_message: Some(EventMessage::Transaction(event)),
+            ..Default::default()
+        };
+        assert_eq!(round_trip(&wrapper), wrapper);
+
+        // Unsigned transactions don't sanitize.
+        let mut unsigned = transaction;
+        unsigned.signatures.clear();
+        assert!(
+            TransactionEventBuilder::new(unsigned, TransactionStatusMeta::default())
+                .build()
+                .is_err()
+        );
+    }
+}
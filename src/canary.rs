@@ -0,0 +1,240 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Canary records produced to every topic at load with `verify_topic_access`, so missing ACLs
+//! fail the load instead of the first notifications.
+
+use {
+    crate::{version::VERSION, wall_clock::unix_millis, Config, PluginLifecycleEvent},
+    log::{error, info, warn},
+    prost::Message,
+    rdkafka::{
+        error::{KafkaError, RDKafkaErrorCode},
+        message::{Header, Message as _, OwnedHeaders},
+        producer::{BaseProducer, BaseRecord, DeliveryResult, ProducerContext},
+        ClientContext,
+    },
+    solana_geyser_plugin_interface::geyser_plugin_interface::{
+        GeyserPluginError, Result as PluginResult,
+    },
+    std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+};
+
+/// Header set to `true` on canary records, which consumers should skip.
+pub const CANARY_HEADER: &str = "canary";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+struct TopicResult {
+    topic: String,
+    fail_open: bool,
+    result: Option<Result<(), KafkaError>>,
+}
+
+/// First delivery result of the canary of each topic.
+#[derive(Debug)]
+pub struct CanaryResults {
+    topics: Mutex<Vec<TopicResult>>,
+}
+
+impl CanaryResults {
+    /// Results of `topics`, with whether failures to produce to them are tolerated.
+    pub fn new(topics: &[(String, bool)]) -> Self {
+        let topics = topics
+            .iter()
+            .map(|(topic, fail_open)| TopicResult {
+                topic: topic.clone(),
+                fail_open: *fail_open,
+                result: None,
+            })
+            .collect();
+        Self {
+            topics: Mutex::new(topics),
+        }
+    }
+
+    pub fn record(&self, topic: &str, result: Result<(), KafkaError>) {
+        let mut topics = self.topics.lock().unwrap();
+        if let Some(entry) = topics.iter_mut().find(|entry| entry.topic == topic) {
+            entry.result.get_or_insert(result);
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        let topics = self.topics.lock().unwrap();
+        topics.iter().all(|entry| entry.result.is_some())
+    }
+
+    /// Fails if any topic that isn't `fail_open` rejected its canary for authorization or
+    /// not existing. Other failures and missing results are logged.
+    pub fn check(&self) -> PluginResult<()> {
+        let topics = self.topics.lock().unwrap();
+        let mut denied = Vec::new();
+        for entry in topics.iter() {
+            let topic = &entry.topic;
+            match &entry.result {
+                Some(Ok(())) => info!("Verified access to topic {topic:?}"),
+                Some(Err(error)) if is_access_error(error) && !entry.fail_open => {
+                    denied.push(format!("{topic:?}: {error}"));
+                }
+                Some(Err(error)) => warn!("Canary to topic {topic:?} failed: {error}"),
+                None => warn!("No delivery report of the canary to topic {topic:?}"),
+            }
+        }
+        if denied.is_empty() {
+            return Ok(());
+        }
+        let error = format!("topic access denied: {}", denied.join(", "));
+        error!("{error}");
+        Err(GeyserPluginError::Custom(error.into()))
+    }
+}
+
+fn is_access_error(error: &KafkaError) -> bool {
+    matches!(
+        error.rdkafka_error_code(),
+        Some(
+            RDKafkaErrorCode::TopicAuthorizationFailed
+                | RDKafkaErrorCode::ClusterAuthorizationFailed
+                | RDKafkaErrorCode::UnknownTopicOrPartition
+                | RDKafkaErrorCode::UnknownTopic
+        )
+    )
+}
+
+struct CanaryContext(Arc<CanaryResults>);
+
+impl ClientContext for CanaryContext {}
+
+impl ProducerContext for CanaryContext {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult<'_>, _: Self::DeliveryOpaque) {
+        match result {
+            Ok(message) => self.0.record(message.topic(), Ok(())),
+            Err((error, message)) => self.0.record(message.topic(), Err(error.clone())),
+        }
+    }
+}
+
+/// `canary_value`, or an encoded `PluginLifecycleEvent` marked as a canary.
+fn canary_payload(config: &Config) -> Vec<u8> {
+    if let Some(value) = &config.canary_value {
+        return value.clone().into_bytes();
+    }
+    PluginLifecycleEvent {
+        canary: true,
+        version: VERSION.version.to_owned(),
+        timestamp: unix_millis(),
+    }
+    .encode_to_vec()
+}
+
+/// Produce a canary to every configured topic on a producer of its own, and wait up to
+/// `verify_topic_access_timeout_ms` for their delivery reports.
+pub fn verify_topic_access(config: &Config) -> PluginResult<()> {
+    let topics = config.access_topics();
+    let results = Arc::new(CanaryResults::new(&topics));
+    let producer: BaseProducer<CanaryContext> = config
+        .client_config()
+        .and_then(|client| client.create_with_context(CanaryContext(Arc::clone(&results))))
+        .map_err(|error| GeyserPluginError::Custom(Box::new(error)))?;
+    let payload = canary_payload(config);
+    let headers = OwnedHeaders::new().insert(Header {
+        key: CANARY_HEADER,
+        value: Some("true"),
+    });
+    for (topic, _) in &topics {
+        let record = BaseRecord::to(topic)
+            .key(config.canary_key.as_bytes())
+            .payload(&payload)
+            .headers(headers.clone());
+        if let Err((error, _)) = producer.send(record) {
+            results.record(topic, Err(error));
+        }
+    }
+
+    let deadline = Instant::now() + Duration::from_millis(config.verify_topic_access_timeout_ms);
+    while !results.is_complete() && Instant::now() < deadline {
+        producer.poll(POLL_INTERVAL);
+    }
+    results.check()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn denied() -> KafkaError {
+        KafkaError::MessageProduction(RDKafkaErrorCode::TopicAuthorizationFailed)
+    }
+
+    #[test]
+    fn test_results() {
+        let topics = [
+            ("accounts".to_owned(), false),
+            ("slots".to_owned(), false),
+            ("blocks".to_owned(), true),
+        ];
+        let results = CanaryResults::new(&topics);
+        results.record("accounts", Ok(()));
+        results.record("other", Err(denied()));
+        assert!(!results.is_complete());
+        // Unreported topics don't fail the load.
+        assert!(results.check().is_ok());
+
+        results.record(
+            "slots",
+            Err(KafkaError::MessageProduction(
+                RDKafkaErrorCode::MessageTimedOut,
+            )),
+        );
+        results.record("blocks", Err(denied()));
+        assert!(results.is_complete());
+        // Neither a timeout nor a denied fail_open topic fail it either.
+        assert!(results.check().is_ok());
+
+        let results = CanaryResults::new(&topics);
+        results.record(
+            "slots",
+            Err(KafkaError::MessageProduction(
+                RDKafkaErrorCode::UnknownTopicOrPartition,
+            )),
+        );
+        // Only the first result of a topic counts.
+        results.record("slots", Ok(()));
+        results.record("accounts", Err(denied()));
+        let error = results.check().unwrap_err().to_string();
+        assert!(
+            error.contains("\"accounts\"") && error.contains("\"slots\""),
+            "{error}"
+        );
+        assert!(!error.contains("blocks"), "{error}");
+    }
+
+    #[test]
+    fn test_payload() {
+        let mut config = Config::default();
+        let event = PluginLifecycleEvent::decode(canary_payload(&config).as_slice()).unwrap();
+        assert!(event.canary);
+        assert_eq!(event.version, VERSION.version);
+
+        config.canary_value = Some("ping".to_owned());
+        assert_eq!(canary_payload(&config), b"ping");
+    }
+}
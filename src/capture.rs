@@ -0,0 +1,690 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Capture of the raw Geyser notifications received by the plugin, and their replay.
+//!
+//! A capture file starts with `CAPTURE_MAGIC` and a little endian `u32` format version,
+//! followed by records, each a little endian `u32` length and a bincode encoded `CaptureRecord`.
+
+use {
+    crate::{wall_clock::unix_secs, KafkaPlugin},
+    log::{error, info, warn},
+    serde::{Deserialize, Serialize},
+    solana_account_decoder::parse_token::UiTokenAmount,
+    solana_geyser_plugin_interface::geyser_plugin_interface::{
+        GeyserPlugin, GeyserPluginError as PluginError, ReplicaAccountInfoV3,
+        ReplicaAccountInfoVersions, ReplicaTransactionInfoV2, ReplicaTransactionInfoVersions,
+        Result as PluginResult, SlotStatus as PluginSlotStatus,
+    },
+    solana_sdk::{
+        hash::Hash,
+        message::{v0::LoadedAddresses, SanitizedMessage, SimpleAddressLoader},
+        signature::Signature,
+        transaction::{SanitizedTransaction, TransactionError, VersionedTransaction},
+        transaction_context::TransactionReturnData,
+    },
+    solana_transaction_status::{
+        InnerInstructions, Rewards, TransactionStatusMeta, TransactionTokenBalance,
+    },
+    std::{
+        fs::{self, File},
+        io::{BufWriter, Error as IoError, ErrorKind, Read, Result as IoResult, Write},
+        path::Path,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Mutex,
+        },
+    },
+};
+
+pub const CAPTURE_MAGIC: &[u8; 8] = b"KPLUGCAP";
+pub const CAPTURE_VERSION: u32 = 1;
+
+/// Largest record accepted by the reader, guarding against corrupt lengths.
+const MAX_RECORD_LEN: usize = 64 << 20;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CaptureRecord {
+    Account(Box<CapturedAccount>),
+    Slot(CapturedSlot),
+    Transaction(Box<CapturedTransaction>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapturedAccount {
+    pub slot: u64,
+    pub is_startup: bool,
+    pub pubkey: Vec<u8>,
+    pub lamports: u64,
+    pub owner: Vec<u8>,
+    pub executable: bool,
+    pub rent_epoch: u64,
+    pub data: Vec<u8>,
+    pub write_version: u64,
+    pub txn: Option<CapturedSanitizedTransaction>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CapturedSlotStatus {
+    Processed,
+    Rooted,
+    Confirmed,
+    /// A status unknown to the capturing build, which replay skips.
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapturedSlot {
+    pub slot: u64,
+    pub parent: Option<u64>,
+    pub status: CapturedSlotStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapturedTransaction {
+    pub slot: u64,
+    pub signature: Signature,
+    pub is_vote: bool,
+    pub index: u64,
+    pub transaction: CapturedSanitizedTransaction,
+    pub meta: CapturedStatusMeta,
+}
+
+/// The parts a `SanitizedTransaction` can be rebuilt from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapturedSanitizedTransaction {
+    pub transaction: VersionedTransaction,
+    pub message_hash: Hash,
+    pub is_simple_vote: bool,
+    pub loaded_addresses: Option<LoadedAddresses>,
+}
+
+impl From<&SanitizedTransaction> for CapturedSanitizedTransaction {
+    fn from(transaction: &SanitizedTransaction) -> Self {
+        let loaded_addresses = match transaction.message() {
+            SanitizedMessage::Legacy(_) => None,
+            SanitizedMessage::V0(message) => Some(message.loaded_addresses.clone().into_owned()),
+        };
+        Self {
+            transaction: transaction.to_versioned_transaction(),
+            message_hash: *transaction.message_hash(),
+            is_simple_vote: transaction.is_simple_vote_transaction(),
+            loaded_addresses,
+        }
+    }
+}
+
+impl CapturedSanitizedTransaction {
+    pub fn to_sanitized(&self) -> Result<SanitizedTransaction, TransactionError> {
+        let loader = match &self.loaded_addresses {
+            Some(addresses) => SimpleAddressLoader::Enabled(addresses.clone()),
+            None => SimpleAddressLoader::Disabled,
+        };
+        SanitizedTransaction::try_create(
+            self.transaction.clone(),
+            self.message_hash,
+            Some(self.is_simple_vote),
+            loader,
+        )
+    }
+}
+
+/// Serializable mirror of `TransactionStatusMeta`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapturedStatusMeta {
+    pub status: Result<(), TransactionError>,
+    pub fee: u64,
+    pub pre_balances: Vec<u64>,
+    pub post_balances: Vec<u64>,
+    pub inner_instructions: Option<Vec<InnerInstructions>>,
+    pub log_messages: Option<Vec<String>>,
+    pub pre_token_balances: Option<Vec<CapturedTokenBalance>>,
+    pub post_token_balances: Option<Vec<CapturedTokenBalance>>,
+    pub rewards: Option<Rewards>,
+    pub loaded_addresses: LoadedAddresses,
+    pub return_data: Option<TransactionReturnData>,
+    pub compute_units_consumed: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapturedTokenBalance {
+    pub account_index: u8,
+    pub mint: String,
+    pub ui_token_amount: UiTokenAmount,
+    pub owner: String,
+    pub program_id: String,
+}
+
+impl From<&TransactionStatusMeta> for CapturedStatusMeta {
+    fn from(meta: &TransactionStatusMeta) -> Self {
+        let balances = |balances: &Option<Vec<TransactionTokenBalance>>| {
+            balances.as_ref().map(|balances| {
+                balances
+                    .iter()
+                    .map(|balance| CapturedTokenBalance {
+                        account_index: balance.account_index,
+                        mint: balance.mint.clone(),
+                        ui_token_amount: balance.ui_token_amount.clone(),
+                        owner: balance.owner.clone(),
+                        program_id: balance.program_id.clone(),
+                    })
+                    .collect()
+            })
+        };
+        Self {
+            status: meta.status.clone(),
+            fee: meta.fee,
+            pre_balances: meta.pre_balances.clone(),
+            post_balances: meta.post_balances.clone(),
+            inner_instructions: meta.inner_instructions.clone(),
+            log_messages: meta.log_messages.clone(),
+            pre_token_balances: balances(&meta.pre_token_balances),
+            post_token_balances: balances(&meta.post_token_balances),
+            rewards: meta.rewards.clone(),
+            loaded_addresses: meta.loaded_addresses.clone(),
+            return_data: meta.return_data.clone(),
+            compute_units_consumed: meta.compute_units_consumed,
+        }
+    }
+}
+
+impl From<&CapturedStatusMeta> for TransactionStatusMeta {
+    fn from(meta: &CapturedStatusMeta) -> Self {
+        let balances = |balances: &Option<Vec<CapturedTokenBalance>>| {
+            balances.as_ref().map(|balances| {
+                balances
+                    .iter()
+                    .map(|balance| TransactionTokenBalance {
+                        account_index: balance.account_index,
+                        mint: balance.mint.clone(),
+                        ui_token_amount: balance.ui_token_amount.clone(),
+                        owner: balance.owner.clone(),
+                        program_id: balance.program_id.clone(),
+                    })
+                    .collect()
+            })
+        };
+        Self {
+            status: meta.status.clone(),
+            fee: meta.fee,
+            pre_balances: meta.pre_balances.clone(),
+            post_balances: meta.post_balances.clone(),
+            inner_instructions: meta.inner_instructions.clone(),
+            log_messages: meta.log_messages.clone(),
+            pre_token_balances: balances(&meta.pre_token_balances),
+            post_token_balances: balances(&meta.post_token_balances),
+            rewards: meta.rewards.clone(),
+            loaded_addresses: meta.loaded_addresses.clone(),
+            return_data: meta.return_data.clone(),
+            compute_units_consumed: meta.compute_units_consumed,
+        }
+    }
+}
+
+impl CaptureRecord {
+    pub fn account(slot: u64, is_startup: bool, info: &ReplicaAccountInfoV3) -> Self {
+        Self::Account(Box::new(CapturedAccount {
+            slot,
+            is_startup,
+            pubkey: info.pubkey.to_vec(),
+            lamports: info.lamports,
+            owner: info.owner.to_vec(),
+            executable: info.executable,
+            rent_epoch: info.rent_epoch,
+            data: info.data.to_vec(),
+            write_version: info.write_version,
+            txn: info.txn.map(CapturedSanitizedTransaction::from),
+        }))
+    }
+
+    pub fn slot(slot: u64, parent: Option<u64>, status: &PluginSlotStatus) -> Self {
+        let status = match status {
+            PluginSlotStatus::Processed => CapturedSlotStatus::Processed,
+            PluginSlotStatus::Rooted => CapturedSlotStatus::Rooted,
+            PluginSlotStatus::Confirmed => CapturedSlotStatus::Confirmed,
+            #[allow(unreachable_patterns)]
+            _ => CapturedSlotStatus::Unknown,
+        };
+        Self::Slot(CapturedSlot {
+            slot,
+            parent,
+            status,
+        })
+    }
+
+    pub fn transaction(slot: u64, info: &ReplicaTransactionInfoV2) -> Self {
+        Self::Transaction(Box::new(CapturedTransaction {
+            slot,
+            signature: *info.signature,
+            is_vote: info.is_vote,
+            index: info.index as u64,
+            transaction: info.transaction.into(),
+            meta: info.transaction_status_meta.into(),
+        }))
+    }
+
+    /// Drive `plugin` with the notification this record was captured from.
+    pub fn replay(&self, plugin: &KafkaPlugin) -> PluginResult<()> {
+        let invalid = |error: TransactionError| PluginError::Custom(Box::new(error));
+        match self {
+            Self::Account(account) => {
+                let txn = account.txn.as_ref().map(|txn| txn.to_sanitized());
+                let txn = txn.transpose().map_err(invalid)?;
+                let info = ReplicaAccountInfoV3 {
+                    pubkey: &account.pubkey,
+                    lamports: account.lamports,
+                    owner: &account.owner,
+                    executable: account.executable,
+                    rent_epoch: account.rent_epoch,
+                    data: &account.data,
+                    write_version: account.write_version,
+                    txn: txn.as_ref(),
+                };
+                plugin.update_account(
+                    ReplicaAccountInfoVersions::V0_0_3(&info),
+                    account.slot,
+                    account.is_startup,
+                )
+            }
+            Self::Slot(slot) => {
+                let status = match slot.status {
+                    CapturedSlotStatus::Processed => PluginSlotStatus::Processed,
+                    CapturedSlotStatus::Rooted => PluginSlotStatus::Rooted,
+                    CapturedSlotStatus::Confirmed => PluginSlotStatus::Confirmed,
+                    CapturedSlotStatus::Unknown => return Ok(()),
+                };
+                plugin.update_slot_status(slot.slot, slot.parent, status)
+            }
+            Self::Transaction(transaction) => {
+                let sanitized = transaction.transaction.to_sanitized().map_err(invalid)?;
+                let meta = TransactionStatusMeta::from(&transaction.meta);
+                let info = ReplicaTransactionInfoV2 {
+                    signature: &transaction.signature,
+                    is_vote: transaction.is_vote,
+                    transaction: &sanitized,
+                    transaction_status_meta: &meta,
+                    index: transaction.index as usize,
+                };
+                plugin.notify_transaction(
+                    ReplicaTransactionInfoVersions::V0_0_2(&info),
+                    transaction.slot,
+                )
+            }
+        }
+    }
+}
+
+pub struct CaptureWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    pub fn new(mut writer: W) -> IoResult<Self> {
+        writer.write_all(CAPTURE_MAGIC)?;
+        writer.write_all(&CAPTURE_VERSION.to_le_bytes())?;
+        Ok(Self { writer })
+    }
+
+    /// Append a record, returning the number of bytes written.
+    pub fn write(&mut self, record: &CaptureRecord) -> IoResult<u64> {
+        let buf = bincode::serialize(record)
+            .map_err(|error| IoError::new(ErrorKind::InvalidData, error))?;
+        self.writer.write_all(&(buf.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&buf)?;
+        Ok(4 + buf.len() as u64)
+    }
+
+    pub fn flush(&mut self) -> IoResult<()> {
+        self.writer.flush()
+    }
+}
+
+pub struct CaptureReader<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut reader: R) -> IoResult<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != CAPTURE_MAGIC {
+            return Err(IoError::new(ErrorKind::InvalidData, "not a capture file"));
+        }
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != CAPTURE_VERSION {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                format!("unsupported capture version {version}"),
+            ));
+        }
+        Ok(Self { reader })
+    }
+
+    /// Read the next record, `None` at the end of the capture.
+    pub fn read(&mut self) -> IoResult<Option<CaptureRecord>> {
+        let mut len = [0u8; 4];
+        match self.reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error),
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_RECORD_LEN {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                format!("capture record of {len} bytes"),
+            ));
+        }
+        let mut buf = vec![0u8; len];
+        self.reader.read_exact(&mut buf)?;
+        bincode::deserialize(&buf)
+            .map(Some)
+            .map_err(|error| IoError::new(ErrorKind::InvalidData, error))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = IoResult<CaptureRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
+
+/// Deterministically keeps `rate` of the notifications, evenly spaced.
+struct Sampler {
+    rate: f64,
+    seen: AtomicU64,
+}
+
+impl Sampler {
+    fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            seen: AtomicU64::new(0),
+        }
+    }
+
+    fn sample(&self) -> bool {
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed) as f64;
+        (seen * self.rate).floor() != ((seen + 1.0) * self.rate).floor()
+    }
+}
+
+/// Sampled, size bounded capture of notifications to a file.
+pub struct Capture {
+    writer: Mutex<CaptureWriter<BufWriter<File>>>,
+    max_bytes: u64,
+    written: AtomicU64,
+    sampler: Sampler,
+}
+
+impl Capture {
+    pub fn create(dir: &str, max_bytes: u64, sample_rate: f64) -> IoResult<Self> {
+        fs::create_dir_all(dir)?;
+        let started = unix_secs();
+        let path = Path::new(dir).join(format!(
+            "notifications-{started}-{}.capture",
+            std::process::id()
+        ));
+        info!("Capturing notifications to {path:?}");
+        let writer = CaptureWriter::new(BufWriter::new(File::create(path)?))?;
+        Ok(Self {
+            writer: Mutex::new(writer),
+            max_bytes,
+            written: AtomicU64::new(0),
+            sampler: Sampler::new(sample_rate),
+        })
+    }
+
+    /// Capture a sampled notification.
+    pub fn record(&self, record: impl FnOnce() -> CaptureRecord) {
+        if self.sampler.sample() {
+            self.record_always(record);
+        }
+    }
+
+    /// Capture a notification regardless of sampling, as slot statuses are.
+    pub fn record_always(&self, record: impl FnOnce() -> CaptureRecord) {
+        if self.written.load(Ordering::Relaxed) >= self.max_bytes {
+            return;
+        }
+        let mut writer = self.writer.lock().unwrap();
+        match writer.write(&record()) {
+            Ok(len) => {
+                let written = self.written.fetch_add(len, Ordering::Relaxed) + len;
+                if written >= self.max_bytes {
+                    warn!("Notification capture reached {written} bytes, stopping");
+                    let _ = writer.flush();
+                }
+            }
+            Err(error) => {
+                error!("Failed to capture notification: {error}");
+                self.written.store(self.max_bytes, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn flush(&self) {
+        if let Err(error) = self.writer.lock().unwrap().flush() {
+            error!("Failed to flush notification capture: {error}");
+        }
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{Config, ConfigFilter},
+        solana_sdk::{
+            instruction::CompiledInstruction,
+            message::{Message, MessageHeader, VersionedMessage},
+            pubkey::Pubkey,
+        },
+        solana_transaction_status::InnerInstruction,
+    };
+
+    const FIXTURE: &[u8] = include_bytes!("../fixtures/notifications.capture");
+    const GOLDEN: &str = include_str!("../fixtures/notifications.golden");
+
+    fn transaction(seed: u8) -> CapturedSanitizedTransaction {
+        let program = Pubkey::new_from_array([seed; 32]);
+        let message = Message {
+            header: MessageHeader {
+                num_required_signatures: 1,
+                num_readonly_signed_accounts: 0,
+                num_readonly_unsigned_accounts: 1,
+            },
+            account_keys: vec![Pubkey::new_from_array([seed + 1; 32]), program],
+            recent_blockhash: Hash::new_from_array([seed + 2; 32]),
+            instructions: vec![CompiledInstruction::new_from_raw_parts(
+                1,
+                vec![seed],
+                vec![0],
+            )],
+        };
+        CapturedSanitizedTransaction {
+            transaction: VersionedTransaction {
+                signatures: vec![Signature::from([seed; 64])],
+                message: VersionedMessage::Legacy(message),
+            },
+            message_hash: Hash::new_from_array([seed + 3; 32]),
+            is_simple_vote: false,
+            loaded_addresses: None,
+        }
+    }
+
+    fn records() -> Vec<CaptureRecord> {
+        vec![
+            CaptureRecord::Slot(CapturedSlot {
+                slot: 100,
+                parent: Some(99),
+                status: CapturedSlotStatus::Processed,
+            }),
+            CaptureRecord::Account(Box::new(CapturedAccount {
+                slot: 100,
+                is_startup: false,
+                pubkey: vec![1; 32],
+                lamports: 5_000,
+                owner: vec![2; 32],
+                executable: false,
+                rent_epoch: 0,
+                data: vec![7; 16],
+                write_version: 3,
+                txn: Some(transaction(10)),
+            })),
+            CaptureRecord::Transaction(Box::new(CapturedTransaction {
+                slot: 100,
+                signature: Signature::from([10; 64]),
+                is_vote: false,
+                index: 4,
+                transaction: transaction(10),
+                meta: CapturedStatusMeta {
+                    status: Ok(()),
+                    fee: 5_000,
+                    pre_balances: vec![10_000, 1],
+                    post_balances: vec![5_000, 1],
+                    inner_instructions: Some(vec![InnerInstructions {
+                        index: 0,
+                        instructions: vec![InnerInstruction {
+                            instruction: CompiledInstruction::new_from_raw_parts(
+                                1,
+                                vec![1, 2],
+                                vec![0],
+                            ),
+                            stack_height: Some(2),
+                        }],
+                    }]),
+                    log_messages: Some(vec!["Program log: hello".to_owned()]),
+                    pre_token_balances: Some(vec![]),
+                    post_token_balances: Some(vec![CapturedTokenBalance {
+                        account_index: 0,
+                        mint: Pubkey::new_from_array([3; 32]).to_string(),
+                        ui_token_amount: UiTokenAmount {
+                            ui_amount: Some(1.5),
+                            decimals: 2,
+                            amount: "150".to_owned(),
+                            ui_amount_string: "1.5".to_owned(),
+                        },
+                        owner: Pubkey::new_from_array([1; 32]).to_string(),
+                        program_id: Pubkey::new_from_array([10; 32]).to_string(),
+                    }]),
+                    rewards: Some(vec![]),
+                    loaded_addresses: LoadedAddresses::default(),
+                    return_data: None,
+                    compute_units_consumed: Some(1_234),
+                },
+            })),
+            CaptureRecord::Slot(CapturedSlot {
+                slot: 100,
+                parent: None,
+                status: CapturedSlotStatus::Rooted,
+            }),
+        ]
+    }
+
+    fn encode(records: &[CaptureRecord]) -> Vec<u8> {
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        for record in records {
+            writer.write(record).unwrap();
+        }
+        writer.writer
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let records = records();
+        let buf = encode(&records);
+        let read = CaptureReader::new(buf.as_slice())
+            .unwrap()
+            .collect::<IoResult<Vec<_>>>()
+            .unwrap();
+        assert_eq!(read, records);
+
+        let CaptureRecord::Transaction(transaction) = &records[2] else {
+            unreachable!()
+        };
+        let sanitized = transaction.transaction.to_sanitized().unwrap();
+        assert_eq!(
+            CapturedSanitizedTransaction::from(&sanitized),
+            transaction.transaction
+        );
+        let meta = TransactionStatusMeta::from(&transaction.meta);
+        assert_eq!(CapturedStatusMeta::from(&meta), transaction.meta);
+
+        assert!(CaptureReader::new(&b"NOTACAPTURE!"[..]).is_err());
+        let truncated = &buf[..buf.len() - 1];
+        assert!(CaptureReader::new(truncated)
+            .unwrap()
+            .collect::<IoResult<Vec<_>>>()
+            .is_err());
+    }
+
+    /// Set `UPDATE_FIXTURES` to rewrite the fixtures instead of comparing against them.
+    fn check_fixture(name: &str, expected: &[u8], actual: &[u8]) {
+        if std::env::var_os("UPDATE_FIXTURES").is_some() {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("fixtures")
+                .join(name);
+            fs::write(path, actual).unwrap();
+        } else {
+            assert_eq!(expected, actual, "fixture {name} is out of date");
+        }
+    }
+
+    #[test]
+    fn test_fixture_is_current() {
+        check_fixture("notifications.capture", FIXTURE, &encode(&records()));
+    }
+
+    #[test]
+    fn test_replay_golden() {
+        let mut config = Config::default();
+        config.filters = vec![ConfigFilter {
+            update_account_topic: Some("accounts".to_owned()),
+            slot_status_topic: Some("slots".to_owned()),
+            transaction_topic: Some("transactions".to_owned()),
+            program_filters: vec![Pubkey::new_from_array([2; 32]).to_string()],
+            account_filters: vec![Pubkey::new_from_array([11; 32]).to_string()],
+            ..Default::default()
+        }];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        for record in CaptureReader::new(FIXTURE).unwrap() {
+            record.unwrap().replay(&plugin).unwrap();
+        }
+        let output: String = plugin
+            .take_recorded()
+            .iter()
+            .map(|message| format!("{message}\n"))
+            .collect();
+        check_fixture("notifications.golden", GOLDEN.as_bytes(), output.as_bytes());
+    }
+
+    #[test]
+    fn test_sampling() {
+        for (rate, expected) in [(1.0, 100), (0.25, 25), (0.1, 10)] {
+            let sampler = Sampler::new(rate);
+            assert_eq!((0..100).filter(|_| sampler.sample()).count(), expected);
+        }
+    }
+}
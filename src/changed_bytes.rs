@@ -0,0 +1,218 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{
+        resources::{hash_map_bytes, MemoryFootprint},
+        Filter,
+    },
+    std::{
+        collections::{BTreeMap, HashMap},
+        mem::size_of,
+        ops::Range,
+        sync::Mutex,
+    },
+};
+
+/// Bytes of the watched ranges last seen for each account, least recently seen evicted first.
+#[derive(Debug, Default)]
+struct RangeCache {
+    /// Watched bytes and last use of each account.
+    accounts: HashMap<[u8; 32], (Vec<u8>, u64)>,
+    /// Accounts by last use.
+    uses: BTreeMap<u64, [u8; 32]>,
+    next_use: u64,
+}
+
+impl RangeCache {
+    /// Store `bytes` for `key`, returning the ones stored before.
+    fn replace(&mut self, key: [u8; 32], bytes: Vec<u8>, max_accounts: usize) -> Option<Vec<u8>> {
+        let used = self.next_use;
+        self.next_use += 1;
+        self.uses.insert(used, key);
+        let previous = self.accounts.insert(key, (bytes, used));
+        if let Some((_, last_used)) = &previous {
+            self.uses.remove(last_used);
+        }
+        while self.accounts.len() > max_accounts {
+            let Some((_, evicted)) = self.uses.pop_first() else {
+                break;
+            };
+            self.accounts.remove(&evicted);
+        }
+        previous.map(|(bytes, _)| bytes)
+    }
+
+    fn memory_footprint(&self) -> usize {
+        let watched: usize = self
+            .accounts
+            .values()
+            .map(|(bytes, _)| bytes.capacity())
+            .sum();
+        hash_map_bytes(&self.accounts) + watched + self.uses.len() * size_of::<(u64, [u8; 32])>()
+    }
+}
+
+/// `publish_if_changed_bytes_at` ranges of a filter with the accounts they were last seen for.
+#[derive(Debug)]
+struct FilterRanges {
+    ranges: Vec<Range<usize>>,
+    max_accounts: usize,
+    cache: Mutex<RangeCache>,
+}
+
+impl FilterRanges {
+    /// Bytes of `data` within the ranges, clamped to its length.
+    fn watched(&self, data: &[u8]) -> Vec<u8> {
+        let mut watched = Vec::with_capacity(self.ranges.iter().map(|range| range.len()).sum());
+        for range in &self.ranges {
+            let start = range.start.min(data.len());
+            let end = range.end.min(data.len());
+            watched.extend_from_slice(&data[start..end]);
+        }
+        watched
+    }
+}
+
+/// Previous account data of filters with `publish_if_changed_bytes_at`, by filter index.
+#[derive(Debug, Default)]
+pub struct ChangedBytes {
+    filters: Vec<Option<FilterRanges>>,
+}
+
+impl ChangedBytes {
+    pub fn new(filters: &[Filter]) -> Self {
+        let filters = filters
+            .iter()
+            .map(|filter| {
+                (!filter.publish_if_changed_bytes_at.is_empty()).then(|| FilterRanges {
+                    ranges: filter.publish_if_changed_bytes_at.clone(),
+                    max_accounts: filter.changed_bytes_max_accounts,
+                    cache: Mutex::default(),
+                })
+            })
+            .collect();
+        Self { filters }
+    }
+
+    /// Whether `data` of `pubkey` differs within the ranges of the filter at `index` from the
+    /// last data seen for it. Accounts not seen before, or evicted since, count as changed, as
+    /// do all accounts of filters without ranges.
+    pub fn changed(&self, index: usize, pubkey: &[u8], data: &[u8]) -> bool {
+        let (Some(Some(filter)), Ok(key)) = (self.filters.get(index), <[u8; 32]>::try_from(pubkey))
+        else {
+            return true;
+        };
+        let watched = filter.watched(data);
+        let previous =
+            filter
+                .cache
+                .lock()
+                .unwrap()
+                .replace(key, watched.clone(), filter.max_accounts);
+        !matches!(previous, Some(previous) if previous == watched)
+    }
+}
+
+impl MemoryFootprint for ChangedBytes {
+    fn memory_footprint(&self) -> usize {
+        self.filters
+            .iter()
+            .flatten()
+            .map(|filter| filter.cache.lock().unwrap().memory_footprint())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::ConfigFilter};
+
+    fn changed_bytes(ranges: Vec<Range<usize>>, max_accounts: usize) -> ChangedBytes {
+        let filter = Filter::new(&ConfigFilter {
+            publish_if_changed_bytes_at: ranges,
+            changed_bytes_max_accounts: max_accounts,
+            ..Default::default()
+        });
+        ChangedBytes::new(&[filter, Filter::new(&ConfigFilter::default())])
+    }
+
+    #[test]
+    fn test_changed_bytes() {
+        // The second range is beyond the data.
+        let changed = changed_bytes(vec![8..16, 40..48], 10);
+        let account = [1; 32];
+        let mut data = vec![0; 32];
+
+        // First sighting.
+        assert!(changed.changed(0, &account, &data));
+        assert!(!changed.changed(0, &account, &data));
+
+        // Outside the range.
+        data[0] = 1;
+        data[20] = 1;
+        assert!(!changed.changed(0, &account, &data));
+
+        // Inside the range.
+        data[15] = 1;
+        assert!(changed.changed(0, &account, &data));
+        assert!(!changed.changed(0, &account, &data));
+
+        // Truncated into the range.
+        assert!(changed.changed(0, &account, &data[..12]));
+        assert!(!changed.changed(0, &account, &data[..12]));
+
+        // Filters without ranges always publish.
+        assert!(changed.changed(1, &account, &data));
+        assert!(changed.changed(1, &account, &data));
+    }
+
+    #[test]
+    fn test_eviction() {
+        let changed = changed_bytes(vec![0..1, 4..5], 2);
+        let data = [7; 8];
+        assert!(changed.changed(0, &[1; 32], &data));
+        assert!(changed.changed(0, &[2; 32], &data));
+        // Seeing the first account again keeps it, the second is evicted for the third.
+        assert!(!changed.changed(0, &[1; 32], &data));
+        assert!(changed.changed(0, &[3; 32], &data));
+        assert!(!changed.changed(0, &[1; 32], &data));
+        assert!(changed.changed(0, &[2; 32], &data));
+    }
+
+    #[test]
+    fn test_memory_footprint() {
+        let changed = changed_bytes(vec![0..8, 8..16], 4);
+        assert_eq!(changed.memory_footprint(), 0);
+        let data = [7; 32];
+        changed.changed(0, &[1; 32], &data);
+        let one = changed.memory_footprint();
+        // The map's buckets, the watched bytes and the entry of its recency.
+        assert!(one >= 16 + size_of::<(u64, [u8; 32])>());
+
+        for key in 2..=4 {
+            changed.changed(0, &[key; 32], &data);
+        }
+        let full = changed.memory_footprint();
+        assert!(full > one);
+        // Bounded by evictions.
+        for key in 5..=40 {
+            changed.changed(0, &[key; 32], &data);
+        }
+        assert_eq!(changed.memory_footprint(), full);
+        // Filters without ranges hold nothing.
+        changed.changed(1, &[1; 32], &data);
+        assert_eq!(changed.memory_footprint(), full);
+    }
+}
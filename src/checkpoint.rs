@@ -0,0 +1,309 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{
+        instance_metrics::InstanceMetrics, resources::ThreadGuard, wall_clock::unix_millis,
+        Publisher, SlotStatus,
+    },
+    log::{error, info},
+    serde_json::{json, Value},
+    std::{
+        sync::{
+            atomic::{AtomicBool, AtomicU64, Ordering},
+            mpsc::{self, RecvTimeoutError, Sender},
+            Arc,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    },
+};
+
+/// Highest slots seen in slot status updates. Values never move backwards,
+/// so out of order notifications are ignored.
+#[derive(Debug, Default)]
+pub struct SlotProgress {
+    last: AtomicU64,
+    processed: AtomicU64,
+    rooted: AtomicU64,
+    updated_at: AtomicU64,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl SlotProgress {
+    pub fn new(metrics: Arc<InstanceMetrics>) -> Self {
+        Self {
+            metrics,
+            ..Self::default()
+        }
+    }
+
+    pub fn observe(&self, slot: u64, status: SlotStatus) {
+        let last = self.last.fetch_max(slot, Ordering::Relaxed).max(slot);
+        self.metrics.last_slot.set(last as i64);
+        let advanced = match status {
+            SlotStatus::Processed => self.processed.fetch_max(slot, Ordering::Relaxed) < slot,
+            SlotStatus::Rooted => {
+                let previous = self.rooted.fetch_max(slot, Ordering::Relaxed);
+                self.metrics
+                    .highest_rooted_slot
+                    .set(previous.max(slot) as i64);
+                previous < slot
+            }
+            SlotStatus::Confirmed | SlotStatus::Unknown => false,
+        };
+        if advanced {
+            let now = unix_millis();
+            self.updated_at.fetch_max(now, Ordering::Relaxed);
+        }
+    }
+
+    /// Highest slot seen with any status.
+    pub fn last_slot(&self) -> u64 {
+        self.last.load(Ordering::Relaxed)
+    }
+
+    pub fn rooted_slot(&self) -> u64 {
+        self.rooted.load(Ordering::Relaxed)
+    }
+
+    pub fn processed_slot(&self) -> u64 {
+        self.processed.load(Ordering::Relaxed)
+    }
+
+    /// Served on `/checkpoint`, `updated_at` in milliseconds since the epoch.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "rooted_slot": self.rooted_slot(),
+            "processed_slot": self.processed_slot(),
+            "updated_at": self.updated_at.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Skips events for slots too far behind the highest slot seen, such as during catch-up.
+/// Nothing is skipped before the first slot status update.
+#[derive(Debug)]
+pub struct SlotHorizon {
+    max_age: u64,
+    slot_progress: Arc<SlotProgress>,
+    skipping: AtomicBool,
+}
+
+impl SlotHorizon {
+    pub fn new(max_age: u64, slot_progress: Arc<SlotProgress>) -> Self {
+        Self {
+            max_age,
+            slot_progress,
+            skipping: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns true if the `event` for `slot` should be skipped, counting it if so.
+    pub fn is_stale(&self, slot: u64, event: &str) -> bool {
+        let highest = self.slot_progress.last_slot();
+        let stale = highest.saturating_sub(slot) > self.max_age;
+        if self.skipping.load(Ordering::Relaxed) != stale
+            && self
+                .skipping
+                .compare_exchange(!stale, stale, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            if stale {
+                info!(
+                    "Skipping events more than {} slots behind slot {highest}, starting with slot {slot}",
+                    self.max_age
+                );
+            } else {
+                info!(
+                    "Stopped skipping events, slot {slot} is within {} slots of slot {highest}",
+                    self.max_age
+                );
+            }
+        }
+        if stale {
+            self.slot_progress
+                .metrics
+                .events_skipped_old_slot_total
+                .with_label_values(&[event])
+                .inc();
+        }
+        stale
+    }
+}
+
+/// Decides when the next checkpoint is due.
+pub struct CheckpointSchedule {
+    interval: Duration,
+    next: Instant,
+}
+
+impl CheckpointSchedule {
+    pub fn new(interval: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            next: now + interval,
+        }
+    }
+
+    /// Returns true if a checkpoint is due at `now`.
+    /// Missed intervals are skipped rather than published in a burst.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        if now < self.next {
+            return false;
+        }
+        while self.next <= now {
+            self.next += self.interval;
+        }
+        true
+    }
+
+    pub fn until_next(&self, now: Instant) -> Duration {
+        self.next.saturating_duration_since(now)
+    }
+}
+
+/// Background thread publishing checkpoint markers to every topic,
+/// and the highest rooted slot to the checkpoint topic.
+pub struct Checkpointer {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Checkpointer {
+    pub fn spawn(
+        publisher: Arc<Publisher>,
+        topics: Vec<(String, bool)>,
+        checkpoint_topic: Option<String>,
+        interval: Duration,
+    ) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("kafkaCheckpoint".to_owned())
+            .spawn(move || {
+                let _thread = ThreadGuard::new(&publisher.metrics(), "background");
+                let mut schedule = CheckpointSchedule::new(interval, Instant::now());
+                while let Err(RecvTimeoutError::Timeout) =
+                    stopped.recv_timeout(schedule.until_next(Instant::now()))
+                {
+                    if !schedule.poll(Instant::now()) {
+                        continue;
+                    }
+                    for (topic, wrap_messages) in topics.iter() {
+                        if let Err(error) = publisher.publish_checkpoint(topic, *wrap_messages) {
+                            error!("Failed to publish checkpoint to {topic:?}: {error}");
+                        }
+                    }
+                    if let Some(topic) = &checkpoint_topic {
+                        if let Err(error) = publisher.publish_rooted_slot(topic) {
+                            error!("Failed to publish rooted slot to {topic:?}: {error}");
+                        }
+                    }
+                }
+            })
+            .expect("failed to spawn checkpoint thread");
+        Self {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for Checkpointer {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedule() {
+        let start = Instant::now();
+        let interval = Duration::from_secs(10);
+        let mut schedule = CheckpointSchedule::new(interval, start);
+
+        assert!(!schedule.poll(start));
+        assert_eq!(schedule.until_next(start), interval);
+        assert!(!schedule.poll(start + Duration::from_secs(9)));
+        assert!(schedule.poll(start + Duration::from_secs(10)));
+        assert!(!schedule.poll(start + Duration::from_secs(11)));
+        assert_eq!(
+            schedule.until_next(start + Duration::from_secs(11)),
+            Duration::from_secs(9)
+        );
+
+        // A stalled thread publishes once and realigns to the interval.
+        assert!(schedule.poll(start + Duration::from_secs(45)));
+        assert!(!schedule.poll(start + Duration::from_secs(49)));
+        assert!(schedule.poll(start + Duration::from_secs(50)));
+    }
+
+    #[test]
+    fn test_slot_horizon() {
+        let progress = Arc::new(SlotProgress::default());
+        let horizon = SlotHorizon::new(10, Arc::clone(&progress));
+        let skipped = || {
+            progress
+                .metrics
+                .events_skipped_old_slot_total
+                .with_label_values(&["horizon-test"])
+                .get()
+        };
+
+        // Startup, before any slot status.
+        assert!(!horizon.is_stale(0, "horizon-test"));
+
+        // Catch-up, the validator is far ahead of the slots being notified.
+        progress.observe(1_000, SlotStatus::Processed);
+        assert!(horizon.is_stale(500, "horizon-test"));
+        assert!(horizon.is_stale(989, "horizon-test"));
+        assert!(horizon.skipping.load(Ordering::Relaxed));
+        assert_eq!(skipped(), 2);
+
+        // Live, within the horizon or ahead of the highest slot.
+        assert!(!horizon.is_stale(990, "horizon-test"));
+        assert!(!horizon.is_stale(1_001, "horizon-test"));
+        assert!(!horizon.skipping.load(Ordering::Relaxed));
+        assert_eq!(skipped(), 2);
+    }
+
+    #[test]
+    fn test_slot_progress_is_monotonic() {
+        let progress = SlotProgress::default();
+        assert_eq!(progress.to_json()["updated_at"], 0);
+
+        progress.observe(10, SlotStatus::Processed);
+        progress.observe(8, SlotStatus::Rooted);
+        progress.observe(7, SlotStatus::Rooted);
+        progress.observe(9, SlotStatus::Processed);
+        progress.observe(11, SlotStatus::Confirmed);
+        assert_eq!(progress.rooted_slot(), 8);
+        assert_eq!(progress.processed_slot(), 10);
+        assert_eq!(progress.last_slot(), 11);
+        assert_eq!(progress.metrics.last_slot.get(), 11);
+        assert_eq!(progress.metrics.highest_rooted_slot.get(), 8);
+
+        progress.observe(10, SlotStatus::Rooted);
+        let json = progress.to_json();
+        assert_eq!(json["rooted_slot"], 10);
+        assert_eq!(json["processed_slot"], 10);
+        assert!(json["updated_at"].as_u64().unwrap() > 0);
+    }
+}
@@ -0,0 +1,84 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! CRC32C of payloads in the `payload-crc32c` header, with `emit_payload_checksum`, for
+//! consumers checking payloads weren't corrupted on their way.
+
+/// Kafka header of the CRC32C of the payload as 8 lowercase hex digits.
+pub const PAYLOAD_CHECKSUM_HEADER: &str = "payload-crc32c";
+
+/// Reflected Castagnoli polynomial.
+const POLYNOMIAL: u32 = 0x82f6_3b78;
+
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc32c(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, byte| {
+        TABLE[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Value of the `payload-crc32c` header of `payload`.
+pub fn payload_checksum(payload: &[u8]) -> Vec<u8> {
+    format!("{:08x}", crc32c(payload)).into_bytes()
+}
+
+/// Whether `payload` matches the value of its `payload-crc32c` header.
+pub fn verify_payload_checksum(payload: &[u8], checksum: &[u8]) -> bool {
+    payload_checksum(payload).eq_ignore_ascii_case(checksum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32c() {
+        // Check values of RFC 3720, appendix B.4.
+        assert_eq!(crc32c(b""), 0);
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(crc32c(&[0; 32]), 0x8a91_36aa);
+        assert_eq!(crc32c(&[0xff; 32]), 0x62a8_ab43);
+        assert_eq!(payload_checksum(b"123456789"), b"e3069283");
+
+        let mut payload = b"payload".to_vec();
+        let checksum = payload_checksum(&payload);
+        assert!(verify_payload_checksum(&payload, &checksum));
+        assert!(verify_payload_checksum(
+            &payload,
+            &checksum.to_ascii_uppercase()
+        ));
+        payload[3] ^= 0x10;
+        assert!(!verify_payload_checksum(&payload, &checksum));
+        assert!(!verify_payload_checksum(b"", b"garbage"));
+    }
+}
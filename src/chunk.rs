@@ -0,0 +1,325 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `chunk_threshold_bytes`, splitting account updates too large for a message across several,
+//! and their reassembly for consumers.
+
+use {
+    crate::{AccountChunk, UpdateAccountEvent},
+    prost::Message,
+    solana_program::hash::hash,
+    std::{
+        cmp::Ordering,
+        collections::hash_map::RandomState,
+        error::Error,
+        fmt::{Display, Formatter},
+        hash::{BuildHasher, Hasher},
+    },
+};
+
+/// Smallest `chunk_threshold_bytes`, leaving room for data next to the repeated fields.
+pub const MIN_CHUNK_THRESHOLD_BYTES: usize = 1024;
+
+/// Room left in each chunk for the tag and length of `data` and the wrapping of the message.
+const FRAMING_BYTES: usize = 16;
+
+/// Chunks of `ev` of at most about `threshold` bytes encoded each, or `ev` alone if it fits.
+/// Every chunk repeats the fields but `data` and the decoded `stake_account` and
+/// `lookup_table`, which only the first chunk carries, and the chunks share a random group id.
+pub fn split_account(mut ev: UpdateAccountEvent, threshold: usize) -> Vec<UpdateAccountEvent> {
+    if ev.data.is_empty() || ev.encoded_len() <= threshold {
+        return vec![ev];
+    }
+    let data = std::mem::take(&mut ev.data);
+    let mut chunk = AccountChunk {
+        // Every `RandomState` is seeded with new random keys.
+        chunk_group_id: RandomState::new().build_hasher().finish(),
+        chunk_index: u32::MAX,
+        chunk_count: u32::MAX,
+        offset: u64::MAX,
+        length: data.len() as u64,
+        data_sha256: hash(&data).to_bytes().to_vec(),
+    };
+    // Sized with the largest values the chunk fields take.
+    ev.chunk = Some(chunk.clone());
+    // A decoded table of 256 addresses is as large as its data, so the first chunk takes what
+    // room it leaves, possibly none.
+    let first_len = threshold
+        .saturating_sub(ev.encoded_len() + FRAMING_BYTES)
+        .min(data.len());
+    let stake_account = ev.stake_account.take();
+    let lookup_table = ev.lookup_table.take();
+    let chunk_len = threshold
+        .saturating_sub(ev.encoded_len() + FRAMING_BYTES)
+        .max(1);
+    let (first, rest) = data.split_at(first_len);
+    let parts = rest.chunks(chunk_len);
+    chunk.chunk_count = parts.len() as u32 + 1;
+    let mut offset = 0;
+    std::iter::once(first)
+        .chain(parts)
+        .enumerate()
+        .map(|(index, part)| {
+            chunk.chunk_index = index as u32;
+            chunk.offset = offset as u64;
+            offset += part.len();
+            let (stake_account, lookup_table) = match index {
+                0 => (stake_account.clone(), lookup_table.clone()),
+                _ => (None, None),
+            };
+            UpdateAccountEvent {
+                data: part.to_vec(),
+                chunk: Some(chunk.clone()),
+                stake_account,
+                lookup_table,
+                ..ev.clone()
+            }
+        })
+        .collect()
+}
+
+/// Why chunks couldn't be reassembled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkError {
+    /// No chunks, or an update that wasn't split.
+    NotChunked,
+    /// Chunks of different updates, or disagreeing on the update.
+    Mismatched,
+    Duplicate(u32),
+    Missing(u32),
+    /// The data of the chunks doesn't add up to the account data.
+    Corrupted,
+}
+
+impl Display for ChunkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotChunked => write!(f, "not a chunked account update"),
+            Self::Mismatched => write!(f, "chunks of different account updates"),
+            Self::Duplicate(index) => write!(f, "duplicate chunk {index}"),
+            Self::Missing(index) => write!(f, "missing chunk {index}"),
+            Self::Corrupted => write!(f, "reassembled data doesn't match its SHA-256"),
+        }
+    }
+}
+
+impl Error for ChunkError {}
+
+/// The account update split into `chunks`, given in any order. Fails unless every chunk of
+/// the update is there once and the data matches the SHA-256 they carry.
+pub fn reassemble_account(
+    chunks: impl IntoIterator<Item = UpdateAccountEvent>,
+) -> Result<UpdateAccountEvent, ChunkError> {
+    // Nothing is sized from the chunk fields before the chunks given agree with them.
+    let mut chunks = chunks
+        .into_iter()
+        .map(|mut ev| {
+            let chunk = ev.chunk.take().ok_or(ChunkError::NotChunked)?;
+            let data = std::mem::take(&mut ev.data);
+            // Only the first chunk carries the decoded data.
+            let decoded = (ev.stake_account.take(), ev.lookup_table.take());
+            Ok((chunk, data, decoded, ev))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    chunks.sort_by_key(|(chunk, ..)| chunk.chunk_index);
+    let (first, _, _, ev) = chunks.first().ok_or(ChunkError::NotChunked)?;
+    let same_update = chunks.iter().all(|(chunk, _, _, other)| {
+        chunk.chunk_group_id == first.chunk_group_id
+            && chunk.chunk_count == first.chunk_count
+            && chunk.length == first.length
+            && chunk.data_sha256 == first.data_sha256
+            && other == ev
+    });
+    if !same_update {
+        return Err(ChunkError::Mismatched);
+    }
+    for (index, (chunk, ..)) in chunks.iter().enumerate() {
+        let index = index as u32;
+        if chunk.chunk_index < index {
+            return Err(ChunkError::Duplicate(chunk.chunk_index));
+        }
+        if chunk.chunk_index > index {
+            return Err(ChunkError::Missing(index));
+        }
+    }
+    match chunks.len().cmp(&(first.chunk_count as usize)) {
+        Ordering::Less => return Err(ChunkError::Missing(chunks.len() as u32)),
+        Ordering::Greater => return Err(ChunkError::Mismatched),
+        Ordering::Equal => {}
+    }
+    let length = chunks.iter().try_fold(0u64, |length, (_, part, ..)| {
+        length.checked_add(part.len() as u64)
+    });
+    if length != Some(first.length) {
+        return Err(ChunkError::Corrupted);
+    }
+
+    let mut data = Vec::with_capacity(first.length as usize);
+    for (chunk, part, ..) in &chunks {
+        if chunk.offset != data.len() as u64 {
+            return Err(ChunkError::Corrupted);
+        }
+        data.extend_from_slice(part);
+    }
+    if hash(&data).as_ref() != first.data_sha256 {
+        return Err(ChunkError::Corrupted);
+    }
+    let (_, _, (stake_account, lookup_table), ev) = chunks.swap_remove(0);
+    Ok(UpdateAccountEvent {
+        data,
+        stake_account,
+        lookup_table,
+        ..ev
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::LookupTable};
+
+    fn account(data_len: usize) -> UpdateAccountEvent {
+        UpdateAccountEvent {
+            slot: 9,
+            pubkey: vec![1; 32],
+            lamports: 100,
+            owner: vec![2; 32],
+            data: (0..data_len).map(|i| i as u8).collect(),
+            write_version: 4,
+            sequence: 7,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_split_account() {
+        let ev = account(1_000);
+        assert_eq!(split_account(ev.clone(), 2_000), std::slice::from_ref(&ev));
+
+        let chunks = split_account(ev.clone(), 300);
+        assert!(chunks.len() > 1);
+        let group = chunks[0].chunk.as_ref().unwrap().chunk_group_id;
+        for (index, chunk) in chunks.iter().enumerate() {
+            assert!(chunk.encoded_len() + FRAMING_BYTES <= 300);
+            let info = chunk.chunk.as_ref().unwrap();
+            assert_eq!(info.chunk_group_id, group);
+            assert_eq!(info.chunk_index as usize, index);
+            assert_eq!(info.chunk_count as usize, chunks.len());
+            assert_eq!(info.length, 1_000);
+            assert_eq!(chunk.pubkey, ev.pubkey);
+            assert_eq!(chunk.sequence, ev.sequence);
+        }
+        // Another update gets another group.
+        let other = split_account(ev.clone(), 300);
+        assert_ne!(other[0].chunk.as_ref().unwrap().chunk_group_id, group);
+
+        assert_eq!(reassemble_account(chunks.clone()), Ok(ev.clone()));
+        // In any order.
+        let mut shuffled = chunks.clone();
+        shuffled.reverse();
+        shuffled.swap(0, 1);
+        assert_eq!(reassemble_account(shuffled), Ok(ev));
+    }
+
+    #[test]
+    fn test_reassemble_errors() {
+        let chunks = split_account(account(1_000), 300);
+        let last = chunks.len() as u32 - 1;
+
+        let mut missing = chunks.clone();
+        missing.remove(1);
+        assert_eq!(reassemble_account(missing), Err(ChunkError::Missing(1)));
+        assert_eq!(
+            reassemble_account(chunks[..last as usize].to_vec()),
+            Err(ChunkError::Missing(last))
+        );
+
+        let mut duplicate = chunks.clone();
+        duplicate.push(chunks[2].clone());
+        assert_eq!(reassemble_account(duplicate), Err(ChunkError::Duplicate(2)));
+
+        let mut corrupted = chunks.clone();
+        corrupted[1].data[0] ^= 1;
+        assert_eq!(reassemble_account(corrupted), Err(ChunkError::Corrupted));
+
+        let mut mixed = chunks.clone();
+        mixed[1] = split_account(account(1_000), 300).remove(1);
+        assert_eq!(reassemble_account(mixed), Err(ChunkError::Mismatched));
+
+        let mut changed = chunks.clone();
+        changed[1].lamports += 1;
+        assert_eq!(reassemble_account(changed), Err(ChunkError::Mismatched));
+
+        let mut unchunked = chunks.clone();
+        unchunked[1].chunk = None;
+        assert_eq!(reassemble_account(unchunked), Err(ChunkError::NotChunked));
+
+        assert_eq!(reassemble_account([]), Err(ChunkError::NotChunked));
+        assert_eq!(
+            reassemble_account([account(10)]),
+            Err(ChunkError::NotChunked)
+        );
+    }
+
+    #[test]
+    fn test_reassemble_forged_chunk() {
+        let chunks = split_account(account(1_000), 300);
+        let forge = |forge: fn(&mut AccountChunk)| {
+            let mut chunks = chunks.clone();
+            for chunk in &mut chunks {
+                forge(chunk.chunk.as_mut().unwrap());
+            }
+            reassemble_account(chunks)
+        };
+        // Neither allocates from the forged fields.
+        assert_eq!(
+            forge(|chunk| chunk.chunk_count = u32::MAX),
+            Err(ChunkError::Missing(chunks.len() as u32))
+        );
+        assert_eq!(
+            forge(|chunk| chunk.length = u64::MAX),
+            Err(ChunkError::Corrupted)
+        );
+        assert_eq!(
+            forge(|chunk| chunk.chunk_count = 1),
+            Err(ChunkError::Mismatched)
+        );
+        assert_eq!(
+            forge(|chunk| chunk.chunk_index = u32::MAX - chunk.chunk_index),
+            Err(ChunkError::Missing(0))
+        );
+    }
+
+    #[test]
+    fn test_split_decoded_account() {
+        // A full lookup table, decoded as large as its data.
+        let addresses: Vec<Vec<u8>> = (0..=255).map(|i| vec![i; 32]).collect();
+        let ev = UpdateAccountEvent {
+            lookup_table: Some(LookupTable {
+                deactivation_slot: u64::MAX,
+                addresses,
+                ..Default::default()
+            }),
+            ..account(56 + 256 * 32)
+        };
+        let chunks = split_account(ev.clone(), MIN_CHUNK_THRESHOLD_BYTES);
+        assert!(chunks.len() <= 12, "{} chunks", chunks.len());
+        assert!(chunks[0].lookup_table.is_some());
+        assert!(chunks[0].data.is_empty());
+        for chunk in &chunks[1..] {
+            assert_eq!(chunk.lookup_table, None);
+            assert!(chunk.encoded_len() + FRAMING_BYTES <= MIN_CHUNK_THRESHOLD_BYTES);
+        }
+        assert_eq!(reassemble_account(chunks), Ok(ev));
+    }
+}
@@ -13,40 +13,489 @@
 // limitations under the License.
 
 use {
-    crate::{prom::StatsThreadedProducerContext, PrometheusService},
+    crate::{
+        adaptive_stats::AdaptiveStatsOptions,
+        chunk::MIN_CHUNK_THRESHOLD_BYTES,
+        instance_metrics::InstanceMetrics,
+        kafka_stats::{KafkaStats, KafkaStatsOptions, KAFKA_BROKER_STATS},
+        meta_fields::META_FIELDS,
+        prom::{HttpLimits, HttpState, StatsThreadedProducerContext},
+        PrometheusService, EVENT_KINDS,
+    },
     rdkafka::{
         config::FromClientConfigAndContext,
-        error::KafkaResult,
+        error::{KafkaError, KafkaResult},
         producer::{DefaultProducerContext, ThreadedProducer},
         ClientConfig,
     },
-    serde::Deserialize,
+    serde::{de, Deserialize, Deserializer, Serialize},
     solana_geyser_plugin_interface::geyser_plugin_interface::{
         GeyserPluginError, Result as PluginResult,
     },
-    std::{collections::HashMap, fs::File, io::Result as IoResult, net::SocketAddr, path::Path},
+    solana_program::epoch_schedule::{EpochSchedule, MINIMUM_SLOTS_PER_EPOCH},
+    solana_sdk::{bs58, pubkey::Pubkey},
+    std::{
+        collections::HashMap,
+        fs::{self, File},
+        io::Result as IoResult,
+        net::{AddrParseError, SocketAddr},
+        ops::Range,
+        path::{Path, PathBuf},
+        str::FromStr,
+        sync::Arc,
+        time::Duration,
+    },
 };
 
 /// Plugin config.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
     #[allow(dead_code)]
     libpath: String,
 
     /// Kafka config.
+    #[serde(default)]
     pub kafka: HashMap<String, String>,
 
-    /// Graceful shutdown timeout.
+    /// Preset of delivery guarantee related Kafka settings, applied below `kafka`.
     #[serde(default)]
+    pub delivery_profile: Option<DeliveryProfile>,
+
+    /// Graceful shutdown timeout.
+    #[serde(default = "default_shutdown_timeout_ms")]
     pub shutdown_timeout_ms: u64,
 
+    /// Install the plugin's own logger on load, unless one is already installed.
+    #[serde(default = "default_true")]
+    pub setup_logger: bool,
+
     /// Accounts, transactions filters
     pub filters: Vec<ConfigFilter>,
 
-    /// Prometheus endpoint.
+    /// Where messages are sent.
+    #[serde(default)]
+    pub sink: SinkKind,
+
+    /// Directory the `file` sink writes to.
+    #[serde(default)]
+    pub file_sink_dir: Option<PathBuf>,
+
+    /// Bytes of messages per file of the `file` sink, before compression.
+    #[serde(default = "default_file_sink_max_file_bytes")]
+    pub file_sink_max_file_bytes: u64,
+
+    /// Compression of the files of the `file` sink.
+    #[serde(default)]
+    pub file_sink_compression: FileSinkCompression,
+
+    /// Compression of account update payloads.
+    #[serde(default)]
+    pub payload_compression: PayloadCompression,
+
+    /// Payloads per owner a `zstd-dict` dictionary is trained on.
+    #[serde(default = "default_zstd_dict_samples")]
+    pub zstd_dict_samples: usize,
+
+    /// Name of the cluster, such as `mainnet`, `testnet` or a custom one.
+    #[serde(default)]
+    pub cluster_name: String,
+
+    /// Base58 identity of the validator.
+    #[serde(default)]
+    pub validator_identity: Option<String>,
+
+    /// Name telling apart instances of the plugin loaded in one validator, as the `instance`
+    /// label of their metrics.
+    #[serde(default)]
+    pub instance_name: String,
+
+    /// Add `cluster` and `validator-identity` headers to every message.
+    #[serde(default)]
+    pub emit_identity_headers: bool,
+
+    /// Add `phase` headers to account updates and `status` and `kind` headers to transactions,
+    /// for consumers branching on them without decoding payloads.
+    #[serde(default)]
+    pub emit_phase_headers: bool,
+
+    /// Add the CRC32C of every payload as produced in the `payload-crc32c` header.
+    #[serde(default)]
+    pub emit_payload_checksum: bool,
+
+    /// Encoded size of account updates beyond which their data is split across messages.
+    #[serde(default)]
+    pub chunk_threshold_bytes: Option<usize>,
+
+    /// Leave the data of executable accounts out of account updates, but for its length and
+    /// SHA-256.
+    #[serde(default)]
+    pub strip_executable_account_data: bool,
+
+    /// Prometheus endpoints, a single address or a list of them.
+    #[serde(default, deserialize_with = "deserialize_prometheus")]
+    pub prometheus: Vec<PrometheusAddress>,
+
+    /// Fail loading if any `prometheus` address can't be bound, instead of serving on the rest.
+    #[serde(default = "default_true")]
+    pub prometheus_require_all_binds: bool,
+
+    /// Connections served by `prometheus` at once, further ones get a 503.
+    #[serde(default = "default_prometheus_max_connections")]
+    pub prometheus_max_connections: usize,
+
+    /// Time for `prometheus` clients to send their request headers.
+    #[serde(default = "default_prometheus_header_timeout_ms")]
+    pub prometheus_header_timeout_ms: u64,
+
+    /// Time to serve a `prometheus` request once its headers are read.
+    #[serde(default = "default_prometheus_response_timeout_ms")]
+    pub prometheus_response_timeout_ms: u64,
+
+    /// Interval to check SSL/SASL credential files for changes.
+    #[serde(default)]
+    pub credential_refresh_interval_secs: Option<u64>,
+
+    /// File holding the SASL password, read on every producer creation.
+    #[serde(default)]
+    pub sasl_password_file: Option<String>,
+
+    /// Kafka topic to publish every slot status to, unwrapped, whatever the filters.
+    #[serde(default)]
+    pub slot_status_topic: Option<String>,
+
+    /// Kafka topic to mirror every published event to, wrapped.
+    #[serde(default)]
+    pub firehose_topic: String,
+
+    /// Event types mirrored to the firehose topic, all if empty.
+    #[serde(default)]
+    pub firehose_include: Vec<String>,
+
+    /// Interval to publish checkpoint markers to every topic.
+    #[serde(default)]
+    pub checkpoint_interval_ms: Option<u64>,
+
+    /// Kafka topic to publish the highest rooted slot to on every checkpoint.
+    #[serde(default)]
+    pub checkpoint_topic: String,
+
+    /// Set `fork_instance_id` on account, transaction, slot and block events.
+    #[serde(default)]
+    pub fork_instance_ids: bool,
+
+    /// Slots behind the highest rooted slot after which account updates are `Catchup`.
+    #[serde(default = "default_catchup_slots")]
+    pub catchup_slots: u64,
+
+    /// Check account updates are produced in increasing slot and write version order per topic
+    /// and key, counting regressions in `ordering_violations_total`.
+    #[serde(default)]
+    pub strict_ordering_assertions: bool,
+
+    /// Delay account updates in proportion to the time brokers throttle the producer for.
+    #[serde(default)]
+    pub respect_broker_throttle: bool,
+
+    /// Kafka topic to publish transactions per program to every `stats_interval_secs`.
+    #[serde(default)]
+    pub program_stats_topic: Option<String>,
+
+    /// Length of the windows program stats are published for.
+    #[serde(default = "default_stats_interval_secs")]
+    pub stats_interval_secs: u64,
+
+    /// Programs listed in each program stats window, the others are summed up.
+    #[serde(default = "default_program_stats_top_k")]
+    pub program_stats_top_k: usize,
+
+    /// Transactions counted in program stats.
+    #[serde(default)]
+    pub program_stats_source: ProgramStatsSource,
+
+    /// Kafka topic to publish transactions that failed resolving their address lookup tables to.
+    #[serde(default)]
+    pub alt_failure_topic: Option<String>,
+
+    /// Produce a canary record to every configured topic at load and fail it if any rejects it
+    /// for authorization or not existing.
+    #[serde(default)]
+    pub verify_topic_access: bool,
+
+    /// Time to wait for the delivery reports of the canary records.
+    #[serde(default = "default_verify_topic_access_timeout_ms")]
+    pub verify_topic_access_timeout_ms: u64,
+
+    /// Message key of canary records.
+    #[serde(default = "default_canary_key")]
+    pub canary_key: String,
+
+    /// Payload of canary records, an encoded `PluginLifecycleEvent` if unset.
+    #[serde(default)]
+    pub canary_value: Option<String>,
+
+    /// Kafka topic to publish the most write locked accounts of every processed slot to.
+    #[serde(default)]
+    pub write_lock_contention_topic: Option<String>,
+
+    /// Accounts listed per slot on the write lock contention topic.
+    #[serde(default = "default_write_lock_contention_top_k")]
+    pub write_lock_contention_top_k: usize,
+
+    /// Compacted Kafka topic to register the plugin in on load and unload.
+    #[serde(default)]
+    pub producer_registry_topic: Option<String>,
+
+    /// Interval to refresh partition counts of explicitly partitioned topics.
+    #[serde(default = "default_partition_refresh_interval_secs")]
+    pub partition_refresh_interval_secs: u64,
+
+    /// HTTP RPC URL to fetch the leader schedule from, to attribute blocks to leaders.
+    #[serde(default)]
+    pub leader_schedule_rpc: Option<String>,
+
+    /// Version of the validator loading the plugin, to fail load if its Geyser interface
+    /// doesn't match the one the plugin was built against.
+    #[serde(default)]
+    pub expected_validator_version: Option<String>,
+
+    /// Fetch the validator version from `leader_schedule_rpc` when
+    /// `expected_validator_version` is unset.
+    #[serde(default)]
+    pub probe_validator_version: bool,
+
+    /// Skip events for slots this many slots behind the highest slot seen.
+    #[serde(default)]
+    pub skip_slots_older_than: Option<u64>,
+
+    /// Account data bytes published per slot, beyond which its account updates are suppressed.
+    #[serde(default)]
+    pub max_account_bytes_per_slot: Option<u64>,
+
+    /// Slots of compute unit prices the priority fee quantile gauges are computed over.
+    #[serde(default = "default_fee_market_window_slots")]
+    pub fee_market_window_slots: usize,
+
+    /// Record filter, build and send timings.
+    #[serde(default = "default_true")]
+    pub timing_metrics: bool,
+
+    /// Deliveries acknowledged later than this are counted per topic.
+    #[serde(default)]
+    pub delivery_latency_slo_ms: Option<u64>,
+
+    /// Key and payload bytes enqueued and not yet delivered beyond which `backpressure_policy`
+    /// applies to further messages.
+    #[serde(default)]
+    pub max_outstanding_bytes: Option<u64>,
+
+    /// Longest an account update or transaction notification spends publishing, the rest of its
+    /// filters and sends are skipped past it.
+    #[serde(default)]
+    pub callback_deadline_ms: Option<u64>,
+
+    /// What happens to messages sent while `max_outstanding_bytes` are outstanding.
+    #[serde(default)]
+    pub backpressure_policy: BackpressurePolicy,
+
+    /// librdkafka statistics arriving sooner than this after the last exported ones are skipped.
+    #[serde(default)]
+    pub kafka_stats_min_interval_ms: u64,
+
+    /// Per broker librdkafka statistics exported to `kafka_stats`, all if unset.
+    #[serde(default)]
+    pub kafka_stats_metrics: Option<Vec<String>>,
+
+    /// Export only every this many librdkafka statistics while the brokers are healthy.
+    #[serde(default = "default_kafka_stats_healthy_every")]
+    pub kafka_stats_healthy_every: u32,
+
+    /// `outbuf_cnt` of a broker above which it counts as unhealthy.
+    #[serde(default = "default_kafka_stats_outbuf_threshold")]
+    pub kafka_stats_outbuf_threshold: i64,
+
+    /// Time the brokers stay healthy before statistics are sampled again.
+    #[serde(default = "default_kafka_stats_cool_down_secs")]
+    pub kafka_stats_cool_down_secs: u64,
+
+    /// Log producer tuning hints from the librdkafka statistics.
+    #[serde(default)]
+    pub producer_advisor: bool,
+
+    /// Period of librdkafka statistics each producer advisory is based on.
+    #[serde(default = "default_producer_advisor_interval_secs")]
+    pub producer_advisor_interval_secs: u64,
+
+    /// Interval to log a JSON snapshot of key metrics.
+    #[serde(default)]
+    pub metrics_log_interval_secs: Option<u64>,
+
+    /// Transaction status meta fields whose absence is logged as an error.
+    #[serde(default)]
+    pub require_meta_fields: Vec<String>,
+
+    /// Epoch schedule of the cluster, mainnet's if unset.
+    #[serde(default)]
+    pub epoch_schedule: Option<ConfigEpochSchedule>,
+
+    /// Filter entries whose last match time is also exported as a gauge.
+    #[serde(default)]
+    pub filter_stats_gauges: Vec<String>,
+
+    /// Serve the `/admin` endpoints next to `/metrics`.
+    #[serde(default)]
+    pub admin_endpoints: bool,
+
+    /// Start in standby, producing nothing until activated through `/admin/activate`.
+    #[serde(default)]
+    pub standby: bool,
+
+    /// What happens to messages for topics paused through `/admin/pause`.
+    #[serde(default)]
+    pub pause_policy: PausePolicy,
+
+    /// What happens to events that fail to serialize.
+    #[serde(default)]
+    pub serialization_failure_policy: SerializationFailurePolicy,
+
+    /// Bytes buffered per paused topic by the `buffer` policy, later messages are dropped.
+    #[serde(default = "default_pause_buffer_max_bytes")]
+    pub pause_buffer_max_bytes: usize,
+
+    /// Publish account, transaction and block events once their slot is rooted, dropping those
+    /// of abandoned forks, and only rooted slot statuses.
+    #[serde(default)]
+    pub rooted_only: bool,
+
+    /// Bytes of events held back by `rooted_only` until their slot is rooted, later ones are
+    /// dropped.
+    #[serde(default = "default_rooted_only_buffer_bytes")]
+    pub rooted_only_buffer_bytes: usize,
+
+    /// Publish the rooted status of a slot once the messages of the slot enqueued so far are
+    /// delivered, or `rooted_barrier_timeout_ms` passed.
     #[serde(default)]
-    pub prometheus: Option<SocketAddr>,
+    pub rooted_barrier: bool,
+
+    /// Longest a rooted status is held back by `rooted_barrier`.
+    #[serde(default = "default_rooted_barrier_timeout_ms")]
+    pub rooted_barrier_timeout_ms: u64,
+
+    /// Embed the block event of a slot in its rooted status.
+    #[serde(default)]
+    pub enrich_rooted_slot_with_block: bool,
+
+    /// Directory to capture received notifications to, for replay.
+    #[serde(default)]
+    pub capture_notifications_dir: Option<String>,
+
+    /// Size at which notification capture stops.
+    #[serde(default = "default_capture_max_bytes")]
+    pub capture_max_bytes: u64,
+
+    /// Fraction of account and transaction notifications captured.
+    #[serde(default = "default_capture_sample_rate")]
+    pub capture_sample_rate: f64,
+
+    /// File the per topic sequence numbers are saved to on unload and continued from on load.
+    #[serde(default)]
+    pub sequence_state_path: Option<PathBuf>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_shutdown_timeout_ms() -> u64 {
+    30_000
+}
+
+fn default_partition_refresh_interval_secs() -> u64 {
+    60
+}
+
+fn default_catchup_slots() -> u64 {
+    150
+}
+
+fn default_capture_max_bytes() -> u64 {
+    1 << 30
+}
+
+fn default_kafka_stats_healthy_every() -> u32 {
+    1
+}
+
+fn default_kafka_stats_outbuf_threshold() -> i64 {
+    100
+}
+
+fn default_kafka_stats_cool_down_secs() -> u64 {
+    60
+}
+
+fn default_producer_advisor_interval_secs() -> u64 {
+    600
+}
+
+fn default_zstd_dict_samples() -> usize {
+    1000
+}
+
+fn default_stats_interval_secs() -> u64 {
+    60
+}
+
+fn default_program_stats_top_k() -> usize {
+    100
+}
+
+fn default_write_lock_contention_top_k() -> usize {
+    20
+}
+
+fn default_fee_market_window_slots() -> usize {
+    150
+}
+
+fn default_prometheus_max_connections() -> usize {
+    HttpLimits::default().max_connections
+}
+
+fn default_prometheus_header_timeout_ms() -> u64 {
+    HttpLimits::default().header_timeout.as_millis() as u64
+}
+
+fn default_prometheus_response_timeout_ms() -> u64 {
+    HttpLimits::default().response_timeout.as_millis() as u64
+}
+
+fn default_verify_topic_access_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_canary_key() -> String {
+    "canary".to_owned()
+}
+
+fn default_capture_sample_rate() -> f64 {
+    1.0
+}
+
+fn default_file_sink_max_file_bytes() -> u64 {
+    256 << 20
+}
+
+fn default_pause_buffer_max_bytes() -> usize {
+    64 << 20
+}
+
+fn default_rooted_only_buffer_bytes() -> usize {
+    256 << 20
+}
+
+fn default_rooted_barrier_timeout_ms() -> u64 {
+    5_000
 }
 
 impl Default for Config {
@@ -54,9 +503,89 @@ impl Default for Config {
         Self {
             libpath: "".to_owned(),
             kafka: HashMap::new(),
-            shutdown_timeout_ms: 30_000,
+            delivery_profile: None,
+            shutdown_timeout_ms: default_shutdown_timeout_ms(),
+            setup_logger: true,
             filters: vec![],
-            prometheus: None,
+            sink: SinkKind::Kafka,
+            file_sink_dir: None,
+            file_sink_max_file_bytes: default_file_sink_max_file_bytes(),
+            file_sink_compression: FileSinkCompression::None,
+            payload_compression: PayloadCompression::None,
+            zstd_dict_samples: default_zstd_dict_samples(),
+            cluster_name: String::new(),
+            validator_identity: None,
+            instance_name: String::new(),
+            emit_identity_headers: false,
+            emit_phase_headers: false,
+            emit_payload_checksum: false,
+            chunk_threshold_bytes: None,
+            strip_executable_account_data: false,
+            prometheus: Vec::new(),
+            prometheus_require_all_binds: true,
+            prometheus_max_connections: default_prometheus_max_connections(),
+            prometheus_header_timeout_ms: default_prometheus_header_timeout_ms(),
+            prometheus_response_timeout_ms: default_prometheus_response_timeout_ms(),
+            credential_refresh_interval_secs: None,
+            sasl_password_file: None,
+            slot_status_topic: None,
+            firehose_topic: "".to_owned(),
+            firehose_include: vec![],
+            checkpoint_interval_ms: None,
+            checkpoint_topic: "".to_owned(),
+            fork_instance_ids: false,
+            catchup_slots: default_catchup_slots(),
+            strict_ordering_assertions: false,
+            respect_broker_throttle: false,
+            program_stats_topic: None,
+            stats_interval_secs: default_stats_interval_secs(),
+            program_stats_top_k: default_program_stats_top_k(),
+            program_stats_source: ProgramStatsSource::default(),
+            alt_failure_topic: None,
+            verify_topic_access: false,
+            verify_topic_access_timeout_ms: default_verify_topic_access_timeout_ms(),
+            canary_key: default_canary_key(),
+            canary_value: None,
+            write_lock_contention_topic: None,
+            write_lock_contention_top_k: default_write_lock_contention_top_k(),
+            producer_registry_topic: None,
+            partition_refresh_interval_secs: default_partition_refresh_interval_secs(),
+            leader_schedule_rpc: None,
+            expected_validator_version: None,
+            probe_validator_version: false,
+            skip_slots_older_than: None,
+            max_account_bytes_per_slot: None,
+            fee_market_window_slots: default_fee_market_window_slots(),
+            timing_metrics: true,
+            delivery_latency_slo_ms: None,
+            max_outstanding_bytes: None,
+            callback_deadline_ms: None,
+            backpressure_policy: BackpressurePolicy::default(),
+            kafka_stats_min_interval_ms: 0,
+            kafka_stats_healthy_every: default_kafka_stats_healthy_every(),
+            kafka_stats_outbuf_threshold: default_kafka_stats_outbuf_threshold(),
+            kafka_stats_cool_down_secs: default_kafka_stats_cool_down_secs(),
+            kafka_stats_metrics: None,
+            producer_advisor: false,
+            producer_advisor_interval_secs: default_producer_advisor_interval_secs(),
+            metrics_log_interval_secs: None,
+            require_meta_fields: vec![],
+            epoch_schedule: None,
+            filter_stats_gauges: vec![],
+            admin_endpoints: false,
+            standby: false,
+            pause_policy: PausePolicy::default(),
+            serialization_failure_policy: SerializationFailurePolicy::default(),
+            pause_buffer_max_bytes: default_pause_buffer_max_bytes(),
+            rooted_only: false,
+            rooted_only_buffer_bytes: default_rooted_only_buffer_bytes(),
+            rooted_barrier: false,
+            rooted_barrier_timeout_ms: default_rooted_barrier_timeout_ms(),
+            enrich_rooted_slot_with_block: false,
+            capture_notifications_dir: None,
+            capture_max_bytes: default_capture_max_bytes(),
+            capture_sample_rate: default_capture_sample_rate(),
+            sequence_state_path: None,
         }
     }
 }
@@ -68,77 +597,1143 @@ impl Config {
         let mut this: Self = serde_json::from_reader(file)
             .map_err(|e| GeyserPluginError::ConfigFileReadError { msg: e.to_string() })?;
         this.fill_defaults();
+        this.validate()?;
         Ok(this)
     }
 
-    /// Create rdkafka::FutureProducer from config.
-    pub fn producer(&self) -> KafkaResult<ThreadedProducer<StatsThreadedProducerContext>> {
+    fn validate(&self) -> PluginResult<()> {
+        if let Some(kind) = self
+            .firehose_include
+            .iter()
+            .find(|kind| !EVENT_KINDS.contains(&kind.as_str()))
+        {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: format!("unknown firehose_include event type: {kind:?}"),
+            });
+        }
+        if let Some(field) = self
+            .require_meta_fields
+            .iter()
+            .find(|field| !META_FIELDS.contains(&field.as_str()))
+        {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: format!("unknown require_meta_fields field: {field:?}"),
+            });
+        }
+        if !(self.capture_sample_rate > 0.0 && self.capture_sample_rate <= 1.0) {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: format!(
+                    "capture_sample_rate must be in (0, 1], got {}",
+                    self.capture_sample_rate
+                ),
+            });
+        }
+        if self.sink == SinkKind::File && self.file_sink_dir.is_none() {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "the file sink requires file_sink_dir".to_owned(),
+            });
+        }
+        if self.payload_compression == PayloadCompression::ZstdDict && self.zstd_dict_samples == 0 {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "zstd_dict_samples must be at least 1".to_owned(),
+            });
+        }
+        if let Some(identity) = &self.validator_identity {
+            Pubkey::from_str(identity).map_err(|error| GeyserPluginError::ConfigFileReadError {
+                msg: format!("invalid validator_identity {identity:?}: {error}"),
+            })?;
+        }
+        if self.kafka_stats_healthy_every == 0 {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "kafka_stats_healthy_every must be at least 1".to_owned(),
+            });
+        }
+        if self.program_stats_topic.is_some() && self.stats_interval_secs == 0 {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "stats_interval_secs must be at least 1".to_owned(),
+            });
+        }
+        if self
+            .chunk_threshold_bytes
+            .is_some_and(|bytes| bytes < MIN_CHUNK_THRESHOLD_BYTES)
+        {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: format!("chunk_threshold_bytes must be at least {MIN_CHUNK_THRESHOLD_BYTES}"),
+            });
+        }
+        if self.rooted_barrier && self.rooted_barrier_timeout_ms == 0 {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "rooted_barrier_timeout_ms must be at least 1".to_owned(),
+            });
+        }
+        if self.prometheus_max_connections == 0 {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "prometheus_max_connections must be at least 1".to_owned(),
+            });
+        }
+        if self.probe_validator_version && self.leader_schedule_rpc.is_none() {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "probe_validator_version requires leader_schedule_rpc".to_owned(),
+            });
+        }
+        if let Some(range) = self
+            .filters
+            .iter()
+            .flat_map(|filter| &filter.publish_if_changed_bytes_at)
+            .find(|range| range.is_empty())
+        {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: format!("empty publish_if_changed_bytes_at range {range:?}"),
+            });
+        }
+        for tiers in self.filters.iter().map(|filter| &filter.size_tiers) {
+            validate_size_tiers(tiers)
+                .map_err(|msg| GeyserPluginError::ConfigFileReadError { msg })?;
+        }
+        for prefix in self
+            .filters
+            .iter()
+            .flat_map(|filter| &filter.account_prefix_filters)
+        {
+            match bs58::decode(prefix).into_vec() {
+                Ok(bytes) if (1..=32).contains(&bytes.len()) => {}
+                _ => {
+                    return Err(GeyserPluginError::ConfigFileReadError {
+                        msg: format!(
+                        "account_prefix_filters entry {prefix:?} must be base58 of 1 to 32 bytes"
+                    ),
+                    })
+                }
+            }
+        }
+        if let Some(metric) = self
+            .kafka_stats_metrics
+            .iter()
+            .flatten()
+            .find(|metric| !KAFKA_BROKER_STATS.contains(&metric.as_str()))
+        {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: format!("unknown kafka_stats_metrics entry {metric:?}"),
+            });
+        }
+        if self.filters.iter().any(|filter| {
+            filter.sticky_partitioning && filter.partitioning != Partitioning::KeyHash
+        }) {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "sticky_partitioning can't be combined with explicit partitioning".to_owned(),
+            });
+        }
+        if self.filters.iter().any(|filter| {
+            filter
+                .failed_tx_error_includes
+                .iter()
+                .chain(&filter.failed_tx_error_excludes)
+                .any(|entry| entry.trim_start_matches('^').is_empty())
+        }) {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "failed_tx_error_includes and failed_tx_error_excludes entries can't be empty"
+                    .to_owned(),
+            });
+        }
+        if let Some(schedule) = &self.epoch_schedule {
+            schedule
+                .validate()
+                .map_err(|msg| GeyserPluginError::ConfigFileReadError { msg })?;
+        }
+        if let Some(filter) = self
+            .filters
+            .iter()
+            .find(|filter| !(0.0..=1.0).contains(&filter.transaction_sample_rate))
+        {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: format!(
+                    "transaction_sample_rate must be in [0, 1], got {}",
+                    filter.transaction_sample_rate
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Client settings of `kafka`, with the password of `sasl_password_file`.
+    pub fn client_config(&self) -> KafkaResult<ClientConfig> {
         let mut config = ClientConfig::new();
         for (k, v) in self.kafka.iter() {
             config.set(k, v);
         }
-        ThreadedProducer::from_config_and_context(&config, StatsThreadedProducerContext)
+        if let Some(path) = &self.sasl_password_file {
+            let password = fs::read_to_string(path).map_err(|error| {
+                KafkaError::ClientCreation(format!("failed to read {path:?}: {error}"))
+            })?;
+            config.set("sasl.password", password.trim_end());
+        }
+        Ok(config)
+    }
+
+    /// Create rdkafka::FutureProducer from config, recording its statistics and deliveries in
+    /// `metrics`.
+    pub fn producer(&self, metrics: Arc<InstanceMetrics>) -> KafkaResult<KafkaProducer> {
+        self.producer_with_stats(Arc::new(KafkaStats::new(
+            &self.kafka_stats_options(),
+            metrics,
+        )))
+    }
+
+    /// Create a producer exporting its statistics through `stats`.
+    pub fn producer_with_stats(&self, stats: Arc<KafkaStats>) -> KafkaResult<KafkaProducer> {
+        ThreadedProducer::from_config_and_context(
+            &self.client_config()?,
+            StatsThreadedProducerContext::new(
+                self.delivery_latency_slo_ms.map(Duration::from_millis),
+                stats,
+            ),
+        )
+    }
+
+    pub fn kafka_stats_options(&self) -> KafkaStatsOptions {
+        KafkaStatsOptions {
+            min_interval: Duration::from_millis(self.kafka_stats_min_interval_ms),
+            metrics: self.kafka_stats_metrics.clone(),
+            advisor_interval: self
+                .producer_advisor
+                .then(|| Duration::from_secs(self.producer_advisor_interval_secs)),
+            adaptive: (self.kafka_stats_healthy_every > 1).then(|| AdaptiveStatsOptions {
+                healthy_every: self.kafka_stats_healthy_every,
+                outbuf_threshold: self.kafka_stats_outbuf_threshold,
+                cool_down: Duration::from_secs(self.kafka_stats_cool_down_secs),
+            }),
+        }
+    }
+
+    /// Credential files watched for rotation.
+    pub fn credential_paths(&self) -> Vec<PathBuf> {
+        ["ssl.certificate.location", "ssl.key.location"]
+            .iter()
+            .filter_map(|key| self.kafka.get(*key))
+            .chain(self.sasl_password_file.iter())
+            .map(PathBuf::from)
+            .collect()
+    }
+
+    /// Value of Kafka setting `key`, possibly set under its alias.
+    fn kafka_value(&self, key: &str) -> Option<&str> {
+        self.kafka
+            .get(key)
+            .or_else(|| alias(key).and_then(|alias| self.kafka.get(alias)))
+            .map(String::as_str)
     }
 
     fn set_default(&mut self, k: &'static str, v: &'static str) {
-        if !self.kafka.contains_key(k) {
+        if self.kafka_value(k).is_none() {
             self.kafka.insert(k.to_owned(), v.to_owned());
         }
     }
 
     fn fill_defaults(&mut self) {
+        if let Some(profile) = self.delivery_profile {
+            for (k, v) in profile.settings() {
+                self.set_default(k, v);
+            }
+        }
         self.set_default("request.required.acks", "1");
         self.set_default("message.timeout.ms", "30000");
         self.set_default("compression.type", "lz4");
         self.set_default("partitioner", "murmur2_random");
     }
 
-    pub fn create_prometheus(&self) -> IoResult<Option<PrometheusService>> {
-        self.prometheus.map(PrometheusService::new).transpose()
+    /// Effective values of the settings covered by `delivery_profile`, including overrides.
+    pub fn delivery_profile_settings(&self) -> Vec<(&'static str, String)> {
+        self.delivery_profile
+            .iter()
+            .flat_map(|profile| profile.settings())
+            .map(|(k, _)| (*k, self.kafka_value(k).unwrap_or_default().to_owned()))
+            .collect()
+    }
+
+    pub fn checkpoint_topic(&self) -> Option<String> {
+        (!self.checkpoint_topic.is_empty()).then(|| self.checkpoint_topic.clone())
+    }
+
+    /// Topics the plugin picks partitions of: account update topics of filters with explicit
+    /// or sticky partitioning, and transaction topics keyed by `slot_index`.
+    pub fn explicit_partition_topics(&self) -> Vec<String> {
+        let account_topics = self
+            .filters
+            .iter()
+            .filter(|filter| {
+                filter.sticky_partitioning || filter.partitioning == Partitioning::Explicit
+            })
+            .flat_map(ConfigFilter::account_topics);
+        let transaction_topics = self
+            .filters
+            .iter()
+            .filter(|filter| filter.transaction_key_source == TransactionKeySource::SlotIndex)
+            .filter_map(|filter| active_topic(&filter.transaction_topic));
+        let mut topics: Vec<String> = account_topics
+            .chain(transaction_topics)
+            .map(str::to_owned)
+            .collect();
+        topics.sort();
+        topics.dedup();
+        topics
+    }
+
+    /// All configured topics, with whether any filter wraps messages on it.
+    /// State topics are left out, they only ever carry account states.
+    pub fn topics(&self) -> Vec<(String, bool)> {
+        let mut topics: Vec<(String, bool)> = Vec::new();
+        let filter_topics = self.filters.iter().flat_map(|filter| {
+            [
+                (&filter.update_account_topic, filter.wrap_account_messages),
+                (&filter.slot_status_topic, filter.wrap_slot_messages),
+                (&filter.transaction_topic, filter.wrap_transaction_messages),
+                (&filter.transaction_index_topic, Some(false)),
+                (&filter.block_events_topic, filter.wrap_block_messages),
+            ]
+            .into_iter()
+            .filter_map(|(topic, wrap)| {
+                Some((active_topic(topic)?, wrap.unwrap_or(filter.wrap_messages)))
+            })
+            .chain(filter.size_tiers.iter().map(|tier| {
+                let wrap = filter.wrap_account_messages.unwrap_or(filter.wrap_messages);
+                (tier.topic.as_str(), wrap)
+            }))
+        });
+        let other_topics = [
+            (
+                active_topic(&self.slot_status_topic).unwrap_or_default(),
+                false,
+            ),
+            (self.firehose_topic.as_str(), true),
+        ];
+        for (topic, wrap_messages) in filter_topics.chain(other_topics) {
+            if topic.is_empty() {
+                continue;
+            }
+            match topics.iter_mut().find(|(t, _)| t == topic) {
+                Some((_, wrap)) => *wrap |= wrap_messages,
+                None => topics.push((topic.to_owned(), wrap_messages)),
+            }
+        }
+        topics
+    }
+
+    /// Every topic produced to, with whether all filters producing to it are `fail_open`.
+    pub fn access_topics(&self) -> Vec<(String, bool)> {
+        let mut topics: Vec<(String, bool)> = Vec::new();
+        let filter_topics = self.filters.iter().flat_map(|filter| {
+            [
+                &filter.update_account_topic,
+                &filter.slot_status_topic,
+                &filter.transaction_topic,
+                &filter.transaction_index_topic,
+                &filter.block_events_topic,
+                &filter.state_topic,
+            ]
+            .into_iter()
+            .filter_map(active_topic)
+            .chain(filter.size_tiers.iter().map(|tier| tier.topic.as_str()))
+            .map(|topic| (topic, filter.fail_open))
+        });
+        let other_topics = [
+            self.slot_status_topic.as_deref(),
+            Some(self.firehose_topic.as_str()),
+            Some(self.checkpoint_topic.as_str()),
+            self.program_stats_topic.as_deref(),
+            self.alt_failure_topic.as_deref(),
+            self.write_lock_contention_topic.as_deref(),
+            self.producer_registry_topic.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|topic| !topic.is_empty())
+        .map(|topic| (topic, false));
+        for (topic, fail_open) in filter_topics.chain(other_topics) {
+            match topics.iter_mut().find(|(t, _)| t == topic) {
+                Some((_, all_fail_open)) => *all_fail_open &= fail_open,
+                None => topics.push((topic.to_owned(), fail_open)),
+            }
+        }
+        topics
+    }
+
+    /// Schedule to derive the epoch of slots with, defaulting to mainnet's fixed size epochs.
+    pub fn epoch_schedule(&self) -> EpochSchedule {
+        match self.epoch_schedule {
+            Some(schedule) => schedule.derive(),
+            None => EpochSchedule::without_warmup(),
+        }
+    }
+
+    pub fn http_limits(&self) -> HttpLimits {
+        HttpLimits {
+            max_connections: self.prometheus_max_connections,
+            header_timeout: Duration::from_millis(self.prometheus_header_timeout_ms),
+            response_timeout: Duration::from_millis(self.prometheus_response_timeout_ms),
+        }
+    }
+
+    pub fn create_prometheus(&self, state: Arc<HttpState>) -> IoResult<Option<PrometheusService>> {
+        if self.prometheus.is_empty() {
+            return Ok(None);
+        }
+        PrometheusService::new(
+            &self.prometheus,
+            self.prometheus_require_all_binds,
+            self.http_limits(),
+            state,
+        )
+        .map(Some)
     }
 }
 
+/// Address the Prometheus and debug endpoints are served on, either a TCP
+/// socket address or a Unix socket path prefixed with `unix:`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrometheusAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for PrometheusAddress {
+    type Err = AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some(path) => Ok(Self::Unix(PathBuf::from(path))),
+            None => s.parse().map(Self::Tcp),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PrometheusAddress {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let address = String::deserialize(deserializer)?;
+        address.parse().map_err(|error| {
+            de::Error::custom(format!("invalid prometheus address {address:?}: {error}"))
+        })
+    }
+}
+
+/// `prometheus` as `null`, a single address or a list of addresses.
+fn deserialize_prometheus<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<PrometheusAddress>, D::Error> {
+    // Strings rather than addresses, so parse errors aren't hidden by the untagged enum.
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Addresses {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    let addresses = match Option::<Addresses>::deserialize(deserializer)? {
+        None => Vec::new(),
+        Some(Addresses::One(address)) => vec![address],
+        Some(Addresses::Many(addresses)) => addresses,
+    };
+    addresses
+        .into_iter()
+        .map(|address| {
+            address.parse().map_err(|error| {
+                de::Error::custom(format!("invalid prometheus address {address:?}: {error}"))
+            })
+        })
+        .collect()
+}
+
+/// The topic of a filter's topic setting, `None` if it is disabled with `null` or empty.
+pub fn active_topic(setting: &Option<String>) -> Option<&str> {
+    setting.as_deref().filter(|topic| !topic.is_empty())
+}
+
+/// Check `size_tiers` have topics and ascending bounds, only the last one going without.
+fn validate_size_tiers(tiers: &[SizeTier]) -> Result<(), String> {
+    let mut previous: Option<usize> = None;
+    for (index, tier) in tiers.iter().enumerate() {
+        if tier.topic.is_empty() {
+            return Err("size_tiers topics can't be empty".to_owned());
+        }
+        match (tier.max_bytes, previous) {
+            (None, _) if index + 1 < tiers.len() => {
+                return Err("only the last of size_tiers can omit max_bytes".to_owned());
+            }
+            (Some(max_bytes), Some(previous)) if max_bytes <= previous => {
+                return Err(format!(
+                    "size_tiers max_bytes must be ascending, got {max_bytes} after {previous}"
+                ));
+            }
+            _ => {}
+        }
+        previous = tier.max_bytes;
+    }
+    Ok(())
+}
+
 /// Plugin config.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(deny_unknown_fields, default)]
 pub struct ConfigFilter {
+    /// Name used in per-filter metrics, the filter's index if empty.
+    pub name: String,
     /// Kafka topic to send account updates to.
-    pub update_account_topic: String,
+    ///
+    /// Topics explicitly set to `null` are disabled, as are empty ones. Omitted topics take the
+    /// default, currently empty, so a later default never revives a topic disabled with `null`.
+    pub update_account_topic: Option<String>,
+    /// Topics of account updates by data length, the first tier the length fits in taking the
+    /// update. Updates fitting in none go to `update_account_topic`.
+    pub size_tiers: Vec<SizeTier>,
     /// Kafka topic to send slot status updates to.
-    pub slot_status_topic: String,
+    pub slot_status_topic: Option<String>,
     /// Kafka topic to send transaction to.
-    pub transaction_topic: String,
+    pub transaction_topic: Option<String>,
+    /// Kafka topic to send compact index entries of matching transactions to, whether or not
+    /// `transaction_topic` is set.
+    pub transaction_index_topic: Option<String>,
+    /// Kafka topic to send block metadata to.
+    pub block_events_topic: Option<String>,
+    /// Compacted Kafka topic holding the latest state of every matching account.
+    ///
+    /// Consumer contract: keys are bare 32 byte pubkeys and values unwrapped
+    /// `UpdateAccountEvent`s of the latest live update, so after compaction the topic holds one
+    /// record per account. An account reaching zero lamports is closed and gets a tombstone, a
+    /// record with a null value, which compaction eventually removes along with the key.
+    /// Updates of one account land on the same partition in write order, as long as the
+    /// topic's partition count doesn't change. Without
+    /// `state_topic_seed_from_startup`, accounts that don't change after startup never appear.
+    pub state_topic: Option<String>,
+    /// Also publish the accounts notified at startup to `state_topic`.
+    pub state_topic_seed_from_startup: bool,
     /// List of programs to ignore.
     pub program_ignores: Vec<String>,
     /// List of programs to include
     pub program_filters: Vec<String>,
     // List of accounts to include
     pub account_filters: Vec<String>,
+    /// Base58 encoded leading bytes of accounts to include.
+    pub account_prefix_filters: Vec<String>,
     /// Publish all accounts on startup.
     pub publish_all_accounts: bool,
     /// Publish vote transactions.
     pub include_vote_transactions: bool,
     /// Publish failed transactions.
     pub include_failed_transactions: bool,
+    /// Publish failed transactions only if their error matches any of these, if set.
+    pub failed_tx_error_includes: Vec<String>,
+    /// Leave out failed transactions whose error matches any of these.
+    pub failed_tx_error_excludes: Vec<String>,
     /// Wrap all event message in a single message type.
     pub wrap_messages: bool,
+    /// Override `wrap_messages` for account updates.
+    pub wrap_account_messages: Option<bool>,
+    /// Override `wrap_messages` for slot status updates.
+    pub wrap_slot_messages: Option<bool>,
+    /// Override `wrap_messages` for transactions.
+    pub wrap_transaction_messages: Option<bool>,
+    /// Override `wrap_messages` for block metadata.
+    pub wrap_block_messages: Option<bool>,
+    /// Decode the state of stake program accounts.
+    pub decode_stake_accounts: bool,
+    /// Decode the contents of address lookup table accounts.
+    pub decode_address_lookup_tables: bool,
+    /// Skip account updates without a causing transaction.
+    pub require_causing_txn: bool,
+    /// What transaction messages are keyed by.
+    pub transaction_key_source: TransactionKeySource,
+    /// How account updates are assigned to partitions.
+    pub partitioning: Partitioning,
+    /// Assign account updates to partitions by a hash of the pubkey alone, so an account stays on
+    /// its partition whatever its message key, while the partition count doesn't change.
+    pub sticky_partitioning: bool,
+    /// Inner instructions kept per transaction, unlimited if unset.
+    pub max_inner_instructions: Option<usize>,
+    /// Log messages kept per transaction, unlimited if unset.
+    pub max_log_messages: Option<usize>,
+    /// Attach the data of Memo program instructions to transactions as `memos`.
+    pub extract_memos: bool,
+    /// Bytes of memos attached per transaction by `extract_memos`.
+    pub max_memo_bytes: usize,
+    /// List accounts whose lamports didn't change in `balance_changes` too.
+    pub include_zero_delta: bool,
+    /// Also publish the serialized transaction as sent on the wire.
+    pub include_raw_transaction: bool,
+    /// Fraction of matching transactions published, chosen by signature.
+    pub transaction_sample_rate: f64,
+    /// Count publish failures of this filter's topics without failing the notification.
+    pub fail_open: bool,
+    /// Byte ranges of account data, publish account updates only if any of them changed.
+    pub publish_if_changed_bytes_at: Vec<Range<usize>>,
+    /// Accounts whose bytes in `publish_if_changed_bytes_at` are kept to compare against.
+    pub changed_bytes_max_accounts: usize,
+    /// Account data size above which publishing an account logs a rate limited warning.
+    pub warn_data_size_bytes: usize,
+    /// Fields of published account updates, for consumers of an older schema.
+    pub schema_compat: SchemaCompat,
+}
+
+impl ConfigFilter {
+    /// Active topics of account updates, `update_account_topic` and those of `size_tiers`.
+    pub fn account_topics(&self) -> impl Iterator<Item = &str> {
+        active_topic(&self.update_account_topic)
+            .into_iter()
+            .chain(self.size_tiers.iter().map(|tier| tier.topic.as_str()))
+    }
+}
+
+/// Topic of account updates with data up to `max_bytes` long, of any length without it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SizeTier {
+    #[serde(default)]
+    pub max_bytes: Option<usize>,
+    pub topic: String,
+}
+
+/// Preset of librdkafka settings for a delivery guarantee.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryProfile {
+    /// At most once: no retries and no batching delay, messages are dropped after 5s.
+    LowLatency,
+    /// At least once: acknowledged by all in-sync replicas and retried in order for up to 5
+    /// minutes, possibly duplicated.
+    Durable,
+    /// Idempotent producer: acknowledged by all in-sync replicas and retried for up to 5
+    /// minutes without duplicates or reordering within a partition.
+    ExactlyOnce,
+}
+
+impl DeliveryProfile {
+    pub fn settings(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::LowLatency => &[
+                ("request.required.acks", "1"),
+                ("message.send.max.retries", "0"),
+                ("enable.idempotence", "false"),
+                ("queue.buffering.max.ms", "0"),
+                ("message.timeout.ms", "5000"),
+            ],
+            Self::Durable => &[
+                ("request.required.acks", "all"),
+                ("message.send.max.retries", "2147483647"),
+                ("enable.idempotence", "false"),
+                ("max.in.flight.requests.per.connection", "1"),
+                ("message.timeout.ms", "300000"),
+            ],
+            Self::ExactlyOnce => &[
+                ("request.required.acks", "all"),
+                ("message.send.max.retries", "2147483647"),
+                ("enable.idempotence", "true"),
+                ("max.in.flight.requests.per.connection", "5"),
+                ("message.timeout.ms", "300000"),
+            ],
+        }
+    }
+}
+
+/// librdkafka alias of a setting used in defaults.
+fn alias(key: &str) -> Option<&'static str> {
+    match key {
+        "request.required.acks" => Some("acks"),
+        "message.send.max.retries" => Some("retries"),
+        "queue.buffering.max.ms" => Some("linger.ms"),
+        "message.timeout.ms" => Some("delivery.timeout.ms"),
+        "max.in.flight.requests.per.connection" => Some("max.in.flight"),
+        "compression.type" => Some("compression.codec"),
+        _ => None,
+    }
+}
+
+/// Key of transaction messages.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionKeySource {
+    /// Transaction signature.
+    #[default]
+    Signature,
+    /// Slot as little endian bytes followed by the big endian index within the slot. The
+    /// partition is picked from the slot, keeping the transactions of a slot on one partition
+    /// in execution order.
+    SlotIndex,
+}
+
+/// Epoch schedule of a cluster, with the values the `getEpochSchedule` RPC method returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigEpochSchedule {
+    /// Slots of each epoch after warmup.
+    pub slots_per_epoch: u64,
+    /// First epoch and slot with `slots_per_epoch` slots, 0 without warmup. Derived from
+    /// `slots_per_epoch` and `warmup`, and only checked against the derived values if set.
+    #[serde(default)]
+    pub first_normal_epoch: u64,
+    #[serde(default)]
+    pub first_normal_slot: u64,
+    /// Whether epochs start short and double in length until `first_normal_epoch`.
+    #[serde(default)]
+    pub warmup: bool,
+}
+
+impl ConfigEpochSchedule {
+    /// Schedule the cluster derives from `slots_per_epoch` and `warmup`, which must be valid.
+    fn derive(&self) -> EpochSchedule {
+        EpochSchedule::custom(self.slots_per_epoch, self.slots_per_epoch, self.warmup)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.slots_per_epoch < MINIMUM_SLOTS_PER_EPOCH {
+            return Err(format!(
+                "epoch_schedule slots_per_epoch must be at least {MINIMUM_SLOTS_PER_EPOCH}, got {}",
+                self.slots_per_epoch
+            ));
+        }
+        let derived = self.derive();
+        let first_normal = (self.first_normal_epoch, self.first_normal_slot);
+        if first_normal != (0, 0)
+            && first_normal != (derived.first_normal_epoch, derived.first_normal_slot)
+        {
+            return Err(format!(
+                "epoch_schedule first_normal_epoch and first_normal_slot must be {} and {} for \
+                 these slots_per_epoch and warmup, got {} and {}",
+                derived.first_normal_epoch,
+                derived.first_normal_slot,
+                self.first_normal_epoch,
+                self.first_normal_slot
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Destination of messages.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkKind {
+    /// Produce them to Kafka.
+    #[default]
+    Kafka,
+    /// Write them to files in `file_sink_dir` instead.
+    File,
+}
+
+/// Compression of the files of the `file` sink.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileSinkCompression {
+    #[default]
+    None,
+    Zstd,
+}
+
+/// Compression of account update payloads, on top of any `compression.codec` of the producer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PayloadCompression {
+    #[default]
+    None,
+    /// Zstd with a dictionary trained per account owner.
+    ZstdDict,
+}
+
+/// Handling of messages for a paused topic.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PausePolicy {
+    /// Drop them, counted in `paused_messages_total`.
+    #[default]
+    Drop,
+    /// Keep them in memory up to `pause_buffer_max_bytes` and send them on resume.
+    Buffer,
+}
+
+/// Handling of messages sent while `max_outstanding_bytes` are outstanding.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackpressurePolicy {
+    /// Wait for deliveries to bring the outstanding bytes below the cap.
+    #[default]
+    Block,
+    /// Fail the send with a queue full error.
+    Error,
+    /// Drop the message.
+    Drop,
+}
+
+/// Handling of events that fail to serialize.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SerializationFailurePolicy {
+    /// Fail the notification, unless the filter is `fail_open`.
+    #[default]
+    Error,
+    /// Skip the event.
+    Drop,
+}
+
+/// Transactions counted in program stats.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProgramStatsSource {
+    /// Those published to a transaction topic.
+    #[default]
+    Published,
+    /// Every transaction notified, published or not.
+    Seen,
+}
+
+/// Partition assignment of account updates.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Partitioning {
+    /// The producer's `partitioner` hashes the message key.
+    #[default]
+    KeyHash,
+    /// First four pubkey bytes as a little endian `u32`, modulo the topic's partition count.
+    Explicit,
+    /// xxHash64 of the pubkey, modulo the topic's partition count. Set by `sticky_partitioning`.
+    #[serde(skip_deserializing)]
+    Sticky,
+}
+
+/// Schema of the account updates of a filter.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaCompat {
+    /// Every field of the current `UpdateAccountEvent`.
+    #[default]
+    Latest,
+    /// Only the fields of the first `UpdateAccountEvent`, `slot` to `write_version`.
+    V1,
 }
 
 impl Default for ConfigFilter {
     fn default() -> Self {
         Self {
-            update_account_topic: "".to_owned(),
-            slot_status_topic: "".to_owned(),
-            transaction_topic: "".to_owned(),
+            name: "".to_owned(),
+            update_account_topic: Some(String::new()),
+            size_tiers: Vec::new(),
+            slot_status_topic: Some(String::new()),
+            transaction_topic: Some(String::new()),
+            transaction_index_topic: Some(String::new()),
+            block_events_topic: Some(String::new()),
+            state_topic: Some(String::new()),
+            state_topic_seed_from_startup: false,
             program_ignores: Vec::new(),
             program_filters: Vec::new(),
             account_filters: Vec::new(),
+            account_prefix_filters: Vec::new(),
             publish_all_accounts: false,
             include_vote_transactions: true,
             include_failed_transactions: true,
+            failed_tx_error_includes: Vec::new(),
+            failed_tx_error_excludes: Vec::new(),
             wrap_messages: false,
+            wrap_account_messages: None,
+            wrap_slot_messages: None,
+            wrap_transaction_messages: None,
+            wrap_block_messages: None,
+            decode_stake_accounts: false,
+            decode_address_lookup_tables: false,
+            require_causing_txn: false,
+            partitioning: Partitioning::KeyHash,
+            sticky_partitioning: false,
+            transaction_key_source: TransactionKeySource::Signature,
+            max_inner_instructions: None,
+            max_log_messages: None,
+            extract_memos: false,
+            max_memo_bytes: 1024,
+            include_zero_delta: false,
+            include_raw_transaction: false,
+            transaction_sample_rate: 1.0,
+            fail_open: false,
+            publish_if_changed_bytes_at: Vec::new(),
+            changed_bytes_max_accounts: 100_000,
+            warn_data_size_bytes: 1024 * 1024,
+            schema_compat: SchemaCompat::Latest,
         }
     }
 }
 
 pub type Producer = ThreadedProducer<DefaultProducerContext>;
+
+pub type KafkaProducer = ThreadedProducer<StatsThreadedProducerContext>;
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        serde_json::{json, Value},
+    };
+
+    fn filled(profile: Option<DeliveryProfile>, kafka: &[(&str, &str)]) -> Config {
+        let mut config = Config {
+            delivery_profile: profile,
+            kafka: kafka
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            ..Default::default()
+        };
+        config.fill_defaults();
+        config
+    }
+
+    #[test]
+    fn test_delivery_profiles() {
+        let config = filled(None, &[]);
+        assert_eq!(config.kafka_value("request.required.acks"), Some("1"));
+        assert_eq!(config.kafka_value("message.timeout.ms"), Some("30000"));
+        assert_eq!(config.kafka_value("enable.idempotence"), None);
+        assert!(config.delivery_profile_settings().is_empty());
+
+        for profile in [
+            DeliveryProfile::LowLatency,
+            DeliveryProfile::Durable,
+            DeliveryProfile::ExactlyOnce,
+        ] {
+            let config = filled(Some(profile), &[]);
+            for (k, v) in profile.settings() {
+                assert_eq!(config.kafka_value(k), Some(*v), "{profile:?} {k}");
+            }
+            // Settings outside the profile keep their defaults.
+            assert_eq!(config.kafka_value("compression.type"), Some("lz4"));
+        }
+        let config = filled(Some(DeliveryProfile::ExactlyOnce), &[]);
+        assert_eq!(config.kafka_value("enable.idempotence"), Some("true"));
+        assert_eq!(config.kafka_value("request.required.acks"), Some("all"));
+        let config = filled(Some(DeliveryProfile::LowLatency), &[]);
+        assert_eq!(config.kafka_value("message.send.max.retries"), Some("0"));
+    }
+
+    #[test]
+    fn test_delivery_profile_overrides() {
+        // Explicit settings win, including when set under an alias.
+        let config = filled(
+            Some(DeliveryProfile::Durable),
+            &[("acks", "1"), ("message.timeout.ms", "60000")],
+        );
+        assert_eq!(config.kafka.get("request.required.acks"), None);
+        assert_eq!(config.kafka_value("request.required.acks"), Some("1"));
+        assert_eq!(config.kafka_value("message.timeout.ms"), Some("60000"));
+        assert_eq!(
+            config.kafka_value("max.in.flight.requests.per.connection"),
+            Some("1")
+        );
+        assert_eq!(
+            config.delivery_profile_settings(),
+            [
+                ("request.required.acks", "1".to_owned()),
+                ("message.send.max.retries", "2147483647".to_owned()),
+                ("enable.idempotence", "false".to_owned()),
+                ("max.in.flight.requests.per.connection", "1".to_owned()),
+                ("message.timeout.ms", "60000".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_delivery_profile() {
+        let parse = |profile: &str| {
+            serde_json::from_str::<Config>(&format!(
+                r#"{{"libpath": "", "kafka": {{}}, "filters": [], "delivery_profile": "{profile}"}}"#
+            ))
+        };
+        assert_eq!(
+            parse("exactly_once").unwrap().delivery_profile,
+            Some(DeliveryProfile::ExactlyOnce)
+        );
+        assert!(parse("fast").is_err());
+    }
+
+    #[test]
+    fn test_validator_identity() {
+        let mut config = Config {
+            validator_identity: Some(Pubkey::new_unique().to_string()),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+        config.validator_identity = Some("not a pubkey".to_owned());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_size_tiers_validation() {
+        let tiers = |tiers: &[(Option<usize>, &str)]| {
+            let tiers: Vec<_> = tiers
+                .iter()
+                .map(|&(max_bytes, topic)| SizeTier {
+                    max_bytes,
+                    topic: topic.to_owned(),
+                })
+                .collect();
+            validate_size_tiers(&tiers)
+        };
+        assert!(tiers(&[]).is_ok());
+        assert!(tiers(&[
+            (Some(1024), "small"),
+            (Some(4096), "medium"),
+            (None, "large")
+        ])
+        .is_ok());
+        assert!(tiers(&[(Some(1024), "small"), (Some(1024), "medium")]).is_err());
+        assert!(tiers(&[(Some(4096), "medium"), (Some(1024), "small")]).is_err());
+        assert!(tiers(&[(None, "large"), (Some(1024), "small")]).is_err());
+        assert!(tiers(&[(Some(1024), "")]).is_err());
+    }
+
+    #[test]
+    fn test_topic_settings() {
+        let parse = |topic: &str| {
+            let filter: ConfigFilter =
+                serde_json::from_str(&format!(r#"{{"name": "f"{topic}}}"#)).unwrap();
+            filter.transaction_topic
+        };
+        assert_eq!(parse(""), Some(String::new()));
+        assert_eq!(parse(r#", "transaction_topic": null"#), None);
+        assert_eq!(parse(r#", "transaction_topic": """#), Some(String::new()));
+        assert_eq!(
+            parse(r#", "transaction_topic": "txs""#),
+            Some("txs".to_owned())
+        );
+
+        for topic in [None, Some(String::new())] {
+            assert_eq!(active_topic(&topic), None);
+        }
+        assert_eq!(active_topic(&Some("txs".to_owned())), Some("txs"));
+    }
+
+    #[test]
+    fn test_access_topics() {
+        let mut config = Config::default();
+        let filter = |accounts: &str, fail_open| ConfigFilter {
+            update_account_topic: Some(accounts.to_owned()),
+            slot_status_topic: Some("slots".to_owned()),
+            state_topic: Some("states".to_owned()),
+            fail_open,
+            ..Default::default()
+        };
+        config.filters = vec![filter("accounts", false), filter("open-accounts", true)];
+        config.checkpoint_topic = "checkpoints".to_owned();
+        config.alt_failure_topic = Some(String::new());
+        let topics = |topics: &[(&str, bool)]| -> Vec<(String, bool)> {
+            topics
+                .iter()
+                .map(|(topic, fail_open)| (topic.to_string(), *fail_open))
+                .collect()
+        };
+        // Topics shared with a filter that isn't fail_open aren't either.
+        assert_eq!(
+            config.access_topics(),
+            topics(&[
+                ("accounts", false),
+                ("slots", false),
+                ("states", false),
+                ("open-accounts", true),
+                ("checkpoints", false),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_prometheus_addresses() {
+        let parse = |prometheus: Value| {
+            serde_json::from_value::<Config>(
+                json!({"libpath": "", "filters": [], "prometheus": prometheus}),
+            )
+            .map(|config| config.prometheus)
+        };
+        assert_eq!(parse(Value::Null).unwrap(), vec![]);
+        assert_eq!(
+            parse(json!("127.0.0.1:9090")).unwrap(),
+            vec![PrometheusAddress::Tcp("127.0.0.1:9090".parse().unwrap())]
+        );
+        assert_eq!(
+            parse(json!([
+                "127.0.0.1:9090",
+                "[::]:9090",
+                "unix:/run/metrics.sock"
+            ]))
+            .unwrap(),
+            vec![
+                PrometheusAddress::Tcp("127.0.0.1:9090".parse().unwrap()),
+                PrometheusAddress::Tcp("[::]:9090".parse().unwrap()),
+                PrometheusAddress::Unix("/run/metrics.sock".into()),
+            ]
+        );
+        let error = parse(json!(["127.0.0.1:9090", "localhost"])).unwrap_err();
+        assert!(error.to_string().contains("\"localhost\""), "{error}");
+        assert!(Config::default().prometheus_require_all_binds);
+    }
+
+    #[test]
+    fn test_epoch_schedule() {
+        // Mainnet epochs, without warmup.
+        let mainnet = Config::default().epoch_schedule();
+        for (slot, epoch, index) in [
+            (0, 0, 0),
+            (431_999, 0, 431_999),
+            (432_000, 1, 0),
+            (250_000_000, 578, 304_000),
+            (300_000_000, 694, 192_000),
+        ] {
+            assert_eq!(mainnet.get_epoch_and_slot_index(slot), (epoch, index));
+        }
+
+        // Epochs of 32 slots doubling up to 432,000 slots from epoch 14.
+        let config: Config = serde_json::from_value(json!({
+            "libpath": "",
+            "kafka": {},
+            "filters": [],
+            "epoch_schedule": {
+                "slots_per_epoch": 432_000,
+                "first_normal_epoch": 14,
+                "first_normal_slot": 524_256,
+                "warmup": true,
+            },
+        }))
+        .unwrap();
+        assert!(config.validate().is_ok());
+        let warmup = config.epoch_schedule();
+        assert_eq!(warmup, EpochSchedule::custom(432_000, 432_000, true));
+        for (slot, epoch, index) in [
+            (0, 0, 0),
+            (31, 0, 31),
+            (32, 1, 0),
+            (95, 1, 63),
+            (96, 2, 0),
+            (524_255, 13, 262_143),
+            (524_256, 14, 0),
+            (956_256, 15, 0),
+        ] {
+            assert_eq!(warmup.get_epoch_and_slot_index(slot), (epoch, index));
+        }
+    }
+
+    #[test]
+    fn test_epoch_schedule_validation() {
+        let config = |slots_per_epoch, first_normal_epoch, first_normal_slot, warmup| Config {
+            epoch_schedule: Some(ConfigEpochSchedule {
+                slots_per_epoch,
+                first_normal_epoch,
+                first_normal_slot,
+                warmup,
+            }),
+            ..Default::default()
+        };
+        assert!(config(432_000, 0, 0, false).validate().is_ok());
+        // The first normal epoch and slot may be left out, and are derived.
+        assert!(config(432_000, 0, 0, true).validate().is_ok());
+        assert_eq!(
+            config(432_000, 0, 0, true).epoch_schedule(),
+            config(432_000, 14, 524_256, true).epoch_schedule()
+        );
+
+        // Epochs of no or too few slots.
+        assert!(config(0, 0, 0, false).validate().is_err());
+        assert!(config(16, 0, 0, false).validate().is_err());
+        // A first normal epoch or slot disagreeing with the warmup.
+        assert!(config(432_000, 13, 524_256, true).validate().is_err());
+        assert!(config(432_000, 14, 524_256, false).validate().is_err());
+    }
+}
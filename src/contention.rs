@@ -0,0 +1,168 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Write locked accounts per slot, published to `write_lock_contention_topic`.
+
+use {
+    crate::{
+        instance_metrics::InstanceMetrics, slot_tracker::SlotTracker, AccountContention,
+        SlotContentionEvent,
+    },
+    solana_program::{message::SanitizedMessage, pubkey::Pubkey},
+    std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    },
+};
+
+/// Slots counted at once, the least recently notified are dropped first if they never get
+/// processed.
+const MAX_TRACKED_SLOTS: usize = 64;
+
+/// Write lock counts of the accounts of each slot until it's processed.
+#[derive(Debug)]
+pub struct WriteLockContention {
+    top_k: usize,
+    slots: Mutex<SlotTracker<HashMap<Pubkey, u32>>>,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl WriteLockContention {
+    pub fn new(top_k: usize, metrics: Arc<InstanceMetrics>) -> Self {
+        let slots = SlotTracker::new("write_lock_contention", MAX_TRACKED_SLOTS, &metrics);
+        Self {
+            top_k,
+            slots: Mutex::new(slots),
+            metrics,
+        }
+    }
+
+    /// Count the accounts a transaction of `message` in `slot` write locks. Accounts the
+    /// message marks writable are demoted to read only if they are invoked programs or
+    /// reserved, as the runtime does when locking.
+    pub fn record(&self, slot: u64, message: &SanitizedMessage) {
+        let mut slots = self.slots.lock().unwrap();
+        let (counts, _) = slots.get_or_insert_with(slot, HashMap::new);
+        for (index, pubkey) in message.account_keys().iter().enumerate() {
+            if message.is_writable(index) {
+                let count = counts.entry(*pubkey).or_default();
+                *count = count.saturating_add(1);
+            }
+        }
+    }
+
+    /// Stop counting `slot`, returning its most contended accounts if it had any
+    /// transactions.
+    pub fn take(&self, slot: u64) -> Option<SlotContentionEvent> {
+        let counts = self.slots.lock().unwrap().remove(slot)?;
+        let mut accounts: Vec<(Pubkey, u32)> = counts.into_iter().collect();
+        accounts.sort_unstable_by(|(a_pubkey, a), (b_pubkey, b)| {
+            b.cmp(a).then_with(|| a_pubkey.cmp(b_pubkey))
+        });
+        accounts.truncate(self.top_k);
+        if let Some((_, count)) = accounts.first() {
+            self.metrics.hottest_account_slot.set(slot as i64);
+            self.metrics
+                .hottest_account_write_locks
+                .set(i64::from(*count));
+        }
+        Some(SlotContentionEvent {
+            slot,
+            accounts: accounts
+                .into_iter()
+                .map(|(pubkey, write_lock_count)| AccountContention {
+                    pubkey: pubkey.to_bytes().to_vec(),
+                    write_lock_count,
+                })
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        solana_sdk::{
+            instruction::{AccountMeta, Instruction},
+            transaction::{SanitizedTransaction, Transaction},
+        },
+    };
+
+    fn record(
+        contention: &WriteLockContention,
+        slot: u64,
+        writable: &[Pubkey],
+        readonly: &[Pubkey],
+    ) {
+        let program = Pubkey::new_from_array([0xff; 32]);
+        let accounts = writable
+            .iter()
+            .map(|pubkey| AccountMeta::new(*pubkey, false))
+            .chain(
+                readonly
+                    .iter()
+                    .map(|pubkey| AccountMeta::new_readonly(*pubkey, false)),
+            )
+            .collect();
+        let transaction =
+            SanitizedTransaction::from_transaction_for_tests(Transaction::new_with_payer(
+                &[Instruction::new_with_bytes(program, &[], accounts)],
+                Some(&Pubkey::new_unique()),
+            ));
+        contention.record(slot, transaction.message());
+    }
+
+    fn account(pubkey: &Pubkey, write_lock_count: u32) -> AccountContention {
+        AccountContention {
+            pubkey: pubkey.to_bytes().to_vec(),
+            write_lock_count,
+        }
+    }
+
+    #[test]
+    fn test_top_k() {
+        let contention = WriteLockContention::new(2, Arc::default());
+        let mut keys: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        keys.sort();
+        let [a, b, c] = [keys[0], keys[1], keys[2]];
+        record(&contention, 5, &[a, b], &[c]);
+        record(&contention, 5, &[b, c], &[a]);
+        record(&contention, 5, &[c], &[]);
+        record(&contention, 6, &[a], &[]);
+
+        // Read only locks don't count, ties go to the lower pubkey, fee payers are writable
+        // once each.
+        let event = contention.take(5).unwrap();
+        assert_eq!(event.slot, 5);
+        assert_eq!(event.accounts, [account(&b, 2), account(&c, 2)]);
+        assert_eq!(contention.take(5), None);
+
+        let event = contention.take(6).unwrap();
+        assert_eq!(event.accounts[..1], [account(&a, 1)]);
+        assert_eq!(event.accounts[1].write_lock_count, 1);
+    }
+
+    #[test]
+    fn test_demoted_programs() {
+        let contention = WriteLockContention::new(10, Arc::default());
+        let program = Pubkey::new_from_array([0xff; 32]);
+        let writable = Pubkey::new_unique();
+        // Listed as writable, but invoked.
+        record(&contention, 1, &[program, writable], &[]);
+        let accounts = contention.take(1).unwrap().accounts;
+        assert!(accounts.contains(&account(&writable, 1)));
+        assert!(!accounts.iter().any(|a| a.pubkey == program.to_bytes()));
+    }
+}
@@ -0,0 +1,156 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `callback_deadline_ms`, the longest an account update or transaction notification spends in
+//! the plugin. The deadline of the callback running on a thread is checked before each filter,
+//! before each send and while pacing or waiting for `max_outstanding_bytes`; past it, the rest
+//! is skipped rather than holding up the validator.
+
+use {
+    crate::instance_metrics::InstanceMetrics,
+    log::warn,
+    std::{
+        cell::Cell,
+        sync::atomic::{AtomicU64, Ordering},
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+};
+
+thread_local! {
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Callbacks passing their deadline are logged at most this often.
+const WARN_INTERVAL_SECS: u64 = 10;
+
+static LAST_WARNED_SECS: AtomicU64 = AtomicU64::new(0);
+
+/// Deadline of the callback running on this thread, lifted when dropped.
+#[derive(Debug)]
+pub struct CallbackDeadline<'a> {
+    metrics: &'a InstanceMetrics,
+    callback: &'static str,
+    at: Option<Instant>,
+    previous: Option<Instant>,
+    exceeded: bool,
+}
+
+impl<'a> CallbackDeadline<'a> {
+    /// Start `callback` with `budget` to spend, unbounded if `None`.
+    pub fn start(
+        metrics: &'a InstanceMetrics,
+        callback: &'static str,
+        budget: Option<Duration>,
+    ) -> Self {
+        let at = budget.map(|budget| Instant::now() + budget);
+        Self {
+            metrics,
+            callback,
+            at,
+            previous: DEADLINE.with(|deadline| deadline.replace(at)),
+            exceeded: false,
+        }
+    }
+
+    /// Whether the deadline passed. The callback is counted in `callback_deadline_exceeded_total`
+    /// the first time.
+    pub fn exceeded(&mut self) -> bool {
+        if !self.exceeded && self.at.is_some_and(|at| Instant::now() >= at) {
+            self.exceeded = true;
+            self.metrics
+                .callback_deadline_exceeded_total
+                .with_label_values(&[self.callback])
+                .inc();
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let last = LAST_WARNED_SECS.load(Ordering::Relaxed);
+            if now >= last + WARN_INTERVAL_SECS
+                && LAST_WARNED_SECS
+                    .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_ok()
+            {
+                warn!(
+                    "{} passed callback_deadline_ms and was published partially, see \
+                     callback_deadline_exceeded_total",
+                    self.callback
+                );
+            }
+        }
+        self.exceeded
+    }
+}
+
+impl Drop for CallbackDeadline<'_> {
+    fn drop(&mut self) {
+        DEADLINE.with(|deadline| deadline.set(self.previous));
+    }
+}
+
+/// Deadline of the callback running on this thread, if it has one.
+pub fn current() -> Option<Instant> {
+    DEADLINE.with(Cell::get)
+}
+
+/// Whether the deadline of the callback running on this thread passed.
+pub fn expired() -> bool {
+    current().is_some_and(|at| Instant::now() >= at)
+}
+
+/// `delay` cut short to the time left until the deadline of the callback on this thread.
+pub fn bounded(delay: Duration) -> Duration {
+    current().map_or(delay, |at| {
+        delay.min(at.saturating_duration_since(Instant::now()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::thread};
+
+    #[test]
+    fn test_deadline() {
+        assert!(current().is_none() && !expired());
+        assert_eq!(bounded(Duration::from_secs(1)), Duration::from_secs(1));
+
+        let metrics = InstanceMetrics::default();
+        let exceeded = || {
+            metrics
+                .callback_deadline_exceeded_total
+                .with_label_values(&["test_deadline"])
+                .get()
+        };
+        let mut deadline =
+            CallbackDeadline::start(&metrics, "test_deadline", Some(Duration::from_millis(20)));
+        assert!(!deadline.exceeded() && !expired());
+        assert!(bounded(Duration::from_secs(1)) <= Duration::from_millis(20));
+        {
+            // Nested callbacks restore the outer deadline.
+            let _inner = CallbackDeadline::start(&metrics, "test_deadline", None);
+            assert!(current().is_none());
+        }
+        assert!(current().is_some());
+
+        thread::sleep(Duration::from_millis(25));
+        assert!(expired());
+        assert_eq!(bounded(Duration::from_secs(1)), Duration::ZERO);
+        assert!(deadline.exceeded());
+        assert!(deadline.exceeded());
+        assert_eq!(exceeded(), 1);
+
+        drop(deadline);
+        assert!(current().is_none() && !expired());
+    }
+}
@@ -12,19 +12,1384 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use solana_geyser_plugin_interface::geyser_plugin_interface::SlotStatus as PluginSlotStatus;
+use {
+    crate::{instance_metrics::InstanceMetrics, SchemaCompat},
+    message_wrapper::EventMessage,
+    solana_geyser_plugin_interface::geyser_plugin_interface::{
+        ReplicaAccountInfoV3, ReplicaBlockInfoV3, SlotStatus as PluginSlotStatus,
+    },
+    solana_program::{
+        epoch_schedule::EpochSchedule,
+        hash::{self, Hash},
+        instruction::CompiledInstruction as SdkCompiledInstruction,
+        message::{
+            AccountKeys, MessageHeader as SdkMessageHeader, SanitizedMessage as SdkSanitizedMessage,
+        },
+        pubkey,
+        pubkey::Pubkey,
+    },
+    solana_sdk::{
+        signature::Signature,
+        transaction::{SanitizedTransaction as SdkSanitizedTransaction, TransactionError},
+    },
+    solana_transaction_status::TransactionStatusMeta as SdkTransactionStatusMeta,
+    std::{iter, str::FromStr},
+};
 
 include!(concat!(
     env!("OUT_DIR"),
     "/blockdaemon.solana.accountsdb_plugin_kafka.types.rs"
 ));
 
+/// Serialized `FileDescriptorSet` of `event.proto` as compiled into this build, for
+/// reflection based decoding against exactly the running version.
+pub fn file_descriptor_set() -> &'static [u8] {
+    include_bytes!(concat!(env!("OUT_DIR"), "/event.bin"))
+}
+
+/// `event.proto` as compiled into this build.
+pub fn event_proto() -> &'static str {
+    include_str!("../proto/event.proto")
+}
+
 impl From<PluginSlotStatus> for SlotStatus {
     fn from(other: PluginSlotStatus) -> Self {
         match other {
             PluginSlotStatus::Processed => SlotStatus::Processed,
             PluginSlotStatus::Rooted => SlotStatus::Rooted,
             PluginSlotStatus::Confirmed => SlotStatus::Confirmed,
+            // Statuses of interface versions newer than the one built against.
+            #[allow(unreachable_patterns)]
+            _ => SlotStatus::Unknown,
+        }
+    }
+}
+
+/// Names of the event types, as used in config.
+pub const EVENT_KINDS: &[&str] = &["account", "slot", "transaction", "block"];
+
+impl EventMessage {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EventMessage::Account(_) => "account",
+            EventMessage::Slot(_) => "slot",
+            EventMessage::Transaction(_) => "transaction",
+            EventMessage::Checkpoint(_) => "checkpoint",
+            EventMessage::Block(_) => "block",
+        }
+    }
+
+    pub fn set_sequence(&mut self, sequence: u64) {
+        match self {
+            EventMessage::Account(event) => event.sequence = sequence,
+            EventMessage::Slot(event) => event.sequence = sequence,
+            EventMessage::Transaction(event) => event.sequence = sequence,
+            EventMessage::Checkpoint(event) => event.sequence = sequence,
+            EventMessage::Block(event) => event.sequence = sequence,
+        }
+    }
+}
+
+impl UpdateAccountEvent {
+    /// Clear the fields newer than `schema`, so they aren't encoded.
+    pub fn project(&mut self, schema: SchemaCompat) {
+        match schema {
+            SchemaCompat::Latest => {}
+            SchemaCompat::V1 => {
+                self.txn_signature = None;
+                self.stake_account = None;
+                self.lookup_table = None;
+                self.sequence = 0;
+                self.fork_instance_id = 0;
+                self.origin = AccountOrigin::Live.into();
+                self.highest_rooted_slot = 0;
+                self.original_data_len = 0;
+                self.data_sha256 = Vec::new();
+            }
+        }
+    }
+}
+
+/// Account update event of `info` at `slot`, without a decoded stake account.
+pub fn build_account_event(slot: u64, info: &ReplicaAccountInfoV3) -> UpdateAccountEvent {
+    build_account_event_stripping(slot, info, false)
+}
+
+/// Account update event of `info` at `slot` as `build_account_event`, the data of executable
+/// accounts replaced by its length and SHA-256 with `strip_executable_data`.
+pub fn build_account_event_stripping(
+    slot: u64,
+    info: &ReplicaAccountInfoV3,
+    strip_executable_data: bool,
+) -> UpdateAccountEvent {
+    let (data, original_data_len, data_sha256) = if strip_executable_data && info.executable {
+        let sha256 = hash::hash(info.data).to_bytes().to_vec();
+        (Vec::new(), info.data.len() as u64, sha256)
+    } else {
+        (info.data.to_vec(), 0, Vec::new())
+    };
+    UpdateAccountEvent {
+        slot,
+        pubkey: info.pubkey.to_vec(),
+        lamports: info.lamports,
+        owner: info.owner.to_vec(),
+        executable: info.executable,
+        rent_epoch: info.rent_epoch,
+        data,
+        write_version: info.write_version,
+        txn_signature: info.txn.map(|v| v.signature().as_ref().to_owned()),
+        stake_account: None,
+        lookup_table: None,
+        // Assigned by the publisher.
+        sequence: 0,
+        // Assigned by the plugin.
+        fork_instance_id: 0,
+        origin: AccountOrigin::Live.into(),
+        highest_rooted_slot: 0,
+        // Assigned by the publisher.
+        chunk: None,
+        original_data_len,
+        data_sha256,
+    }
+}
+
+/// Origin of an account update of `slot`, with `highest_rooted_slot` seen so far. Updates more
+/// than `catchup_slots` behind it are catch-up.
+pub fn account_origin(
+    is_startup: bool,
+    slot: u64,
+    highest_rooted_slot: u64,
+    catchup_slots: u64,
+) -> AccountOrigin {
+    if is_startup {
+        AccountOrigin::Startup
+    } else if slot < highest_rooted_slot.saturating_sub(catchup_slots) {
+        AccountOrigin::Catchup
+    } else {
+        AccountOrigin::Live
+    }
+}
+
+/// Block event of `info`, with `leader` if the leader schedule knows it.
+pub fn build_block_event(
+    info: &ReplicaBlockInfoV3,
+    leader: Option<Pubkey>,
+    epoch_schedule: &EpochSchedule,
+) -> BlockEvent {
+    let (epoch, slot_index_in_epoch) = epoch_schedule.get_epoch_and_slot_index(info.slot);
+    let blockhash = valid_hash(info.blockhash);
+    let parent_blockhash = valid_hash(info.parent_blockhash);
+    BlockEvent {
+        slot: info.slot,
+        blockhash_invalid: blockhash.is_none() || parent_blockhash.is_none(),
+        blockhash: blockhash.unwrap_or_default(),
+        rewards: info.rewards.iter().map(build_reward).collect(),
+        block_time: info.block_time,
+        block_height: info.block_height,
+        parent_slot: info.parent_slot,
+        parent_blockhash: parent_blockhash.unwrap_or_default(),
+        executed_transaction_count: info.executed_transaction_count,
+        entry_count: info.entry_count,
+        leader_pubkey: leader
+            .map(|leader| leader.to_bytes().to_vec())
+            .unwrap_or_default(),
+        epoch,
+        slot_index_in_epoch,
+        sequence: 0,
+        fork_instance_id: 0,
+    }
+}
+
+/// `hash` if it is base58 of 32 bytes. Strings too long for that aren't decoded.
+fn valid_hash(hash: &str) -> Option<String> {
+    Hash::from_str(hash).ok().map(|_| hash.to_owned())
+}
+
+fn build_reward(reward: &solana_transaction_status::Reward) -> Reward {
+    Reward {
+        pubkey: reward.pubkey.clone(),
+        lamports: reward.lamports,
+        post_balance: reward.post_balance,
+        reward_type: match reward.reward_type {
+            Some(r) => r as i32,
+            None => 0,
+        },
+        commission: match reward.commission {
+            Some(v) => v as u32,
+            None => 0,
+        },
+    }
+}
+
+fn build_compiled_instruction(ix: &SdkCompiledInstruction) -> CompiledInstruction {
+    CompiledInstruction {
+        program_id_index: ix.program_id_index as u32,
+        accounts: ix.accounts.iter().map(|v| *v as u32).collect(),
+        data: ix.data.clone(),
+    }
+}
+
+fn build_inner_instruction(ix: &solana_transaction_status::InnerInstruction) -> InnerInstruction {
+    InnerInstruction {
+        instruction: Some(build_compiled_instruction(&ix.instruction)),
+        stack_height: ix.stack_height,
+    }
+}
+
+fn build_message_header(header: &SdkMessageHeader) -> MessageHeader {
+    MessageHeader {
+        num_required_signatures: header.num_required_signatures as u32,
+        num_readonly_signed_accounts: header.num_readonly_signed_accounts as u32,
+        num_readonly_unsigned_accounts: header.num_readonly_unsigned_accounts as u32,
+    }
+}
+
+/// Fields legacy and V0 messages have in common.
+#[derive(Debug, PartialEq)]
+struct MessageCommon {
+    header: MessageHeader,
+    account_keys: Vec<Vec<u8>>,
+    recent_block_hash: Vec<u8>,
+    instructions: Vec<CompiledInstruction>,
+}
+
+fn build_message_common(
+    header: &SdkMessageHeader,
+    account_keys: &[Pubkey],
+    recent_blockhash: &Hash,
+    instructions: &[SdkCompiledInstruction],
+) -> MessageCommon {
+    MessageCommon {
+        header: build_message_header(header),
+        account_keys: account_keys.iter().map(|k| k.as_ref().into()).collect(),
+        recent_block_hash: recent_blockhash.as_ref().into(),
+        instructions: instructions
+            .iter()
+            .map(build_compiled_instruction)
+            .collect(),
+    }
+}
+
+/// Whether each account of the message, static keys followed by loaded addresses, is writable.
+fn build_writable_account_cache(message: &SdkSanitizedMessage) -> Vec<bool> {
+    (0..message.account_keys().len())
+        .map(|i| message.is_writable(i))
+        .collect()
+}
+
+fn build_sanitized_message(message: &SdkSanitizedMessage) -> SanitizedMessage {
+    let is_writable_account_cache = build_writable_account_cache(message);
+    let message_payload = match message {
+        SdkSanitizedMessage::Legacy(legacy) => {
+            let common = build_message_common(
+                &legacy.message.header,
+                &legacy.message.account_keys,
+                &legacy.message.recent_blockhash,
+                &legacy.message.instructions,
+            );
+            sanitized_message::MessagePayload::Legacy(LegacyLoadedMessage {
+                message: Some(LegacyMessage {
+                    header: Some(common.header),
+                    account_keys: common.account_keys,
+                    instructions: common.instructions,
+                    recent_block_hash: common.recent_block_hash,
+                }),
+                is_writable_account_cache,
+            })
+        }
+        SdkSanitizedMessage::V0(v0) => {
+            let common = build_message_common(
+                &v0.message.header,
+                &v0.message.account_keys,
+                &v0.message.recent_blockhash,
+                &v0.message.instructions,
+            );
+            sanitized_message::MessagePayload::V0(V0LoadedMessage {
+                message: Some(V0Message {
+                    header: Some(common.header),
+                    account_keys: common.account_keys,
+                    recent_block_hash: common.recent_block_hash,
+                    instructions: common.instructions,
+                    address_table_lookup: v0
+                        .message
+                        .address_table_lookups
+                        .iter()
+                        .map(|lookup| MessageAddressTableLookup {
+                            account_key: lookup.account_key.as_ref().into(),
+                            writable_indexes: lookup
+                                .writable_indexes
+                                .iter()
+                                .map(|x| *x as u32)
+                                .collect(),
+                            readonly_indexes: lookup
+                                .readonly_indexes
+                                .iter()
+                                .map(|x| *x as u32)
+                                .collect(),
+                        })
+                        .collect(),
+                }),
+                loaded_adresses: Some(LoadedAddresses {
+                    writable: v0
+                        .loaded_addresses
+                        .writable
+                        .iter()
+                        .map(|x| x.as_ref().into())
+                        .collect(),
+                    readonly: v0
+                        .loaded_addresses
+                        .readonly
+                        .iter()
+                        .map(|x| x.as_ref().into())
+                        .collect(),
+                }),
+                is_writable_account_cache,
+            })
+        }
+    };
+    SanitizedMessage {
+        message_payload: Some(message_payload),
+    }
+}
+
+fn build_transaction_token_balance(
+    transaction_token_account_balance: solana_transaction_status::TransactionTokenBalance,
+) -> TransactionTokenBalance {
+    TransactionTokenBalance {
+        account_index: transaction_token_account_balance.account_index as u32,
+        ui_token_account: Some(UiTokenAmount {
+            ui_amount: transaction_token_account_balance.ui_token_amount.ui_amount,
+            decimals: transaction_token_account_balance.ui_token_amount.decimals as u32,
+            amount: transaction_token_account_balance.ui_token_amount.amount,
+            ui_amount_string: transaction_token_account_balance
+                .ui_token_amount
+                .ui_amount_string,
+        }),
+        mint: transaction_token_account_balance.mint,
+        owner: transaction_token_account_balance.owner,
+    }
+}
+
+/// Caps on the lists of a transaction event, unlimited when `None`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionCaps {
+    /// Inner instructions kept across all instructions, in execution order.
+    pub max_inner_instructions: Option<usize>,
+    pub max_log_messages: Option<usize>,
+    /// Bytes of memos kept across all memos, none are extracted when `None`.
+    pub max_memo_bytes: Option<usize>,
+    /// Keep accounts whose lamports didn't change in `balance_changes`.
+    pub include_zero_delta: bool,
+}
+
+/// Memo program, v1 and v2.
+const MEMO_PROGRAM_IDS: [Pubkey; 2] = [
+    pubkey!("Memo1UhkJRfHyvLMcVucJwxXeuD728EqVDDwQDxFMNo"),
+    pubkey!("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr"),
+];
+
+/// Memos of a transaction and whether any was lossy or truncated.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Memos {
+    memos: Vec<String>,
+    lossy: bool,
+    truncated: bool,
+}
+
+/// Data of the Memo program instructions of `transaction` as UTF-8, top level ones each followed
+/// by the inner ones they invoked, up to `max_bytes` in total.
+fn extract_memos(
+    transaction: &SdkSanitizedTransaction,
+    transaction_status_meta: &SdkTransactionStatusMeta,
+    max_bytes: usize,
+) -> Memos {
+    let message = transaction.message();
+    let account_keys = message.account_keys();
+    let inner_instructions = transaction_status_meta
+        .inner_instructions
+        .as_deref()
+        .unwrap_or(&[]);
+    let instructions =
+        message
+            .instructions()
+            .iter()
+            .enumerate()
+            .flat_map(|(index, instruction)| {
+                let inner = inner_instructions
+                    .iter()
+                    .filter(move |inner| inner.index as usize == index)
+                    .flat_map(|inner| inner.instructions.iter().map(|ix| &ix.instruction));
+                iter::once(instruction).chain(inner)
+            });
+    let mut memos = Memos::default();
+    let mut remaining = max_bytes;
+    for instruction in instructions {
+        let is_memo = account_keys
+            .get(instruction.program_id_index as usize)
+            .is_some_and(|program| MEMO_PROGRAM_IDS.contains(program));
+        if !is_memo {
+            continue;
+        }
+        if remaining == 0 {
+            memos.truncated = true;
+            break;
+        }
+        let mut memo = String::from_utf8_lossy(&instruction.data).into_owned();
+        memos.lossy |= memo.as_bytes() != instruction.data;
+        if memo.len() > remaining {
+            let mut end = remaining;
+            while !memo.is_char_boundary(end) {
+                end -= 1;
+            }
+            memo.truncate(end);
+            memos.truncated = true;
+        }
+        remaining -= memo.len();
+        memos.memos.push(memo);
+    }
+    memos
+}
+
+/// Lamports of the accounts of a transaction before and after it, by zipping `pre_balances` and
+/// `post_balances` with `account_keys`. Those left unchanged are skipped unless
+/// `include_zero_delta`. If the lengths differ, which the validator shouldn't let happen, the
+/// extra entries are dropped and the transaction counted in
+/// `transaction_balance_length_mismatches_total` of `metrics`.
+fn build_balance_changes(
+    metrics: &InstanceMetrics,
+    account_keys: AccountKeys,
+    pre_balances: &[u64],
+    post_balances: &[u64],
+    include_zero_delta: bool,
+) -> Vec<BalanceChange> {
+    if pre_balances.len() != post_balances.len() || pre_balances.len() != account_keys.len() {
+        metrics.transaction_balance_length_mismatches_total.inc();
+    }
+    account_keys
+        .iter()
+        .zip(pre_balances.iter().zip(post_balances))
+        .enumerate()
+        .filter(|(_, (_, (pre, post)))| include_zero_delta || pre != post)
+        .map(|(index, (pubkey, (&pre, &post)))| BalanceChange {
+            account_index: index as u32,
+            pubkey: pubkey.as_ref().into(),
+            pre_lamports: pre,
+            post_lamports: post,
+            // Exact, lamports in existence fit an i64.
+            delta: post.wrapping_sub(pre) as i64,
+        })
+        .collect()
+}
+
+/// Bincode serialization of `transaction` as the `VersionedTransaction` it was sent as.
+pub fn serialize_transaction(transaction: &SdkSanitizedTransaction) -> Vec<u8> {
+    bincode::serialize(&transaction.to_versioned_transaction())
+        .expect("serializing a transaction into a Vec can't fail")
+}
+
+/// Transaction event of a sanitized transaction and its status, `index` being its position in the block.
+/// Inner instructions, log messages and memos beyond `caps` are dropped and the event flagged as
+/// truncated. Balances not matching the account keys in length are counted in `metrics`.
+#[allow(clippy::too_many_arguments)]
+pub fn build_transaction_event(
+    metrics: &InstanceMetrics,
+    slot: u64,
+    signature: &Signature,
+    is_vote: bool,
+    transaction: &SdkSanitizedTransaction,
+    transaction_status_meta: &SdkTransactionStatusMeta,
+    index: usize,
+    caps: TransactionCaps,
+) -> TransactionEvent {
+    let log_messages = transaction_status_meta
+        .log_messages
+        .as_deref()
+        .unwrap_or(&[]);
+    let max_log_messages = caps.max_log_messages.unwrap_or(usize::MAX);
+    let inner_instructions = transaction_status_meta
+        .inner_instructions
+        .as_deref()
+        .unwrap_or(&[]);
+    let inner_instruction_count: usize = inner_instructions
+        .iter()
+        .map(|inner| inner.instructions.len())
+        .sum();
+    let mut remaining = caps.max_inner_instructions.unwrap_or(usize::MAX);
+    let memos = caps
+        .max_memo_bytes
+        .map_or_else(Memos::default, |max_bytes| {
+            extract_memos(transaction, transaction_status_meta, max_bytes)
+        });
+    TransactionEvent {
+        is_vote,
+        slot,
+        index: index as u64,
+        signature: signature.as_ref().into(),
+        transaction_status_meta: Some(TransactionStatusMeta {
+            is_status_err: transaction_status_meta.status.is_err(),
+            error_info: match &transaction_status_meta.status {
+                Err(e) => e.to_string(),
+                Ok(_) => "".to_owned(),
+            },
+            rewards: transaction_status_meta
+                .rewards
+                .iter()
+                .flatten()
+                .map(build_reward)
+                .collect(),
+            fee: transaction_status_meta.fee,
+            log_messages: log_messages
+                .iter()
+                .take(max_log_messages)
+                .cloned()
+                .collect(),
+            log_message_count: log_messages.len() as u32,
+            log_messages_truncated: log_messages.len() > max_log_messages,
+            inner_instructions: inner_instructions
+                .iter()
+                .map_while(|inner| {
+                    if remaining == 0 {
+                        return None;
+                    }
+                    let count = inner.instructions.len().min(remaining);
+                    remaining -= count;
+                    Some(InnerInstructions {
+                        index: inner.index as u32,
+                        instructions: inner.instructions[..count]
+                            .iter()
+                            .map(build_inner_instruction)
+                            .collect(),
+                    })
+                })
+                .collect(),
+            inner_instruction_count: inner_instruction_count as u32,
+            inner_instructions_truncated: caps
+                .max_inner_instructions
+                .is_some_and(|max| inner_instruction_count > max),
+            pre_balances: transaction_status_meta.pre_balances.clone(),
+            post_balances: transaction_status_meta.post_balances.clone(),
+            balance_changes: build_balance_changes(
+                metrics,
+                transaction.message().account_keys(),
+                &transaction_status_meta.pre_balances,
+                &transaction_status_meta.post_balances,
+                caps.include_zero_delta,
+            ),
+            pre_token_balances: match &transaction_status_meta.pre_token_balances {
+                Some(v) => v
+                    .clone()
+                    .into_iter()
+                    .map(build_transaction_token_balance)
+                    .collect(),
+                None => vec![],
+            },
+            post_token_balances: match &transaction_status_meta.post_token_balances {
+                Some(v) => v
+                    .clone()
+                    .into_iter()
+                    .map(build_transaction_token_balance)
+                    .collect(),
+                None => vec![],
+            },
+        }),
+        transaction: Some(SanitizedTransaction {
+            message_hash: transaction.message_hash().to_bytes().into(),
+            is_simple_vote_transaction: transaction.is_simple_vote_transaction(),
+            message: Some(build_sanitized_message(transaction.message())),
+            resolved_account_keys: transaction
+                .message()
+                .account_keys()
+                .iter()
+                .map(|key| key.as_ref().into())
+                .collect(),
+            signatures: transaction
+                .signatures()
+                .iter()
+                .copied()
+                .map(|x| x.as_ref().into())
+                .collect(),
+        }),
+        sequence: 0,
+        raw_transaction: vec![],
+        fork_instance_id: 0,
+        memos: memos.memos,
+        memos_lossy: memos.lossy,
+        memos_truncated: memos.truncated,
+    }
+}
+
+/// Index entry of a transaction, `index` being its position in the block.
+pub fn build_transaction_index_event(
+    slot: u64,
+    signature: &Signature,
+    transaction: &SdkSanitizedTransaction,
+    transaction_status_meta: &SdkTransactionStatusMeta,
+    index: usize,
+) -> TransactionIndexEvent {
+    TransactionIndexEvent {
+        slot,
+        signature: signature.as_ref().into(),
+        index: index as u64,
+        is_status_err: transaction_status_meta.status.is_err(),
+        fee_payer: transaction.message().fee_payer().as_ref().into(),
+        matched_program: vec![],
+        sequence: 0,
+    }
+}
+
+/// Whether `error` is a failure to resolve the address lookup tables of a transaction.
+pub fn is_alt_resolution_failure(error: &TransactionError) -> bool {
+    match error {
+        TransactionError::AddressLookupTableNotFound
+        | TransactionError::InvalidAddressLookupTableOwner
+        | TransactionError::InvalidAddressLookupTableData
+        | TransactionError::InvalidAddressLookupTableIndex => true,
+        // Variants added by later validators.
+        error => {
+            format!("{error:?}").contains("AddressLookupTable")
+                || mentions_address_table(&error.to_string())
+        }
+    }
+}
+
+fn mentions_address_table(message: &str) -> bool {
+    let message = message.to_ascii_lowercase();
+    message.contains("address table") || message.contains("address lookup table")
+}
+
+/// Address lookup table resolution failure event of a transaction, if it failed that way.
+pub fn build_alt_failure_event(
+    slot: u64,
+    signature: &Signature,
+    transaction: &SdkSanitizedTransaction,
+    transaction_status_meta: &SdkTransactionStatusMeta,
+) -> Option<AltResolutionFailureEvent> {
+    let error = transaction_status_meta.status.as_ref().err()?;
+    is_alt_resolution_failure(error).then(|| AltResolutionFailureEvent {
+        slot,
+        signature: signature.as_ref().into(),
+        tables: transaction
+            .message()
+            .message_address_table_lookups()
+            .iter()
+            .map(|lookup| lookup.account_key.to_bytes().to_vec())
+            .collect(),
+        error: error.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        prost::Message,
+        solana_program::message::{
+            legacy,
+            v0::{
+                self, LoadedAddresses as SdkLoadedAddresses, LoadedMessage,
+                MessageAddressTableLookup as SdkLookup,
+            },
+            LegacyMessage as SdkLegacyMessage, SimpleAddressLoader, VersionedMessage,
+        },
+        solana_sdk::{bs58, transaction::VersionedTransaction},
+        solana_transaction_status::RewardType,
+    };
+
+    /// Encoding of the block event built by `test_block_event_golden`.
+    const BLOCK_EVENT_GOLDEN: &str = concat!(
+        "0864122b476742614373334e4342755a4e31326b434a67415736337964716f68466b484564666445",
+        "5842507a4c48711a340a2b34764a394a5531624a4a4539364657534a4b7648736d6d464144436734",
+        "67705a516666345033626b4c4b6910882718c0843d1a3c0a2b387162486277324262625448425731",
+        "73626571616b5958564b52514d384e6537704c4b376d36435666655210ecffffffffffffffff0120",
+        "02280a2080e2cfaa0630633a2b4c625569574c33785656386854465942566462544e7270446f3431",
+        "4e4b53366f334c484875447a6a666359400c48045220030303030303030303030303030303030303",
+        "03030303030303030303030303036064",
+    );
+
+    /// Encoding of the account event of `test_account_event_v1_golden` in the first schema.
+    const ACCOUNT_EVENT_V1_GOLDEN: &str = concat!(
+        "087b1220010101010101010101010101010101010101010101010101010101010101010118c0843d",
+        "22200202020202020202020202020202020202020202020202020202020202020202280130e9023a",
+        "03030405402a",
+    );
+
+    #[test]
+    fn test_block_event_golden() {
+        let rewards = [
+            solana_transaction_status::Reward {
+                pubkey: Pubkey::new_from_array([1; 32]).to_string(),
+                lamports: 5_000,
+                post_balance: 1_000_000,
+                reward_type: Some(RewardType::Fee),
+                commission: None,
+            },
+            solana_transaction_status::Reward {
+                pubkey: Pubkey::new_from_array([2; 32]).to_string(),
+                lamports: -20,
+                post_balance: 0,
+                reward_type: Some(RewardType::Staking),
+                commission: Some(10),
+            },
+        ];
+        let (blockhash, parent_blockhash) = (
+            Hash::new_from_array([4; 32]).to_string(),
+            Hash::new_from_array([5; 32]).to_string(),
+        );
+        let info = ReplicaBlockInfoV3 {
+            parent_slot: 99,
+            parent_blockhash: &parent_blockhash,
+            slot: 100,
+            blockhash: &blockhash,
+            rewards: &rewards,
+            block_time: Some(1_700_000_000),
+            block_height: None,
+            executed_transaction_count: 12,
+            entry_count: 4,
+        };
+        let event = build_block_event(
+            &info,
+            Some(Pubkey::new_from_array([3; 32])),
+            &EpochSchedule::without_warmup(),
+        );
+        let encoded: String = event
+            .encode_to_vec()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        assert_eq!(encoded, BLOCK_EVENT_GOLDEN);
+    }
+
+    #[test]
+    fn test_invalid_blockhash() {
+        let valid = Hash::new_from_array([4; 32]).to_string();
+        let short = bs58::encode([4; 31]).into_string();
+        let long = bs58::encode([4; 33]).into_string();
+        let block = |blockhash: &str, parent_blockhash: &str| {
+            let info = ReplicaBlockInfoV3 {
+                parent_slot: 99,
+                parent_blockhash,
+                slot: 100,
+                blockhash,
+                rewards: &[],
+                block_time: None,
+                block_height: None,
+                executed_transaction_count: 0,
+                entry_count: 0,
+            };
+            build_block_event(&info, None, &EpochSchedule::without_warmup())
+        };
+        let event = block(&valid, &valid);
+        assert_eq!(
+            (event.blockhash, event.parent_blockhash),
+            (valid.clone(), valid.clone())
+        );
+        assert!(!event.blockhash_invalid);
+
+        for invalid in [
+            short.as_str(),
+            long.as_str(),
+            "",
+            // Characters outside the base58 alphabet.
+            "0OIl111111111111111111111111111111111111111",
+            &"1".repeat(10_000),
+        ] {
+            let event = block(invalid, &valid);
+            assert_eq!(
+                (event.blockhash.as_str(), event.parent_blockhash.as_str()),
+                ("", valid.as_str())
+            );
+            assert!(event.blockhash_invalid, "{invalid:?}");
+            let event = block(&valid, invalid);
+            assert_eq!(
+                (event.blockhash.as_str(), event.parent_blockhash.as_str()),
+                (valid.as_str(), "")
+            );
+            assert!(event.blockhash_invalid, "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn test_account_event_v1_golden() {
+        let mut event = UpdateAccountEvent {
+            slot: 123,
+            pubkey: vec![1; 32],
+            lamports: 1_000_000,
+            owner: vec![2; 32],
+            executable: true,
+            rent_epoch: 361,
+            data: vec![3, 4, 5],
+            write_version: 42,
+            txn_signature: Some(vec![9; 64]),
+            stake_account: Some(StakeAccount::default()),
+            lookup_table: Some(LookupTable::default()),
+            sequence: 7,
+            fork_instance_id: 5,
+            origin: AccountOrigin::Catchup.into(),
+            highest_rooted_slot: 400,
+            chunk: None,
+            original_data_len: 3,
+            data_sha256: vec![6; 32],
+        };
+        let latest = event.clone();
+        event.project(SchemaCompat::Latest);
+        assert_eq!(event, latest);
+
+        event.project(SchemaCompat::V1);
+        let encoded: String = event
+            .encode_to_vec()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        assert_eq!(encoded, ACCOUNT_EVENT_V1_GOLDEN);
+    }
+
+    #[test]
+    fn test_strip_executable_data() {
+        let program = vec![0x7f, b'E', b'L', b'F', 2, 1, 1];
+        let info = |executable| ReplicaAccountInfoV3 {
+            pubkey: &[1; 32],
+            lamports: 1_000_000,
+            owner: &[2; 32],
+            executable,
+            rent_epoch: 0,
+            data: &program,
+            write_version: 42,
+            txn: None,
+        };
+
+        let event = build_account_event_stripping(9, &info(true), true);
+        assert!(event.data.is_empty());
+        assert_eq!(event.original_data_len, 7);
+        assert_eq!(
+            bs58::encode(&event.data_sha256).into_string(),
+            hash::hashv(&[&[0x7f], b"ELF", &[2, 1, 1]]).to_string()
+        );
+
+        // Left as built unless both executable and stripping.
+        for (executable, strip) in [(true, false), (false, true), (false, false)] {
+            let event = build_account_event_stripping(9, &info(executable), strip);
+            assert_eq!(event, build_account_event(9, &info(executable)));
+            assert_eq!(event.data, program);
+            assert_eq!(event.original_data_len, 0);
+            assert!(event.data_sha256.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_balance_changes() {
+        let keys = keys(3);
+        let metrics = InstanceMetrics::default();
+        let changes = |pre: &[u64], post: &[u64], include_zero_delta| {
+            build_balance_changes(
+                &metrics,
+                AccountKeys::new(&keys, None),
+                pre,
+                post,
+                include_zero_delta,
+            )
+            .into_iter()
+            .map(|change| {
+                assert_eq!(
+                    change.pubkey,
+                    keys[change.account_index as usize].to_bytes()
+                );
+                (
+                    change.account_index,
+                    change.pre_lamports,
+                    change.post_lamports,
+                    change.delta,
+                )
+            })
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            changes(&[10, 5, 7], &[4, 5, 9], false),
+            [(0, 10, 4, -6), (2, 7, 9, 2)]
+        );
+        assert!(changes(&[1, 2, 3], &[1, 2, 3], false).is_empty());
+        assert_eq!(
+            changes(&[1, 2, 3], &[1, 2, 4], true),
+            [(0, 1, 1, 0), (1, 2, 2, 0), (2, 3, 4, 1)]
+        );
+
+        // Cut to the shortest of the balances and keys.
+        assert_eq!(
+            changes(&[1, 2, 3], &[0, 0], false),
+            [(0, 1, 0, -1), (1, 2, 0, -2)]
+        );
+        assert_eq!(
+            changes(&[1, 2, 3, 4], &[0, 0, 0, 0], false),
+            [(0, 1, 0, -1), (1, 2, 0, -2), (2, 3, 0, -3)]
+        );
+        assert_eq!(metrics.transaction_balance_length_mismatches_total.get(), 2);
+
+        let transaction = SdkSanitizedTransaction::from_transaction_for_tests(
+            solana_sdk::transaction::Transaction::new_unsigned(legacy::Message {
+                header: header(1),
+                account_keys: keys.clone(),
+                recent_blockhash: Hash::new_from_array([7; 32]),
+                instructions: vec![],
+            }),
+        );
+        let meta = SdkTransactionStatusMeta {
+            pre_balances: vec![100, 0, 1],
+            post_balances: vec![95, 5, 1],
+            ..Default::default()
+        };
+        let event = build_transaction_event(
+            &metrics,
+            1,
+            &Signature::default(),
+            false,
+            &transaction,
+            &meta,
+            0,
+            TransactionCaps::default(),
+        );
+        let meta = event.transaction_status_meta.unwrap();
+        assert_eq!(meta.balance_changes.len(), 2);
+        assert_eq!(meta.balance_changes[1].pubkey, keys[1].to_bytes());
+        assert_eq!(meta.balance_changes[1].delta, 5);
+    }
+
+    #[test]
+    fn test_slot_status_mapping() {
+        // Without a wildcard, a status added to the interface fails to build here until it is
+        // given its own `SlotStatus` rather than falling back to `Unknown`.
+        let expected = |status| match status {
+            PluginSlotStatus::Processed => SlotStatus::Processed,
+            PluginSlotStatus::Rooted => SlotStatus::Rooted,
+            PluginSlotStatus::Confirmed => SlotStatus::Confirmed,
+        };
+        for status in [
+            PluginSlotStatus::Processed,
+            PluginSlotStatus::Rooted,
+            PluginSlotStatus::Confirmed,
+        ] {
+            let mapped = SlotStatus::from(status);
+            assert_eq!(mapped, expected(status));
+            assert_eq!(mapped.as_str_name().to_lowercase(), status.as_str());
+        }
+        assert_eq!(
+            SlotStatus::from_str_name("Unknown"),
+            Some(SlotStatus::Unknown)
+        );
+    }
+
+    #[test]
+    fn test_account_origin() {
+        // Startup wins over the slot's age.
+        assert_eq!(account_origin(true, 10, 1_000, 150), AccountOrigin::Startup);
+        assert_eq!(
+            account_origin(true, 999, 1_000, 150),
+            AccountOrigin::Startup
+        );
+        assert_eq!(
+            account_origin(false, 849, 1_000, 150),
+            AccountOrigin::Catchup
+        );
+        assert_eq!(account_origin(false, 850, 1_000, 150), AccountOrigin::Live);
+        assert_eq!(
+            account_origin(false, 1_001, 1_000, 150),
+            AccountOrigin::Live
+        );
+        // Nothing is catch-up before the first rooted slot, or close to genesis.
+        assert_eq!(account_origin(false, 0, 0, 150), AccountOrigin::Live);
+        assert_eq!(account_origin(false, 0, 100, 150), AccountOrigin::Live);
+        assert_eq!(account_origin(false, 0, 100, 0), AccountOrigin::Catchup);
+    }
+
+    fn header(readonly_unsigned: u8) -> SdkMessageHeader {
+        SdkMessageHeader {
+            num_required_signatures: 1,
+            num_readonly_signed_accounts: 0,
+            num_readonly_unsigned_accounts: readonly_unsigned,
+        }
+    }
+
+    fn keys(count: u8) -> Vec<Pubkey> {
+        (1..=count)
+            .map(|i| Pubkey::new_from_array([i; 32]))
+            .collect()
+    }
+
+    #[test]
+    fn test_build_message_common() {
+        let common = build_message_common(
+            &header(1),
+            &keys(2),
+            &Hash::new_from_array([7; 32]),
+            &[SdkCompiledInstruction::new_from_raw_parts(
+                1,
+                vec![9],
+                vec![0],
+            )],
+        );
+        assert_eq!(
+            common,
+            MessageCommon {
+                header: MessageHeader {
+                    num_required_signatures: 1,
+                    num_readonly_signed_accounts: 0,
+                    num_readonly_unsigned_accounts: 1,
+                },
+                account_keys: vec![vec![1; 32], vec![2; 32]],
+                recent_block_hash: vec![7; 32],
+                instructions: vec![CompiledInstruction {
+                    program_id_index: 1,
+                    accounts: vec![0],
+                    data: vec![9],
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn test_build_sanitized_message() {
+        let instructions = vec![SdkCompiledInstruction::new_from_raw_parts(
+            2,
+            vec![],
+            vec![0, 1],
+        )];
+        let legacy = SdkSanitizedMessage::Legacy(SdkLegacyMessage::new(legacy::Message {
+            header: header(1),
+            account_keys: keys(3),
+            recent_blockhash: Hash::new_from_array([7; 32]),
+            instructions: instructions.clone(),
+        }));
+        let v0 = SdkSanitizedMessage::V0(LoadedMessage::new(
+            v0::Message {
+                header: header(1),
+                account_keys: keys(3),
+                recent_blockhash: Hash::new_from_array([7; 32]),
+                instructions,
+                address_table_lookups: vec![SdkLookup {
+                    account_key: Pubkey::new_from_array([8; 32]),
+                    writable_indexes: vec![0],
+                    readonly_indexes: vec![1],
+                }],
+            },
+            SdkLoadedAddresses {
+                writable: vec![Pubkey::new_from_array([4; 32])],
+                readonly: vec![Pubkey::new_from_array([5; 32])],
+            },
+        ));
+
+        // The trailing readonly unsigned program account is included.
+        assert_eq!(build_writable_account_cache(&legacy), [true, true, false]);
+        assert_eq!(
+            build_writable_account_cache(&v0),
+            [true, true, false, true, false]
+        );
+
+        let legacy = match build_sanitized_message(&legacy).message_payload.unwrap() {
+            sanitized_message::MessagePayload::Legacy(legacy) => legacy.message.unwrap(),
+            _ => panic!("expected a legacy message"),
+        };
+        let v0 = match build_sanitized_message(&v0).message_payload.unwrap() {
+            sanitized_message::MessagePayload::V0(v0) => {
+                assert_eq!(
+                    v0.loaded_adresses.unwrap(),
+                    LoadedAddresses {
+                        writable: vec![vec![4; 32]],
+                        readonly: vec![vec![5; 32]],
+                    }
+                );
+                v0.message.unwrap()
+            }
+            _ => panic!("expected a V0 message"),
+        };
+        assert_eq!(legacy.header, v0.header);
+        assert_eq!(legacy.account_keys, v0.account_keys);
+        assert_eq!(legacy.recent_block_hash, v0.recent_block_hash);
+        assert_eq!(legacy.instructions, v0.instructions);
+        assert_eq!(v0.address_table_lookup[0].readonly_indexes, [1]);
+    }
+
+    #[test]
+    fn test_resolved_account_keys() {
+        let message = v0::Message {
+            header: header(1),
+            account_keys: keys(3),
+            recent_blockhash: Hash::new_from_array([7; 32]),
+            instructions: vec![SdkCompiledInstruction::new_from_raw_parts(
+                2,
+                vec![],
+                vec![0, 3],
+            )],
+            address_table_lookups: vec![SdkLookup {
+                account_key: Pubkey::new_from_array([8; 32]),
+                writable_indexes: vec![0],
+                readonly_indexes: vec![1],
+            }],
+        };
+        let transaction = SdkSanitizedTransaction::try_create(
+            VersionedTransaction {
+                signatures: vec![Signature::default()],
+                message: VersionedMessage::V0(message),
+            },
+            Hash::default(),
+            Some(false),
+            SimpleAddressLoader::Enabled(SdkLoadedAddresses {
+                writable: vec![Pubkey::new_from_array([4; 32])],
+                readonly: vec![Pubkey::new_from_array([5; 32])],
+            }),
+        )
+        .unwrap();
+        let meta = SdkTransactionStatusMeta {
+            inner_instructions: Some(vec![solana_transaction_status::InnerInstructions {
+                index: 0,
+                instructions: vec![solana_transaction_status::InnerInstruction {
+                    instruction: SdkCompiledInstruction::new_from_raw_parts(2, vec![], vec![4, 1]),
+                    stack_height: Some(2),
+                }],
+            }]),
+            ..Default::default()
+        };
+        let event = build_transaction_event(
+            &InstanceMetrics::default(),
+            1,
+            &Signature::default(),
+            false,
+            &transaction,
+            &meta,
+            0,
+            TransactionCaps::default(),
+        );
+        let resolved = event.transaction.unwrap().resolved_account_keys;
+        assert_eq!(resolved.len(), 5);
+        let inner = &event.transaction_status_meta.unwrap().inner_instructions[0].instructions[0];
+        let accounts = &inner.instruction.as_ref().unwrap().accounts;
+        // The loaded readonly address follows the loaded writable one.
+        assert_eq!(resolved[accounts[0] as usize], [5; 32]);
+        assert_eq!(resolved[accounts[1] as usize], resolved[1]);
+        assert_eq!(resolved[3], [4; 32]);
+
+        let legacy = SdkSanitizedTransaction::from_transaction_for_tests(
+            solana_sdk::transaction::Transaction::new_unsigned(legacy::Message {
+                header: header(1),
+                account_keys: keys(3),
+                recent_blockhash: Hash::new_from_array([7; 32]),
+                instructions: vec![],
+            }),
+        );
+        let event = build_transaction_event(
+            &InstanceMetrics::default(),
+            1,
+            &Signature::default(),
+            false,
+            &legacy,
+            &meta,
+            0,
+            TransactionCaps::default(),
+        );
+        assert_eq!(
+            event.transaction.unwrap().resolved_account_keys,
+            keys(3)
+                .iter()
+                .map(|key| key.to_bytes().to_vec())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_memos() {
+        // The first instruction invokes the memo program, the second is a v2 memo.
+        let memo = |program: Pubkey, first: &[u8], second: &[u8]| {
+            let message = legacy::Message {
+                header: header(1),
+                account_keys: vec![
+                    Pubkey::new_from_array([1; 32]),
+                    MEMO_PROGRAM_IDS[1],
+                    program,
+                ],
+                recent_blockhash: Hash::new_from_array([7; 32]),
+                instructions: vec![
+                    SdkCompiledInstruction::new_from_raw_parts(2, first.to_vec(), vec![]),
+                    SdkCompiledInstruction::new_from_raw_parts(1, second.to_vec(), vec![]),
+                ],
+            };
+            SdkSanitizedTransaction::from_transaction_for_tests(
+                solana_sdk::transaction::Transaction::new_unsigned(message),
+            )
+        };
+        let meta = SdkTransactionStatusMeta {
+            inner_instructions: Some(vec![solana_transaction_status::InnerInstructions {
+                index: 0,
+                instructions: vec![solana_transaction_status::InnerInstruction {
+                    instruction: SdkCompiledInstruction::new_from_raw_parts(
+                        1,
+                        b"cpi".to_vec(),
+                        vec![],
+                    ),
+                    stack_height: Some(2),
+                }],
+            }]),
+            ..Default::default()
+        };
+        let build = |transaction: &SdkSanitizedTransaction, max_memo_bytes| {
+            let caps = TransactionCaps {
+                max_memo_bytes,
+                ..Default::default()
+            };
+            let event = build_transaction_event(
+                &InstanceMetrics::default(),
+                1,
+                &Signature::default(),
+                false,
+                transaction,
+                &meta,
+                0,
+                caps,
+            );
+            (event.memos, event.memos_lossy, event.memos_truncated)
+        };
+        let memos = |memos: &[&str]| {
+            memos
+                .iter()
+                .map(|memo| memo.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // A top level memo and one invoked by a program.
+        let program = Pubkey::new_from_array([9; 32]);
+        let transaction = memo(program, b"swap", b"invoice 42");
+        assert_eq!(
+            build(&transaction, Some(1024)),
+            (memos(&["cpi", "invoice 42"]), false, false)
+        );
+        assert_eq!(build(&transaction, None), (vec![], false, false));
+        assert_eq!(
+            build(&transaction, Some(10)),
+            (memos(&["cpi", "invoice"]), false, true)
+        );
+        assert_eq!(build(&transaction, Some(3)), (memos(&["cpi"]), false, true));
+
+        // v1 memos, in execution order.
+        let transaction = memo(MEMO_PROGRAM_IDS[0], b"first", b"last");
+        assert_eq!(
+            build(&transaction, Some(1024)),
+            (memos(&["first", "cpi", "last"]), false, false)
+        );
+
+        // Invalid UTF-8 is replaced.
+        let transaction = memo(program, b"", b"caf\xc3\xa9 \xff");
+        assert_eq!(
+            build(&transaction, Some(1024)),
+            (memos(&["cpi", "caf\u{e9} \u{fffd}"]), true, false)
+        );
+        // Cut on a character boundary.
+        assert_eq!(
+            build(&transaction, Some(7)),
+            (memos(&["cpi", "caf"]), true, true)
+        );
+    }
+
+    #[test]
+    fn test_alt_resolution_failures() {
+        // Current formats, fallback matching relies on them.
+        for (error, message) in [
+            (
+                TransactionError::AddressLookupTableNotFound,
+                "Transaction loads an address table account that doesn't exist",
+            ),
+            (
+                TransactionError::InvalidAddressLookupTableOwner,
+                "Transaction loads an address table account with an invalid owner",
+            ),
+            (
+                TransactionError::InvalidAddressLookupTableData,
+                "Transaction loads an address table account with invalid data",
+            ),
+            (
+                TransactionError::InvalidAddressLookupTableIndex,
+                "Transaction address table lookup uses an invalid index",
+            ),
+        ] {
+            assert!(is_alt_resolution_failure(&error));
+            assert_eq!(error.to_string(), message);
+            assert!(mentions_address_table(message));
+        }
+        assert!(mentions_address_table(
+            "Transaction loads an Address Lookup Table that was deactivated"
+        ));
+        assert!(!is_alt_resolution_failure(
+            &TransactionError::AccountNotFound
+        ));
+        assert!(!is_alt_resolution_failure(
+            &TransactionError::InvalidAccountIndex
+        ));
+
+        let message = v0::Message {
+            header: header(1),
+            account_keys: keys(2),
+            recent_blockhash: Hash::new_from_array([7; 32]),
+            instructions: vec![],
+            address_table_lookups: vec![SdkLookup {
+                account_key: Pubkey::new_from_array([8; 32]),
+                writable_indexes: vec![0],
+                readonly_indexes: vec![],
+            }],
+        };
+        let transaction = SdkSanitizedTransaction::try_create(
+            VersionedTransaction {
+                signatures: vec![Signature::from([3; 64])],
+                message: VersionedMessage::V0(message),
+            },
+            Hash::default(),
+            Some(false),
+            SimpleAddressLoader::Enabled(SdkLoadedAddresses {
+                writable: vec![Pubkey::new_from_array([4; 32])],
+                readonly: vec![],
+            }),
+        )
+        .unwrap();
+        let meta = |status| SdkTransactionStatusMeta {
+            status,
+            ..Default::default()
+        };
+        let event = build_alt_failure_event(
+            9,
+            transaction.signature(),
+            &transaction,
+            &meta(Err(TransactionError::InvalidAddressLookupTableIndex)),
+        )
+        .unwrap();
+        assert_eq!(event.slot, 9);
+        assert_eq!(event.signature, [3; 64]);
+        assert_eq!(event.tables, [[8; 32]]);
+        assert_eq!(
+            event.error,
+            "Transaction address table lookup uses an invalid index"
+        );
+        let failure = |status| {
+            build_alt_failure_event(9, transaction.signature(), &transaction, &meta(status))
+        };
+        assert_eq!(failure(Ok(())), None);
+        assert_eq!(failure(Err(TransactionError::AccountInUse)), None);
+    }
+
+    #[test]
+    fn test_file_descriptor_set() {
+        let pool = prost_reflect::DescriptorPool::decode(file_descriptor_set()).unwrap();
+        for name in [
+            "MessageWrapper",
+            "UpdateAccountEvent",
+            "SlotStatusEvent",
+            "TransactionEvent",
+            "BlockEvent",
+            "CheckpointEvent",
+            "ProgramActivityEvent",
+        ] {
+            let name = format!("blockdaemon.solana.accountsdb_plugin_kafka.types.{name}");
+            assert!(pool.get_message_by_name(&name).is_some(), "{name}");
         }
+        let account = pool
+            .get_message_by_name(
+                "blockdaemon.solana.accountsdb_plugin_kafka.types.UpdateAccountEvent",
+            )
+            .unwrap();
+        assert_eq!(account.get_field_by_name("sequence").unwrap().number(), 12);
     }
 }
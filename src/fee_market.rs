@@ -0,0 +1,232 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::instance_metrics::InstanceMetrics,
+    solana_program::message::SanitizedMessage,
+    solana_sdk::compute_budget,
+    std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+    },
+};
+
+/// Tag of `ComputeBudgetInstruction::SetComputeUnitPrice`, followed by a little endian u64.
+const SET_COMPUTE_UNIT_PRICE: u8 = 3;
+
+/// Quantiles exported in `transaction_priority_fee_microlamports`.
+const QUANTILES: [(f64, &str); 2] = [(0.5, "0.5"), (0.9, "0.9")];
+
+/// Price buckets, four per power of two, so quantiles are within 25% of the actual price.
+const BUCKETS: usize = 252;
+
+/// Compute unit price set by `message` in micro-lamports, 0 if it sets none.
+pub fn compute_unit_price(message: &SanitizedMessage) -> u64 {
+    message
+        .program_instructions_iter()
+        .filter(|(program_id, _)| compute_budget::check_id(program_id))
+        .find_map(|(_, instruction)| match instruction.data.split_first() {
+            Some((&SET_COMPUTE_UNIT_PRICE, price)) => price.try_into().ok(),
+            _ => None,
+        })
+        .map_or(0, u64::from_le_bytes)
+}
+
+fn bucket(price: u64) -> usize {
+    if price < 4 {
+        return price as usize;
+    }
+    let exponent = 63 - price.leading_zeros();
+    let sub_bucket = (price >> (exponent - 2)) & 3;
+    (4 * (exponent - 1) + sub_bucket as u32) as usize
+}
+
+/// Lowest price of `bucket`.
+fn bucket_floor(bucket: usize) -> u64 {
+    if bucket < 4 {
+        return bucket as u64;
+    }
+    let exponent = bucket / 4 + 1;
+    (4 + bucket as u64 % 4) << (exponent - 2)
+}
+
+/// Price histograms of a slot, by whether transactions matched a filter.
+struct SlotFees {
+    slot: u64,
+    counts: Box<[[u32; BUCKETS]; 2]>,
+}
+
+/// Compute unit prices of the last `window` slots, exported as quantile gauges.
+pub struct FeeMarket {
+    window: usize,
+    slots: Mutex<VecDeque<SlotFees>>,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl FeeMarket {
+    pub fn new(window: usize, metrics: Arc<InstanceMetrics>) -> Self {
+        Self {
+            window,
+            slots: Mutex::default(),
+            metrics,
+        }
+    }
+
+    /// Record a non-vote transaction of `slot` with its compute unit `price` and consumed
+    /// `units`, `matched` if a filter wanted it.
+    pub fn record(&self, slot: u64, matched: bool, price: u64, units: Option<u64>) {
+        let label = if matched { "true" } else { "false" };
+        self.metrics
+            .transaction_compute_unit_price
+            .with_label_values(&[label])
+            .observe(price as f64);
+        if let Some(units) = units {
+            self.metrics
+                .transaction_compute_units_consumed
+                .with_label_values(&[label])
+                .observe(units as f64);
+        }
+        if self.window == 0 {
+            return;
+        }
+
+        let mut slots = self.slots.lock().unwrap();
+        let before = slots.iter().rposition(|fees| fees.slot <= slot);
+        let index = match before {
+            Some(index) if slots[index].slot == slot => index,
+            // Older than every slot of a full window.
+            None if slots.len() >= self.window => return,
+            _ => {
+                let mut index = before.map_or(0, |index| index + 1);
+                slots.insert(
+                    index,
+                    SlotFees {
+                        slot,
+                        counts: Box::new([[0; BUCKETS]; 2]),
+                    },
+                );
+                if slots.len() > self.window {
+                    slots.pop_front();
+                    index -= 1;
+                }
+                // Slots are mostly complete by the time a later one starts.
+                self.refresh_gauges(&slots);
+                index
+            }
+        };
+        let count = &mut slots[index].counts[matched as usize][bucket(price)];
+        *count = count.saturating_add(1);
+    }
+
+    /// Compute unit price at `quantile` of the transactions in the window, rounded down to a
+    /// quarter of its power of two.
+    #[cfg(test)]
+    pub fn quantile(&self, matched: bool, quantile: f64) -> Option<u64> {
+        Self::slots_quantile(&self.slots.lock().unwrap(), matched, quantile)
+    }
+
+    fn slots_quantile(slots: &VecDeque<SlotFees>, matched: bool, quantile: f64) -> Option<u64> {
+        let mut merged = [0u64; BUCKETS];
+        for fees in slots {
+            for (total, count) in merged.iter_mut().zip(&fees.counts[matched as usize]) {
+                *total += u64::from(*count);
+            }
+        }
+        let total: u64 = merged.iter().sum();
+        let rank = ((quantile * total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        merged
+            .iter()
+            .position(|count| {
+                seen += count;
+                seen >= rank
+            })
+            .map(bucket_floor)
+    }
+
+    fn refresh_gauges(&self, slots: &VecDeque<SlotFees>) {
+        for (matched, label) in [(false, "false"), (true, "true")] {
+            for (quantile, quantile_label) in QUANTILES {
+                let price = Self::slots_quantile(slots, matched, quantile).unwrap_or(0);
+                self.metrics
+                    .transaction_priority_fee
+                    .with_label_values(&[label, quantile_label])
+                    .set(price.min(i64::MAX as u64) as i64);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        solana_sdk::{
+            compute_budget::ComputeBudgetInstruction,
+            pubkey::Pubkey,
+            transaction::{SanitizedTransaction, Transaction},
+        },
+    };
+
+    #[test]
+    fn test_buckets() {
+        for price in [0, 1, 3, 4, 7, 8, 10, 1_000, 123_456, u64::MAX] {
+            let floor = bucket_floor(bucket(price));
+            assert!(
+                floor <= price && price - floor <= price / 4,
+                "{price} {floor}"
+            );
+        }
+        assert_eq!(bucket(u64::MAX), BUCKETS - 1);
+        assert_eq!(bucket_floor(bucket(1 << 20)), 1 << 20);
+    }
+
+    #[test]
+    fn test_compute_unit_price() {
+        let price = |instructions: &[_]| {
+            let transaction = SanitizedTransaction::from_transaction_for_tests(
+                Transaction::new_with_payer(instructions, Some(&Pubkey::new_unique())),
+            );
+            compute_unit_price(transaction.message())
+        };
+        assert_eq!(price(&[]), 0);
+        assert_eq!(
+            price(&[
+                ComputeBudgetInstruction::set_compute_unit_limit(200_000),
+                ComputeBudgetInstruction::set_compute_unit_price(12_345),
+            ]),
+            12_345
+        );
+    }
+
+    #[test]
+    fn test_quantiles() {
+        let market = FeeMarket::new(2, Arc::default());
+        assert_eq!(market.quantile(true, 0.5), None);
+        for price in 1..=10 {
+            market.record(10, true, price * 1_000, Some(100_000));
+        }
+        market.record(10, false, 0, None);
+        assert_eq!(market.quantile(true, 0.5), Some(4_096));
+        assert_eq!(market.quantile(true, 0.9), Some(8_192));
+        assert_eq!(market.quantile(false, 0.9), Some(0));
+
+        // Slot 11 fills the window, then slot 12 evicts slot 10 and late slot 9 is ignored.
+        market.record(11, true, 64, None);
+        market.record(12, true, 64, None);
+        market.record(9, true, 1_000_000, None);
+        assert_eq!(market.quantile(true, 0.9), Some(64));
+        assert_eq!(market.quantile(false, 0.5), None);
+    }
+}
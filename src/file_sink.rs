@@ -0,0 +1,456 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Messages written to local files instead of Kafka, and their reader.
+//!
+//! A sink file starts with `FILE_SINK_MAGIC`, a little endian `u32` format version and a
+//! compression byte, 0 for none and 1 for zstd, which applies to everything after it. Then
+//! follow frames, each a little endian `u32` length and the frame: the topic and the key,
+//! each a `u32` length and its bytes, the `i32` partition or -1 if unassigned, and the payload,
+//! a `u32` length and its bytes, or a length of `u32::MAX` for tombstones. Since version 2,
+//! frames end with a `u32` count of headers, each a name and a value as `u32` length and bytes.
+
+use {
+    crate::{wall_clock::unix_secs, FileSinkCompression, RecordedMessage},
+    log::info,
+    std::{
+        fs::{self, File},
+        io::{BufWriter, Error as IoError, ErrorKind, Read, Result as IoResult, Write},
+        path::{Path, PathBuf},
+        sync::Mutex,
+    },
+};
+
+pub const FILE_SINK_MAGIC: &[u8; 8] = b"KPLUGEVT";
+pub const FILE_SINK_VERSION: u32 = 2;
+
+/// Largest frame accepted by the reader, guarding against corrupt lengths.
+const MAX_FRAME_LEN: usize = 256 << 20;
+
+/// Partition of frames without an explicitly assigned one.
+const UNASSIGNED_PARTITION: i32 = -1;
+
+/// Payload length of tombstones.
+const TOMBSTONE_LEN: u32 = u32::MAX;
+
+const ZSTD_LEVEL: i32 = 3;
+
+fn encode_frame(
+    topic: &str,
+    key: &[u8],
+    payload: Option<&[u8]>,
+    partition: Option<i32>,
+    headers: &[(String, Vec<u8>)],
+    buf: &mut Vec<u8>,
+) {
+    buf.clear();
+    buf.extend_from_slice(&[0; 4]);
+    for field in [topic.as_bytes(), key] {
+        buf.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        buf.extend_from_slice(field);
+    }
+    buf.extend_from_slice(&partition.unwrap_or(UNASSIGNED_PARTITION).to_le_bytes());
+    match payload {
+        Some(payload) => {
+            buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            buf.extend_from_slice(payload);
+        }
+        None => buf.extend_from_slice(&TOMBSTONE_LEN.to_le_bytes()),
+    }
+    buf.extend_from_slice(&(headers.len() as u32).to_le_bytes());
+    for (name, value) in headers {
+        for field in [name.as_bytes(), value] {
+            buf.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            buf.extend_from_slice(field);
+        }
+    }
+    let len = (buf.len() - 4) as u32;
+    buf[..4].copy_from_slice(&len.to_le_bytes());
+}
+
+fn decode_frame(frame: &[u8], version: u32) -> IoResult<RecordedMessage> {
+    fn invalid() -> IoError {
+        IoError::new(ErrorKind::InvalidData, "truncated frame")
+    }
+    fn take<'a>(frame: &mut &'a [u8], len: usize) -> IoResult<&'a [u8]> {
+        if frame.len() < len {
+            return Err(invalid());
+        }
+        let (head, rest) = frame.split_at(len);
+        *frame = rest;
+        Ok(head)
+    }
+    fn take_u32(frame: &mut &[u8]) -> IoResult<u32> {
+        Ok(u32::from_le_bytes(take(frame, 4)?.try_into().unwrap()))
+    }
+
+    let mut frame = frame;
+    let len = take_u32(&mut frame)? as usize;
+    let topic = String::from_utf8(take(&mut frame, len)?.to_vec())
+        .map_err(|error| IoError::new(ErrorKind::InvalidData, error))?;
+    let len = take_u32(&mut frame)? as usize;
+    let key = take(&mut frame, len)?.to_vec();
+    let partition = i32::from_le_bytes(take(&mut frame, 4)?.try_into().unwrap());
+    let payload = match take_u32(&mut frame)? {
+        TOMBSTONE_LEN => None,
+        len => Some(take(&mut frame, len as usize)?.to_vec()),
+    };
+    let mut headers = Vec::new();
+    if version >= 2 {
+        for _ in 0..take_u32(&mut frame)? {
+            let len = take_u32(&mut frame)? as usize;
+            let name = String::from_utf8(take(&mut frame, len)?.to_vec())
+                .map_err(|error| IoError::new(ErrorKind::InvalidData, error))?;
+            let len = take_u32(&mut frame)? as usize;
+            headers.push((name, take(&mut frame, len)?.to_vec()));
+        }
+    }
+    if !frame.is_empty() {
+        return Err(IoError::new(ErrorKind::InvalidData, "trailing frame bytes"));
+    }
+    Ok(RecordedMessage {
+        topic,
+        key,
+        payload,
+        partition: (partition != UNASSIGNED_PARTITION).then_some(partition),
+        headers,
+    })
+}
+
+enum FrameWriter {
+    Plain(BufWriter<File>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl FrameWriter {
+    fn create(path: &Path, compression: FileSinkCompression) -> IoResult<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(FILE_SINK_MAGIC)?;
+        file.write_all(&FILE_SINK_VERSION.to_le_bytes())?;
+        Ok(match compression {
+            FileSinkCompression::None => {
+                file.write_all(&[0])?;
+                Self::Plain(file)
+            }
+            FileSinkCompression::Zstd => {
+                file.write_all(&[1])?;
+                Self::Zstd(zstd::Encoder::new(file, ZSTD_LEVEL)?)
+            }
+        })
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> IoResult<()> {
+        match self {
+            Self::Plain(file) => file.write_all(buf),
+            Self::Zstd(encoder) => encoder.write_all(buf),
+        }
+    }
+
+    /// Complete the file, ending the zstd stream.
+    fn finish(self) -> IoResult<()> {
+        match self {
+            Self::Plain(mut file) => file.flush(),
+            Self::Zstd(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+struct OpenFile {
+    writer: FrameWriter,
+    written: u64,
+}
+
+struct FileSinkState {
+    file: Option<OpenFile>,
+    sequence: u64,
+    buf: Vec<u8>,
+}
+
+/// Writes messages to size rotated files in a directory, in the order they are sent.
+pub struct FileSink {
+    dir: PathBuf,
+    prefix: String,
+    max_file_bytes: u64,
+    compression: FileSinkCompression,
+    state: Mutex<FileSinkState>,
+}
+
+impl FileSink {
+    pub fn create(
+        dir: &Path,
+        max_file_bytes: u64,
+        compression: FileSinkCompression,
+    ) -> IoResult<Self> {
+        fs::create_dir_all(dir)?;
+        let started = unix_secs();
+        info!("Writing events to files in {dir:?}");
+        Ok(Self {
+            dir: dir.to_owned(),
+            prefix: format!("events-{started}-{}", std::process::id()),
+            max_file_bytes,
+            compression,
+            state: Mutex::new(FileSinkState {
+                file: None,
+                sequence: 0,
+                buf: Vec::new(),
+            }),
+        })
+    }
+
+    /// Append a message, starting a new file once the current one holds `max_file_bytes` of
+    /// frames before compression.
+    pub fn write(
+        &self,
+        topic: &str,
+        key: &[u8],
+        payload: Option<&[u8]>,
+        partition: Option<i32>,
+        headers: &[(String, Vec<u8>)],
+    ) -> IoResult<()> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        if state
+            .file
+            .as_ref()
+            .is_some_and(|file| file.written >= self.max_file_bytes)
+        {
+            state.file.take().unwrap().writer.finish()?;
+        }
+        if state.file.is_none() {
+            let extension = match self.compression {
+                FileSinkCompression::None => "frames",
+                FileSinkCompression::Zstd => "frames.zst",
+            };
+            let path = self
+                .dir
+                .join(format!("{}-{:06}.{extension}", self.prefix, state.sequence));
+            state.sequence += 1;
+            state.file = Some(OpenFile {
+                writer: FrameWriter::create(&path, self.compression)?,
+                written: 0,
+            });
+        }
+        let file = state.file.as_mut().unwrap();
+        encode_frame(topic, key, payload, partition, headers, &mut state.buf);
+        file.writer.write_all(&state.buf)?;
+        file.written += state.buf.len() as u64;
+        Ok(())
+    }
+
+    /// Complete the current file, the next write starts a new one.
+    pub fn flush(&self) -> IoResult<()> {
+        match self.state.lock().unwrap().file.take() {
+            Some(file) => file.writer.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reads the messages of a sink file.
+pub struct FileSinkReader {
+    reader: Box<dyn Read>,
+    version: u32,
+}
+
+impl FileSinkReader {
+    pub fn new<R: Read + 'static>(mut reader: R) -> IoResult<Self> {
+        let mut header = [0u8; 13];
+        reader.read_exact(&mut header)?;
+        if &header[..8] != FILE_SINK_MAGIC {
+            return Err(IoError::new(ErrorKind::InvalidData, "not a sink file"));
+        }
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if !(1..=FILE_SINK_VERSION).contains(&version) {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                format!("unsupported sink file version {version}"),
+            ));
+        }
+        let reader: Box<dyn Read> = match header[12] {
+            0 => Box::new(reader),
+            1 => Box::new(zstd::Decoder::new(reader)?),
+            compression => {
+                return Err(IoError::new(
+                    ErrorKind::InvalidData,
+                    format!("unknown sink file compression {compression}"),
+                ))
+            }
+        };
+        Ok(Self { reader, version })
+    }
+
+    pub fn open(path: &Path) -> IoResult<Self> {
+        Self::new(File::open(path)?)
+    }
+
+    /// Read the next message, `None` at the end of the file.
+    pub fn read(&mut self) -> IoResult<Option<RecordedMessage>> {
+        let mut len = [0u8; 4];
+        match self.reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error),
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                format!("frame of {len} bytes"),
+            ));
+        }
+        let mut frame = vec![0u8; len];
+        self.reader.read_exact(&mut frame)?;
+        decode_frame(&frame, self.version).map(Some)
+    }
+}
+
+impl Iterator for FileSinkReader {
+    type Item = IoResult<RecordedMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages() -> Vec<RecordedMessage> {
+        (0..20u8)
+            .map(|i| RecordedMessage {
+                topic: format!("topic-{}", i % 3),
+                key: vec![i; 32],
+                payload: (i % 5 != 0).then(|| vec![i; 100]),
+                partition: (i % 2 == 0).then_some(i32::from(i)),
+                headers: if i % 4 == 0 {
+                    vec![("header".to_owned(), vec![i; 8])]
+                } else {
+                    Vec::new()
+                },
+            })
+            .collect()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kafka-plugin-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// Messages of all files in `dir`, in file order.
+    fn read_dir(dir: &Path) -> (Vec<PathBuf>, Vec<RecordedMessage>) {
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+        let messages = paths
+            .iter()
+            .flat_map(|path| FileSinkReader::open(path).unwrap())
+            .collect::<IoResult<_>>()
+            .unwrap();
+        (paths, messages)
+    }
+
+    fn write_all(sink: &FileSink, messages: &[RecordedMessage]) {
+        for message in messages {
+            sink.write(
+                &message.topic,
+                &message.key,
+                message.payload.as_deref(),
+                message.partition,
+                &message.headers,
+            )
+            .unwrap();
+        }
+        sink.flush().unwrap();
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = temp_dir("sink-round-trip");
+        let sink = FileSink::create(&dir, u64::MAX, FileSinkCompression::None).unwrap();
+        write_all(&sink, &messages());
+        let (paths, read) = read_dir(&dir);
+        assert_eq!(paths.len(), 1);
+        assert_eq!(read, messages());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = temp_dir("sink-rotation");
+        // Frames of payload messages are up to 185 bytes, files end with the frame reaching 500.
+        let sink = FileSink::create(&dir, 500, FileSinkCompression::None).unwrap();
+        write_all(&sink, &messages());
+        let (paths, read) = read_dir(&dir);
+        assert_eq!(read, messages());
+        assert!(paths.len() >= 4, "{paths:?}");
+        for path in &paths {
+            assert!(fs::metadata(path).unwrap().len() < 13 + 500 + 185);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_compression() {
+        let plain_dir = temp_dir("sink-plain");
+        let zstd_dir = temp_dir("sink-zstd");
+        let plain = FileSink::create(&plain_dir, u64::MAX, FileSinkCompression::None).unwrap();
+        let zstd = FileSink::create(&zstd_dir, u64::MAX, FileSinkCompression::Zstd).unwrap();
+        write_all(&plain, &messages());
+        write_all(&zstd, &messages());
+
+        let (plain_paths, _) = read_dir(&plain_dir);
+        let (zstd_paths, read) = read_dir(&zstd_dir);
+        assert_eq!(read, messages());
+        assert!(zstd_paths[0].to_str().unwrap().ends_with(".frames.zst"));
+        let size = |path: &PathBuf| fs::metadata(path).unwrap().len();
+        assert!(size(&zstd_paths[0]) * 4 < size(&plain_paths[0]));
+        fs::remove_dir_all(plain_dir).unwrap();
+        fs::remove_dir_all(zstd_dir).unwrap();
+    }
+
+    #[test]
+    fn test_reader_errors() {
+        assert!(FileSinkReader::new(&b"KPLUGCAP\x01\0\0\0\0"[..]).is_err());
+        assert!(FileSinkReader::new(&b"KPLUGEVT\x01\0\0\0\x07"[..]).is_err());
+        // A frame cut short.
+        let mut file = b"KPLUGEVT\x01\0\0\0\0".to_vec();
+        let mut buf = Vec::new();
+        encode_frame("topic", b"key", Some(b"payload"), None, &[], &mut buf);
+        file.extend_from_slice(&buf[..buf.len() - 1]);
+        let mut reader = FileSinkReader::new(std::io::Cursor::new(file)).unwrap();
+        assert!(reader.read().is_err());
+    }
+
+    #[test]
+    fn test_version_1() {
+        // Frames of version 1 files end after the payload.
+        let mut buf = Vec::new();
+        encode_frame("topic", b"key", Some(b"payload"), Some(3), &[], &mut buf);
+        buf.truncate(buf.len() - 4);
+        let len = (buf.len() - 4) as u32;
+        buf[..4].copy_from_slice(&len.to_le_bytes());
+        let file = [&b"KPLUGEVT\x01\0\0\0\0"[..], &buf].concat();
+
+        let mut reader = FileSinkReader::new(std::io::Cursor::new(file)).unwrap();
+        let message = reader.read().unwrap().unwrap();
+        assert_eq!(message.payload.as_deref(), Some(&b"payload"[..]));
+        assert_eq!(message.partition, Some(3));
+        assert!(message.headers.is_empty());
+        assert!(reader.read().unwrap().is_none());
+    }
+}
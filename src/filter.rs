@@ -13,56 +13,251 @@
 // limitations under the License.
 
 use {
-    crate::ConfigFilter,
-    solana_program::pubkey::Pubkey,
-    std::{collections::HashSet, str::FromStr},
+    crate::{
+        config::{active_topic, SizeTier},
+        resources::{hash_set_bytes, MemoryFootprint},
+        ConfigFilter, Partitioning, SchemaCompat, TransactionCaps, TransactionKeySource,
+    },
+    serde::Serialize,
+    serde_json::{json, Value},
+    solana_program::{hash::hash, pubkey::Pubkey},
+    solana_sdk::{bs58, transaction::TransactionError},
+    std::{collections::HashSet, ops::Range, str::FromStr, sync::Arc},
 };
 
+/// Reason of transactions left out by `failed_tx_error_includes`.
+pub const ERROR_NOT_INCLUDED: &str = "not_included";
+
+/// Whether a `failed_tx_error_includes` or `failed_tx_error_excludes` entry matches a
+/// transaction error, rendered for display and debugging. Entries starting with `^` match the
+/// start of either, others anywhere in them.
+fn error_entry_matches(entry: &str, rendered: &[String; 2]) -> bool {
+    match entry.strip_prefix('^') {
+        Some(prefix) => rendered.iter().any(|error| error.starts_with(prefix)),
+        None => rendered.iter().any(|error| error.contains(entry)),
+    }
+}
+
+/// Why a filter does or doesn't match an account, in order of precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchDecision {
+    /// The address is in `account_filters`.
+    Account,
+    /// The address starts with one of `account_prefix_filters`.
+    AccountPrefix,
+    /// The owner is in `program_filters`.
+    Program,
+    /// Any owner matches, `program_filters` being empty.
+    AnyProgram,
+    /// The owner is in `program_ignores`.
+    IgnoredProgram,
+    /// Neither the address nor the owner is filtered for.
+    NoMatch,
+}
+
+impl MatchDecision {
+    pub fn is_match(self) -> bool {
+        !matches!(self, Self::IgnoredProgram | Self::NoMatch)
+    }
+}
+
+/// Filter settings of one `ConfigFilter`, cheap to clone as the key sets are shared.
+#[derive(Debug, Clone)]
 pub struct Filter {
+    pub name: String,
     pub publish_all_accounts: bool,
-    pub program_ignores: HashSet<[u8; 32]>,
-    pub program_filters: HashSet<[u8; 32]>,
-    pub account_filters: HashSet<[u8; 32]>,
+    pub program_ignores: Arc<HashSet<[u8; 32]>>,
+    pub program_filters: Arc<HashSet<[u8; 32]>>,
+    pub account_filters: Arc<HashSet<[u8; 32]>>,
+    pub account_prefix_filters: Arc<[Vec<u8>]>,
     pub include_vote_transactions: bool,
     pub include_failed_transactions: bool,
+    pub failed_tx_error_includes: Arc<[String]>,
+    pub failed_tx_error_excludes: Arc<[String]>,
 
-    pub update_account_topic: String,
-    pub slot_status_topic: String,
-    pub transaction_topic: String,
+    pub update_account_topic: Option<String>,
+    pub size_tiers: Vec<SizeTier>,
+    pub slot_status_topic: Option<String>,
+    pub transaction_topic: Option<String>,
+    pub transaction_index_topic: Option<String>,
+    pub block_events_topic: Option<String>,
+    pub state_topic: Option<String>,
+    pub state_topic_seed_from_startup: bool,
 
-    pub wrap_messages: bool,
+    pub wrap_account_messages: bool,
+    pub wrap_slot_messages: bool,
+    pub wrap_transaction_messages: bool,
+    pub wrap_block_messages: bool,
+    pub decode_stake_accounts: bool,
+    pub decode_address_lookup_tables: bool,
+    pub require_causing_txn: bool,
+    pub transaction_key_source: TransactionKeySource,
+    pub partitioning: Partitioning,
+    pub transaction_caps: TransactionCaps,
+    pub include_raw_transaction: bool,
+    pub transaction_sample_rate: f64,
+    pub fail_open: bool,
+    pub publish_if_changed_bytes_at: Vec<Range<usize>>,
+    pub changed_bytes_max_accounts: usize,
+    pub warn_data_size_bytes: usize,
+    pub schema_compat: SchemaCompat,
 }
 
 impl Filter {
     pub fn new(config: &ConfigFilter) -> Self {
         Self {
+            name: config.name.clone(),
             publish_all_accounts: config.publish_all_accounts,
-            program_ignores: config
-                .program_ignores
-                .iter()
-                .flat_map(|p| Pubkey::from_str(p).ok().map(|p| p.to_bytes()))
-                .collect(),
-            program_filters: config
-                .program_filters
-                .iter()
-                .flat_map(|p| Pubkey::from_str(p).ok().map(|p| p.to_bytes()))
-                .collect(),
-            account_filters: config
-                .account_filters
+            program_ignores: Arc::new(
+                config
+                    .program_ignores
+                    .iter()
+                    .flat_map(|p| Pubkey::from_str(p).ok().map(|p| p.to_bytes()))
+                    .collect(),
+            ),
+            program_filters: Arc::new(
+                config
+                    .program_filters
+                    .iter()
+                    .flat_map(|p| Pubkey::from_str(p).ok().map(|p| p.to_bytes()))
+                    .collect(),
+            ),
+            account_filters: Arc::new(
+                config
+                    .account_filters
+                    .iter()
+                    .flat_map(|p| Pubkey::from_str(p).ok().map(|p| p.to_bytes()))
+                    .collect(),
+            ),
+            account_prefix_filters: config
+                .account_prefix_filters
                 .iter()
-                .flat_map(|p| Pubkey::from_str(p).ok().map(|p| p.to_bytes()))
+                .flat_map(|p| bs58::decode(p).into_vec().ok())
+                .filter(|p| (1..=32).contains(&p.len()))
                 .collect(),
             include_vote_transactions: config.include_vote_transactions,
             include_failed_transactions: config.include_failed_transactions,
+            failed_tx_error_includes: config.failed_tx_error_includes.as_slice().into(),
+            failed_tx_error_excludes: config.failed_tx_error_excludes.as_slice().into(),
 
             update_account_topic: config.update_account_topic.clone(),
+            size_tiers: config.size_tiers.clone(),
             slot_status_topic: config.slot_status_topic.clone(),
             transaction_topic: config.transaction_topic.clone(),
+            transaction_index_topic: config.transaction_index_topic.clone(),
+            block_events_topic: config.block_events_topic.clone(),
+            state_topic: config.state_topic.clone(),
+            state_topic_seed_from_startup: config.state_topic_seed_from_startup,
 
-            wrap_messages: config.wrap_messages,
+            wrap_account_messages: config.wrap_account_messages.unwrap_or(config.wrap_messages),
+            wrap_slot_messages: config.wrap_slot_messages.unwrap_or(config.wrap_messages),
+            wrap_transaction_messages: config
+                .wrap_transaction_messages
+                .unwrap_or(config.wrap_messages),
+            wrap_block_messages: config.wrap_block_messages.unwrap_or(config.wrap_messages),
+            decode_stake_accounts: config.decode_stake_accounts,
+            decode_address_lookup_tables: config.decode_address_lookup_tables,
+            require_causing_txn: config.require_causing_txn,
+            transaction_key_source: config.transaction_key_source,
+            partitioning: if config.sticky_partitioning {
+                Partitioning::Sticky
+            } else {
+                config.partitioning
+            },
+            transaction_caps: TransactionCaps {
+                max_inner_instructions: config.max_inner_instructions,
+                max_log_messages: config.max_log_messages,
+                max_memo_bytes: config.extract_memos.then_some(config.max_memo_bytes),
+                include_zero_delta: config.include_zero_delta,
+            },
+            include_raw_transaction: config.include_raw_transaction,
+            transaction_sample_rate: config.transaction_sample_rate,
+            fail_open: config.fail_open,
+            publish_if_changed_bytes_at: config.publish_if_changed_bytes_at.clone(),
+            changed_bytes_max_accounts: config.changed_bytes_max_accounts,
+            warn_data_size_bytes: config.warn_data_size_bytes,
+            schema_compat: config.schema_compat,
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn program_filter_count(&self) -> usize {
+        self.program_filters.len()
+    }
+
+    /// Active topics of the filter, in config order.
+    pub fn topics(&self) -> Vec<&str> {
+        let other_topics = [
+            &self.slot_status_topic,
+            &self.transaction_topic,
+            &self.transaction_index_topic,
+            &self.block_events_topic,
+            &self.state_topic,
+        ];
+        self.account_topics()
+            .chain(other_topics.into_iter().filter_map(active_topic))
+            .collect()
+    }
+
+    /// Active topics of account updates, `update_account_topic` and those of `size_tiers`.
+    pub fn account_topics(&self) -> impl Iterator<Item = &str> {
+        active_topic(&self.update_account_topic)
+            .into_iter()
+            .chain(self.size_tiers.iter().map(|tier| tier.topic.as_str()))
+    }
+
+    /// Topic of account updates with `data_len` bytes of data, that of the first of
+    /// `size_tiers` it fits in or else `update_account_topic`.
+    pub fn account_topic(&self, data_len: usize) -> Option<&str> {
+        let tier = self
+            .size_tiers
+            .iter()
+            .find(|tier| data_len <= tier.max_bytes.unwrap_or(usize::MAX));
+        match tier {
+            Some(tier) => Some(&tier.topic),
+            None => active_topic(&self.update_account_topic),
+        }
+    }
+
+    /// Whether account updates of `pubkey` owned by `owner` match, and why.
+    pub fn matches_summary(&self, pubkey: &[u8], owner: &[u8]) -> MatchDecision {
+        if self.wants_account(pubkey) {
+            MatchDecision::Account
+        } else if self.wants_account_prefix(pubkey) {
+            MatchDecision::AccountPrefix
+        } else if self.wants_program(owner) {
+            if self.program_filters.is_empty() {
+                MatchDecision::AnyProgram
+            } else {
+                MatchDecision::Program
+            }
+        } else if <&[u8; 32]>::try_from(owner).is_ok_and(|key| self.program_ignores.contains(key)) {
+            MatchDecision::IgnoredProgram
+        } else {
+            MatchDecision::NoMatch
+        }
+    }
+
+    /// Whether a live update of `pubkey` owned by `owner` would be published, with the match
+    /// decision and the topics it would go to.
+    pub fn explain(&self, pubkey: &Pubkey, owner: &Pubkey) -> Value {
+        let decision = self.matches_summary(pubkey.as_ref(), owner.as_ref());
+        let topics: Vec<&str> = self
+            .account_topics()
+            .chain(active_topic(&self.state_topic))
+            .collect();
+        json!({
+            "name": self.name,
+            "decision": decision,
+            "published": decision.is_match() && !topics.is_empty(),
+            "topics": topics,
+        })
+    }
+
     pub fn wants_program(&self, program: &[u8]) -> bool {
         match <&[u8; 32]>::try_from(program) {
             Ok(key) => {
@@ -80,6 +275,58 @@ impl Filter {
         }
     }
 
+    /// Whether the leading bytes of `account` match any of `account_prefix_filters`, such as
+    /// to include program derived addresses sharing a prefix.
+    pub fn wants_account_prefix(&self, account: &[u8]) -> bool {
+        self.account_prefix_filters
+            .iter()
+            .any(|prefix| account.starts_with(prefix))
+    }
+
+    /// Whether account updates are published at all, to the update or the state topic.
+    pub fn wants_accounts(&self) -> bool {
+        self.account_topics().next().is_some() || active_topic(&self.state_topic).is_some()
+    }
+
+    /// State topic of account updates at `is_startup`, if they go to one.
+    pub fn state_topic(&self, is_startup: bool) -> Option<&str> {
+        active_topic(&self.state_topic)
+            .filter(|_| !is_startup || self.state_topic_seed_from_startup)
+    }
+
+    /// Whether account updates at `is_startup` go to the state topic.
+    pub fn wants_state(&self, is_startup: bool) -> bool {
+        self.state_topic(is_startup).is_some()
+    }
+
+    /// Topic of published transactions, a sample rate of 0 disabling them like an empty topic.
+    pub fn transaction_topic(&self) -> Option<&str> {
+        active_topic(&self.transaction_topic).filter(|_| self.transaction_sample_rate > 0.0)
+    }
+
+    /// Topic of the index entries of matching transactions, whatever `transaction_sample_rate`.
+    pub fn transaction_index_topic(&self) -> Option<&str> {
+        active_topic(&self.transaction_index_topic)
+    }
+
+    /// Whether transactions are published at all, in full or as index entries.
+    pub fn wants_transactions(&self) -> bool {
+        self.transaction_topic().is_some() || self.transaction_index_topic().is_some()
+    }
+
+    /// Whether the transaction with `signature` is in the sample, decided by its first 8 bytes
+    /// so every validator with the same config picks the same transactions.
+    pub fn samples_transaction(&self, signature: &[u8]) -> bool {
+        if self.transaction_sample_rate >= 1.0 {
+            return true;
+        }
+        let Some(prefix) = signature.get(..8) else {
+            return true;
+        };
+        let value = u64::from_le_bytes(prefix.try_into().unwrap());
+        (value as f64) < self.transaction_sample_rate * 2f64.powi(64)
+    }
+
     pub fn wants_vote_tx(&self) -> bool {
         self.include_vote_transactions
     }
@@ -87,13 +334,144 @@ impl Filter {
     pub fn wants_failed_tx(&self) -> bool {
         self.include_failed_transactions
     }
+
+    /// Why a transaction with `status` is left out by `failed_tx_error_includes` or
+    /// `failed_tx_error_excludes`: the matching exclude entry, or `ERROR_NOT_INCLUDED`.
+    pub fn failed_tx_exclusion(&self, status: &Result<(), TransactionError>) -> Option<&str> {
+        let Err(error) = status else {
+            return None;
+        };
+        if self.failed_tx_error_includes.is_empty() && self.failed_tx_error_excludes.is_empty() {
+            return None;
+        }
+        let rendered = [error.to_string(), format!("{error:?}")];
+        let matches = |entry: &&String| error_entry_matches(entry, &rendered);
+        if !self.failed_tx_error_includes.is_empty()
+            && !self
+                .failed_tx_error_includes
+                .iter()
+                .any(|entry| matches(&entry))
+        {
+            return Some(ERROR_NOT_INCLUDED);
+        }
+        self.failed_tx_error_excludes
+            .iter()
+            .find(matches)
+            .map(String::as_str)
+    }
+
+    /// Effective filter settings as JSON, with pubkey sets sorted.
+    pub fn canonical(&self) -> Value {
+        fn sorted(keys: &HashSet<[u8; 32]>) -> Vec<String> {
+            let mut keys: Vec<String> = keys
+                .iter()
+                .map(|key| Pubkey::new_from_array(*key).to_string())
+                .collect();
+            keys.sort();
+            keys
+        }
+
+        let mut prefixes: Vec<String> = self
+            .account_prefix_filters
+            .iter()
+            .map(|prefix| bs58::encode(prefix).into_string())
+            .collect();
+        prefixes.sort();
+
+        json!({
+            "publish_all_accounts": self.publish_all_accounts,
+            "program_ignores": sorted(&self.program_ignores),
+            "program_filters": sorted(&self.program_filters),
+            "account_filters": sorted(&self.account_filters),
+            "account_prefix_filters": prefixes,
+            "include_vote_transactions": self.include_vote_transactions,
+            "include_failed_transactions": self.include_failed_transactions,
+            "failed_tx_error_includes": self.failed_tx_error_includes,
+            "failed_tx_error_excludes": self.failed_tx_error_excludes,
+            "update_account_topic": self.update_account_topic,
+            "size_tiers": self.size_tiers,
+            "slot_status_topic": self.slot_status_topic,
+            "transaction_topic": self.transaction_topic,
+            "transaction_index_topic": self.transaction_index_topic,
+            "block_events_topic": self.block_events_topic,
+            "state_topic": self.state_topic,
+            "state_topic_seed_from_startup": self.state_topic_seed_from_startup,
+            "wrap_account_messages": self.wrap_account_messages,
+            "wrap_slot_messages": self.wrap_slot_messages,
+            "wrap_transaction_messages": self.wrap_transaction_messages,
+            "wrap_block_messages": self.wrap_block_messages,
+            "decode_stake_accounts": self.decode_stake_accounts,
+            "decode_address_lookup_tables": self.decode_address_lookup_tables,
+            "require_causing_txn": self.require_causing_txn,
+            "transaction_key_source": self.transaction_key_source,
+            "partitioning": self.partitioning,
+            "max_inner_instructions": self.transaction_caps.max_inner_instructions,
+            "max_log_messages": self.transaction_caps.max_log_messages,
+            "max_memo_bytes": self.transaction_caps.max_memo_bytes,
+            "include_zero_delta": self.transaction_caps.include_zero_delta,
+            "include_raw_transaction": self.include_raw_transaction,
+            "transaction_sample_rate": self.transaction_sample_rate,
+            "fail_open": self.fail_open,
+            "publish_if_changed_bytes_at": self.publish_if_changed_bytes_at,
+            "changed_bytes_max_accounts": self.changed_bytes_max_accounts,
+            "warn_data_size_bytes": self.warn_data_size_bytes,
+            "schema_compat": self.schema_compat,
+        })
+    }
+}
+
+/// Key sets of the filters, the bulk of their memory.
+impl MemoryFootprint for Vec<Filter> {
+    fn memory_footprint(&self) -> usize {
+        self.iter()
+            .map(|filter| {
+                let prefixes: usize = filter.account_prefix_filters.iter().map(Vec::len).sum();
+                hash_set_bytes(&filter.program_ignores)
+                    + hash_set_bytes(&filter.program_filters)
+                    + hash_set_bytes(&filter.account_filters)
+                    + prefixes
+            })
+            .sum()
+    }
+}
+
+impl From<&ConfigFilter> for Filter {
+    fn from(config: &ConfigFilter) -> Self {
+        Self::new(config)
+    }
+}
+
+/// Canonical JSON of a filter set and the hash identifying it.
+#[derive(Debug, Clone)]
+pub struct FilterFingerprint {
+    pub json: String,
+    pub hash: String,
+}
+
+impl FilterFingerprint {
+    /// Independent of the order of filters and of keys within them.
+    pub fn new(filters: &[Filter]) -> Self {
+        let mut canonical: Vec<String> = filters
+            .iter()
+            .map(|filter| filter.canonical().to_string())
+            .collect();
+        canonical.sort();
+        let json = format!("[{}]", canonical.join(","));
+        let hash = hash(json.as_bytes()).to_string();
+        Self { json, hash }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use {
-        crate::{ConfigFilter, Filter},
-        solana_program::pubkey::Pubkey,
+        crate::{
+            filter::{FilterFingerprint, MatchDecision, ERROR_NOT_INCLUDED},
+            ConfigFilter, Filter, SizeTier,
+        },
+        serde_json::json,
+        solana_program::{hash::hash, instruction::InstructionError, pubkey::Pubkey},
+        solana_sdk::{bs58, transaction::TransactionError},
         std::str::FromStr,
     };
 
@@ -187,4 +565,320 @@ mod tests {
                 .to_bytes()
         ));
     }
+
+    #[test]
+    fn test_wrap_messages() {
+        let config: ConfigFilter = serde_json::from_str(r#"{"wrap_messages": true}"#).unwrap();
+        let filter = Filter::new(&config);
+        assert!(filter.wrap_account_messages);
+        assert!(filter.wrap_slot_messages);
+        assert!(filter.wrap_transaction_messages);
+
+        let config: ConfigFilter = serde_json::from_str(
+            r#"{"wrap_account_messages": false, "wrap_transaction_messages": true}"#,
+        )
+        .unwrap();
+        let filter = Filter::new(&config);
+        assert!(!filter.wrap_account_messages);
+        assert!(!filter.wrap_slot_messages);
+        assert!(filter.wrap_transaction_messages);
+
+        let config: ConfigFilter =
+            serde_json::from_str(r#"{"wrap_messages": true, "wrap_account_messages": false}"#)
+                .unwrap();
+        let filter = Filter::new(&config);
+        assert!(!filter.wrap_account_messages);
+        assert!(filter.wrap_slot_messages);
+        assert!(filter.wrap_transaction_messages);
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let fingerprint = |filters: &[ConfigFilter]| {
+            let filters: Vec<Filter> = filters.iter().map(Filter::new).collect();
+            FilterFingerprint::new(&filters)
+        };
+        let first = ConfigFilter {
+            program_filters: vec![
+                "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin".to_owned(),
+                "cndy3Z4yapfJBmL3ShUp5exZKqR3z33thTzeNMm2gRZ".to_owned(),
+            ],
+            update_account_topic: Some("accounts".to_owned()),
+            ..Default::default()
+        };
+        let second = ConfigFilter {
+            transaction_topic: Some("transactions".to_owned()),
+            ..Default::default()
+        };
+        let base = fingerprint(&[first.clone(), second.clone()]);
+
+        let mut reordered = first.clone();
+        reordered.program_filters.reverse();
+        assert_eq!(fingerprint(&[second.clone(), reordered]).hash, base.hash);
+
+        let mut changed = first;
+        changed.program_filters[0] = "Vote111111111111111111111111111111111111111".to_owned();
+        assert_ne!(fingerprint(&[changed, second]).hash, base.hash);
+    }
+
+    #[test]
+    fn test_transaction_sampling() {
+        let filter = |rate| {
+            Filter::new(&ConfigFilter {
+                transaction_topic: Some("transactions".to_owned()),
+                transaction_sample_rate: rate,
+                ..Default::default()
+            })
+        };
+        let signatures: Vec<[u8; 64]> = (0..1000u64)
+            .map(|i| {
+                let mut signature = [0; 64];
+                signature[..32].copy_from_slice(&hash(&i.to_le_bytes()).to_bytes());
+                signature
+            })
+            .collect();
+        let sampled = |filter: &Filter| {
+            signatures
+                .iter()
+                .filter(|signature| filter.samples_transaction(*signature))
+                .count()
+        };
+
+        let all = filter(1.0);
+        assert!(all.wants_transactions());
+        assert_eq!(sampled(&all), 1000);
+        assert!(all.samples_transaction(&[0xff; 64]));
+
+        let none = filter(0.0);
+        assert!(!none.wants_transactions());
+        assert_eq!(sampled(&none), 0);
+        assert!(!none.samples_transaction(&[0; 64]));
+
+        // Deterministic, so the same transactions are picked every time.
+        let tenth = filter(0.1);
+        let picked: Vec<bool> = signatures
+            .iter()
+            .map(|signature| tenth.samples_transaction(signature))
+            .collect();
+        let again: Vec<bool> = signatures
+            .iter()
+            .map(|signature| filter(0.1).samples_transaction(signature))
+            .collect();
+        assert_eq!(picked, again);
+        assert!((70..130).contains(&sampled(&tenth)), "{}", sampled(&tenth));
+        // Only the first 8 bytes count.
+        let mut signature = signatures[0];
+        signature[8..].fill(0xaa);
+        assert_eq!(tenth.samples_transaction(&signature), picked[0]);
+    }
+
+    #[test]
+    fn test_disabled_topics() {
+        let filter = Filter::new(&ConfigFilter {
+            update_account_topic: None,
+            transaction_topic: Some("txs".to_owned()),
+            transaction_sample_rate: 0.0,
+            ..Default::default()
+        });
+        assert!(!filter.wants_accounts());
+        assert!(!filter.wants_transactions());
+        // Both inactive, told apart in the canonical settings.
+        let canonical = filter.canonical();
+        assert!(canonical["update_account_topic"].is_null());
+        assert_eq!(canonical["slot_status_topic"], "");
+    }
+
+    #[test]
+    fn test_size_tiers() {
+        let tier = |max_bytes: Option<usize>, topic: &str| SizeTier {
+            max_bytes,
+            topic: topic.to_owned(),
+        };
+        let mut config = ConfigFilter {
+            update_account_topic: Some("accounts".to_owned()),
+            size_tiers: vec![tier(Some(1024), "small"), tier(Some(65_536), "medium")],
+            ..Default::default()
+        };
+        let filter = Filter::new(&config);
+        assert_eq!(filter.account_topic(0), Some("small"));
+        assert_eq!(filter.account_topic(1024), Some("small"));
+        assert_eq!(filter.account_topic(1025), Some("medium"));
+        assert_eq!(filter.account_topic(65_536), Some("medium"));
+        // Past the last tier.
+        assert_eq!(filter.account_topic(65_537), Some("accounts"));
+        assert_eq!(filter.topics(), ["accounts", "small", "medium"]);
+
+        config.size_tiers.push(tier(None, "large"));
+        config.update_account_topic = None;
+        let filter = Filter::new(&config);
+        assert_eq!(filter.account_topic(65_537), Some("large"));
+        assert_eq!(filter.account_topic(usize::MAX), Some("large"));
+        assert!(filter.wants_accounts());
+
+        config.size_tiers.pop();
+        let filter = Filter::new(&config);
+        assert_eq!(filter.account_topic(65_537), None);
+    }
+
+    #[test]
+    fn test_explain() {
+        let account = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let ignored = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        let prefixed = Pubkey::new_from_array([7; 32]);
+        let filter = Filter::new(&ConfigFilter {
+            name: "explained".to_owned(),
+            update_account_topic: Some("accounts".to_owned()),
+            account_filters: vec![account.to_string()],
+            account_prefix_filters: vec![bs58::encode([7, 7]).into_string()],
+            program_filters: vec![program.to_string()],
+            program_ignores: vec![ignored.to_string()],
+            ..Default::default()
+        });
+        let decision = |pubkey: &Pubkey, owner: &Pubkey| {
+            let explained = filter.explain(pubkey, owner);
+            assert_eq!(explained["name"], "explained");
+            assert_eq!(explained["topics"], json!(["accounts"]));
+            let decision = filter.matches_summary(pubkey.as_ref(), owner.as_ref());
+            assert_eq!(explained["decision"], json!(decision));
+            assert_eq!(explained["published"], decision.is_match());
+            decision
+        };
+
+        // Addresses win over owners, even ignored ones.
+        assert_eq!(decision(&account, &ignored), MatchDecision::Account);
+        assert_eq!(decision(&prefixed, &other), MatchDecision::AccountPrefix);
+        assert_eq!(decision(&other, &program), MatchDecision::Program);
+        assert_eq!(decision(&other, &ignored), MatchDecision::IgnoredProgram);
+        assert_eq!(decision(&other, &other), MatchDecision::NoMatch);
+        assert_eq!(
+            serde_json::to_value(MatchDecision::AccountPrefix).unwrap(),
+            "account_prefix"
+        );
+
+        // Any owner matches without program filters, but without an account topic nothing is
+        // published.
+        let filter = Filter::new(&ConfigFilter {
+            transaction_topic: Some("txs".to_owned()),
+            ..Default::default()
+        });
+        let explained = filter.explain(&other, &other);
+        assert_eq!(explained["decision"], "any_program");
+        assert_eq!(explained["published"], false);
+        assert_eq!(filter.topics(), ["txs"]);
+    }
+
+    #[test]
+    fn test_shared_snapshot() {
+        let config = ConfigFilter {
+            program_filters: vec![Pubkey::new_unique().to_string()],
+            ..Default::default()
+        };
+        let filter = Filter::from(&config);
+        let snapshot = filter.clone();
+        assert!(std::sync::Arc::ptr_eq(
+            &filter.program_filters,
+            &snapshot.program_filters
+        ));
+        assert_eq!(snapshot.program_filter_count(), 1);
+    }
+
+    #[test]
+    fn test_account_prefix_filter() {
+        let key = Pubkey::from_str("5KKsLVU6TcbVDK4BS6K1DGDxnh4Q9xjYJ8XaDCG5t8ht")
+            .unwrap()
+            .to_bytes();
+        let prefix = |len: usize| bs58::encode(&key[..len]).into_string();
+        let filter = |prefixes: Vec<String>| {
+            Filter::new(&ConfigFilter {
+                account_prefix_filters: prefixes,
+                ..Default::default()
+            })
+        };
+
+        for len in [1, 4, 32] {
+            let filter = filter(vec![prefix(len)]);
+            assert!(filter.wants_account_prefix(&key), "{len}");
+            let mut other = key;
+            other[len - 1] ^= 1;
+            assert!(!filter.wants_account_prefix(&other), "{len}");
+            // Exact matches are a separate list.
+            assert!(!filter.wants_account(&key));
+        }
+
+        let mut other = key;
+        other[0] ^= 1;
+        let filter = filter(vec![bs58::encode(&other[..2]).into_string(), prefix(3)]);
+        assert!(filter.wants_account_prefix(&key));
+        assert!(filter.wants_account_prefix(&other));
+        assert!(!filter.wants_account_prefix(&[0; 32]));
+        assert_eq!(
+            filter.canonical()["account_prefix_filters"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(!Filter::new(&ConfigFilter::default()).wants_account_prefix(&key));
+    }
+
+    #[test]
+    fn test_failed_tx_errors() {
+        let filter = |includes: &[&str], excludes: &[&str]| {
+            Filter::new(&ConfigFilter {
+                failed_tx_error_includes: includes.iter().map(|&s| s.to_owned()).collect(),
+                failed_tx_error_excludes: excludes.iter().map(|&s| s.to_owned()).collect(),
+                ..Default::default()
+            })
+        };
+        // "Error processing Instruction 0: custom program error: 0x1771"
+        let custom = Err(TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(6001),
+        ));
+        let processed = Err(TransactionError::AlreadyProcessed);
+        let blockhash = Err(TransactionError::BlockhashNotFound);
+        let funds = Err(TransactionError::InsufficientFundsForFee);
+
+        let all = filter(&[], &[]);
+        for status in [&custom, &processed, &blockhash, &funds, &Ok(())] {
+            assert_eq!(all.failed_tx_exclusion(status), None);
+        }
+
+        let excludes = filter(&[], &["already been processed", "^BlockhashNotFound"]);
+        assert_eq!(excludes.failed_tx_exclusion(&custom), None);
+        assert_eq!(
+            excludes.failed_tx_exclusion(&processed),
+            Some("already been processed")
+        );
+        assert_eq!(
+            excludes.failed_tx_exclusion(&blockhash),
+            Some("^BlockhashNotFound")
+        );
+        assert_eq!(excludes.failed_tx_exclusion(&Ok(())), None);
+
+        // Entries match the displayed or debugged error, prefixes only at the start.
+        let includes = filter(&["custom program error: 0x1771", "^Insufficient"], &[]);
+        assert_eq!(includes.failed_tx_exclusion(&custom), None);
+        assert_eq!(includes.failed_tx_exclusion(&funds), None);
+        assert_eq!(
+            includes.failed_tx_exclusion(&processed),
+            Some(ERROR_NOT_INCLUDED)
+        );
+        assert_eq!(
+            filter(&["^Custom(6001)"], &[]).failed_tx_exclusion(&custom),
+            Some(ERROR_NOT_INCLUDED)
+        );
+
+        let both = filter(&["InstructionError"], &["Custom(6001)"]);
+        assert_eq!(both.failed_tx_exclusion(&custom), Some("Custom(6001)"));
+        assert_eq!(
+            both.failed_tx_exclusion(&Err(TransactionError::InstructionError(
+                1,
+                InstructionError::Custom(6000)
+            ))),
+            None
+        );
+    }
 }
@@ -0,0 +1,246 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Filters worth evaluating for an event, so that configs with many filters don't run every
+//! one of them on each notification. Built once at load, the index leaves out filters without
+//! a topic for the event type, and for account updates those whose `account_filters`,
+//! `account_prefix_filters` and `program_filters` can't match the first byte of the address or
+//! owner. The filters left are evaluated as before, in config order, and how many were is
+//! observed in `filters_evaluated_per_event`.
+
+use crate::filter::Filter;
+
+/// Indexes of the filters that may match each kind of event, in ascending order.
+#[derive(Debug, Default)]
+pub struct FilterIndex {
+    /// Filters publishing account updates at all.
+    accounts: Vec<usize>,
+    /// Filters publishing account updates of any owner, `program_filters` being empty.
+    any_owner: Vec<usize>,
+    /// Filters by the first byte of their `account_filters` and `account_prefix_filters`.
+    by_account_byte: Vec<Vec<usize>>,
+    /// Filters by the first byte of their `program_filters`.
+    by_owner_byte: Vec<Vec<usize>>,
+    /// Filters publishing transactions, in full or as index entries.
+    transactions: Vec<usize>,
+}
+
+impl FilterIndex {
+    pub fn new(filters: &[Filter]) -> Self {
+        let mut index = Self {
+            by_account_byte: vec![Vec::new(); 256],
+            by_owner_byte: vec![Vec::new(); 256],
+            ..Self::default()
+        };
+        for (position, filter) in filters.iter().enumerate() {
+            if filter.wants_transactions() {
+                index.transactions.push(position);
+            }
+            if !filter.wants_accounts() {
+                continue;
+            }
+            index.accounts.push(position);
+            if filter.program_filters.is_empty() {
+                index.any_owner.push(position);
+                continue;
+            }
+            let account_bytes = filter
+                .account_filters
+                .iter()
+                .map(|key| key[0])
+                .chain(filter.account_prefix_filters.iter().map(|prefix| prefix[0]));
+            for byte in account_bytes {
+                push_once(&mut index.by_account_byte[byte as usize], position);
+            }
+            for key in filter.program_filters.iter() {
+                push_once(&mut index.by_owner_byte[key[0] as usize], position);
+            }
+        }
+        index
+    }
+
+    /// Filters that may publish the update of `pubkey` owned by `owner`, in ascending order.
+    /// Keys that aren't 32 bytes match every filter, so they get every filter with an account
+    /// topic.
+    pub fn account_candidates(&self, pubkey: &[u8], owner: &[u8]) -> Candidates<'_> {
+        if pubkey.len() != 32 || owner.len() != 32 {
+            return Candidates {
+                buckets: [&self.accounts, &[], &[]],
+            };
+        }
+        Candidates {
+            buckets: [
+                &self.any_owner,
+                &self.by_account_byte[pubkey[0] as usize],
+                &self.by_owner_byte[owner[0] as usize],
+            ],
+        }
+    }
+
+    /// Filters that may publish a transaction.
+    pub fn transaction_candidates(&self) -> &[usize] {
+        &self.transactions
+    }
+}
+
+/// Filters of `FilterIndex::account_candidates`, merged from buckets sorted in ascending order,
+/// so that each is yielded once without collecting them.
+pub struct Candidates<'a> {
+    buckets: [&'a [usize]; 3],
+}
+
+impl Iterator for Candidates<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let next = *self
+            .buckets
+            .iter()
+            .filter_map(|bucket| bucket.first())
+            .min()?;
+        for bucket in self.buckets.iter_mut() {
+            if bucket.first() == Some(&next) {
+                *bucket = &bucket[1..];
+            }
+        }
+        Some(next)
+    }
+}
+
+/// Push `position` unless it was the last pushed, filters being indexed in order.
+fn push_once(bucket: &mut Vec<usize>, position: usize) {
+    if bucket.last() != Some(&position) {
+        bucket.push(position);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::ConfigFilter, solana_program::pubkey::Pubkey, solana_sdk::bs58};
+
+    /// Xorshift, deterministic so failures reproduce.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            (self.next() % bound as u64) as usize
+        }
+
+        fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+            &items[self.below(items.len())]
+        }
+    }
+
+    /// Random keys sharing their first bytes, so that buckets collide without matching.
+    fn keys(rng: &mut Rng) -> Vec<[u8; 32]> {
+        (0..24)
+            .map(|_| {
+                let mut key = [0u8; 32];
+                key.iter_mut().for_each(|byte| *byte = rng.next() as u8);
+                key[0] = rng.below(4) as u8;
+                key
+            })
+            .collect()
+    }
+
+    fn random_filter(rng: &mut Rng, keys: &[[u8; 32]]) -> Filter {
+        let pick = |rng: &mut Rng, most: usize| -> Vec<String> {
+            (0..rng.below(most + 1))
+                .map(|_| Pubkey::new_from_array(*rng.pick(keys)).to_string())
+                .collect()
+        };
+        let topic = |rng: &mut Rng, name: &str| (rng.below(3) > 0).then(|| name.to_owned());
+        let config = ConfigFilter {
+            account_filters: pick(rng, 2),
+            program_filters: pick(rng, 2),
+            program_ignores: pick(rng, 1),
+            account_prefix_filters: (0..rng.below(2))
+                .map(|_| bs58::encode(&rng.pick(keys)[..1 + rng.below(3)]).into_string())
+                .collect(),
+            update_account_topic: topic(rng, "accounts"),
+            state_topic: topic(rng, "states"),
+            transaction_topic: topic(rng, "transactions"),
+            ..Default::default()
+        };
+        Filter::new(&config)
+    }
+
+    #[test]
+    fn test_equivalent_to_every_filter() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..200 {
+            let keys = keys(&mut rng);
+            let filters: Vec<Filter> = (0..1 + rng.below(12))
+                .map(|_| random_filter(&mut rng, &keys))
+                .collect();
+            let index = FilterIndex::new(&filters);
+            for _ in 0..50 {
+                let pubkey = rng.pick(&keys);
+                let owner = rng.pick(&keys);
+                let naive: Vec<usize> = (0..filters.len())
+                    .filter(|&i| filters[i].wants_accounts())
+                    .filter(|&i| filters[i].matches_summary(pubkey, owner).is_match())
+                    .collect();
+                let indexed: Vec<usize> = index
+                    .account_candidates(pubkey, owner)
+                    .filter(|&i| filters[i].matches_summary(pubkey, owner).is_match())
+                    .collect();
+                assert_eq!(indexed, naive);
+            }
+            let transactions: Vec<usize> = (0..filters.len())
+                .filter(|&i| filters[i].wants_transactions())
+                .collect();
+            assert_eq!(index.transaction_candidates(), transactions);
+        }
+    }
+
+    #[test]
+    fn test_candidates() {
+        let [a, b] = [[1u8; 32], [2u8; 32]];
+        let filter = |accounts: &[[u8; 32]], programs: &[[u8; 32]], topic: bool| {
+            let keys = |keys: &[[u8; 32]]| {
+                keys.iter()
+                    .map(|key| Pubkey::new_from_array(*key).to_string())
+                    .collect()
+            };
+            Filter::new(&ConfigFilter {
+                account_filters: keys(accounts),
+                program_filters: keys(programs),
+                update_account_topic: topic.then(|| "accounts".to_owned()),
+                ..Default::default()
+            })
+        };
+        let index = FilterIndex::new(&[
+            filter(&[a], &[b], true),
+            filter(&[], &[], true),
+            filter(&[b], &[a], true),
+            filter(&[a], &[a], false),
+        ]);
+        let candidates = |pubkey: &[u8], owner: &[u8]| {
+            index.account_candidates(pubkey, owner).collect::<Vec<_>>()
+        };
+        assert_eq!(candidates(&a, &a), [0, 1, 2]);
+        assert_eq!(candidates(&a, &[9; 32]), [0, 1]);
+        assert_eq!(candidates(&[9; 32], &[9; 32]), [1]);
+        // Short keys can't be bucketed.
+        assert_eq!(candidates(&[9], &[9; 32]), [0, 1, 2]);
+    }
+}
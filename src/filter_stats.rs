@@ -0,0 +1,258 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{instance_metrics::InstanceMetrics, wall_clock::unix_millis, Filter},
+    prometheus::{Gauge, GaugeVec},
+    serde_json::{json, Map, Value},
+    solana_program::pubkey::Pubkey,
+    std::{
+        collections::{HashMap, HashSet},
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+    },
+};
+
+/// Matches of one `account_filters` or `program_filters` entry.
+struct EntryStats {
+    matches: AtomicU64,
+    /// Milliseconds since the epoch, 0 if never matched.
+    last_match_ms: AtomicU64,
+    /// Set for entries listed in `filter_stats_gauges`.
+    gauge: Option<Gauge>,
+}
+
+impl EntryStats {
+    fn record(&self) {
+        let now = unix_millis();
+        self.matches.fetch_add(1, Ordering::Relaxed);
+        self.last_match_ms.store(now, Ordering::Relaxed);
+        if let Some(gauge) = &self.gauge {
+            gauge.set(now as f64 / 1000.0);
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let last_match_ms = self.last_match_ms.load(Ordering::Relaxed);
+        json!({
+            "matches": self.matches.load(Ordering::Relaxed),
+            "last_matched_ms": (last_match_ms > 0).then_some(last_match_ms),
+        })
+    }
+}
+
+/// Entries of one list of a filter, fixed at load so recording never locks.
+struct EntryList {
+    list: &'static str,
+    entries: HashMap<[u8; 32], EntryStats>,
+}
+
+impl EntryList {
+    fn new(
+        filter: &str,
+        list: &'static str,
+        keys: &HashSet<[u8; 32]>,
+        gauge_keys: &HashSet<[u8; 32]>,
+        gauges: &GaugeVec,
+    ) -> Self {
+        let entries = keys
+            .iter()
+            .map(|key| {
+                let gauge = gauge_keys.contains(key).then(|| {
+                    gauges.with_label_values(&[
+                        filter,
+                        list,
+                        &Pubkey::new_from_array(*key).to_string(),
+                    ])
+                });
+                let stats = EntryStats {
+                    matches: AtomicU64::new(0),
+                    last_match_ms: AtomicU64::new(0),
+                    gauge,
+                };
+                (*key, stats)
+            })
+            .collect();
+        Self { list, entries }
+    }
+
+    fn record(&self, key: &[u8]) {
+        if let Some(stats) = <&[u8; 32]>::try_from(key)
+            .ok()
+            .and_then(|key| self.entries.get(key))
+        {
+            stats.record();
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        Value::Object(
+            self.entries
+                .iter()
+                .map(|(key, stats)| (Pubkey::new_from_array(*key).to_string(), stats.to_json()))
+                .collect::<Map<_, _>>(),
+        )
+    }
+
+    fn remove_gauges(&self, filter: &str, gauges: &GaugeVec) {
+        for (key, stats) in &self.entries {
+            if stats.gauge.is_some() {
+                let _ = gauges.remove_label_values(&[
+                    filter,
+                    self.list,
+                    &Pubkey::new_from_array(*key).to_string(),
+                ]);
+            }
+        }
+    }
+}
+
+struct FilterEntries {
+    name: String,
+    accounts: EntryList,
+    programs: EntryList,
+}
+
+/// Match count and last match time of each configured pubkey of each filter, by filter index.
+#[derive(Default)]
+pub struct FilterStats {
+    filters: Vec<FilterEntries>,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl std::fmt::Debug for FilterStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.filters.iter().map(|filter| &filter.name))
+            .finish()
+    }
+}
+
+impl FilterStats {
+    /// Entries in `gauge_keys` also export their last match time as a gauge.
+    pub fn new(
+        filters: &[Filter],
+        gauge_keys: &HashSet<[u8; 32]>,
+        metrics: Arc<InstanceMetrics>,
+    ) -> Self {
+        let gauges = &metrics.filter_entry_last_match_seconds;
+        let filters = filters
+            .iter()
+            .map(|filter| FilterEntries {
+                name: filter.name.clone(),
+                accounts: EntryList::new(
+                    &filter.name,
+                    "account_filters",
+                    &filter.account_filters,
+                    gauge_keys,
+                    gauges,
+                ),
+                programs: EntryList::new(
+                    &filter.name,
+                    "program_filters",
+                    &filter.program_filters,
+                    gauge_keys,
+                    gauges,
+                ),
+            })
+            .collect();
+        Self { filters, metrics }
+    }
+
+    /// Credit `key` if it is an `account_filters` entry of the filter at `index`.
+    pub fn record_account(&self, index: usize, key: &[u8]) {
+        if let Some(filter) = self.filters.get(index) {
+            filter.accounts.record(key);
+        }
+    }
+
+    /// Credit `key` if it is a `program_filters` entry of the filter at `index`.
+    pub fn record_program(&self, index: usize, key: &[u8]) {
+        if let Some(filter) = self.filters.get(index) {
+            filter.programs.record(key);
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        self.filters
+            .iter()
+            .map(|filter| {
+                json!({
+                    "name": filter.name,
+                    "account_filters": filter.accounts.to_json(),
+                    "program_filters": filter.programs.to_json(),
+                })
+            })
+            .collect()
+    }
+
+    /// Remove this filter set's gauges, so entries don't linger after a reload.
+    pub fn remove_gauges(&self) {
+        let gauges = &self.metrics.filter_entry_last_match_seconds;
+        for filter in &self.filters {
+            filter.accounts.remove_gauges(&filter.name, gauges);
+            filter.programs.remove_gauges(&filter.name, gauges);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::ConfigFilter};
+
+    #[test]
+    fn test_filter_stats() {
+        let account = Pubkey::new_from_array([1; 32]);
+        let program = Pubkey::new_from_array([2; 32]);
+        let mut filter = Filter::new(&ConfigFilter {
+            account_filters: vec![account.to_string()],
+            program_filters: vec![program.to_string()],
+            ..Default::default()
+        });
+        filter.name = "stats".to_owned();
+        let stats = FilterStats::new(
+            &[filter],
+            &HashSet::from([program.to_bytes()]),
+            Arc::default(),
+        );
+
+        stats.record_account(0, account.as_ref());
+        stats.record_account(0, account.as_ref());
+        // Not an entry, or not of that list, or not a filter.
+        stats.record_account(0, program.as_ref());
+        stats.record_account(0, &[1; 4]);
+        stats.record_account(1, account.as_ref());
+
+        let json = stats.to_json();
+        let entry = &json[0]["account_filters"][account.to_string()];
+        assert_eq!(entry["matches"], 2);
+        assert!(entry["last_matched_ms"].as_u64().unwrap() > 0);
+        assert_eq!(
+            json[0]["program_filters"],
+            json!({program.to_string(): {"matches": 0, "last_matched_ms": null}})
+        );
+
+        let gauges = &stats.metrics.filter_entry_last_match_seconds;
+        let gauge = gauges.with_label_values(&["stats", "program_filters", &program.to_string()]);
+        assert_eq!(gauge.get(), 0.0);
+        stats.record_program(0, program.as_ref());
+        assert!(gauge.get() > 0.0);
+        stats.remove_gauges();
+        assert!(gauges
+            .remove_label_values(&["stats", "program_filters", &program.to_string()])
+            .is_err());
+    }
+}
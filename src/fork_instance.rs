@@ -0,0 +1,117 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Correlation ids of the banks of a slot, telling apart replays of the same slot.
+//!
+//! The plugin interface only reports processed, confirmed and rooted slots, so a slot
+//! replayed after it died or was dumped is recognized by account updates or transactions
+//! arriving once the slot was already processed, its bank frozen.
+
+use {
+    crate::{instance_metrics::InstanceMetrics, slot_tracker::SlotTracker},
+    std::{
+        collections::hash_map::RandomState,
+        hash::{BuildHasher, Hasher},
+        sync::{Arc, Mutex},
+    },
+};
+
+/// Slots tracked at once, the least recently seen are forgotten first.
+const MAX_TRACKED_SLOTS: usize = 1024;
+
+#[derive(Debug)]
+struct Instance {
+    id: u64,
+    /// Whether the slot was processed since `id` was assigned.
+    frozen: bool,
+}
+
+impl Instance {
+    fn new() -> Self {
+        // Every `RandomState` is seeded with new random keys.
+        let id = RandomState::new().build_hasher().finish();
+        Self {
+            id: id.max(1),
+            frozen: false,
+        }
+    }
+}
+
+/// `fork_instance_id` of every slot, a random non-zero id per bank of the slot.
+#[derive(Debug)]
+pub struct ForkInstances {
+    slots: Mutex<SlotTracker<Instance>>,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl ForkInstances {
+    pub fn new(metrics: Arc<InstanceMetrics>) -> Self {
+        let slots = SlotTracker::new("fork_instance", MAX_TRACKED_SLOTS, &metrics);
+        Self {
+            slots: Mutex::new(slots),
+            metrics,
+        }
+    }
+
+    /// Id of the bank replaying `slot`, a new one if the slot was already processed.
+    pub fn replaying(&self, slot: u64) -> u64 {
+        let mut slots = self.slots.lock().unwrap();
+        let (instance, _) = slots.get_or_insert_with(slot, Instance::new);
+        if instance.frozen {
+            *instance = Instance::new();
+            self.metrics.fork_instance_resets_total.inc();
+        }
+        instance.id
+    }
+
+    /// Id of the last bank of `slot`, for events that follow its replay.
+    pub fn current(&self, slot: u64) -> u64 {
+        let mut slots = self.slots.lock().unwrap();
+        slots.get_or_insert_with(slot, Instance::new).0.id
+    }
+
+    /// Id of the bank of the processed `slot`. Later account updates or transactions of the
+    /// slot start a new one.
+    pub fn processed(&self, slot: u64) -> u64 {
+        let mut slots = self.slots.lock().unwrap();
+        let (instance, _) = slots.get_or_insert_with(slot, Instance::new);
+        instance.frozen = true;
+        instance.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replayed_slot() {
+        let instances = ForkInstances::new(Arc::default());
+        let first = instances.replaying(10);
+        assert_ne!(first, 0);
+        assert_eq!(instances.replaying(10), first);
+        assert_ne!(instances.replaying(11), first);
+        assert_eq!(instances.processed(10), first);
+        // Block metadata and later statuses belong to the processed bank.
+        assert_eq!(instances.current(10), first);
+        assert_eq!(instances.processed(10), first);
+
+        // The slot died and is replayed.
+        let second = instances.replaying(10);
+        assert_ne!(second, first);
+        assert_eq!(instances.current(10), second);
+        assert_eq!(instances.processed(10), second);
+        assert_eq!(instances.metrics.fork_instance_resets_total.get(), 1);
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `cluster_name` and `validator_identity`, telling apart the streams of several clusters or
+//! validators sharing a Kafka deployment: as headers with `emit_identity_headers`, in the
+//! `MessageWrapper` of wrapped messages and as constant labels of every metric.
+
+use {
+    crate::{Config, MessageWrapper},
+    solana_sdk::pubkey::Pubkey,
+    std::{collections::HashMap, str::FromStr},
+};
+
+/// Header carrying `cluster_name`.
+pub const CLUSTER_HEADER: &str = "cluster";
+
+/// Header carrying `validator_identity` in base58.
+pub const VALIDATOR_IDENTITY_HEADER: &str = "validator-identity";
+
+/// Cluster and validator messages and metrics are attributed to, either left out when unset.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Identity {
+    pub cluster_name: String,
+    pub validator_identity: Option<Pubkey>,
+}
+
+impl Identity {
+    /// Identity of `config`, whose `validator_identity` was validated.
+    pub fn new(config: &Config) -> Self {
+        Self {
+            cluster_name: config.cluster_name.clone(),
+            validator_identity: config
+                .validator_identity
+                .as_deref()
+                .and_then(|identity| Pubkey::from_str(identity).ok()),
+        }
+    }
+
+    /// Headers added to every message with `emit_identity_headers`.
+    pub fn headers(&self) -> Vec<(String, Vec<u8>)> {
+        let mut headers = Vec::new();
+        if !self.cluster_name.is_empty() {
+            headers.push((CLUSTER_HEADER.to_owned(), self.cluster_name.clone().into()));
+        }
+        if let Some(identity) = &self.validator_identity {
+            headers.push((
+                VALIDATOR_IDENTITY_HEADER.to_owned(),
+                identity.to_string().into(),
+            ));
+        }
+        headers
+    }
+
+    /// Labels of every metric in the registry.
+    pub fn const_labels(&self) -> HashMap<String, String> {
+        let mut labels = HashMap::new();
+        if !self.cluster_name.is_empty() {
+            labels.insert("cluster".to_owned(), self.cluster_name.clone());
+        }
+        if let Some(identity) = &self.validator_identity {
+            labels.insert("validator_identity".to_owned(), identity.to_string());
+        }
+        labels
+    }
+
+    /// Stamp `wrapper` with the identity.
+    pub fn stamp(&self, wrapper: &mut MessageWrapper) {
+        wrapper.cluster_name.clone_from(&self.cluster_name);
+        wrapper.validator_identity = self
+            .validator_identity
+            .map(|identity| identity.to_bytes().to_vec())
+            .unwrap_or_default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity() {
+        let identity = Identity::default();
+        assert!(identity.headers().is_empty());
+        assert!(identity.const_labels().is_empty());
+
+        let pubkey = Pubkey::new_unique();
+        let identity = Identity {
+            cluster_name: "testnet".to_owned(),
+            validator_identity: Some(pubkey),
+        };
+        assert_eq!(
+            identity.headers(),
+            [
+                (CLUSTER_HEADER.to_owned(), b"testnet".to_vec()),
+                (
+                    VALIDATOR_IDENTITY_HEADER.to_owned(),
+                    pubkey.to_string().into_bytes()
+                ),
+            ]
+        );
+        let mut wrapper = MessageWrapper::default();
+        identity.stamp(&mut wrapper);
+        assert_eq!(wrapper.cluster_name, "testnet");
+        assert_eq!(wrapper.validator_identity, pubkey.to_bytes());
+    }
+}
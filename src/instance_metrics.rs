@@ -0,0 +1,547 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Metrics of one instance of the plugin, for validators loading it more than once. Every
+//! metric derived from an instance's producer or state lives in a registry of its own per
+//! instance, labelled with its `instance_name`, so instances don't count or overwrite each
+//! other's. The few process wide metrics left in `prom` are registered along.
+
+use {
+    crate::{
+        identity::Identity,
+        prom::{new_registry, register_metrics, register_process_metrics},
+        Config,
+    },
+    prometheus::{
+        Counter, CounterVec, Gauge, GaugeVec, HistogramOpts, HistogramVec, IntCounter,
+        IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    },
+    std::{
+        collections::HashMap,
+        fmt::{Debug, Formatter},
+    },
+};
+
+/// Label of every metric of an instance with its `instance_name`.
+pub const INSTANCE_LABEL: &str = "instance";
+
+/// Buckets from 1us to ~1s for hot path timings.
+const TIMING_BUCKETS: &[f64] = &[
+    1e-6, 4e-6, 16e-6, 64e-6, 256e-6, 1e-3, 4e-3, 16e-3, 64e-3, 256e-3, 1.0,
+];
+
+/// Buckets from 1ms up to the default `message.timeout.ms` for delivery latency.
+const DELIVERY_BUCKETS: &[f64] = &[
+    1e-3, 2.5e-3, 5e-3, 10e-3, 25e-3, 50e-3, 100e-3, 250e-3, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// Buckets of compute unit prices in micro-lamports, from no priority fee up.
+const COMPUTE_UNIT_PRICE_BUCKETS: &[f64] = &[0.0, 1.0, 10.0, 100.0, 1e3, 1e4, 1e5, 1e6, 1e7, 1e8];
+
+/// Buckets of compute units consumed, up to the transaction maximum of 1.4M.
+const COMPUTE_UNITS_BUCKETS: &[f64] = &[1e3, 5e3, 1e4, 2.5e4, 5e4, 1e5, 2e5, 4e5, 8e5, 1.4e6];
+
+/// Filters evaluated per event, up to configs with a few dozen filters.
+const FILTERS_EVALUATED_BUCKETS: &[f64] = &[0.0, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0];
+
+macro_rules! instance_metrics {
+    ($($name:ident: $ty:ty = $metric:expr;)*) => {
+        /// Metrics of one plugin instance, gathered from `registry`.
+        pub struct InstanceMetrics {
+            registry: Registry,
+            $(pub $name: $ty,)*
+        }
+
+        impl InstanceMetrics {
+            fn with_labels(labels: HashMap<String, String>) -> Self {
+                let metrics = Self {
+                    registry: new_registry(labels),
+                    $($name: $metric.unwrap(),)*
+                };
+                $(
+                    metrics
+                        .registry
+                        .register(Box::new(metrics.$name.clone()))
+                        .expect("collector can't be registered");
+                )*
+                // `version` is filled in once per process, by whichever instance comes first.
+                register_metrics();
+                register_process_metrics(&metrics.registry);
+                metrics
+            }
+        }
+    };
+}
+
+instance_metrics! {
+    upload_accounts_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_accounts_total", "Status of uploaded accounts"),
+        &["status"]
+    );
+
+    upload_slots_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_slots_total", "Status of uploaded slots"),
+        &["status"]
+    );
+
+    upload_transactions_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_transactions_total", "Status of uploaded transactions"),
+        &["status"]
+    );
+
+    upload_transaction_index_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_transaction_index_total", "Status of uploaded transaction index entries"),
+        &["status"]
+    );
+
+    upload_account_states_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_account_states_total", "Status of account states and tombstones sent to state topics"),
+        &["kind", "status"]
+    );
+
+    upload_blocks_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_blocks_total", "Status of uploaded blocks"),
+        &["status"]
+    );
+
+    blockhash_invalid_total: IntCounter = IntCounter::new(
+        "blockhash_invalid_total", "Block events with a blockhash or parent blockhash that isn't base58 of 32 bytes"
+    );
+
+    account_updates_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("account_updates_total", "Account updates received after startup filtering"),
+        &["is_startup"]
+    );
+
+    account_updates_missing_txn_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("account_updates_missing_txn_total", "Account updates received without a causing transaction"),
+        &["is_startup"]
+    );
+
+    account_updates_skipped_missing_txn_total: IntCounter = IntCounter::new(
+        "account_updates_skipped_missing_txn_total",
+        "Account updates skipped by filters requiring a causing transaction"
+    );
+
+    slot_statuses_unknown_total: IntCounter = IntCounter::new(
+        "slot_statuses_unknown_total", "Slot statuses unknown to this build, published as Unknown"
+    );
+
+    account_updates_skipped_unchanged_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("account_updates_skipped_unchanged_total", "Account updates skipped as their publish_if_changed_bytes_at ranges didn't change"),
+        &["filter"]
+    );
+
+    account_updates_missing_txn_ratio: Gauge = Gauge::new(
+        "account_updates_missing_txn_ratio",
+        "Share of live account updates without a causing transaction over the last metrics log interval"
+    );
+
+    transactions_truncated_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("transactions_truncated_total", "Transactions with lists truncated by max_inner_instructions or max_log_messages"),
+        &["field"]
+    );
+
+    transactions_sampled_out_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("transactions_sampled_out_total", "Matching transactions left out by a filter's transaction_sample_rate"),
+        &["filter"]
+    );
+
+    publish_errors_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("publish_errors_total", "Failed sends of account updates and transactions by topic"),
+        &["topic"]
+    );
+
+    transactions_filtered_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("transactions_filtered_total", "Transactions evaluated by each filter with a transaction topic, by the reason they were left out or published"),
+        &["filter", "reason"]
+    );
+
+    transaction_errors_excluded_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("transaction_errors_excluded_total", "Failed transactions left out by their error, by filter and matching exclude entry or not_included"),
+        &["filter", "reason"]
+    );
+
+    upload_firehose_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_firehose_total", "Status of events mirrored to the firehose topic"),
+        &["event", "status"]
+    );
+
+    upload_checkpoints_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_checkpoints_total", "Status of uploaded checkpoint markers"),
+        &["status"]
+    );
+
+    upload_program_stats_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_program_stats_total", "Status of uploaded program activity events"),
+        &["status"]
+    );
+
+    upload_alt_failures_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_alt_failures_total", "Status of uploaded address lookup table resolution failure events"),
+        &["status"]
+    );
+
+    upload_producer_registrations_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_producer_registrations_total", "Status of uploaded producer registry records"),
+        &["status"]
+    );
+
+    upload_slot_contention_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_slot_contention_total", "Status of uploaded slot contention events"),
+        &["status"]
+    );
+
+    filter_config_hash: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("filter_config_hash", "Hash of the effective filter configuration"),
+        &["hash"]
+    );
+
+    duplicate_sends_skipped_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("duplicate_sends_skipped_total", "Sends skipped as another filter already published the event to the same topic"),
+        &["type"]
+    );
+
+    outstanding_bytes_exceeded_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("outstanding_bytes_exceeded_total", "Messages sent beyond max_outstanding_bytes by topic and action"),
+        &["topic", "action"]
+    );
+
+    account_pacing_delay_seconds_total: Counter = Counter::new(
+        "account_pacing_delay_seconds_total",
+        "Delay inserted before account updates while brokers throttle"
+    );
+
+    account_chunks_total: IntCounter = IntCounter::new(
+        "account_chunks_total", "Messages account updates over chunk_threshold_bytes were split into"
+    );
+
+    tx_meta_field_present_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("tx_meta_field_present_total", "Notified transactions with each optional field of their status meta set"),
+        &["field"]
+    );
+
+    transaction_balance_length_mismatches_total: IntCounter = IntCounter::new(
+        "transaction_balance_length_mismatches_total", "Transactions whose pre balances, post balances and account keys differ in length"
+    );
+
+    tx_meta_required_field_missing_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("tx_meta_required_field_missing_total", "Notified transactions without a field of their status meta listed in require_meta_fields"),
+        &["field"]
+    );
+
+    filters_evaluated_per_event: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("filters_evaluated_per_event", "Filters evaluated per account update or transaction, after the filter index left out those that can't match").buckets(FILTERS_EVALUATED_BUCKETS.to_vec()),
+        &["event"]
+    );
+
+    account_updates_suppressed_budget_total: IntCounter = IntCounter::new(
+        "account_updates_suppressed_budget_total", "Account updates suppressed as their slot exceeded max_account_bytes_per_slot"
+    );
+
+    slots_over_account_budget_total: IntCounter = IntCounter::new(
+        "slots_over_account_budget_total", "Slots whose account updates exceeded max_account_bytes_per_slot"
+    );
+
+    kafka_stats_detailed: IntGauge = IntGauge::new(
+        "kafka_stats_detailed", "1 while every librdkafka statistics are exported as brokers are unhealthy, 0 while only every kafka_stats_healthy_every-th are"
+    );
+
+    rooted_slots_without_block_total: IntCounter = IntCounter::new(
+        "rooted_slots_without_block_total", "Rooted statuses published without a block by enrich_rooted_slot_with_block"
+    );
+
+    slot_tracker_entries: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("slot_tracker_entries", "Slots held by per slot state"),
+        &["tracker"]
+    );
+
+    slot_tracker_evictions_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("slot_tracker_evictions_total", "Least recently used slots evicted from per slot state at capacity"),
+        &["tracker"]
+    );
+
+    large_accounts_published_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("large_accounts_published_total", "Accounts published with more data than their filter's warn_data_size_bytes"),
+        &["topic"]
+    );
+
+    largest_account_bytes: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("largest_account_bytes", "Largest account data published"),
+        &["topic"]
+    );
+
+    events_skipped_old_slot_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("events_skipped_old_slot_total", "Events skipped for slots older than skip_slots_older_than"),
+        &["event"]
+    );
+
+    explicit_partition_sends_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("explicit_partition_sends_total", "Account updates sent with explicit partitioning by partition"),
+        &["partition"]
+    );
+
+    leader_schedule_fetches_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("leader_schedule_fetches_total", "Status of leader schedule refreshes from leader_schedule_rpc"),
+        &["status"]
+    );
+
+    hottest_account_write_locks: IntGauge = IntGauge::new(
+        "hottest_account_write_locks",
+        "Transactions write locking the most contended account of the last processed slot"
+    );
+
+    hottest_account_slot: IntGauge = IntGauge::new(
+        "hottest_account_slot",
+        "Slot of hottest_account_write_locks"
+    );
+
+    filter_account_rate_1m: GaugeVec = GaugeVec::new(
+        Opts::new("filter_account_rate_1m", "Account updates published per second by a filter over the last minute"),
+        &["filter"]
+    );
+
+    filter_transaction_rate_1m: GaugeVec = GaugeVec::new(
+        Opts::new("filter_transaction_rate_1m", "Transactions published per second by a filter over the last minute"),
+        &["filter"]
+    );
+
+    filter_entry_last_match_seconds: GaugeVec = GaugeVec::new(
+        Opts::new("filter_entry_last_match_timestamp_seconds", "Unix time a filter's account or program entry last matched"),
+        &["filter", "list", "pubkey"]
+    );
+
+    topic_paused: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("topic_paused", "Whether a topic is paused through /admin/pause"),
+        &["topic"]
+    );
+
+    topic_pause_buffered_bytes: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("topic_pause_buffered_bytes", "Bytes of messages buffered for a paused topic"),
+        &["topic"]
+    );
+
+    topic_sequence: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("topic_sequence", "Last sequence number assigned to an event published to a topic"),
+        &["topic"]
+    );
+
+    paused_messages_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("paused_messages_total", "Messages for paused topics by action"),
+        &["topic", "action"]
+    );
+
+    http_connections_rejected_total: IntCounter = IntCounter::new(
+        "http_connections_rejected_total", "Connections to the metrics endpoint rejected with a 503 over prometheus_max_connections"
+    );
+
+    plugin_loaded: IntGauge = IntGauge::new(
+        "plugin_loaded", "Whether the plugin is loaded"
+    );
+
+    credential_rotations_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("credential_rotations_total", "Status of producer swaps after credential changes"),
+        &["status"]
+    );
+
+    filter_eval_seconds: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("filter_eval_seconds", "Time spent evaluating filters").buckets(TIMING_BUCKETS.to_vec()),
+        &["event"]
+    );
+
+    event_build_seconds: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("event_build_seconds", "Time spent building events").buckets(TIMING_BUCKETS.to_vec()),
+        &["event"]
+    );
+
+    event_send_seconds: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("event_send_seconds", "Time spent serializing and enqueueing events").buckets(TIMING_BUCKETS.to_vec()),
+        &["event"]
+    );
+
+    transaction_compute_unit_price: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("transaction_compute_unit_price_microlamports", "Compute unit price of non-vote transactions").buckets(COMPUTE_UNIT_PRICE_BUCKETS.to_vec()),
+        &["matched"]
+    );
+
+    transaction_compute_units_consumed: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("transaction_compute_units_consumed", "Compute units consumed by non-vote transactions").buckets(COMPUTE_UNITS_BUCKETS.to_vec()),
+        &["matched"]
+    );
+
+    transaction_priority_fee: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("transaction_priority_fee_microlamports", "Compute unit price quantiles of non-vote transactions over the last fee_market_window_slots"),
+        &["matched", "quantile"]
+    );
+
+    highest_rooted_slot: IntGauge = IntGauge::new(
+        "highest_rooted_slot", "Highest slot seen with rooted status"
+    );
+
+    last_slot: IntGauge = IntGauge::new(
+        "last_slot", "Highest slot seen in slot status updates"
+    );
+
+    delivery_errors_total: IntCounter = IntCounter::new(
+        "delivery_errors_total", "Messages Kafka failed to deliver"
+    );
+
+    kafka_delivery_latency_seconds: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("kafka_delivery_latency_seconds", "Time from enqueueing a message to its acknowledgement").buckets(DELIVERY_BUCKETS.to_vec()),
+        &["topic"]
+    );
+
+    kafka_delivery_slo_exceeded_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("kafka_delivery_slo_exceeded_total", "Messages acknowledged later than delivery_latency_slo_ms"),
+        &["topic"]
+    );
+
+    kafka_delivery_timeouts_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("kafka_delivery_timeouts_total", "Messages not delivered within message.timeout.ms"),
+        &["topic"]
+    );
+
+    kafka_outstanding_bytes: IntGauge = IntGauge::new(
+        "kafka_outstanding_bytes", "Key and payload bytes enqueued and not yet delivered or failed"
+    );
+
+    kafka_outstanding_bytes_max: IntGauge = IntGauge::new(
+        "kafka_outstanding_bytes_max", "Highest kafka_outstanding_bytes seen"
+    );
+
+    callback_deadline_exceeded_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("callback_deadline_exceeded_total", "Callbacks that passed callback_deadline_ms and were published partially"),
+        &["callback"]
+    );
+
+    callback_deadline_dropped_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("callback_deadline_dropped_total", "Messages not sent as their callback passed callback_deadline_ms"),
+        &["topic"]
+    );
+
+    rooted_barrier_statuses_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("rooted_barrier_statuses_total", "Rooted slot statuses held back by rooted_barrier by action"),
+        &["action"]
+    );
+
+    kafka_queue_messages: IntGauge = IntGauge::new(
+        "kafka_queue_messages", "Messages waiting in the librdkafka producer queue"
+    );
+
+    partition_count_changes_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("partition_count_changes_total", "Partition count changes of explicitly or sticky partitioned topics by topic"),
+        &["topic"]
+    );
+
+    fork_instance_resets_total: IntCounter = IntCounter::new(
+        "fork_instance_resets_total",
+        "Slots replayed after they were processed, getting a new fork_instance_id"
+    );
+
+    events_before_slot_status_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("events_before_slot_status_total", "Live events notified before any status of their slot by event type"),
+        &["event_type"]
+    );
+
+    plugin_standby: IntGauge = IntGauge::new(
+        "plugin_standby",
+        "1 while in standby, producing nothing"
+    );
+
+    standby_messages_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("standby_messages_total", "Messages serialized but not produced in standby by topic"),
+        &["topic"]
+    );
+
+    ordering_violations_total: IntCounter = IntCounter::new(
+        "ordering_violations_total",
+        "Account updates produced with a lower slot and write version than the last of their key"
+    );
+
+    kafka_broker_throttle_ms: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("kafka_broker_throttle_ms", "Longest throttle time of a broker in the latest statistics"),
+        &["broker"]
+    );
+
+    kafka_broker_throttle_ms_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("kafka_broker_throttle_ms_total", "Throttle time of a broker's responses"),
+        &["broker"]
+    );
+
+    kafka_stats: GaugeVec = GaugeVec::new(
+        Opts::new("kafka_stats", "librdkafka metrics"),
+        &["broker", "metric"]
+    );
+
+    zstd_dictionaries_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("zstd_dictionaries_total", "Zstd dictionaries of account owners trained or failed to train"),
+        &["status"]
+    );
+
+    rooted_only_messages_total: IntCounterVec = IntCounterVec::new(
+        Opts::new("rooted_only_messages_total", "Messages held back by rooted_only by action"),
+        &["action"]
+    );
+
+    rooted_only_buffered_bytes: IntGauge = IntGauge::new(
+        "rooted_only_buffered_bytes", "Bytes of messages held back by rooted_only until their slot is rooted"
+    );
+
+    kafka_stats_skipped_total: IntCounter = IntCounter::new(
+        "kafka_stats_skipped_total", "librdkafka statistics skipped within kafka_stats_min_interval_ms"
+    );
+
+    plugin_memory_bytes: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("plugin_memory_bytes", "Approximate heap bytes held by plugin buffers and caches"),
+        &["component"]
+    );
+
+    plugin_threads: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("plugin_threads", "Threads owned by the plugin"),
+        &["kind"]
+    );
+
+    callback_seconds_total: CounterVec = CounterVec::new(
+        Opts::new("callback_seconds_total", "Time spent inside Geyser callbacks"),
+        &["callback"]
+    );
+}
+
+impl InstanceMetrics {
+    /// Metrics of the instance loaded with `config`.
+    pub fn new(config: &Config) -> Self {
+        let mut labels = Identity::new(config).const_labels();
+        if !config.instance_name.is_empty() {
+            labels.insert(INSTANCE_LABEL.to_owned(), config.instance_name.clone());
+        }
+        Self::with_labels(labels)
+    }
+
+    /// Registry of the instance metrics and the process wide ones.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+}
+
+impl Default for InstanceMetrics {
+    fn default() -> Self {
+        Self::with_labels(HashMap::new())
+    }
+}
+
+impl Debug for InstanceMetrics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InstanceMetrics").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,473 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{
+        adaptive_stats::{AdaptiveStats, AdaptiveStatsOptions},
+        instance_metrics::InstanceMetrics,
+        outstanding::OutstandingBytes,
+        producer_advisor::{Advisory, ProducerAdvisor},
+        rooted_barrier::SlotDeliveries,
+        throttle::ThrottlePacer,
+    },
+    log::error,
+    prometheus::Gauge,
+    rdkafka::statistics::{Broker, Statistics, Window},
+    std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+};
+
+/// Per broker librdkafka statistics exported as the `metric` label of `kafka_stats`, in the
+/// order of `broker_values`.
+pub const KAFKA_BROKER_STATS: &[&str] = &[
+    "outbuf_cnt",
+    "outbuf_msg_cnt",
+    "waitresp_cnt",
+    "waitresp_msg_cnt",
+    "tx",
+    "txerrs",
+    "txretries",
+    "req_timeouts",
+    "int_latency.min",
+    "int_latency.max",
+    "int_latency.avg",
+    "int_latency.sum",
+    "int_latency.cnt",
+    "int_latency.stddev",
+    "int_latency.hdrsize",
+    "int_latency.p50",
+    "int_latency.p75",
+    "int_latency.p90",
+    "int_latency.p95",
+    "int_latency.p99",
+    "int_latency.p99_99",
+    "int_latency.outofrange",
+    "outbuf_latency.min",
+    "outbuf_latency.max",
+    "outbuf_latency.avg",
+    "outbuf_latency.sum",
+    "outbuf_latency.cnt",
+    "outbuf_latency.stddev",
+    "outbuf_latency.hdrsize",
+    "outbuf_latency.p50",
+    "outbuf_latency.p75",
+    "outbuf_latency.p90",
+    "outbuf_latency.p95",
+    "outbuf_latency.p99",
+    "outbuf_latency.p99_99",
+    "outbuf_latency.outofrange",
+];
+
+/// Values of `KAFKA_BROKER_STATS` of `broker`, `None` for latency windows it doesn't report.
+fn broker_values(broker: &Broker) -> Vec<Option<f64>> {
+    fn window(window: &Option<Window>) -> [Option<f64>; 14] {
+        let values = window.as_ref().map(|w| {
+            [
+                w.min as f64,
+                w.max as f64,
+                w.avg as f64,
+                w.sum as f64,
+                w.cnt as f64,
+                w.stddev as f64,
+                w.hdrsize as f64,
+                w.p50 as f64,
+                w.p75 as f64,
+                w.p90 as f64,
+                w.p95 as f64,
+                w.p99 as f64,
+                w.p99_99 as f64,
+                w.outofrange as f64,
+            ]
+        });
+        values.map_or([None; 14], |values| values.map(Some))
+    }
+
+    let counters = [
+        broker.outbuf_cnt as f64,
+        broker.outbuf_msg_cnt as f64,
+        broker.waitresp_cnt as f64,
+        broker.waitresp_msg_cnt as f64,
+        broker.tx as f64,
+        broker.txerrs as f64,
+        broker.txretries as f64,
+        broker.req_timeouts as f64,
+    ];
+    counters
+        .into_iter()
+        .map(Some)
+        .chain(window(&broker.int_latency))
+        .chain(window(&broker.outbuf_latency))
+        .collect()
+}
+
+/// How librdkafka statistics are exported.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KafkaStatsOptions {
+    /// Statistics arriving sooner than this after the last exported ones are skipped.
+    pub min_interval: Duration,
+    /// Names in `KAFKA_BROKER_STATS` to export, all if unset.
+    pub metrics: Option<Vec<String>>,
+    /// Period of the producer advisor, disabled if unset.
+    pub advisor_interval: Option<Duration>,
+    /// Export only some statistics while brokers are healthy, every one if unset.
+    pub adaptive: Option<AdaptiveStatsOptions>,
+}
+
+#[derive(Debug, Default)]
+struct ExportState {
+    last_export: Option<Instant>,
+    /// Gauges of each broker by position in `KAFKA_BROKER_STATS`, `None` if not exported.
+    gauges: HashMap<String, Vec<Option<Gauge>>>,
+    /// Statistics JSON last exported, served on `/debug/kafka-stats`.
+    raw: Option<String>,
+    adaptive: Option<AdaptiveStats>,
+}
+
+/// Exports librdkafka statistics to `kafka_stats`, rate limited and restricted to an allowlist.
+#[derive(Debug)]
+pub struct KafkaStats {
+    min_interval: Duration,
+    /// Whether each of `KAFKA_BROKER_STATS` is exported.
+    exported: Vec<bool>,
+    advisor: Option<ProducerAdvisor>,
+    /// Fed the broker throttle times, for `respect_broker_throttle`.
+    pacer: Arc<ThrottlePacer>,
+    outstanding: OutstandingBytes,
+    slot_deliveries: Arc<SlotDeliveries>,
+    state: Mutex<ExportState>,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl Default for KafkaStats {
+    fn default() -> Self {
+        Self::new(&KafkaStatsOptions::default(), Arc::default())
+    }
+}
+
+impl KafkaStats {
+    /// Statistics exported to `metrics`, which the producer context also records deliveries in.
+    pub fn new(options: &KafkaStatsOptions, metrics: Arc<InstanceMetrics>) -> Self {
+        let exported = KAFKA_BROKER_STATS
+            .iter()
+            .map(|name| match &options.metrics {
+                Some(metrics) => metrics.iter().any(|metric| metric == name),
+                None => true,
+            })
+            .collect();
+        Self {
+            min_interval: options.min_interval,
+            exported,
+            advisor: options.advisor_interval.map(ProducerAdvisor::new),
+            pacer: Arc::default(),
+            outstanding: OutstandingBytes::new(Arc::clone(&metrics)),
+            slot_deliveries: Arc::default(),
+            state: Mutex::new(ExportState {
+                adaptive: options
+                    .adaptive
+                    .map(|adaptive| AdaptiveStats::new(adaptive, &metrics)),
+                ..Default::default()
+            }),
+            metrics,
+        }
+    }
+
+    /// Export the statistics JSON `raw` received at `now`, unless it arrived within
+    /// `min_interval` of the last exported one or is skipped by adaptive sampling. Returns
+    /// whether it was exported.
+    pub fn export_raw(&self, raw: &[u8], now: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .last_export
+            .is_some_and(|last| now.saturating_duration_since(last) < self.min_interval)
+            || state
+                .adaptive
+                .as_mut()
+                .is_some_and(|adaptive| !adaptive.sample())
+        {
+            self.metrics.kafka_stats_skipped_total.inc();
+            return false;
+        }
+        state.last_export = Some(now);
+        state.raw = Some(String::from_utf8_lossy(raw).into_owned());
+        match serde_json::from_slice(raw) {
+            Ok(statistics) => {
+                self.export(&mut state, statistics, now);
+                true
+            }
+            Err(error) => {
+                error!("Could not parse statistics JSON: {error}");
+                false
+            }
+        }
+    }
+
+    fn export(&self, state: &mut ExportState, statistics: Statistics, now: Instant) {
+        self.metrics
+            .kafka_queue_messages
+            .set(statistics.msg_cnt as i64);
+        if let Some(adaptive) = &mut state.adaptive {
+            adaptive.observe(&statistics, now);
+        }
+        if let Some(advisor) = &self.advisor {
+            advisor.observe(&statistics, now);
+        }
+        let mut throttle_ms = 0;
+        for (name, broker) in &statistics.brokers {
+            // Throttle times of the requests since the previous statistics.
+            let Some(throttle) = &broker.throttle else {
+                continue;
+            };
+            self.metrics
+                .kafka_broker_throttle_ms
+                .with_label_values(&[name])
+                .set(throttle.max);
+            self.metrics
+                .kafka_broker_throttle_ms_total
+                .with_label_values(&[name])
+                .inc_by(throttle.sum.max(0) as u64);
+            throttle_ms = throttle_ms.max(throttle.max.max(0) as u64);
+        }
+        self.pacer.observe(throttle_ms, now);
+        for (name, broker) in statistics.brokers {
+            let gauges = state.gauges.entry(name).or_insert_with_key(|name| {
+                KAFKA_BROKER_STATS
+                    .iter()
+                    .zip(&self.exported)
+                    .map(|(metric, exported)| {
+                        exported.then(|| {
+                            self.metrics
+                                .kafka_stats
+                                .with_label_values(&[name.as_str(), metric])
+                        })
+                    })
+                    .collect()
+            });
+            for (gauge, value) in gauges.iter().zip(broker_values(&broker)) {
+                if let (Some(gauge), Some(value)) = (gauge, value) {
+                    gauge.set(value);
+                }
+            }
+        }
+    }
+
+    /// Latest advisory of the producer advisor, if enabled and a period completed.
+    pub fn advisory(&self) -> Option<Advisory> {
+        self.advisor.as_ref().and_then(ProducerAdvisor::latest)
+    }
+
+    /// Pacing of account updates by the broker throttle times.
+    pub fn pacer(&self) -> Arc<ThrottlePacer> {
+        Arc::clone(&self.pacer)
+    }
+
+    /// Bytes enqueued and not yet delivered, by every producer sharing these statistics.
+    pub fn outstanding(&self) -> &OutstandingBytes {
+        &self.outstanding
+    }
+
+    /// Messages outstanding per slot for `rooted_barrier`, by every producer sharing these
+    /// statistics.
+    pub fn slot_deliveries(&self) -> &Arc<SlotDeliveries> {
+        &self.slot_deliveries
+    }
+
+    /// Metrics of the instance owning the producer.
+    pub fn metrics(&self) -> &Arc<InstanceMetrics> {
+        &self.metrics
+    }
+
+    /// Statistics JSON last exported, if any was received.
+    pub fn raw(&self) -> Option<String> {
+        self.state.lock().unwrap().raw.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, serde_json::json};
+
+    /// Statistics of `broker` with `tx` requests sent and an internal latency window.
+    fn statistics(broker: &str, tx: u64) -> Vec<u8> {
+        let window = json!({
+            "min": 1, "max": 9, "avg": 5, "sum": 50, "cnt": 10, "stddev": 2, "hdrsize": 0,
+            "p50": 5, "p75": 6, "p90": 8, "p95": 9, "p99": 9, "p99_99": 9, "outofrange": 0,
+        });
+        let broker = json!({
+            "name": broker, "nodeid": 1, "nodename": broker, "source": "configured",
+            "state": "UP", "stateage": 0, "outbuf_cnt": 0, "outbuf_msg_cnt": 0,
+            "waitresp_cnt": 0, "waitresp_msg_cnt": 0, "tx": tx, "txbytes": 0,
+            "txerrs": 0, "txretries": 0, "txidle": 0, "req_timeouts": 0, "rx": 0, "rxbytes": 0,
+            "rxerrs": 0, "rxcorriderrs": 0, "rxpartial": 0, "rxidle": 0, "zbuf_grow": 0,
+            "buf_grow": 0, "wakeups": 0, "connects": 0, "disconnects": 0,
+            "int_latency": window, "throttle": window, "req": {}, "toppars": {},
+        });
+        let mut statistics = json!({
+            "name": "producer", "client_id": "test", "type": "producer", "ts": 0, "time": 0,
+            "age": 0, "replyq": 0, "msg_cnt": 3, "msg_size": 0, "msg_max": 0,
+            "msg_size_max": 0, "tx": tx, "tx_bytes": 0, "rx": 0, "rx_bytes": 0, "txmsgs": 0,
+            "txmsg_bytes": 0, "rxmsgs": 0, "rxmsg_bytes": 0, "simple_cnt": 0,
+            "metadata_cache_cnt": 0, "topics": {},
+        });
+        statistics["brokers"] = json!({ broker["name"].as_str().unwrap(): broker });
+        statistics.to_string().into_bytes()
+    }
+
+    fn stat(stats: &KafkaStats, broker: &str, metric: &str) -> f64 {
+        stats
+            .metrics()
+            .kafka_stats
+            .with_label_values(&[broker, metric])
+            .get()
+    }
+
+    #[test]
+    fn test_broker_values() {
+        let statistics: Statistics = serde_json::from_slice(&statistics("b", 4)).unwrap();
+        let values = broker_values(&statistics.brokers["b"]);
+        assert_eq!(values.len(), KAFKA_BROKER_STATS.len());
+        let value =
+            |name: &str| values[KAFKA_BROKER_STATS.iter().position(|n| *n == name).unwrap()];
+        assert_eq!(value("tx"), Some(4.0));
+        assert_eq!(value("int_latency.p90"), Some(8.0));
+        assert_eq!(value("outbuf_latency.p90"), None);
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let stats = KafkaStats::new(
+            &KafkaStatsOptions {
+                min_interval: Duration::ZERO,
+                metrics: Some(vec!["tx".to_owned(), "int_latency.p99".to_owned()]),
+                ..Default::default()
+            },
+            Arc::default(),
+        );
+        assert!(stats.export_raw(&statistics("allow:9092/1", 7), Instant::now()));
+        assert_eq!(stat(&stats, "allow:9092/1", "tx"), 7.0);
+        assert_eq!(stat(&stats, "allow:9092/1", "int_latency.p99"), 9.0);
+        // Never created for the broker.
+        assert_eq!(stat(&stats, "allow:9092/1", "int_latency.p50"), 0.0);
+        assert_eq!(stat(&stats, "allow:9092/1", "txerrs"), 0.0);
+        assert!(stats.raw().unwrap().contains("allow:9092/1"));
+
+        let all = KafkaStats::default();
+        assert!(all.export_raw(&statistics("all:9092/1", 7), Instant::now()));
+        assert_eq!(stat(&all, "all:9092/1", "int_latency.p50"), 5.0);
+    }
+
+    #[test]
+    fn test_min_interval() {
+        let stats = KafkaStats::new(
+            &KafkaStatsOptions {
+                min_interval: Duration::from_secs(10),
+                ..Default::default()
+            },
+            Arc::default(),
+        );
+        let start = Instant::now();
+        assert!(stats.export_raw(&statistics("interval:9092/1", 1), start));
+        assert!(!stats.export_raw(
+            &statistics("interval:9092/1", 2),
+            start + Duration::from_secs(5)
+        ));
+        assert_eq!(stat(&stats, "interval:9092/1", "tx"), 1.0);
+        assert_eq!(stats.metrics().kafka_stats_skipped_total.get(), 1);
+        // Measured from the last export, not the last skip.
+        assert!(stats.export_raw(
+            &statistics("interval:9092/1", 3),
+            start + Duration::from_secs(10)
+        ));
+        assert_eq!(stat(&stats, "interval:9092/1", "tx"), 3.0);
+        assert!(stats.raw().unwrap().contains(r#""tx":3"#));
+
+        // Unparseable statistics are kept for inspection.
+        assert!(!stats.export_raw(b"{", start + Duration::from_secs(20)));
+        assert_eq!(stats.raw().unwrap(), "{");
+    }
+
+    #[test]
+    fn test_adaptive() {
+        let stats = KafkaStats::new(
+            &KafkaStatsOptions {
+                adaptive: Some(AdaptiveStatsOptions {
+                    healthy_every: 2,
+                    outbuf_threshold: 100,
+                    cool_down: Duration::from_secs(60),
+                }),
+                ..Default::default()
+            },
+            Arc::default(),
+        );
+        let now = Instant::now();
+        let exported: Vec<bool> = (1..=5)
+            .map(|tx| stats.export_raw(&statistics("adaptive:9092/1", tx), now))
+            .collect();
+        assert_eq!(exported, [true, false, true, false, true]);
+        assert_eq!(stat(&stats, "adaptive:9092/1", "tx"), 5.0);
+    }
+
+    #[test]
+    fn test_throttle() {
+        let stats = KafkaStats::default();
+        let total = || {
+            stats
+                .metrics()
+                .kafka_broker_throttle_ms_total
+                .with_label_values(&["throttle:9092/1"])
+                .get()
+        };
+        let start = Instant::now();
+        stats.export_raw(&statistics("throttle:9092/1", 1), start);
+        stats.export_raw(&statistics("throttle:9092/1", 2), start);
+        assert_eq!(
+            stats
+                .metrics()
+                .kafka_broker_throttle_ms
+                .with_label_values(&["throttle:9092/1"])
+                .get(),
+            9
+        );
+        assert_eq!(total(), 100);
+        assert!(stats.pacer().delay(start) > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_advisor() {
+        assert_eq!(KafkaStats::default().advisory(), None);
+        let stats = KafkaStats::new(
+            &KafkaStatsOptions {
+                advisor_interval: Some(Duration::from_secs(60)),
+                ..Default::default()
+            },
+            Arc::default(),
+        );
+        let start = Instant::now();
+        stats.export_raw(&statistics("advisor:9092/1", 1), start);
+        assert_eq!(stats.advisory(), None);
+        stats.export_raw(
+            &statistics("advisor:9092/1", 2),
+            start + Duration::from_secs(60),
+        );
+        assert_eq!(
+            stats.advisory(),
+            Some(Advisory {
+                samples: 2,
+                suggestions: vec![],
+            })
+        );
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{
+        instance_metrics::InstanceMetrics,
+        rate::{Clock, MonotonicClock},
+    },
+    log::warn,
+    solana_program::pubkey::Pubkey,
+    std::sync::{Arc, Mutex},
+};
+
+/// Large account warnings logged per filter and hour.
+const WARNINGS_PER_HOUR: u32 = 10;
+
+const HOUR_SECS: u64 = 3600;
+
+/// Allows `limit` events per fixed window of `window_secs`.
+#[derive(Debug)]
+pub struct WarnLimiter {
+    limit: u32,
+    window_secs: u64,
+    /// Start of the current window and the events allowed in it.
+    window: Mutex<(u64, u32)>,
+}
+
+impl WarnLimiter {
+    pub fn new(limit: u32, window_secs: u64) -> Self {
+        Self {
+            limit,
+            window_secs,
+            window: Mutex::new((0, 0)),
+        }
+    }
+
+    /// Whether an event at `now` is within the limit of its window, counting it if so.
+    pub fn allow(&self, now: u64) -> bool {
+        let mut window = self.window.lock().unwrap();
+        let start = now - now % self.window_secs;
+        if window.0 != start {
+            *window = (start, 0);
+        }
+        if window.1 >= self.limit {
+            return false;
+        }
+        window.1 += 1;
+        true
+    }
+}
+
+/// Accounts published above each filter's `warn_data_size_bytes`, by filter index.
+pub struct LargeAccounts {
+    clock: Arc<dyn Clock>,
+    limiters: Vec<WarnLimiter>,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl LargeAccounts {
+    pub fn new(filters: usize, metrics: Arc<InstanceMetrics>) -> Self {
+        Self::with_clock(filters, Arc::new(MonotonicClock::default()), metrics)
+    }
+
+    pub fn with_clock(
+        filters: usize,
+        clock: Arc<dyn Clock>,
+        metrics: Arc<InstanceMetrics>,
+    ) -> Self {
+        Self {
+            clock,
+            limiters: (0..filters)
+                .map(|_| WarnLimiter::new(WARNINGS_PER_HOUR, HOUR_SECS))
+                .collect(),
+            metrics,
+        }
+    }
+
+    /// Record `pubkey` owned by `owner` with `size` bytes of data published to `topic` by the
+    /// filter at `index`, if above its `threshold`. Returns whether a warning was logged.
+    pub fn record(
+        &self,
+        index: usize,
+        threshold: usize,
+        topic: &str,
+        pubkey: &[u8],
+        owner: &[u8],
+        size: usize,
+    ) -> bool {
+        let largest = self
+            .metrics
+            .largest_account_bytes
+            .with_label_values(&[topic]);
+        if largest.get() < size as i64 {
+            largest.set(size as i64);
+        }
+        if threshold == 0 || size <= threshold {
+            return false;
+        }
+        self.metrics
+            .large_accounts_published_total
+            .with_label_values(&[topic])
+            .inc();
+        let Some(limiter) = self.limiters.get(index) else {
+            return false;
+        };
+        if !limiter.allow(self.clock.now_secs()) {
+            return false;
+        }
+        let key = |bytes: &[u8]| match <[u8; 32]>::try_from(bytes) {
+            Ok(bytes) => Pubkey::new_from_array(bytes).to_string(),
+            Err(_) => format!("{bytes:?}"),
+        };
+        warn!(
+            "Published account {} owned by {} with {size} bytes of data to {topic}, above \
+             warn_data_size_bytes {threshold}. Consider program_ignores or \
+             publish_if_changed_bytes_at for it if it isn't needed on every update",
+            key(pubkey),
+            key(owner),
+        );
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::sync::atomic::AtomicU64, std::sync::atomic::Ordering};
+
+    #[derive(Default)]
+    struct TestClock(AtomicU64);
+
+    impl Clock for TestClock {
+        fn now_secs(&self) -> u64 {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    #[test]
+    fn test_limiter() {
+        let limiter = WarnLimiter::new(2, 10);
+        assert!(limiter.allow(0));
+        assert!(limiter.allow(5));
+        assert!(!limiter.allow(9));
+        // A new window.
+        assert!(limiter.allow(10));
+        assert!(limiter.allow(19));
+        assert!(!limiter.allow(19));
+        assert!(limiter.allow(35));
+    }
+
+    #[test]
+    fn test_threshold() {
+        let clock = Arc::new(TestClock::default());
+        let large =
+            LargeAccounts::with_clock(2, Arc::clone(&clock) as Arc<dyn Clock>, Arc::default());
+        let (pubkey, owner) = ([1; 32], [2; 32]);
+        let count = || {
+            large
+                .metrics
+                .large_accounts_published_total
+                .with_label_values(&["large-test"])
+                .get()
+        };
+
+        assert!(!large.record(0, 100, "large-test", &pubkey, &owner, 100));
+        assert_eq!(count(), 0);
+        let warned = (0..12)
+            .filter(|_| large.record(0, 100, "large-test", &pubkey, &owner, 101))
+            .count();
+        assert_eq!(warned, WARNINGS_PER_HOUR as usize);
+        assert_eq!(count(), 12);
+        // Each filter has its own limit, and 0 disables the threshold.
+        assert!(large.record(1, 100, "large-test", &pubkey, &owner, 101));
+        assert!(!large.record(1, 0, "large-test", &pubkey, &owner, 5000));
+        assert_eq!(count(), 13);
+        assert_eq!(
+            large
+                .metrics
+                .largest_account_bytes
+                .with_label_values(&["large-test"])
+                .get(),
+            5000
+        );
+
+        clock.0.store(HOUR_SECS, Ordering::Relaxed);
+        assert!(large.record(0, 100, "large-test", &pubkey, &owner, 101));
+    }
+}
@@ -0,0 +1,363 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{instance_metrics::InstanceMetrics, resources::ThreadGuard},
+    arc_swap::ArcSwap,
+    log::{info, warn},
+    serde_json::{json, Value},
+    solana_program::pubkey::Pubkey,
+    solana_sdk::epoch_info::EpochInfo,
+    std::{
+        str::FromStr,
+        sync::{
+            mpsc::{self, RecvTimeoutError, Sender},
+            Arc,
+        },
+        thread::{self, JoinHandle},
+        time::Duration,
+    },
+};
+
+/// Epochs kept in the cache, the newest ones win.
+const MAX_CACHED_EPOCHS: usize = 3;
+
+/// Timeout of a single RPC request.
+const RPC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Marks slots without a leader in `EpochLeaders::slots`.
+const NO_LEADER: u32 = u32::MAX;
+
+/// Leaders of one epoch, with each slot pointing into the distinct leaders.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EpochLeaders {
+    epoch: u64,
+    first_slot: u64,
+    leaders: Vec<Pubkey>,
+    slots: Vec<u32>,
+}
+
+impl EpochLeaders {
+    /// Parse a `getLeaderSchedule` result, mapping leaders to slot indexes within the epoch.
+    pub fn from_rpc(epoch: u64, first_slot: u64, schedule: &Value) -> Result<Self, String> {
+        let schedule = schedule
+            .as_object()
+            .ok_or_else(|| format!("leader schedule is not an object: {schedule}"))?;
+        let mut leaders = Vec::with_capacity(schedule.len());
+        let mut slots = Vec::new();
+        for (leader, indexes) in schedule {
+            let pubkey =
+                Pubkey::from_str(leader).map_err(|error| format!("leader {leader:?}: {error}"))?;
+            let indexes = indexes
+                .as_array()
+                .ok_or_else(|| format!("slots of leader {leader} are not an array"))?;
+            for index in indexes {
+                let index = index
+                    .as_u64()
+                    .ok_or_else(|| format!("slot index {index} of leader {leader}"))?
+                    as usize;
+                if index >= slots.len() {
+                    slots.resize(index + 1, NO_LEADER);
+                }
+                slots[index] = leaders.len() as u32;
+            }
+            leaders.push(pubkey);
+        }
+        Ok(Self {
+            epoch,
+            first_slot,
+            leaders,
+            slots,
+        })
+    }
+
+    pub fn leader(&self, slot: u64) -> Option<&Pubkey> {
+        let index = slot.checked_sub(self.first_slot)?;
+        let leader = *self.slots.get(usize::try_from(index).ok()?)?;
+        self.leaders.get(leader as usize)
+    }
+}
+
+/// Source of epoch info and leader schedules.
+pub trait LeaderScheduleSource {
+    fn epoch_info(&self) -> Result<EpochInfo, String>;
+
+    /// Schedule of the epoch containing `slot`, `None` if not known yet.
+    fn leader_schedule(&self, slot: u64) -> Result<Option<Value>, String>;
+}
+
+/// JSON RPC over HTTP.
+pub struct RpcLeaderScheduleSource {
+    url: String,
+    client: reqwest::blocking::Client,
+}
+
+impl RpcLeaderScheduleSource {
+    pub fn new(url: String) -> Result<Self, String> {
+        let client = reqwest::blocking::Client::builder()
+            .timeout(RPC_TIMEOUT)
+            .build()
+            .map_err(|error| error.to_string())?;
+        Ok(Self { url, client })
+    }
+
+    fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+        let request = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+        let mut response: Value = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.json())
+            .map_err(|error| format!("{method}: {error}"))?;
+        match response.get("error") {
+            Some(error) => Err(format!("{method}: {error}")),
+            None => Ok(response["result"].take()),
+        }
+    }
+
+    /// `solana-core` version reported by `getVersion`.
+    pub fn validator_version(&self) -> Result<String, String> {
+        let version = self.call("getVersion", json!([]))?;
+        version["solana-core"]
+            .as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| format!("getVersion: no solana-core version in {version}"))
+    }
+}
+
+impl LeaderScheduleSource for RpcLeaderScheduleSource {
+    fn epoch_info(&self) -> Result<EpochInfo, String> {
+        serde_json::from_value(self.call("getEpochInfo", json!([]))?)
+            .map_err(|error| format!("getEpochInfo: {error}"))
+    }
+
+    fn leader_schedule(&self, slot: u64) -> Result<Option<Value>, String> {
+        let schedule = self.call("getLeaderSchedule", json!([slot]))?;
+        Ok((!schedule.is_null()).then_some(schedule))
+    }
+}
+
+/// Leader schedules of recent epochs, looked up without locking.
+#[derive(Debug, Default)]
+pub struct LeaderSchedule {
+    epochs: ArcSwap<Vec<Arc<EpochLeaders>>>,
+}
+
+impl LeaderSchedule {
+    /// Leader of `slot`, `None` if its epoch isn't cached.
+    pub fn leader(&self, slot: u64) -> Option<Pubkey> {
+        self.epochs
+            .load()
+            .iter()
+            .find_map(|epoch| epoch.leader(slot))
+            .copied()
+    }
+
+    fn contains(&self, epoch: u64) -> bool {
+        self.epochs
+            .load()
+            .iter()
+            .any(|cached| cached.epoch == epoch)
+    }
+
+    pub fn insert(&self, leaders: EpochLeaders) {
+        let mut epochs = Vec::clone(&self.epochs.load());
+        epochs.retain(|cached| cached.epoch != leaders.epoch);
+        epochs.push(Arc::new(leaders));
+        epochs.sort_by_key(|cached| std::cmp::Reverse(cached.epoch));
+        epochs.truncate(MAX_CACHED_EPOCHS);
+        self.epochs.store(Arc::new(epochs));
+    }
+
+    /// Fetch the schedules of the current and next epoch, unless already cached.
+    pub fn refresh(&self, source: &dyn LeaderScheduleSource) -> Result<(), String> {
+        let info = source.epoch_info()?;
+        let first_slot = info.absolute_slot - info.slot_index;
+        for (epoch, first_slot) in [
+            (info.epoch, first_slot),
+            (info.epoch + 1, first_slot + info.slots_in_epoch),
+        ] {
+            if self.contains(epoch) {
+                continue;
+            }
+            if let Some(schedule) = source.leader_schedule(first_slot)? {
+                self.insert(EpochLeaders::from_rpc(epoch, first_slot, &schedule)?);
+                info!("Cached leader schedule of epoch {epoch}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Background thread keeping the leader schedule cache filled.
+pub struct LeaderScheduleFetcher {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl LeaderScheduleFetcher {
+    pub fn spawn(
+        schedule: Arc<LeaderSchedule>,
+        url: String,
+        interval: Duration,
+        metrics: Arc<InstanceMetrics>,
+    ) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("kafkaLeaders".to_owned())
+            .spawn(move || {
+                let _thread = ThreadGuard::new(&metrics, "background");
+                let source = match RpcLeaderScheduleSource::new(url) {
+                    Ok(source) => source,
+                    Err(error) => {
+                        warn!("Failed to create leader schedule RPC client: {error}");
+                        return;
+                    }
+                };
+                loop {
+                    let status = match schedule.refresh(&source) {
+                        Ok(()) => "success",
+                        Err(error) => {
+                            warn!("Failed to fetch leader schedule: {error}");
+                            "failed"
+                        }
+                    };
+                    metrics
+                        .leader_schedule_fetches_total
+                        .with_label_values(&[status])
+                        .inc();
+                    if !matches!(
+                        stopped.recv_timeout(interval),
+                        Err(RecvTimeoutError::Timeout)
+                    ) {
+                        break;
+                    }
+                }
+            })
+            .expect("failed to spawn leader schedule thread");
+        Self {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for LeaderScheduleFetcher {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::cell::Cell};
+
+    const LEADER_A: &str = "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin";
+    const LEADER_B: &str = "Vote111111111111111111111111111111111111111";
+
+    /// Canned RPC responses for a cluster with 8 slot epochs.
+    struct CannedSource {
+        absolute_slot: Cell<u64>,
+        fail: Cell<bool>,
+    }
+
+    impl LeaderScheduleSource for CannedSource {
+        fn epoch_info(&self) -> Result<EpochInfo, String> {
+            if self.fail.get() {
+                return Err("connection refused".to_owned());
+            }
+            let slot = self.absolute_slot.get();
+            Ok(serde_json::from_value(json!({
+                "absoluteSlot": slot,
+                "blockHeight": slot,
+                "epoch": slot / 8,
+                "slotIndex": slot % 8,
+                "slotsInEpoch": 8,
+                "transactionCount": null,
+            }))
+            .unwrap())
+        }
+
+        fn leader_schedule(&self, slot: u64) -> Result<Option<Value>, String> {
+            // Only the current epoch's schedule is known.
+            Ok((slot / 8 == self.absolute_slot.get() / 8).then(|| {
+                json!({
+                    LEADER_A: [0, 1, 4, 5],
+                    LEADER_B: [2, 3, 6, 7],
+                })
+            }))
+        }
+    }
+
+    #[test]
+    fn test_from_rpc() {
+        let leaders = EpochLeaders::from_rpc(
+            2,
+            16,
+            &json!({LEADER_A: [0, 1, 4, 5], LEADER_B: [2, 3, 6, 7]}),
+        )
+        .unwrap();
+        let a = Pubkey::from_str(LEADER_A).unwrap();
+        let b = Pubkey::from_str(LEADER_B).unwrap();
+        assert_eq!(leaders.leader(15), None);
+        assert_eq!(leaders.leader(16), Some(&a));
+        assert_eq!(leaders.leader(18), Some(&b));
+        assert_eq!(leaders.leader(21), Some(&a));
+        assert_eq!(leaders.leader(23), Some(&b));
+        assert_eq!(leaders.leader(24), None);
+
+        assert!(EpochLeaders::from_rpc(0, 0, &json!(null)).is_err());
+        assert!(EpochLeaders::from_rpc(0, 0, &json!({"bad": [0]})).is_err());
+        assert!(EpochLeaders::from_rpc(0, 0, &json!({LEADER_A: [-1]})).is_err());
+    }
+
+    #[test]
+    fn test_refresh() {
+        let source = CannedSource {
+            absolute_slot: Cell::new(19),
+            fail: Cell::new(true),
+        };
+        let schedule = LeaderSchedule::default();
+        assert!(schedule.refresh(&source).is_err());
+        assert_eq!(schedule.leader(19), None);
+
+        source.fail.set(false);
+        schedule.refresh(&source).unwrap();
+        let b = Pubkey::from_str(LEADER_B).unwrap();
+        // Inside the cached epoch 2, slots 16 to 23.
+        assert_eq!(schedule.leader(19), Some(b));
+        assert_eq!(schedule.leader(23), Some(b));
+        // Outside of it.
+        assert_eq!(schedule.leader(15), None);
+        assert_eq!(schedule.leader(24), None);
+
+        // Failures keep what's cached, older epochs are evicted.
+        source.fail.set(true);
+        assert!(schedule.refresh(&source).is_err());
+        assert_eq!(schedule.leader(19), Some(b));
+        source.fail.set(false);
+        for slot in [27, 35, 43] {
+            source.absolute_slot.set(slot);
+            schedule.refresh(&source).unwrap();
+            assert_eq!(schedule.leader(slot), Some(b));
+        }
+        assert_eq!(schedule.leader(19), None);
+        assert_eq!(schedule.leader(27), Some(b));
+    }
+}
@@ -12,23 +12,87 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+// The metrics in `prom` outgrow the default limit of `lazy_static!`.
+#![recursion_limit = "256"]
+
 use solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPlugin;
 
+mod account_data;
+mod adaptive_stats;
+#[cfg(feature = "fixtures")]
+pub mod builders;
+mod canary;
+pub mod capture;
+mod changed_bytes;
+mod checkpoint;
+mod checksum;
+mod chunk;
 mod config;
+mod contention;
+mod deadline;
 mod event;
+mod fee_market;
+pub mod file_sink;
 mod filter;
+mod filter_index;
+mod filter_stats;
+mod fork_instance;
+mod identity;
+mod instance_metrics;
+mod kafka_stats;
+mod large_accounts;
+mod leader_schedule;
+mod meta_fields;
+mod metrics_json;
+mod metrics_log;
+mod ordering;
+mod outstanding;
+mod partition;
+mod pause;
 mod plugin;
+mod producer_advisor;
+mod producer_registry;
+mod program_stats;
 mod prom;
 mod publisher;
+mod rate;
+mod resources;
+mod rooted_barrier;
+mod rooted_blocks;
+mod rooted_gate;
+mod rotation;
+mod sequence;
+mod slot_budget;
+mod slot_order;
+mod slot_tracker;
+mod standby;
+mod tail;
+mod throttle;
 mod version;
+mod wall_clock;
+mod zstd_dict;
 
 pub use {
-    config::{Config, ConfigFilter, Producer},
+    canary::CANARY_HEADER,
+    checksum::{verify_payload_checksum, PAYLOAD_CHECKSUM_HEADER},
+    chunk::{reassemble_account, ChunkError},
+    config::{
+        BackpressurePolicy, Config, ConfigEpochSchedule, ConfigFilter, DeliveryProfile,
+        FileSinkCompression, KafkaProducer, Partitioning, PausePolicy, PayloadCompression,
+        Producer, ProgramStatsSource, PrometheusAddress, SchemaCompat, SerializationFailurePolicy,
+        SinkKind, SizeTier, TransactionKeySource,
+    },
     event::*,
-    filter::Filter,
+    filter::{Filter, MatchDecision},
+    identity::{CLUSTER_HEADER, VALIDATOR_IDENTITY_HEADER},
+    instance_metrics::{InstanceMetrics, INSTANCE_LABEL},
     plugin::KafkaPlugin,
     prom::PrometheusService,
-    publisher::Publisher,
+    publisher::{
+        MessageHeaders, PublishError, Publisher, RecordedMessage, KIND_HEADER, PHASE_HEADER,
+        STATUS_HEADER,
+    },
+    zstd_dict::{decompress_payload, ZSTD_DICT_ID_HEADER},
 };
 
 #[no_mangle]
@@ -40,7 +104,14 @@ pub use {
 /// The Solana validator and this plugin must be compiled with the same Rust compiler version and Solana core version.
 /// Loading this plugin with mismatching versions is undefined behavior and will likely cause memory corruption.
 pub unsafe extern "C" fn _create_plugin() -> *mut dyn GeyserPlugin {
-    let plugin = KafkaPlugin::new();
+    // Unwinding across the FFI boundary is undefined behavior, abort instead.
+    let plugin = std::panic::catch_unwind(KafkaPlugin::new).unwrap_or_else(|payload| {
+        log::error!(
+            "Panic while creating plugin: {}",
+            plugin::panic_message(payload.as_ref())
+        );
+        std::process::abort()
+    });
     let plugin: Box<dyn GeyserPlugin> = Box::new(plugin);
     Box::into_raw(plugin)
 }
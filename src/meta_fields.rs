@@ -0,0 +1,184 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Presence of the optional fields of transaction status metas, which validator upgrades may
+//! start or stop filling in. Absent fields are published empty, so consumers can't tell them
+//! from empty ones; `tx_meta_field_present_total` tells them apart per notified transaction,
+//! and `require_meta_fields` turns an absent field into a logged error.
+
+use {
+    crate::{
+        instance_metrics::InstanceMetrics,
+        large_accounts::WarnLimiter,
+        rate::{Clock, MonotonicClock},
+    },
+    log::error,
+    solana_sdk::signature::Signature,
+    solana_transaction_status::TransactionStatusMeta,
+};
+
+/// Optional fields of transaction status metas, as named in `require_meta_fields`.
+pub const META_FIELDS: &[&str] = &[
+    "rewards",
+    "log_messages",
+    "inner_instructions",
+    "pre_token_balances",
+    "post_token_balances",
+    "compute_units_consumed",
+    "return_data",
+];
+
+/// Errors logged per minute about required fields missing.
+const ERRORS_PER_MINUTE: u32 = 5;
+
+const MINUTE_SECS: u64 = 60;
+
+/// Whether each of `META_FIELDS` is set in `meta`, in order.
+fn presence(meta: &TransactionStatusMeta) -> [bool; 7] {
+    [
+        meta.rewards.is_some(),
+        meta.log_messages.is_some(),
+        meta.inner_instructions.is_some(),
+        meta.pre_token_balances.is_some(),
+        meta.post_token_balances.is_some(),
+        meta.compute_units_consumed.is_some(),
+        meta.return_data.is_some(),
+    ]
+}
+
+/// Counts of the fields present in transaction status metas, with those required.
+pub struct MetaFields {
+    required: Vec<bool>,
+    clock: MonotonicClock,
+    limiter: WarnLimiter,
+}
+
+impl MetaFields {
+    /// Fields named in `required` are expected in every meta, the names being validated in
+    /// the config.
+    pub fn new(required: &[String]) -> Self {
+        Self {
+            required: META_FIELDS
+                .iter()
+                .map(|field| required.iter().any(|name| name == field))
+                .collect(),
+            clock: MonotonicClock::default(),
+            limiter: WarnLimiter::new(ERRORS_PER_MINUTE, MINUTE_SECS),
+        }
+    }
+
+    /// Count the fields present in the meta of the transaction with `signature`, and those
+    /// required but missing, logging an error about them at most a few times a minute.
+    /// Returns the required fields missing.
+    pub fn observe(
+        &self,
+        metrics: &InstanceMetrics,
+        signature: &Signature,
+        meta: &TransactionStatusMeta,
+    ) -> Vec<&'static str> {
+        let mut missing = Vec::new();
+        for ((field, present), required) in
+            META_FIELDS.iter().zip(presence(meta)).zip(&self.required)
+        {
+            if present {
+                metrics
+                    .tx_meta_field_present_total
+                    .with_label_values(&[field])
+                    .inc();
+            } else if *required {
+                metrics
+                    .tx_meta_required_field_missing_total
+                    .with_label_values(&[field])
+                    .inc();
+                missing.push(*field);
+            }
+        }
+        if !missing.is_empty() && self.limiter.allow(self.clock.now_secs()) {
+            error!(
+                "Transaction {signature} notified without required meta fields {missing:?}, \
+                 the validator may have stopped filling them in"
+            );
+        }
+        missing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        solana_transaction_status::{Reward, TransactionTokenBalance},
+    };
+
+    #[test]
+    fn test_presence_counts() {
+        let metrics = InstanceMetrics::default();
+        let fields = MetaFields::new(&["log_messages".to_owned(), "return_data".to_owned()]);
+        let present = |field: &str| {
+            metrics
+                .tx_meta_field_present_total
+                .with_label_values(&[field])
+                .get()
+        };
+        let missing = |field: &str| {
+            metrics
+                .tx_meta_required_field_missing_total
+                .with_label_values(&[field])
+                .get()
+        };
+
+        let empty = TransactionStatusMeta {
+            rewards: None,
+            log_messages: None,
+            inner_instructions: None,
+            pre_token_balances: None,
+            post_token_balances: None,
+            compute_units_consumed: None,
+            return_data: None,
+            ..Default::default()
+        };
+        let full = TransactionStatusMeta {
+            rewards: Some(Vec::<Reward>::new()),
+            log_messages: Some(vec!["Program log: hi".to_owned()]),
+            inner_instructions: Some(vec![]),
+            pre_token_balances: Some(Vec::<TransactionTokenBalance>::new()),
+            post_token_balances: Some(vec![]),
+            compute_units_consumed: Some(150),
+            return_data: Some(Default::default()),
+            ..Default::default()
+        };
+        let signature = Signature::default();
+        assert_eq!(
+            fields.observe(&metrics, &signature, &empty),
+            ["log_messages", "return_data"]
+        );
+        assert!(fields.observe(&metrics, &signature, &full).is_empty());
+        let partial = TransactionStatusMeta {
+            log_messages: Some(vec![]),
+            ..empty.clone()
+        };
+        assert_eq!(
+            fields.observe(&metrics, &signature, &partial),
+            ["return_data"]
+        );
+
+        assert_eq!(present("log_messages"), 2);
+        assert_eq!(present("compute_units_consumed"), 1);
+        assert_eq!(present("rewards"), 1);
+        assert_eq!(missing("log_messages"), 1);
+        assert_eq!(missing("return_data"), 2);
+        // Fields not required aren't counted missing.
+        assert_eq!(missing("rewards"), 0);
+    }
+}
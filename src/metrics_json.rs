@@ -0,0 +1,157 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Gathered metrics as JSON, served on `/metrics.json` for agents that don't read the
+//! Prometheus text format.
+
+use {
+    prometheus::proto::{LabelPair, Metric, MetricFamily, MetricType},
+    serde_json::{json, Map, Value},
+};
+
+/// Series of each metric of `families` by name, with their labels and value. Histograms have
+/// their sample `count`, `sum` and cumulative `buckets` as `[upper_bound, count]` pairs instead of
+/// a value.
+pub fn to_json(families: &[MetricFamily]) -> Value {
+    let metrics: Map<String, Value> = families
+        .iter()
+        .map(|family| {
+            let series = family
+                .get_metric()
+                .iter()
+                .map(|metric| series(family.get_field_type(), metric))
+                .collect();
+            (family.name().to_owned(), Value::Array(series))
+        })
+        .collect();
+    Value::Object(metrics)
+}
+
+/// Labels by name, sorted by the registry.
+fn labels(pairs: &[LabelPair]) -> Value {
+    let labels: Map<String, Value> = pairs
+        .iter()
+        .map(|pair| (pair.name().to_owned(), pair.value().into()))
+        .collect();
+    Value::Object(labels)
+}
+
+fn series(kind: MetricType, metric: &Metric) -> Value {
+    let labels = labels(metric.get_label());
+    match kind {
+        MetricType::COUNTER => json!({
+            "labels": labels,
+            "value": metric.get_counter().value(),
+        }),
+        MetricType::GAUGE => json!({
+            "labels": labels,
+            "value": metric.get_gauge().value(),
+        }),
+        MetricType::HISTOGRAM => {
+            let histogram = metric.get_histogram();
+            let buckets: Vec<Value> = histogram
+                .get_bucket()
+                .iter()
+                .map(|bucket| json!([bucket.upper_bound(), bucket.cumulative_count()]))
+                .collect();
+            json!({
+                "labels": labels,
+                "count": histogram.get_sample_count(),
+                "sum": histogram.get_sample_sum(),
+                "buckets": buckets,
+            })
+        }
+        // Not produced by this crate's metric types.
+        MetricType::SUMMARY | MetricType::UNTYPED => json!({ "labels": labels }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        prometheus::{
+            GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry,
+        },
+    };
+
+    #[test]
+    fn test_counters_and_gauges() {
+        let registry = Registry::new();
+        let uploads =
+            IntCounterVec::new(Opts::new("uploads_total", "Uploads"), &["topic", "status"])
+                .unwrap();
+        let errors = IntCounter::new("errors_total", "Errors").unwrap();
+        let lag = GaugeVec::new(Opts::new("lag", "Lag"), &["topic"]).unwrap();
+        registry.register(Box::new(uploads.clone())).unwrap();
+        registry.register(Box::new(errors.clone())).unwrap();
+        registry.register(Box::new(lag.clone())).unwrap();
+        uploads.with_label_values(&["slots", "success"]).inc_by(3);
+        uploads.with_label_values(&["accounts", "failed"]).inc();
+        lag.with_label_values(&["accounts"]).set(-1.5);
+
+        let json = to_json(&registry.gather());
+        assert_eq!(
+            json,
+            json!({
+                "errors_total": [{"labels": {}, "value": 0.0}],
+                "lag": [{"labels": {"topic": "accounts"}, "value": -1.5}],
+                "uploads_total": [
+                    {"labels": {"status": "failed", "topic": "accounts"}, "value": 1.0},
+                    {"labels": {"status": "success", "topic": "slots"}, "value": 3.0},
+                ],
+            })
+        );
+        // Labels are in name order rather than declaration order.
+        let rendered = json.to_string();
+        assert!(
+            rendered.contains(r#"{"labels":{"status":"failed","topic":"accounts"},"value":1.0}"#)
+        );
+    }
+
+    #[test]
+    fn test_histograms() {
+        let registry = Registry::new();
+        let latency = HistogramVec::new(
+            HistogramOpts::new("latency_seconds", "Latency").buckets(vec![0.01, 0.1, 1.0]),
+            &["topic"],
+        )
+        .unwrap();
+        registry.register(Box::new(latency.clone())).unwrap();
+        for seconds in [0.0078125, 0.0625, 0.5, 4.0] {
+            latency.with_label_values(&["blocks"]).observe(seconds);
+        }
+        latency.with_label_values(&["slots"]);
+
+        assert_eq!(
+            to_json(&registry.gather()),
+            json!({
+                "latency_seconds": [
+                    {
+                        "labels": {"topic": "blocks"},
+                        "count": 4,
+                        "sum": 4.5703125,
+                        "buckets": [[0.01, 1], [0.1, 2], [1.0, 3]],
+                    },
+                    {
+                        "labels": {"topic": "slots"},
+                        "count": 0,
+                        "sum": 0.0,
+                        "buckets": [[0.01, 0], [0.1, 0], [1.0, 0]],
+                    },
+                ],
+            })
+        );
+    }
+}
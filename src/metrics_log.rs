@@ -0,0 +1,292 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{instance_metrics::InstanceMetrics, rate::FilterRates, resources::ThreadGuard},
+    log::info,
+    prometheus::proto::{Metric, MetricFamily},
+    serde_json::{json, Map, Value},
+    std::{
+        sync::{
+            mpsc::{self, RecvTimeoutError, Sender},
+            Arc,
+        },
+        thread::{self, JoinHandle},
+        time::Duration,
+    },
+};
+
+/// Project the key counters out of gathered metrics into a compact JSON object.
+pub fn snapshot(families: &[MetricFamily]) -> Value {
+    let mut uploads = Map::new();
+    let mut account_updates = Map::new();
+    let mut missing_txn = Map::new();
+    let mut account_rates = Map::new();
+    let mut transaction_rates = Map::new();
+    let mut delivery_errors = 0;
+    let mut queue_depth = 0;
+    let mut last_slot = 0;
+    for family in families {
+        let kind = match family.name() {
+            "upload_accounts_total" => "account",
+            "upload_slots_total" => "slot",
+            "upload_transactions_total" => "transaction",
+            "upload_blocks_total" => "block",
+            "account_updates_total" => {
+                account_updates = by_label(family, "is_startup");
+                continue;
+            }
+            "account_updates_missing_txn_total" => {
+                missing_txn = by_label(family, "is_startup");
+                continue;
+            }
+            "filter_account_rate_1m" => {
+                account_rates = gauges_by_label(family, "filter");
+                continue;
+            }
+            "filter_transaction_rate_1m" => {
+                transaction_rates = gauges_by_label(family, "filter");
+                continue;
+            }
+            "delivery_errors_total" => {
+                delivery_errors = counter_sum(family);
+                continue;
+            }
+            "kafka_queue_messages" => {
+                queue_depth = gauge_value(family);
+                continue;
+            }
+            "last_slot" => {
+                last_slot = gauge_value(family);
+                continue;
+            }
+            _ => continue,
+        };
+        uploads.insert(kind.to_owned(), Value::Object(by_label(family, "status")));
+    }
+    json!({
+        "uploads": uploads,
+        "account_updates": account_updates,
+        "account_updates_missing_txn": missing_txn,
+        "delivery_errors": delivery_errors,
+        "queue_depth": queue_depth,
+        "last_slot": last_slot,
+        "filter_rates": {"account": account_rates, "transaction": transaction_rates},
+    })
+}
+
+/// Counter values of `family` keyed by the value of label `name`.
+fn by_label(family: &MetricFamily, name: &str) -> Map<String, Value> {
+    family
+        .get_metric()
+        .iter()
+        .map(|metric| {
+            (
+                label_value(metric, name),
+                json!(metric.get_counter().value() as u64),
+            )
+        })
+        .collect()
+}
+
+/// Gauge values of `family` keyed by the value of label `name`.
+fn gauges_by_label(family: &MetricFamily, name: &str) -> Map<String, Value> {
+    family
+        .get_metric()
+        .iter()
+        .map(|metric| (label_value(metric, name), json!(metric.get_gauge().value())))
+        .collect()
+}
+
+fn label_value(metric: &Metric, name: &str) -> String {
+    metric
+        .get_label()
+        .iter()
+        .find(|label| label.name() == name)
+        .map(|label| label.value().to_owned())
+        .unwrap_or_default()
+}
+
+/// Share of live account updates without a causing transaction between two snapshots.
+pub fn missing_txn_ratio(previous: &Value, current: &Value) -> Option<f64> {
+    let delta = |key: &str| {
+        let count = |snapshot: &Value| snapshot[key]["false"].as_u64().unwrap_or_default();
+        count(current).saturating_sub(count(previous))
+    };
+    let updates = delta("account_updates");
+    (updates > 0).then(|| delta("account_updates_missing_txn") as f64 / updates as f64)
+}
+
+fn counter_sum(family: &MetricFamily) -> u64 {
+    family
+        .get_metric()
+        .iter()
+        .map(|metric| metric.get_counter().value() as u64)
+        .sum()
+}
+
+fn gauge_value(family: &MetricFamily) -> i64 {
+    family
+        .get_metric()
+        .first()
+        .map(|metric| metric.get_gauge().value() as i64)
+        .unwrap_or_default()
+}
+
+fn json_with_ratio(snapshot: &Value, ratio: Option<f64>) -> Value {
+    let mut snapshot = snapshot.clone();
+    snapshot["missing_txn_ratio"] = json!(ratio);
+    snapshot
+}
+
+/// Background thread logging a metrics snapshot, for deployments without an HTTP port.
+pub struct MetricsLogger {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MetricsLogger {
+    pub fn spawn(
+        interval: Duration,
+        filter_rates: Arc<FilterRates>,
+        metrics: Arc<InstanceMetrics>,
+    ) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("kafkaMetricsLog".to_owned())
+            .spawn(move || {
+                let _thread = ThreadGuard::new(&metrics, "background");
+                let mut previous = snapshot(&[]);
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    filter_rates.refresh_gauges();
+                    let mut current = snapshot(&metrics.registry().gather());
+                    let ratio = missing_txn_ratio(&previous, &current);
+                    metrics
+                        .account_updates_missing_txn_ratio
+                        .set(ratio.unwrap_or_default());
+                    info!("metrics {}", json_with_ratio(&current, ratio));
+                    std::mem::swap(&mut previous, &mut current);
+                }
+            })
+            .expect("failed to spawn metrics log thread");
+        Self {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for MetricsLogger {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        prometheus::{GaugeVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry},
+    };
+
+    #[test]
+    fn test_snapshot() {
+        let registry = Registry::new();
+        let accounts =
+            IntCounterVec::new(Opts::new("upload_accounts_total", "accounts"), &["status"])
+                .unwrap();
+        let errors = IntCounter::new("delivery_errors_total", "errors").unwrap();
+        let queue = IntGauge::new("kafka_queue_messages", "queue").unwrap();
+        let last_slot = IntGauge::new("last_slot", "slot").unwrap();
+        let rates =
+            GaugeVec::new(Opts::new("filter_account_rate_1m", "rates"), &["filter"]).unwrap();
+        let unrelated = IntGauge::new("plugin_loaded", "loaded").unwrap();
+        registry.register(Box::new(accounts.clone())).unwrap();
+        registry.register(Box::new(errors.clone())).unwrap();
+        registry.register(Box::new(queue.clone())).unwrap();
+        registry.register(Box::new(last_slot.clone())).unwrap();
+        registry.register(Box::new(rates.clone())).unwrap();
+        registry.register(Box::new(unrelated.clone())).unwrap();
+
+        accounts.with_label_values(&["success"]).inc_by(5);
+        accounts.with_label_values(&["failed"]).inc();
+        errors.inc_by(2);
+        queue.set(17);
+        last_slot.set(1234);
+        rates.with_label_values(&["0"]).set(2.5);
+        unrelated.set(1);
+
+        assert_eq!(
+            snapshot(&registry.gather()),
+            json!({
+                "uploads": {"account": {"success": 5, "failed": 1}},
+                "account_updates": {},
+                "account_updates_missing_txn": {},
+                "delivery_errors": 2,
+                "queue_depth": 17,
+                "last_slot": 1234,
+                "filter_rates": {"account": {"0": 2.5}, "transaction": {}},
+            })
+        );
+        assert_eq!(
+            snapshot(&[]),
+            json!({
+                "uploads": {},
+                "account_updates": {},
+                "account_updates_missing_txn": {},
+                "delivery_errors": 0,
+                "queue_depth": 0,
+                "last_slot": 0,
+                "filter_rates": {"account": {}, "transaction": {}},
+            })
+        );
+    }
+
+    #[test]
+    fn test_missing_txn_ratio() {
+        let registry = Registry::new();
+        let updates = IntCounterVec::new(
+            Opts::new("account_updates_total", "updates"),
+            &["is_startup"],
+        )
+        .unwrap();
+        let missing = IntCounterVec::new(
+            Opts::new("account_updates_missing_txn_total", "missing"),
+            &["is_startup"],
+        )
+        .unwrap();
+        registry.register(Box::new(updates.clone())).unwrap();
+        registry.register(Box::new(missing.clone())).unwrap();
+
+        updates.with_label_values(&["true"]).inc_by(1_000);
+        missing.with_label_values(&["true"]).inc_by(1_000);
+        updates.with_label_values(&["false"]).inc_by(10);
+        missing.with_label_values(&["false"]).inc_by(1);
+        let first = snapshot(&registry.gather());
+        assert_eq!(
+            first["account_updates"],
+            json!({"true": 1_000, "false": 10})
+        );
+        assert_eq!(missing_txn_ratio(&snapshot(&[]), &first), Some(0.1));
+
+        updates.with_label_values(&["false"]).inc_by(4);
+        missing.with_label_values(&["false"]).inc_by(2);
+        let second = snapshot(&registry.gather());
+        assert_eq!(missing_txn_ratio(&first, &second), Some(0.5));
+        assert_eq!(missing_txn_ratio(&second, &second), None);
+    }
+}
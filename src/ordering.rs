@@ -0,0 +1,162 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Detection of account updates produced out of order, for `strict_ordering_assertions`.
+//!
+//! Account updates carry their slot and write version in an internal header through pausing
+//! and `rooted_only` buffering, which is checked and stripped right before the message is handed
+//! to the sink.
+
+use {
+    crate::{instance_metrics::InstanceMetrics, MessageHeaders},
+    log::error,
+    std::{
+        collections::HashMap,
+        mem,
+        sync::{Arc, Mutex},
+    },
+};
+
+/// Internal header of the slot and write version of an account update, never produced.
+pub const ORDER_STAMP_HEADER: &str = "x-plugin-order-stamp";
+
+/// Keys remembered per generation, at most twice as many are tracked.
+const GENERATION_KEYS: usize = 1 << 19;
+
+/// Header carrying `slot` and `write_version`, big endian.
+pub fn order_stamp(slot: u64, write_version: u64) -> (String, Vec<u8>) {
+    let mut value = slot.to_be_bytes().to_vec();
+    value.extend_from_slice(&write_version.to_be_bytes());
+    (ORDER_STAMP_HEADER.to_owned(), value)
+}
+
+type Stamps = HashMap<(String, Vec<u8>), (u64, u64)>;
+
+#[derive(Debug, Default)]
+struct Generations {
+    current: Stamps,
+    previous: Stamps,
+}
+
+/// Last slot and write version produced per topic and key. Keys not produced for two
+/// generations are forgotten.
+#[derive(Debug, Default)]
+pub struct OrderingChecker {
+    generations: Mutex<Generations>,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl OrderingChecker {
+    pub fn new(metrics: Arc<InstanceMetrics>) -> Self {
+        Self {
+            generations: Mutex::default(),
+            metrics,
+        }
+    }
+
+    /// Check the order stamp of a message for `topic` and `key` against the last one produced,
+    /// returning `headers` without it. Returns whether the order regressed.
+    pub fn check(
+        &self,
+        topic: &str,
+        key: &[u8],
+        headers: &[(String, Vec<u8>)],
+    ) -> (MessageHeaders, bool) {
+        let mut stripped = MessageHeaders::with_capacity(headers.len());
+        let mut stamp = None;
+        for (name, value) in headers {
+            if name == ORDER_STAMP_HEADER && value.len() == 16 {
+                let (slot, write_version) = value.split_at(8);
+                stamp = Some((
+                    u64::from_be_bytes(slot.try_into().unwrap()),
+                    u64::from_be_bytes(write_version.try_into().unwrap()),
+                ));
+            } else {
+                stripped.push((name.clone(), value.clone()));
+            }
+        }
+        let Some(stamp) = stamp else {
+            return (stripped, false);
+        };
+
+        let mut generations = self.generations.lock().unwrap();
+        let id = (topic.to_owned(), key.to_vec());
+        let last = generations
+            .current
+            .get(&id)
+            .or_else(|| generations.previous.get(&id))
+            .copied();
+        if generations.current.len() >= GENERATION_KEYS {
+            generations.previous = mem::take(&mut generations.current);
+        }
+        let regressed = last.is_some_and(|last| stamp < last);
+        if regressed {
+            let last = last.unwrap();
+            self.metrics.ordering_violations_total.inc();
+            error!(
+                "Ordering violation on {topic:?} for key {key:02x?}: slot {} write version {} \
+                 produced after slot {} write version {}",
+                stamp.0, stamp.1, last.0, last.1
+            );
+        } else {
+            generations.current.insert(id, stamp);
+        }
+        drop(generations);
+        if regressed && cfg!(feature = "abort-on-ordering-violation") {
+            panic!("ordering violation on {topic:?}");
+        }
+        (stripped, regressed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::panic};
+
+    fn check(checker: &OrderingChecker, key: u8, slot: u64, write_version: u64) -> bool {
+        let headers = [
+            ("zstd-dict-id".to_owned(), b"7".to_vec()),
+            order_stamp(slot, write_version),
+        ];
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            checker.check("accounts", &[key], &headers)
+        }));
+        match result {
+            Ok((stripped, regressed)) => {
+                assert_eq!(stripped, [headers[0].clone()]);
+                regressed
+            }
+            Err(_) if cfg!(feature = "abort-on-ordering-violation") => true,
+            Err(panic) => panic::resume_unwind(panic),
+        }
+    }
+
+    #[test]
+    fn test_regressions() {
+        let checker = OrderingChecker::default();
+        assert!(!check(&checker, 1, 10, 5));
+        assert!(!check(&checker, 1, 10, 6));
+        assert!(!check(&checker, 1, 11, 2));
+        // Other keys are ordered on their own.
+        assert!(!check(&checker, 2, 9, 1));
+        assert!(check(&checker, 1, 10, 7));
+        assert!(check(&checker, 1, 11, 1));
+        assert_eq!(checker.metrics.ordering_violations_total.get(), 2);
+        // A regression doesn't lower the bar.
+        assert!(!check(&checker, 1, 11, 3));
+
+        let (stripped, regressed) = checker.check("accounts", &[1], &[]);
+        assert!(stripped.is_empty() && !regressed);
+    }
+}
@@ -0,0 +1,193 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Estimate of the message bytes librdkafka holds, enqueued but not yet delivered, and the
+//! `max_outstanding_bytes` cap on them.
+
+use {
+    crate::{instance_metrics::InstanceMetrics, BackpressurePolicy},
+    rdkafka::error::{KafkaError, RDKafkaErrorCode},
+    std::{
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Condvar, Mutex,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+/// Longest a blocked send waits for a delivery before checking the outstanding bytes again.
+const RECHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Key and payload bytes of the messages enqueued and not yet reported delivered or failed,
+/// shared by the producers replacing each other.
+#[derive(Debug)]
+pub struct OutstandingBytes {
+    bytes: AtomicU64,
+    max: AtomicU64,
+    lock: Mutex<()>,
+    delivered: Condvar,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl OutstandingBytes {
+    pub fn new(metrics: Arc<InstanceMetrics>) -> Self {
+        Self {
+            bytes: AtomicU64::default(),
+            max: AtomicU64::default(),
+            lock: Mutex::default(),
+            delivered: Condvar::default(),
+            metrics,
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Highest outstanding bytes seen.
+    pub fn max(&self) -> u64 {
+        self.max.load(Ordering::Relaxed)
+    }
+
+    pub fn enqueued(&self, bytes: usize) {
+        let outstanding = self.bytes.fetch_add(bytes as u64, Ordering::Relaxed) + bytes as u64;
+        self.metrics.kafka_outstanding_bytes.set(outstanding as i64);
+        if self.max.fetch_max(outstanding, Ordering::Relaxed) < outstanding {
+            self.metrics
+                .kafka_outstanding_bytes_max
+                .set(outstanding as i64);
+        }
+    }
+
+    /// Account for the delivery report of a message, successful or not.
+    pub fn delivered(&self, bytes: usize) {
+        let previous = self
+            .bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |outstanding| {
+                Some(outstanding.saturating_sub(bytes as u64))
+            })
+            .unwrap();
+        self.metrics
+            .kafka_outstanding_bytes
+            .set(previous.saturating_sub(bytes as u64) as i64);
+        self.delivered.notify_all();
+    }
+
+    /// Whether a message may be enqueued while `limit` bytes are outstanding: `Ok(false)` to
+    /// drop it, an error to fail it, or with `Block` once deliveries brought them below. A
+    /// blocked message is dropped once `deadline` passes.
+    pub fn admit(
+        &self,
+        limit: u64,
+        policy: BackpressurePolicy,
+        deadline: Option<Instant>,
+    ) -> Result<bool, KafkaError> {
+        if self.bytes() < limit {
+            return Ok(true);
+        }
+        match policy {
+            BackpressurePolicy::Block => {
+                let mut lock = self.lock.lock().unwrap();
+                while self.bytes() >= limit {
+                    let wait = match deadline {
+                        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                            Some(left) if !left.is_zero() => left.min(RECHECK_INTERVAL),
+                            _ => return Ok(false),
+                        },
+                        None => RECHECK_INTERVAL,
+                    };
+                    (lock, _) = self.delivered.wait_timeout(lock, wait).unwrap();
+                }
+                Ok(true)
+            }
+            BackpressurePolicy::Error => {
+                Err(KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull))
+            }
+            BackpressurePolicy::Drop => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::thread};
+
+    #[test]
+    fn test_accounting() {
+        let metrics = Arc::new(InstanceMetrics::default());
+        let outstanding = OutstandingBytes::new(Arc::clone(&metrics));
+        outstanding.enqueued(100);
+        outstanding.enqueued(50);
+        assert_eq!((outstanding.bytes(), outstanding.max()), (150, 150));
+        outstanding.delivered(100);
+        outstanding.enqueued(20);
+        assert_eq!((outstanding.bytes(), outstanding.max()), (70, 150));
+        assert_eq!(metrics.kafka_outstanding_bytes.get(), 70);
+        outstanding.delivered(500);
+        assert_eq!((outstanding.bytes(), outstanding.max()), (0, 150));
+        assert_eq!(metrics.kafka_outstanding_bytes.get(), 0);
+        assert_eq!(metrics.kafka_outstanding_bytes_max.get(), 150);
+    }
+
+    #[test]
+    fn test_policies() {
+        let outstanding = OutstandingBytes::new(Arc::default());
+        outstanding.enqueued(100);
+        for policy in [
+            BackpressurePolicy::Block,
+            BackpressurePolicy::Error,
+            BackpressurePolicy::Drop,
+        ] {
+            assert!(outstanding.admit(101, policy, None).unwrap());
+        }
+        assert!(!outstanding
+            .admit(100, BackpressurePolicy::Drop, None)
+            .unwrap());
+        assert_eq!(
+            outstanding
+                .admit(100, BackpressurePolicy::Error, None)
+                .unwrap_err()
+                .rdkafka_error_code(),
+            Some(RDKafkaErrorCode::QueueFull)
+        );
+        // Blocking gives up on the message at the deadline.
+        let deadline = Instant::now() + Duration::from_millis(20);
+        assert!(!outstanding
+            .admit(100, BackpressurePolicy::Block, Some(deadline))
+            .unwrap());
+        assert!(Instant::now() >= deadline);
+    }
+
+    #[test]
+    fn test_block_until_delivered() {
+        let outstanding = Arc::new(OutstandingBytes::new(Arc::default()));
+        outstanding.enqueued(100);
+        outstanding.enqueued(100);
+        let deliveries = thread::spawn({
+            let outstanding = Arc::clone(&outstanding);
+            move || {
+                thread::sleep(Duration::from_millis(20));
+                outstanding.delivered(100);
+                thread::sleep(Duration::from_millis(20));
+                outstanding.delivered(100);
+            }
+        });
+        assert!(outstanding
+            .admit(100, BackpressurePolicy::Block, None)
+            .unwrap());
+        assert!(outstanding.bytes() < 100);
+        deliveries.join().unwrap();
+    }
+}
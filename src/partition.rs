@@ -0,0 +1,295 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{instance_metrics::InstanceMetrics, resources::ThreadGuard, KafkaProducer},
+    arc_swap::ArcSwap,
+    log::warn,
+    rdkafka::{error::KafkaError, producer::Producer},
+    std::{
+        collections::HashMap,
+        sync::{
+            mpsc::{self, RecvTimeoutError, Sender},
+            Arc,
+        },
+        thread::{self, JoinHandle},
+        time::Duration,
+    },
+    xxhash_rust::xxh64::xxh64,
+};
+
+/// Partitions above this are counted under a single `other` label.
+const MAX_PARTITION_LABELS: i32 = 256;
+
+/// Partition of an account in explicit partitioning mode: the first four bytes of the
+/// pubkey as a little endian `u32`, modulo the partition count.
+pub fn explicit_partition(pubkey: &[u8], count: u32) -> Option<i32> {
+    let prefix = <[u8; 4]>::try_from(pubkey.get(..4)?).ok()?;
+    (count > 0).then(|| (u32::from_le_bytes(prefix) % count) as i32)
+}
+
+/// Partition of an account in sticky partitioning mode: the xxHash64 of the pubkey with seed 0,
+/// modulo the partition count. Only depends on the pubkey, never on the message key.
+pub fn sticky_partition(pubkey: &[u8], count: u32) -> Option<i32> {
+    (count > 0).then(|| (xxh64(pubkey, 0) % u64::from(count)) as i32)
+}
+
+/// Count a send in explicit partitioning mode, `None` if the partition count is unknown.
+pub fn count_send(metrics: &InstanceMetrics, partition: Option<i32>) {
+    let label = match partition {
+        Some(partition) if partition < MAX_PARTITION_LABELS => partition.to_string(),
+        Some(_) => "other".to_owned(),
+        None => "unassigned".to_owned(),
+    };
+    metrics
+        .explicit_partition_sends_total
+        .with_label_values(&[&label])
+        .inc();
+}
+
+/// Source of topic partition counts.
+pub trait PartitionSource: Send {
+    fn partition_count(&self, topic: &str) -> Result<u32, KafkaError>;
+}
+
+/// Partition counts from the metadata of the current producer.
+pub struct KafkaPartitionSource {
+    producer: Arc<ArcSwap<KafkaProducer>>,
+    timeout: Duration,
+}
+
+impl KafkaPartitionSource {
+    pub fn new(producer: Arc<ArcSwap<KafkaProducer>>, timeout: Duration) -> Self {
+        Self { producer, timeout }
+    }
+}
+
+impl PartitionSource for KafkaPartitionSource {
+    fn partition_count(&self, topic: &str) -> Result<u32, KafkaError> {
+        let metadata = self
+            .producer
+            .load()
+            .client()
+            .fetch_metadata(Some(topic), self.timeout)?;
+        match metadata.topics().iter().find(|meta| meta.name() == topic) {
+            Some(meta) => match meta.error() {
+                Some(error) => Err(KafkaError::MetadataFetch(error.into())),
+                None => Ok(meta.partitions().len() as u32),
+            },
+            None => Ok(0),
+        }
+    }
+}
+
+/// Latest known partition count of each explicitly partitioned topic.
+#[derive(Debug, Default)]
+pub struct PartitionCounts {
+    counts: ArcSwap<HashMap<String, u32>>,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl PartitionCounts {
+    pub fn new(metrics: Arc<InstanceMetrics>) -> Self {
+        Self {
+            counts: ArcSwap::default(),
+            metrics,
+        }
+    }
+
+    pub fn get(&self, topic: &str) -> Option<u32> {
+        self.counts.load().get(topic).copied()
+    }
+
+    /// Fetch the partition count of each of `topics`, keeping the previous count on failure.
+    pub fn refresh(&self, source: &dyn PartitionSource, topics: &[String]) {
+        let mut counts = HashMap::clone(&self.counts.load());
+        for topic in topics {
+            match source.partition_count(topic) {
+                Ok(0) => warn!("Topic {topic:?} has no partitions"),
+                Ok(count) => {
+                    if let Some(previous) = counts.insert(topic.clone(), count) {
+                        if previous != count {
+                            warn!(
+                                "Topic {topic:?} partition count changed from {previous} to \
+                                 {count}, accounts are now assigned to different partitions"
+                            );
+                            self.metrics
+                                .partition_count_changes_total
+                                .with_label_values(&[topic])
+                                .inc();
+                        }
+                    }
+                }
+                Err(error) => warn!("Failed to fetch partition count of topic {topic:?}: {error}"),
+            }
+        }
+        self.counts.store(Arc::new(counts));
+    }
+}
+
+/// Background thread refreshing partition counts.
+pub struct PartitionRefresher {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl PartitionRefresher {
+    pub fn spawn(
+        counts: Arc<PartitionCounts>,
+        source: Box<dyn PartitionSource>,
+        topics: Vec<String>,
+        interval: Duration,
+    ) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("kafkaPartitions".to_owned())
+            .spawn(move || {
+                let _thread = ThreadGuard::new(&counts.metrics, "background");
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    counts.refresh(source.as_ref(), &topics);
+                }
+            })
+            .expect("failed to spawn partition refresh thread");
+        Self {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for PartitionRefresher {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        rdkafka::types::RDKafkaErrorCode,
+        std::sync::{atomic::AtomicU32, atomic::Ordering},
+    };
+
+    /// Stubbed metadata, failing while the count is `u32::MAX`.
+    struct StubSource(Arc<AtomicU32>);
+
+    impl PartitionSource for StubSource {
+        fn partition_count(&self, topic: &str) -> Result<u32, KafkaError> {
+            match self.0.load(Ordering::Relaxed) {
+                u32::MAX => Err(KafkaError::MetadataFetch(
+                    RDKafkaErrorCode::BrokerTransportFailure,
+                )),
+                count if topic == "accounts" => Ok(count),
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn test_explicit_partition() {
+        let mut pubkey = [0xffu8; 32];
+        pubkey[..4].copy_from_slice(&10u32.to_le_bytes());
+        assert_eq!(explicit_partition(&pubkey, 4), Some(2));
+        assert_eq!(explicit_partition(&pubkey, 16), Some(10));
+        assert_eq!(explicit_partition(&pubkey, 1), Some(0));
+        assert_eq!(explicit_partition(&pubkey, 0), None);
+        assert_eq!(explicit_partition(&[1, 2, 3], 4), None);
+        assert_eq!(
+            explicit_partition(&[0xff; 32], 7),
+            Some((u32::MAX % 7) as i32)
+        );
+    }
+
+    #[test]
+    fn test_sticky_partition() {
+        // Pinned, consumers shard on these assignments.
+        assert_eq!(xxh64(&[0; 32], 0), 0xf6e9_be5d_7063_2cf5);
+        let pubkey = [7u8; 32];
+        let hash = xxh64(&pubkey, 0);
+        assert_eq!(sticky_partition(&pubkey, 12), Some((hash % 12) as i32));
+        assert_eq!(sticky_partition(&pubkey, 1), Some(0));
+        assert_eq!(sticky_partition(&pubkey, 0), None);
+
+        // Accounts sharing leading bytes still spread over partitions.
+        let partitions: std::collections::HashSet<_> = (0..=255u8)
+            .map(|last| {
+                let mut pubkey = [0u8; 32];
+                pubkey[31] = last;
+                sticky_partition(&pubkey, 8)
+            })
+            .collect();
+        assert_eq!(partitions.len(), 8);
+    }
+
+    #[test]
+    fn test_refresh() {
+        let count = Arc::new(AtomicU32::new(u32::MAX));
+        let source = StubSource(Arc::clone(&count));
+        let counts = PartitionCounts::default();
+        let topics = vec!["accounts".to_owned(), "missing".to_owned()];
+
+        counts.refresh(&source, &topics);
+        assert_eq!(counts.get("accounts"), None);
+
+        count.store(4, Ordering::Relaxed);
+        counts.refresh(&source, &topics);
+        assert_eq!(counts.get("accounts"), Some(4));
+        assert_eq!(counts.get("missing"), None);
+
+        // Failures keep the last known count, changes are picked up.
+        count.store(u32::MAX, Ordering::Relaxed);
+        counts.refresh(&source, &topics);
+        assert_eq!(counts.get("accounts"), Some(4));
+        count.store(8, Ordering::Relaxed);
+        counts.refresh(&source, &topics);
+        assert_eq!(counts.get("accounts"), Some(8));
+        assert_eq!(
+            counts
+                .metrics
+                .partition_count_changes_total
+                .with_label_values(&["accounts"])
+                .get(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_refresher() {
+        let count = Arc::new(AtomicU32::new(3));
+        let counts = Arc::new(PartitionCounts::default());
+        let refresher = PartitionRefresher::spawn(
+            Arc::clone(&counts),
+            Box::new(StubSource(Arc::clone(&count))),
+            vec!["accounts".to_owned()],
+            Duration::from_millis(5),
+        );
+        let wait_for = |expected| {
+            for _ in 0..1_000 {
+                if counts.get("accounts") == Some(expected) {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+            panic!("partition count never became {expected}");
+        };
+        wait_for(3);
+        count.store(6, Ordering::Relaxed);
+        wait_for(6);
+        drop(refresher);
+    }
+}
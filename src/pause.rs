@@ -0,0 +1,362 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{
+        config::active_topic, instance_metrics::InstanceMetrics, resources::MemoryFootprint,
+        Config, MessageHeaders, PausePolicy,
+    },
+    serde_json::{json, Map, Value},
+    std::{
+        collections::{HashMap, VecDeque},
+        mem::size_of,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+    },
+};
+
+/// Message held back while its topic is paused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpilledMessage {
+    pub key: Vec<u8>,
+    pub payload: Option<Vec<u8>>,
+    pub partition: Option<i32>,
+    pub headers: MessageHeaders,
+}
+
+impl SpilledMessage {
+    pub fn len(&self) -> usize {
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|(name, value)| name.len() + value.len())
+            .sum();
+        self.key.len() + self.payload.as_ref().map_or(0, Vec::len) + headers
+    }
+}
+
+#[derive(Debug, Default)]
+struct Spill {
+    messages: VecDeque<SpilledMessage>,
+    bytes: usize,
+}
+
+#[derive(Debug, Default)]
+struct TopicPause {
+    paused: AtomicBool,
+    /// Messages in `spill`, read without locking on every send.
+    spilled: AtomicUsize,
+    spill: Mutex<Spill>,
+}
+
+/// Pause state of every configured topic, flipped through `/admin/pause` and `/admin/resume`.
+#[derive(Debug, Default)]
+pub struct TopicPauses {
+    topics: HashMap<String, TopicPause>,
+    policy: PausePolicy,
+    max_bytes: usize,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl TopicPauses {
+    pub fn new(config: &Config, metrics: Arc<InstanceMetrics>) -> Self {
+        let topics = config
+            .topics()
+            .into_iter()
+            .map(|(topic, _)| topic)
+            .chain(
+                config
+                    .filters
+                    .iter()
+                    .filter_map(|filter| active_topic(&filter.state_topic))
+                    .map(str::to_owned),
+            )
+            .chain(config.checkpoint_topic())
+            .filter(|topic| !topic.is_empty())
+            .map(|topic| (topic, TopicPause::default()))
+            .collect();
+        Self {
+            topics,
+            policy: config.pause_policy,
+            max_bytes: config.pause_buffer_max_bytes,
+            metrics,
+        }
+    }
+
+    /// Returns false for topics that aren't configured.
+    pub fn pause(&self, topic: &str) -> bool {
+        self.set_paused(topic, true)
+    }
+
+    /// Buffered messages are sent ahead of the next message to the topic.
+    pub fn resume(&self, topic: &str) -> bool {
+        self.set_paused(topic, false)
+    }
+
+    fn set_paused(&self, topic: &str, paused: bool) -> bool {
+        match self.topics.get(topic) {
+            Some(pause) => {
+                // Under the spill lock, so sends see either state with a consistent spill.
+                let _spill = pause.spill.lock().unwrap();
+                pause.paused.store(paused, Ordering::Relaxed);
+                self.metrics
+                    .topic_paused
+                    .with_label_values(&[topic])
+                    .set(paused.into());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Send a message with `produce`, unless its topic is paused. Messages buffered while paused
+    /// are produced first once resumed, keeping their order.
+    pub fn send<E>(
+        &self,
+        topic: &str,
+        key: &[u8],
+        payload: Option<&[u8]>,
+        partition: Option<i32>,
+        headers: &[(String, Vec<u8>)],
+        mut produce: impl FnMut(
+            &[u8],
+            Option<&[u8]>,
+            Option<i32>,
+            &[(String, Vec<u8>)],
+        ) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let pause = match self.topics.get(topic) {
+            Some(pause)
+                if pause.paused.load(Ordering::Relaxed)
+                    || pause.spilled.load(Ordering::Relaxed) > 0 =>
+            {
+                pause
+            }
+            _ => return produce(key, payload, partition, headers),
+        };
+
+        let mut spill = pause.spill.lock().unwrap();
+        if pause.paused.load(Ordering::Relaxed) {
+            let message = SpilledMessage {
+                key: key.to_vec(),
+                payload: payload.map(<[u8]>::to_vec),
+                partition,
+                headers: headers.to_vec(),
+            };
+            let action = match self.policy {
+                PausePolicy::Buffer if spill.bytes + message.len() <= self.max_bytes => {
+                    spill.bytes += message.len();
+                    spill.messages.push_back(message);
+                    "buffered"
+                }
+                _ => "dropped",
+            };
+            self.metrics
+                .paused_messages_total
+                .with_label_values(&[topic, action])
+                .inc();
+        } else {
+            while let Some(message) = spill.messages.pop_front() {
+                if let Err(error) = produce(
+                    &message.key,
+                    message.payload.as_deref(),
+                    message.partition,
+                    &message.headers,
+                ) {
+                    spill.messages.push_front(message);
+                    self.update_spilled(topic, pause, &spill);
+                    return Err(error);
+                }
+                spill.bytes -= message.len();
+                self.metrics
+                    .paused_messages_total
+                    .with_label_values(&[topic, "flushed"])
+                    .inc();
+            }
+            produce(key, payload, partition, headers)?;
+        }
+        self.update_spilled(topic, pause, &spill);
+        Ok(())
+    }
+
+    fn update_spilled(&self, topic: &str, pause: &TopicPause, spill: &Spill) {
+        pause.spilled.store(spill.messages.len(), Ordering::Relaxed);
+        self.metrics
+            .topic_pause_buffered_bytes
+            .with_label_values(&[topic])
+            .set(spill.bytes as i64);
+    }
+
+    /// Resume every topic and take what is still buffered, in order, for a final flush.
+    pub fn drain(&self) -> Vec<(String, SpilledMessage)> {
+        let mut drained = Vec::new();
+        for (topic, pause) in &self.topics {
+            self.resume(topic);
+            let mut spill = pause.spill.lock().unwrap();
+            drained.extend(spill.messages.drain(..).map(|m| (topic.clone(), m)));
+            spill.bytes = 0;
+            self.update_spilled(topic, pause, &spill);
+        }
+        drained
+    }
+
+    pub fn to_json(&self) -> Value {
+        let topics: Map<String, Value> = self
+            .topics
+            .iter()
+            .map(|(topic, pause)| {
+                let spill = pause.spill.lock().unwrap();
+                let state = json!({
+                    "paused": pause.paused.load(Ordering::Relaxed),
+                    "buffered_messages": spill.messages.len(),
+                    "buffered_bytes": spill.bytes,
+                });
+                (topic.clone(), state)
+            })
+            .collect();
+        json!({"policy": self.policy, "topics": topics})
+    }
+
+    pub fn remove_gauges(&self) {
+        for topic in self.topics.keys() {
+            let _ = self.metrics.topic_paused.remove_label_values(&[topic]);
+            let _ = self
+                .metrics
+                .topic_pause_buffered_bytes
+                .remove_label_values(&[topic]);
+        }
+    }
+}
+
+/// Messages buffered while paused.
+impl MemoryFootprint for TopicPauses {
+    fn memory_footprint(&self) -> usize {
+        self.topics
+            .values()
+            .map(|pause| {
+                let spill = pause.spill.lock().unwrap();
+                spill.bytes + spill.messages.capacity() * size_of::<SpilledMessage>()
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::ConfigFilter};
+
+    /// Topics `<test>-txs` and `<test>-accounts`.
+    fn pauses(test: &str, policy: PausePolicy, max_bytes: usize) -> TopicPauses {
+        let mut config = Config::default();
+        config.filters = vec![ConfigFilter {
+            transaction_topic: Some(format!("{test}-txs")),
+            update_account_topic: Some(format!("{test}-accounts")),
+            ..Default::default()
+        }];
+        config.pause_policy = policy;
+        config.pause_buffer_max_bytes = max_bytes;
+        TopicPauses::new(&config, Arc::default())
+    }
+
+    fn send(pauses: &TopicPauses, topic: &str, key: u8, sent: &mut Vec<u8>) -> Result<(), ()> {
+        pauses.send(topic, &[key], Some(&[0; 4]), None, &[], |key, _, _, _| {
+            sent.push(key[0]);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_drop_policy() {
+        let pauses = pauses("drop", PausePolicy::Drop, 0);
+        let dropped = || {
+            pauses
+                .metrics
+                .paused_messages_total
+                .with_label_values(&["drop-txs", "dropped"])
+                .get()
+        };
+        let mut sent = Vec::new();
+        assert!(pauses.pause("drop-txs"));
+        assert!(!pauses.pause("unknown"));
+        send(&pauses, "drop-txs", 1, &mut sent).unwrap();
+        // Other topics are unaffected.
+        send(&pauses, "drop-accounts", 2, &mut sent).unwrap();
+        assert_eq!(sent, [2]);
+        assert_eq!(dropped(), 1);
+        let paused = pauses.metrics.topic_paused.with_label_values(&["drop-txs"]);
+        assert_eq!(paused.get(), 1);
+
+        assert!(pauses.resume("drop-txs"));
+        send(&pauses, "drop-txs", 3, &mut sent).unwrap();
+        assert_eq!(sent, [2, 3]);
+        assert_eq!(paused.get(), 0);
+    }
+
+    #[test]
+    fn test_buffer_policy() {
+        // Room for two messages of five bytes.
+        let pauses = pauses("buffer", PausePolicy::Buffer, 12);
+        let mut sent = Vec::new();
+        pauses.pause("buffer-txs");
+        for key in 1..=3 {
+            send(&pauses, "buffer-txs", key, &mut sent).unwrap();
+        }
+        assert!(sent.is_empty());
+        let state = pauses.to_json();
+        assert_eq!(
+            state["topics"]["buffer-txs"],
+            json!({"paused": true, "buffered_messages": 2, "buffered_bytes": 10})
+        );
+        assert_eq!(state["policy"], "buffer");
+
+        // A failing flush keeps the rest buffered, in order.
+        pauses.resume("buffer-txs");
+        assert!(pauses
+            .send("buffer-txs", &[4], None, None, &[], |_, _, _, _| Err(()))
+            .is_err());
+        assert_eq!(
+            pauses.to_json()["topics"]["buffer-txs"]["buffered_messages"],
+            2
+        );
+
+        let buffered = pauses.memory_footprint();
+        assert!(buffered >= 10 + 2 * size_of::<SpilledMessage>());
+
+        send(&pauses, "buffer-txs", 4, &mut sent).unwrap();
+        send(&pauses, "buffer-txs", 5, &mut sent).unwrap();
+        assert_eq!(sent, [1, 2, 4, 5]);
+        // Only the capacity of the flushed queue is left.
+        assert_eq!(buffered - pauses.memory_footprint(), 10);
+        assert_eq!(
+            pauses.to_json()["topics"]["buffer-txs"],
+            json!({"paused": false, "buffered_messages": 0, "buffered_bytes": 0})
+        );
+    }
+
+    #[test]
+    fn test_drain() {
+        let pauses = pauses("drain", PausePolicy::Buffer, 1 << 10);
+        let mut sent = Vec::new();
+        pauses.pause("drain-accounts");
+        send(&pauses, "drain-accounts", 7, &mut sent).unwrap();
+        let drained = pauses.drain();
+        assert_eq!(drained.len(), 1);
+        assert_eq!(drained[0].0, "drain-accounts");
+        assert_eq!(drained[0].1.key, [7]);
+        send(&pauses, "drain-accounts", 8, &mut sent).unwrap();
+        assert_eq!(sent, [8]);
+    }
+}
@@ -14,29 +14,411 @@
 
 use {
     crate::{
-        sanitized_message, CompiledInstruction, Config, Filter, InnerInstruction,
-        InnerInstructions, LegacyLoadedMessage, LegacyMessage, LoadedAddresses,
-        MessageAddressTableLookup, MessageHeader, PrometheusService, Publisher, Reward,
-        SanitizedMessage, SanitizedTransaction, SlotStatus, SlotStatusEvent, TransactionEvent,
-        TransactionStatusMeta, TransactionTokenBalance, UiTokenAmount, UpdateAccountEvent,
-        V0LoadedMessage, V0Message,
+        account_data::{
+            decode_lookup_table, decode_stake_account, is_lookup_table_account, is_stake_account,
+        },
+        account_origin, build_account_event_stripping, build_alt_failure_event, build_block_event,
+        build_transaction_event, build_transaction_index_event,
+        canary::verify_topic_access,
+        capture::{Capture, CaptureRecord},
+        changed_bytes::ChangedBytes,
+        checkpoint::{Checkpointer, SlotHorizon},
+        config::active_topic,
+        contention::WriteLockContention,
+        deadline::CallbackDeadline,
+        fee_market::{compute_unit_price, FeeMarket},
+        file_sink::FileSink,
+        filter::FilterFingerprint,
+        filter_index::FilterIndex,
+        filter_stats::FilterStats,
+        fork_instance::ForkInstances,
+        instance_metrics::InstanceMetrics,
+        large_accounts::LargeAccounts,
+        leader_schedule::{LeaderSchedule, LeaderScheduleFetcher, RpcLeaderScheduleSource},
+        message_wrapper::EventMessage,
+        meta_fields::MetaFields,
+        metrics_log::MetricsLogger,
+        producer_registry::ProducerRegistry,
+        program_stats::{ProgramStats, ProgramStatsPublisher},
+        prom::{
+            CallbackTimer, HttpState, Timer, NOTIFICATIONS_UNLOADED_TOTAL, PLUGIN_PANICS_TOTAL,
+        },
+        rate::FilterRates,
+        resources::MemoryFootprints,
+        rooted_barrier::RootedBarrierPoller,
+        rooted_blocks::RootedBlocks,
+        sequence::Sequences,
+        serialize_transaction,
+        slot_order::SlotStatusesSeen,
+        version::{check_validator_version, VERSION},
+        wall_clock, BlockEvent, Config, Filter, ProgramStatsSource, PrometheusService,
+        PublishError, Publisher, RecordedMessage, SinkKind, SlotStatus, SlotStatusEvent,
+        TransactionCaps, TransactionEvent, TransactionIndexEvent,
+    },
+    arc_swap::ArcSwapOption,
+    log::{
+        debug, error, info, log_enabled, warn, Level, LevelFilter, Log, Metadata, SetLoggerError,
     },
-    log::{debug, error, info, log_enabled},
     rdkafka::util::get_rdkafka_version,
     solana_geyser_plugin_interface::geyser_plugin_interface::{
         GeyserPlugin, GeyserPluginError as PluginError, ReplicaAccountInfoV3,
-        ReplicaAccountInfoVersions, ReplicaTransactionInfoV2, ReplicaTransactionInfoVersions,
-        Result as PluginResult, SlotStatus as PluginSlotStatus,
+        ReplicaAccountInfoVersions, ReplicaBlockInfoV3, ReplicaBlockInfoVersions,
+        ReplicaTransactionInfoV2, ReplicaTransactionInfoVersions, Result as PluginResult,
+        SlotStatus as PluginSlotStatus,
+    },
+    solana_program::{epoch_schedule::EpochSchedule, pubkey::Pubkey},
+    std::{
+        fmt::{Debug, Display, Formatter},
+        panic::{self, AssertUnwindSafe},
+        path::Path,
+        str::FromStr,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+        time::{Duration, Instant},
     },
-    solana_program::pubkey::Pubkey,
-    std::fmt::{Debug, Formatter},
 };
 
+/// Interval to check for the leader schedule of a new epoch.
+const LEADER_SCHEDULE_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Default)]
 pub struct KafkaPlugin {
-    publisher: Option<Publisher>,
-    filter: Option<Vec<Filter>>,
+    /// Swapped out on unload, callbacks still running keep their own reference.
+    state: ArcSwapOption<LoadedState>,
+}
+
+/// Everything created by `on_load`, only assigned once fully initialized.
+struct LoadedState {
+    publisher: Arc<Publisher>,
+    metrics: Arc<InstanceMetrics>,
+    filters: Vec<Filter>,
+    /// Filters that may match each event, to evaluate only those.
+    filter_index: FilterIndex,
+    filter_rates: Arc<FilterRates>,
+    filter_stats: Arc<FilterStats>,
+    changed_bytes: Arc<ChangedBytes>,
+    slot_horizon: Option<SlotHorizon>,
+    fee_market: FeeMarket,
+    large_accounts: LargeAccounts,
+    leader_schedule: Arc<LeaderSchedule>,
+    epoch_schedule: EpochSchedule,
+    workers: Mutex<Workers>,
+    /// Counts of the program stats publisher of `workers`.
+    program_stats: Option<Arc<ProgramStats>>,
+    program_stats_source: ProgramStatsSource,
+    alt_failure_topic: Option<String>,
+    meta_fields: MetaFields,
+    /// Top-level `slot_status_topic`, published to besides those of the filters.
+    slot_status_topic: Option<String>,
+    fork_instances: Option<ForkInstances>,
+    /// Slots with a status, to count events arriving ahead of them.
+    slot_statuses: SlotStatusesSeen,
+    catchup_slots: u64,
+    callback_deadline: Option<Duration>,
+    /// Longest unload waits for running callbacks to let go of the state.
+    shutdown_timeout: Duration,
+    /// Topic and counts of `write_lock_contention_topic`.
+    write_lock_contention: Option<(String, WriteLockContention)>,
+    capture: Option<Capture>,
+    producer_registry: Option<ProducerRegistry>,
+    rooted_blocks: Option<RootedBlocks>,
+    strip_executable_data: bool,
+    timing_metrics: bool,
+}
+
+/// Background threads and the metrics server of a loaded state, stopped on unload even while
+/// a callback still holds the state.
+#[derive(Default)]
+struct Workers {
+    leader_fetcher: Option<LeaderScheduleFetcher>,
     prometheus: Option<PrometheusService>,
+    checkpointer: Option<Checkpointer>,
+    /// Publishes rooted statuses released by `rooted_barrier`.
+    rooted_barrier: Option<RootedBarrierPoller>,
+    program_stats: Option<ProgramStatsPublisher>,
+    metrics_logger: Option<MetricsLogger>,
+}
+
+impl LoadedState {
+    fn new(config: &Config) -> PluginResult<Self> {
+        let publisher = match config.sink {
+            SinkKind::Kafka => {
+                let metrics = Arc::new(InstanceMetrics::new(config));
+                let producer = config.producer(metrics).map_err(|error| {
+                    error!("Failed to create kafka producer: {error:?}");
+                    PluginError::Custom(Box::new(error))
+                })?;
+                info!("Created rdkafka::FutureProducer");
+                Publisher::new(producer, config)
+            }
+            SinkKind::File => {
+                let sink = FileSink::create(
+                    config.file_sink_dir.as_deref().unwrap_or(Path::new(".")),
+                    config.file_sink_max_file_bytes,
+                    config.file_sink_compression,
+                )
+                .map_err(|error| {
+                    error!("Failed to create file sink: {error:?}");
+                    PluginError::Custom(Box::new(error))
+                })?;
+                Publisher::to_files(sink, config)
+            }
+        };
+        Self::with_publisher(config, publisher)
+    }
+
+    fn with_publisher(config: &Config, publisher: Publisher) -> PluginResult<Self> {
+        let filters: Vec<Filter> = config
+            .filters
+            .iter()
+            .enumerate()
+            .map(|(index, config)| {
+                let mut filter = Filter::new(config);
+                if filter.name.is_empty() {
+                    filter.name = index.to_string();
+                }
+                filter
+            })
+            .collect();
+        let fingerprint = FilterFingerprint::new(&filters);
+        info!(
+            "Filter config hash {}: {}",
+            fingerprint.hash, fingerprint.json
+        );
+
+        let metrics = publisher.metrics();
+        let publisher = match &config.sequence_state_path {
+            Some(path) => {
+                let sequences = Sequences::load(path, &metrics).map_err(|error| {
+                    error!("Failed to load sequence state from {path:?}: {error:?}");
+                    PluginError::Custom(Box::new(error))
+                })?;
+                publisher.with_sequences(sequences)
+            }
+            None => publisher,
+        };
+        let publisher = Arc::new(publisher);
+        let filter_rates = Arc::new(FilterRates::new(
+            filters.iter().map(|filter| filter.name.clone()).collect(),
+            Arc::clone(&metrics),
+        ));
+        let filter_stats = Arc::new(FilterStats::new(
+            &filters,
+            &config
+                .filter_stats_gauges
+                .iter()
+                .flat_map(|p| Pubkey::from_str(p).ok().map(|p| p.to_bytes()))
+                .collect(),
+            Arc::clone(&metrics),
+        ));
+        let changed_bytes = Arc::new(ChangedBytes::new(&filters));
+        let footprints = Arc::new(MemoryFootprints::default());
+        footprints.register("filters", Arc::new(filters.clone()));
+        footprints.register("changed_bytes", Arc::clone(&changed_bytes) as _);
+        publisher.register_footprints(&footprints);
+        let http_state = Arc::new(HttpState {
+            filters: filters.clone(),
+            filter_rates: Arc::clone(&filter_rates),
+            filter_stats: Arc::clone(&filter_stats),
+            slot_progress: publisher.slot_progress(),
+            pauses: publisher.pauses(),
+            kafka_stats: publisher.kafka_stats(),
+            admin_endpoints: config.admin_endpoints,
+            standby: publisher.standby(),
+            tail: publisher.tail(),
+            zstd_dicts: publisher.zstd_dictionaries(),
+            footprints,
+            metrics: Arc::clone(&metrics),
+        });
+        let prometheus = config.create_prometheus(http_state).map_err(|error| {
+            error!("Failed to create prometheus service: {error:?}");
+            PluginError::Custom(Box::new(error))
+        })?;
+        let slot_horizon = config
+            .skip_slots_older_than
+            .map(|max_age| SlotHorizon::new(max_age, publisher.slot_progress()));
+        let fee_market = FeeMarket::new(config.fee_market_window_slots, Arc::clone(&metrics));
+        let large_accounts = LargeAccounts::new(filters.len(), Arc::clone(&metrics));
+        let filter_index = FilterIndex::new(&filters);
+        let leader_schedule = Arc::new(LeaderSchedule::default());
+        let leader_fetcher = config.leader_schedule_rpc.clone().map(|url| {
+            LeaderScheduleFetcher::spawn(
+                Arc::clone(&leader_schedule),
+                url,
+                LEADER_SCHEDULE_REFRESH_INTERVAL,
+                Arc::clone(&metrics),
+            )
+        });
+        let checkpointer = config.checkpoint_interval_ms.map(|ms| {
+            // Consumers of unwrapped topics only expect their own event type.
+            let wrapped_topics = config
+                .topics()
+                .into_iter()
+                .filter(|(_, wrap_messages)| *wrap_messages)
+                .collect();
+            Checkpointer::spawn(
+                Arc::clone(&publisher),
+                wrapped_topics,
+                config.checkpoint_topic(),
+                Duration::from_millis(ms),
+            )
+        });
+        let rooted_barrier = publisher
+            .has_rooted_barrier()
+            .then(|| RootedBarrierPoller::spawn(Arc::clone(&publisher)));
+        let program_stats = config.program_stats_topic.clone().map(|topic| {
+            ProgramStatsPublisher::spawn(
+                Arc::clone(&publisher),
+                config.program_stats_top_k,
+                topic,
+                Duration::from_secs(config.stats_interval_secs),
+            )
+        });
+        let write_lock_contention = config.write_lock_contention_topic.clone().map(|topic| {
+            (
+                topic,
+                WriteLockContention::new(config.write_lock_contention_top_k, Arc::clone(&metrics)),
+            )
+        });
+        let metrics_logger = config.metrics_log_interval_secs.map(|secs| {
+            MetricsLogger::spawn(
+                Duration::from_secs(secs),
+                Arc::clone(&filter_rates),
+                Arc::clone(&metrics),
+            )
+        });
+        let capture = config
+            .capture_notifications_dir
+            .as_deref()
+            .map(|dir| Capture::create(dir, config.capture_max_bytes, config.capture_sample_rate))
+            .transpose()
+            .map_err(|error| {
+                error!("Failed to create notification capture: {error:?}");
+                PluginError::Custom(Box::new(error))
+            })?;
+        let producer_registry = active_topic(&config.producer_registry_topic)
+            .map(|topic| ProducerRegistry::new(config, topic.to_owned(), &fingerprint.hash));
+        if let Some(registry) = &producer_registry {
+            registry.register(&publisher);
+        }
+        metrics
+            .filter_config_hash
+            .with_label_values(&[&fingerprint.hash])
+            .set(1);
+        metrics.plugin_loaded.set(1);
+        Ok(Self {
+            publisher,
+            metrics: Arc::clone(&metrics),
+            filters,
+            filter_index,
+            filter_rates,
+            filter_stats,
+            changed_bytes,
+            slot_horizon,
+            fee_market,
+            large_accounts,
+            leader_schedule,
+            epoch_schedule: config.epoch_schedule(),
+            program_stats: program_stats
+                .as_ref()
+                .map(|publisher| Arc::clone(publisher.stats())),
+            workers: Mutex::new(Workers {
+                leader_fetcher,
+                prometheus,
+                checkpointer,
+                rooted_barrier,
+                program_stats,
+                metrics_logger,
+            }),
+            program_stats_source: config.program_stats_source,
+            alt_failure_topic: config.alt_failure_topic.clone(),
+            meta_fields: MetaFields::new(&config.require_meta_fields),
+            slot_status_topic: active_topic(&config.slot_status_topic).map(str::to_owned),
+            fork_instances: config
+                .fork_instance_ids
+                .then(|| ForkInstances::new(Arc::clone(&metrics))),
+            slot_statuses: SlotStatusesSeen::new(Arc::clone(&metrics)),
+            catchup_slots: config.catchup_slots,
+            callback_deadline: config.callback_deadline_ms.map(Duration::from_millis),
+            shutdown_timeout: Duration::from_millis(config.shutdown_timeout_ms),
+            write_lock_contention,
+            capture,
+            producer_registry,
+            rooted_blocks: config
+                .enrich_rooted_slot_with_block
+                .then(|| RootedBlocks::new(Arc::clone(&metrics))),
+            strip_executable_data: config.strip_executable_account_data,
+            timing_metrics: config.timing_metrics,
+        })
+    }
+
+    /// `fork_instance_id` of the events of `slot`, 0 if disabled.
+    fn fork_instance_id(&self, slot: u64, id: impl FnOnce(&ForkInstances, u64) -> u64) -> u64 {
+        self.fork_instances
+            .as_ref()
+            .map_or(0, |instances| id(instances, slot))
+    }
+
+    /// Whether events for `slot` are beyond `skip_slots_older_than`.
+    fn is_stale(&self, slot: u64, event: &str) -> bool {
+        self.slot_horizon
+            .as_ref()
+            .is_some_and(|horizon| horizon.is_stale(slot, event))
+    }
+
+    /// Stop background work, flush the producer, then stop serving metrics.
+    fn shutdown(self) {
+        let prometheus = self.stop_workers();
+        drop(self.capture);
+        if let Some(registry) = &self.producer_registry {
+            registry.deregister(&self.publisher);
+        }
+        drop(self.publisher);
+        self.filter_rates.remove_gauges();
+        self.filter_stats.remove_gauges();
+        if let Some(prometheus) = prometheus {
+            prometheus.shutdown();
+        }
+    }
+
+    /// `shutdown` of a state a callback still holds, flushing the producer and the capture
+    /// rather than dropping them.
+    fn shutdown_shared(&self) {
+        let prometheus = self.stop_workers();
+        if let Some(capture) = &self.capture {
+            capture.flush();
+        }
+        if let Some(registry) = &self.producer_registry {
+            registry.deregister(&self.publisher);
+        }
+        self.publisher.flush();
+        self.filter_rates.remove_gauges();
+        self.filter_stats.remove_gauges();
+        if let Some(prometheus) = prometheus {
+            prometheus.shutdown();
+        }
+    }
+
+    /// Stop and join the background threads, leaving the metrics server to stop last.
+    fn stop_workers(&self) -> Option<PrometheusService> {
+        let Workers {
+            leader_fetcher,
+            prometheus,
+            checkpointer,
+            rooted_barrier,
+            program_stats,
+            metrics_logger,
+        } = std::mem::take(&mut *self.workers.lock().unwrap());
+        drop(checkpointer);
+        drop(rooted_barrier);
+        drop(program_stats);
+        drop(leader_fetcher);
+        drop(metrics_logger);
+        prometheus
+    }
 }
 
 impl Debug for KafkaPlugin {
@@ -50,46 +432,66 @@ impl GeyserPlugin for KafkaPlugin {
         "KafkaPlugin"
     }
 
-    fn on_load(&mut self, config_file: &str, _: bool) -> PluginResult<()> {
-        if self.publisher.is_some() {
-            return Err(PluginError::Custom("plugin already loaded".into()));
+    fn setup_logger(&self, logger: &'static dyn Log, level: LevelFilter) -> PluginResult<()> {
+        // Adopt the validator's logger so that on_load finds it installed.
+        if install_logger(|| log::set_logger(logger), level) {
+            info!("Using validator logger");
         }
+        Ok(())
+    }
 
-        solana_logger::setup_with_default("info");
-        info!(
-            "Loading plugin {:?} from config_file {:?}",
-            self.name(),
-            config_file
-        );
-        let config = Config::read_from(config_file)?;
-
-        let (version_n, version_s) = get_rdkafka_version();
-        info!("rd_kafka_version: {:#08x}, {}", version_n, version_s);
+    fn on_load(&mut self, config_file: &str, _: bool) -> PluginResult<()> {
+        catch_panic(
+            "on_load",
+            format_args!("config_file {config_file:?}"),
+            || {
+                if self.is_loaded() {
+                    return Err(PluginError::Custom("plugin already loaded".into()));
+                }
 
-        let producer = config.producer().map_err(|error| {
-            error!("Failed to create kafka producer: {error:?}");
-            PluginError::Custom(Box::new(error))
-        })?;
-        info!("Created rdkafka::FutureProducer");
+                let config = Config::read_from(config_file)?;
+                // Times stamped from here on derive from an anchor taken at load.
+                wall_clock::reanchor();
+                if config.setup_logger {
+                    if !install_default_logger() {
+                        debug!("Logger already installed, not replacing it");
+                    }
+                    let (version_n, version_s) = get_rdkafka_version();
+                    info!("rd_kafka_version: {:#08x}, {}", version_n, version_s);
+                }
+                info!(
+                    "Loading plugin {:?} from config_file {:?}",
+                    self.name(),
+                    config_file
+                );
+                info!(
+                    "Built against solana-geyser-plugin-interface {}, the validator must run \
+                     the same minor version",
+                    VERSION.geyser_interface
+                );
+                Self::check_validator_version(&config)?;
+                if config.verify_topic_access && config.sink == SinkKind::Kafka {
+                    verify_topic_access(&config)?;
+                }
+                if let Some(profile) = config.delivery_profile {
+                    let settings: Vec<String> = config
+                        .delivery_profile_settings()
+                        .into_iter()
+                        .map(|(k, v)| format!("{k}={v}"))
+                        .collect();
+                    info!("Delivery profile {profile:?}: {}", settings.join(", "));
+                }
 
-        let publisher = Publisher::new(producer, &config);
-        let prometheus = config
-            .create_prometheus()
-            .map_err(|error| PluginError::Custom(Box::new(error)))?;
-        self.publisher = Some(publisher);
-        self.filter = Some(config.filters.iter().map(Filter::new).collect());
-        self.prometheus = prometheus;
-        info!("Spawned producer");
+                self.state.store(Some(Arc::new(LoadedState::new(&config)?)));
+                info!("Spawned producer");
 
-        Ok(())
+                Ok(())
+            },
+        )
     }
 
     fn on_unload(&mut self) {
-        self.publisher = None;
-        self.filter = None;
-        if let Some(prometheus) = self.prometheus.take() {
-            prometheus.shutdown();
-        }
+        self.unload()
     }
 
     fn update_account(
@@ -98,39 +500,206 @@ impl GeyserPlugin for KafkaPlugin {
         slot: u64,
         is_startup: bool,
     ) -> PluginResult<()> {
-        let filters = self.unwrap_filters();
-        if is_startup && filters.iter().all(|filter| !filter.publish_all_accounts) {
-            return Ok(());
-        }
+        catch_panic("update_account", format_args!("slot {slot}"), || {
+            let Some(state) = self.loaded("update_account") else {
+                return Ok(());
+            };
+            let _timer =
+                CallbackTimer::start(&state.metrics, state.timing_metrics, "update_account");
+            let mut deadline =
+                CallbackDeadline::start(&state.metrics, "update_account", state.callback_deadline);
+            let filters = &state.filters;
+            // Each filter decides for itself whether it takes startup accounts.
+            let publishes_updates = |filter: &Filter| !is_startup || filter.publish_all_accounts;
+            if is_startup
+                && !filters
+                    .iter()
+                    .any(|filter| filter.publish_all_accounts || filter.wants_state(true))
+            {
+                return Ok(());
+            }
 
-        let info = Self::unwrap_update_account(account);
-        let publisher = self.unwrap_publisher();
-        for filter in filters {
-            if !filter.update_account_topic.is_empty() {
-                if !filter.wants_program(info.owner) && !filter.wants_account(info.pubkey) {
+            if is_startup {
+                state.publisher.observe_startup_slot(slot);
+            } else {
+                state.slot_statuses.observe_event(slot, "account");
+            }
+            let info = Self::unwrap_update_account(account);
+            if let Some(capture) = &state.capture {
+                capture.record(|| CaptureRecord::account(slot, is_startup, info));
+            }
+            let is_startup_label = if is_startup { "true" } else { "false" };
+            state
+                .metrics
+                .account_updates_total
+                .with_label_values(&[is_startup_label])
+                .inc();
+            if info.txn.is_none() {
+                state
+                    .metrics
+                    .account_updates_missing_txn_total
+                    .with_label_values(&[is_startup_label])
+                    .inc();
+            }
+            if state.is_stale(slot, "account") {
+                return Ok(());
+            }
+            let publisher = &state.publisher;
+            let timing = state.timing_metrics;
+            let filter_rates = &state.filter_rates;
+            let filter_stats = &state.filter_stats;
+            // Startup accounts come from a snapshot rather than a bank being replayed.
+            let fork_instance_id = if is_startup {
+                0
+            } else {
+                state.fork_instance_id(slot, ForkInstances::replaying)
+            };
+            let highest_rooted_slot = publisher.rooted_slot();
+            let origin = account_origin(is_startup, slot, highest_rooted_slot, state.catchup_slots);
+            let mut failures = PublishFailures::new(&state.metrics);
+            let mut destinations = Destinations::new(&state.metrics);
+            let mut published = false;
+            let mut evaluated = 0;
+            for index in state
+                .filter_index
+                .account_candidates(info.pubkey, info.owner)
+            {
+                if deadline.exceeded() {
+                    break;
+                }
+                let filter = &filters[index];
+                evaluated += 1;
+                let state_topic = filter.state_topic(is_startup);
+                let update_topic = filter
+                    .account_topic(info.data.len())
+                    .filter(|_| publishes_updates(filter));
+                if update_topic.is_none() && state_topic.is_none() {
+                    continue;
+                }
+                let timer = Timer::start(timing);
+                let decision = filter.matches_summary(info.pubkey, info.owner);
+                timer.observe(&state.metrics.filter_eval_seconds, "account");
+                if !decision.is_match() {
                     Self::log_ignore_account_update(info);
                     continue;
                 }
+                // Only entries of the filter's lists are credited.
+                filter_stats.record_program(index, info.owner);
+                filter_stats.record_account(index, info.pubkey);
+                let build_event = || {
+                    let timer = Timer::start(timing);
+                    let mut event =
+                        build_account_event_stripping(slot, info, state.strip_executable_data);
+                    event.fork_instance_id = fork_instance_id;
+                    event.set_origin(origin);
+                    event.highest_rooted_slot = highest_rooted_slot;
+                    if filter.decode_stake_accounts && is_stake_account(info.owner) {
+                        event.stake_account = Some(decode_stake_account(info.data));
+                    }
+                    if filter.decode_address_lookup_tables && is_lookup_table_account(info.owner) {
+                        event.lookup_table = Some(decode_lookup_table(info.data));
+                    }
+                    timer.observe(&state.metrics.event_build_seconds, "account");
+                    event
+                };
+                let record_large = |topic: &str| {
+                    state.large_accounts.record(
+                        index,
+                        filter.warn_data_size_bytes,
+                        topic,
+                        info.pubkey,
+                        info.owner,
+                        info.data.len(),
+                    );
+                };
+                if let Some(topic) =
+                    state_topic.filter(|topic| destinations.insert("account_state", topic, false))
+                {
+                    let deleted = info.lamports == 0;
+                    let result = if deleted {
+                        publisher.delete_account_state(slot, info.pubkey, is_startup, topic)
+                    } else {
+                        publisher.update_account_state(
+                            build_event(),
+                            filter.schema_compat,
+                            is_startup,
+                            topic,
+                        )
+                    };
+                    // The rest of the slot's account messages are suppressed too.
+                    if let Err(PublishError::OverBudget) = result {
+                        break;
+                    }
+                    if failures.check(topic, filter.fail_open, result) {
+                        if !deleted {
+                            record_large(topic);
+                        }
+                        published = true;
+                    }
+                }
 
-                let event = UpdateAccountEvent {
-                    slot,
-                    pubkey: info.pubkey.to_vec(),
-                    lamports: info.lamports,
-                    owner: info.owner.to_vec(),
-                    executable: info.executable,
-                    rent_epoch: info.rent_epoch,
-                    data: info.data.to_vec(),
-                    write_version: info.write_version,
-                    txn_signature: info.txn.map(|v| v.signature().as_ref().to_owned()),
+                let Some(topic) = update_topic else {
+                    continue;
                 };
+                // Before the changed bytes are taken for published.
+                if deadline.exceeded() {
+                    break;
+                }
+                if filter.require_causing_txn && info.txn.is_none() {
+                    state
+                        .metrics
+                        .account_updates_skipped_missing_txn_total
+                        .inc();
+                    continue;
+                }
+                if !state.changed_bytes.changed(index, info.pubkey, info.data) {
+                    state
+                        .metrics
+                        .account_updates_skipped_unchanged_total
+                        .with_label_values(&[&filter.name])
+                        .inc();
+                    continue;
+                }
+                if !destinations.insert("account", topic, filter.wrap_account_messages) {
+                    continue;
+                }
+                let result = publisher.update_account(
+                    build_event(),
+                    filter.wrap_account_messages,
+                    filter.partitioning,
+                    filter.schema_compat,
+                    is_startup,
+                    topic,
+                );
+                if let Err(PublishError::OverBudget) = result {
+                    break;
+                }
+                if failures.check(topic, filter.fail_open, result) {
+                    filter_rates.record_account(index);
+                    record_large(topic);
+                    published = true;
+                }
+            }
+            state
+                .metrics
+                .filters_evaluated_per_event
+                .with_label_values(&["account"])
+                .observe(evaluated as f64);
 
-                publisher
-                    .update_account(event, filter.wrap_messages, &filter.update_account_topic)
-                    .map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() })?;
+            if published && publisher.wants_firehose("account") {
+                let mut event =
+                    build_account_event_stripping(slot, info, state.strip_executable_data);
+                event.fork_instance_id = fork_instance_id;
+                event.set_origin(origin);
+                event.highest_rooted_slot = highest_rooted_slot;
+                let result = publisher.update_firehose(EventMessage::Account(event), slot);
+                failures.check(publisher.firehose_topic(), false, result);
             }
-        }
+            // Counts callbacks whose sends alone passed the deadline.
+            deadline.exceeded();
 
-        Ok(())
+            failures.into_result(|msg| PluginError::AccountsUpdateError { msg })
+        })
     }
 
     fn update_slot_status(
@@ -139,22 +708,111 @@ impl GeyserPlugin for KafkaPlugin {
         parent: Option<u64>,
         status: PluginSlotStatus,
     ) -> PluginResult<()> {
-        let publisher = self.unwrap_publisher();
-        for filter in self.unwrap_filters() {
-            if !filter.slot_status_topic.is_empty() {
-                let event = SlotStatusEvent {
-                    slot,
-                    parent: parent.unwrap_or(0),
-                    status: SlotStatus::from(status).into(),
+        catch_panic("update_slot_status", format_args!("slot {slot}"), || {
+            let Some(state) = self.loaded("update_slot_status") else {
+                return Ok(());
+            };
+            let _timer =
+                CallbackTimer::start(&state.metrics, state.timing_metrics, "update_slot_status");
+            if let Some(capture) = &state.capture {
+                capture.record_always(|| CaptureRecord::slot(slot, parent, &status));
+            }
+            state.slot_statuses.observe_status(slot);
+            let publisher = &state.publisher;
+            let notified = status;
+            let status = SlotStatus::from(notified);
+            let status_description = if status == SlotStatus::Unknown {
+                state.metrics.slot_statuses_unknown_total.inc();
+                format!("{notified:?}")
+            } else {
+                String::new()
+            };
+            publisher.observe_slot(slot, status);
+            let fork_instance_id = if status == SlotStatus::Processed {
+                state.fork_instance_id(slot, ForkInstances::processed)
+            } else {
+                state.fork_instance_id(slot, ForkInstances::current)
+            };
+            let suppressed_account_updates = publisher.account_budget().map_or(0, |budget| {
+                let suppressed = budget.suppressed(slot);
+                if status == SlotStatus::Rooted {
+                    budget.root(slot);
+                }
+                suppressed
+            });
+            if let (SlotStatus::Processed, Some((topic, contention))) =
+                (status, &state.write_lock_contention)
+            {
+                if let Some(event) = contention.take(slot) {
+                    if let Err(error) = publisher.publish_slot_contention(topic, &event) {
+                        error!("Failed to publish write lock contention of slot {slot}: {error}");
+                    }
+                }
+            }
+            if publisher.is_rooted_only() {
+                // Other statuses would announce slots whose events are held back.
+                if is_dead(&status_description) {
+                    publisher.drop_slot(slot);
+                } else if status != SlotStatus::Rooted {
+                    return Ok(());
+                } else {
+                    publisher
+                        .root_slot(slot)
+                        .map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() })?;
+                }
+            }
+            let block = match (status, &state.rooted_blocks) {
+                (SlotStatus::Rooted, Some(blocks)) => blocks.take_rooted(slot),
+                _ => None,
+            };
+            if state.is_stale(slot, "slot") {
+                return Ok(());
+            }
+            let (epoch, slot_index_in_epoch) = state.epoch_schedule.get_epoch_and_slot_index(slot);
+            let event = SlotStatusEvent {
+                slot,
+                parent: parent.unwrap_or(0),
+                status: status.into(),
+                epoch,
+                slot_index_in_epoch,
+                sequence: 0,
+                suppressed_account_updates,
+                fork_instance_id,
+                barrier_timeout: false,
+                block,
+                status_description,
+            };
+            let mut destinations = Destinations::new(&state.metrics);
+            let mut published = false;
+            for filter in &state.filters {
+                let Some(topic) = active_topic(&filter.slot_status_topic) else {
+                    continue;
                 };
+                if destinations.insert("slot", topic, filter.wrap_slot_messages) {
+                    published |= sent(publisher.update_slot_status(
+                        event.clone(),
+                        filter.wrap_slot_messages,
+                        topic,
+                    ))
+                    .map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() })?;
+                }
+            }
+            if let Some(topic) = state
+                .slot_status_topic
+                .as_deref()
+                .filter(|topic| destinations.insert("slot", topic, false))
+            {
+                published |= sent(publisher.update_slot_status(event.clone(), false, topic))
+                    .map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() })?;
+            }
 
-                publisher
-                    .update_slot_status(event, filter.wrap_messages, &filter.slot_status_topic)
+            if published && publisher.wants_firehose("slot") {
+                sent(publisher.update_firehose(EventMessage::Slot(event), slot))
                     .map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() })?;
             }
-        }
 
-        Ok(())
+            Ok(())
+        })
     }
 
     fn notify_transaction(
@@ -162,54 +820,290 @@ impl GeyserPlugin for KafkaPlugin {
         transaction: ReplicaTransactionInfoVersions,
         slot: u64,
     ) -> PluginResult<()> {
-        let info = Self::unwrap_transaction(transaction);
-        let publisher = self.unwrap_publisher();
-        for filter in self.unwrap_filters() {
-            if !filter.transaction_topic.is_empty() {
+        catch_panic("notify_transaction", format_args!("slot {slot}"), || {
+            let Some(state) = self.loaded("notify_transaction") else {
+                return Ok(());
+            };
+            let _timer =
+                CallbackTimer::start(&state.metrics, state.timing_metrics, "notify_transaction");
+            let mut deadline = CallbackDeadline::start(
+                &state.metrics,
+                "notify_transaction",
+                state.callback_deadline,
+            );
+            let info = Self::unwrap_transaction(transaction);
+            if let Some(capture) = &state.capture {
+                capture.record(|| CaptureRecord::transaction(slot, info));
+            }
+            state.slot_statuses.observe_event(slot, "transaction");
+            state
+                .meta_fields
+                .observe(&state.metrics, info.signature, info.transaction_status_meta);
+            if state.is_stale(slot, "transaction") {
+                return Ok(());
+            }
+            let publisher = &state.publisher;
+            let timing = state.timing_metrics;
+            let filter_rates = &state.filter_rates;
+            let filter_stats = &state.filter_stats;
+            let fork_instance_id = state.fork_instance_id(slot, ForkInstances::replaying);
+            let mut failures = PublishFailures::new(&state.metrics);
+            let mut destinations = Destinations::new(&state.metrics);
+            let mut published = false;
+            let mut matched = false;
+            let mut evaluated = 0;
+            for &index in state.filter_index.transaction_candidates() {
+                if deadline.exceeded() {
+                    break;
+                }
+                let filter = &state.filters[index];
+                evaluated += 1;
+                let topic = filter.transaction_topic();
+                let index_topic = filter.transaction_index_topic();
+                if topic.is_none() && index_topic.is_none() {
+                    continue;
+                }
+                let filtered = |reason: &str| {
+                    state
+                        .metrics
+                        .transactions_filtered_total
+                        .with_label_values(&[&filter.name, reason])
+                        .inc();
+                };
+                let timer = Timer::start(timing);
                 let is_failed = info.transaction_status_meta.status.is_err();
-                if (!filter.wants_vote_tx() && info.is_vote)
-                    || (!filter.wants_failed_tx() && is_failed)
+                let rejection = if !filter.wants_vote_tx() && info.is_vote {
+                    Some("vote")
+                } else if !filter.wants_failed_tx() && is_failed {
+                    Some("failed")
+                } else if let Some(reason) =
+                    filter.failed_tx_exclusion(&info.transaction_status_meta.status)
                 {
+                    state
+                        .metrics
+                        .transaction_errors_excluded_total
+                        .with_label_values(&[&filter.name, reason])
+                        .inc();
+                    Some("error_excluded")
+                } else {
+                    None
+                };
+                if let Some(reason) = rejection {
+                    timer.observe(&state.metrics.filter_eval_seconds, "transaction");
+                    filtered(reason);
                     debug!("Ignoring vote/failed transaction");
                     continue;
                 }
 
-                if !info
-                    .transaction
-                    .message()
-                    .account_keys()
-                    .iter()
-                    .any(|pubkey| {
-                        filter.wants_program(pubkey.as_ref())
-                            || filter.wants_account(pubkey.as_ref())
-                    })
-                {
+                let account_keys = info.transaction.message().account_keys();
+                let wanted = account_keys.iter().any(|pubkey| {
+                    filter.wants_program(pubkey.as_ref()) || filter.wants_account(pubkey.as_ref())
+                });
+                timer.observe(&state.metrics.filter_eval_seconds, "transaction");
+                if !wanted {
+                    filtered("no_key_match");
                     debug!("Ignoring transaction {:?}", info.signature);
                     continue;
                 }
+                matched = true;
+                for pubkey in account_keys.iter() {
+                    filter_stats.record_program(index, pubkey.as_ref());
+                    filter_stats.record_account(index, pubkey.as_ref());
+                }
+                let (mut index_sent, mut index_skipped) = (true, None);
+                if let Some(index_topic) = index_topic {
+                    if destinations.insert("transaction_index", index_topic, false) {
+                        let event = Self::build_transaction_index_event(slot, info, filter);
+                        let result = publisher.update_transaction_index(event, index_topic);
+                        index_skipped = result.as_ref().err().and_then(PublishError::skip_status);
+                        index_sent = failures.check(index_topic, filter.fail_open, result);
+                    }
+                }
+                let Some(topic) = topic else {
+                    filtered(match (index_sent, index_skipped) {
+                        (true, _) => "published",
+                        (false, Some(status)) => status,
+                        (false, None) => "send_failed",
+                    });
+                    continue;
+                };
+                if !filter.samples_transaction(info.signature.as_ref()) {
+                    state
+                        .metrics
+                        .transactions_sampled_out_total
+                        .with_label_values(&[&filter.name])
+                        .inc();
+                    filtered("sampled_out");
+                    continue;
+                }
+                if !destinations.insert("transaction", topic, filter.wrap_transaction_messages) {
+                    // Published by an earlier filter sharing the topic.
+                    filtered("published");
+                    continue;
+                }
 
-                let event = Self::build_transaction_event(slot, info);
-                publisher
-                    .update_transaction(event, filter.wrap_messages, &filter.transaction_topic)
-                    .map_err(|e| PluginError::TransactionUpdateError { msg: e.to_string() })?;
+                let timer = Timer::start(timing);
+                let mut event = Self::build_transaction_event(
+                    &state.metrics,
+                    slot,
+                    fork_instance_id,
+                    info,
+                    filter.transaction_caps,
+                );
+                if filter.include_raw_transaction {
+                    event.raw_transaction = serialize_transaction(info.transaction);
+                }
+                timer.observe(&state.metrics.event_build_seconds, "transaction");
+                Self::count_truncation(&state.metrics, &event);
+                let result = publisher.update_transaction(
+                    event,
+                    filter.wrap_transaction_messages,
+                    filter.transaction_key_source,
+                    topic,
+                );
+                let skipped = result.as_ref().err().and_then(PublishError::skip_status);
+                if failures.check(topic, filter.fail_open, result) {
+                    filtered("published");
+                    filter_rates.record_transaction(index);
+                    published = true;
+                } else {
+                    filtered(skipped.unwrap_or("send_failed"));
+                }
             }
-        }
+            state
+                .metrics
+                .filters_evaluated_per_event
+                .with_label_values(&["transaction"])
+                .observe(evaluated as f64);
 
-        Ok(())
+            if let Some(program_stats) = &state.program_stats {
+                if published || state.program_stats_source == ProgramStatsSource::Seen {
+                    program_stats.record(
+                        info.transaction.message(),
+                        info.transaction_status_meta.status.is_err(),
+                        info.transaction_status_meta.compute_units_consumed,
+                    );
+                }
+            }
+
+            if let Some(topic) = &state.alt_failure_topic {
+                let event = build_alt_failure_event(
+                    slot,
+                    info.signature,
+                    info.transaction,
+                    info.transaction_status_meta,
+                );
+                if let Some(event) = event {
+                    let result = publisher.publish_alt_failure(topic, &event);
+                    failures.check(topic, false, result.map_err(PublishError::Kafka));
+                }
+            }
+
+            if let Some((_, contention)) = &state.write_lock_contention {
+                contention.record(slot, info.transaction.message());
+            }
+
+            if !info.is_vote {
+                state.fee_market.record(
+                    slot,
+                    matched,
+                    compute_unit_price(info.transaction.message()),
+                    info.transaction_status_meta.compute_units_consumed,
+                );
+            }
+
+            if published && publisher.wants_firehose("transaction") {
+                let event = Self::build_transaction_event(
+                    &state.metrics,
+                    slot,
+                    fork_instance_id,
+                    info,
+                    TransactionCaps::default(),
+                );
+                let result = publisher.update_firehose(EventMessage::Transaction(event), slot);
+                failures.check(publisher.firehose_topic(), false, result);
+            }
+            deadline.exceeded();
+
+            failures.into_result(|msg| PluginError::TransactionUpdateError { msg })
+        })
+    }
+
+    fn notify_block_metadata(&self, blockinfo: ReplicaBlockInfoVersions) -> PluginResult<()> {
+        catch_panic("notify_block_metadata", "", || {
+            let Some(state) = self.loaded("notify_block_metadata") else {
+                return Ok(());
+            };
+            let _timer = CallbackTimer::start(
+                &state.metrics,
+                state.timing_metrics,
+                "notify_block_metadata",
+            );
+            let info = Self::unwrap_block_metadata(blockinfo);
+            let slot = info.slot;
+            state.slot_statuses.observe_event(slot, "block");
+            if state.is_stale(slot, "block") {
+                return Ok(());
+            }
+            let publisher = &state.publisher;
+            let leader = state.leader_schedule.leader(slot);
+            let fork_instance_id = state.fork_instance_id(slot, ForkInstances::current);
+            let event = BlockEvent {
+                fork_instance_id,
+                ..build_block_event(info, leader, &state.epoch_schedule)
+            };
+            if event.blockhash_invalid {
+                state.metrics.blockhash_invalid_total.inc();
+                warn!(
+                    "Block of slot {slot} has blockhash {:?} and parent blockhash {:?}, not both \
+                     base58 of 32 bytes",
+                    info.blockhash, info.parent_blockhash
+                );
+            }
+            if let Some(blocks) = &state.rooted_blocks {
+                blocks.insert(slot, event.clone());
+            }
+            let build_event = || event.clone();
+            let mut destinations = Destinations::new(&state.metrics);
+            let mut published = false;
+            for filter in &state.filters {
+                let Some(topic) = active_topic(&filter.block_events_topic) else {
+                    continue;
+                };
+                if destinations.insert("block", topic, filter.wrap_block_messages) {
+                    let event = build_event();
+                    published |=
+                        sent(publisher.update_block(event, filter.wrap_block_messages, topic))
+                            .map_err(|e| PluginError::SlotStatusUpdateError {
+                                msg: e.to_string(),
+                            })?;
+                }
+            }
+
+            if published && publisher.wants_firehose("block") {
+                let event = build_event();
+                sent(publisher.update_firehose(EventMessage::Block(event), slot))
+                    .map_err(|e| PluginError::SlotStatusUpdateError { msg: e.to_string() })?;
+            }
+
+            Ok(())
+        })
     }
 
     fn account_data_notifications_enabled(&self) -> bool {
-        let filters = self.unwrap_filters();
-        filters
-            .iter()
-            .any(|filter| !filter.update_account_topic.is_empty())
+        self.state
+            .load()
+            .as_ref()
+            .is_some_and(|state| state.filters.iter().any(|filter| filter.wants_accounts()))
     }
 
     fn transaction_notifications_enabled(&self) -> bool {
-        let filters = self.unwrap_filters();
-        filters
-            .iter()
-            .any(|filter| !filter.transaction_topic.is_empty())
+        self.state.load().as_ref().is_some_and(|state| {
+            state
+                .filters
+                .iter()
+                .any(|filter| filter.wants_transactions())
+        })
     }
 }
 
@@ -218,12 +1112,103 @@ impl KafkaPlugin {
         Default::default()
     }
 
-    fn unwrap_publisher(&self) -> &Publisher {
-        self.publisher.as_ref().expect("publisher is unavailable")
+    pub fn is_loaded(&self) -> bool {
+        self.state.load().is_some()
     }
 
-    fn unwrap_filters(&self) -> &Vec<Filter> {
-        self.filter.as_ref().expect("filter is unavailable")
+    /// Fail unless `expected_validator_version`, or the version probed from
+    /// `leader_schedule_rpc`, runs the Geyser interface the plugin was built against.
+    fn check_validator_version(config: &Config) -> PluginResult<()> {
+        let validator = match (
+            &config.expected_validator_version,
+            &config.leader_schedule_rpc,
+        ) {
+            (Some(version), _) => version.clone(),
+            (None, Some(url)) if config.probe_validator_version => {
+                let url = url.clone();
+                // On its own thread, as the blocking client panics inside an async runtime.
+                thread::spawn(move || RpcLeaderScheduleSource::new(url)?.validator_version())
+                    .join()
+                    .unwrap_or_else(|_| Err("probe panicked".to_owned()))
+                    .map_err(|error| {
+                        PluginError::Custom(
+                            format!("failed to probe validator version: {error}").into(),
+                        )
+                    })?
+            }
+            _ => return Ok(()),
+        };
+        check_validator_version(&validator).map_err(|error| {
+            error!("{error}");
+            PluginError::Custom(error.into())
+        })?;
+        info!("Validator version {validator} matches the Geyser interface");
+        Ok(())
+    }
+
+    /// Plugin loaded from `config` with a publisher recording messages instead of producing them.
+    pub fn dry_run(config: &Config) -> PluginResult<Self> {
+        Ok(Self {
+            state: ArcSwapOption::from_pointee(LoadedState::with_publisher(
+                config,
+                Publisher::dry_run(config),
+            )?),
+        })
+    }
+
+    /// Messages recorded by a dry run plugin since the previous call.
+    pub fn take_recorded(&self) -> Vec<RecordedMessage> {
+        self.state
+            .load()
+            .as_ref()
+            .map(|state| state.publisher.take_recorded())
+            .unwrap_or_default()
+    }
+
+    /// Metrics of the loaded instance.
+    pub fn metrics(&self) -> Option<Arc<InstanceMetrics>> {
+        self.state
+            .load()
+            .as_ref()
+            .map(|state| Arc::clone(&state.metrics))
+    }
+
+    /// State of a loaded plugin, counting `callback` as ignored once unloaded.
+    fn loaded(&self, callback: &'static str) -> Option<Arc<LoadedState>> {
+        let state = self.state.load_full();
+        if state.is_none() {
+            NOTIFICATIONS_UNLOADED_TOTAL
+                .with_label_values(&[callback])
+                .inc();
+        }
+        state
+    }
+
+    /// Stop handing the state to new callbacks, wait up to `shutdown_timeout_ms` for running
+    /// ones to let go of it, then shut it down.
+    fn unload(&self) {
+        catch_panic("on_unload", "", || {
+            if let Some(mut state) = self.state.swap(None) {
+                state.metrics.plugin_loaded.set(0);
+                let timeout = state.shutdown_timeout;
+                let deadline = Instant::now() + timeout;
+                loop {
+                    match Arc::try_unwrap(state) {
+                        Ok(state) => break state.shutdown(),
+                        Err(shared) if Instant::now() >= deadline => {
+                            warn!("Unloading while a callback still runs after {timeout:?}");
+                            break shared.shutdown_shared();
+                        }
+                        Err(shared) => {
+                            state = shared;
+                            thread::sleep(Duration::from_millis(1));
+                        }
+                    }
+                }
+            }
+            Ok(())
+        })
+        .unwrap_or(())
     }
 
     fn unwrap_update_account(account: ReplicaAccountInfoVersions) -> &ReplicaAccountInfoV3 {
@@ -238,6 +1223,18 @@ impl KafkaPlugin {
         }
     }
 
+    fn unwrap_block_metadata(block: ReplicaBlockInfoVersions) -> &ReplicaBlockInfoV3 {
+        match block {
+            ReplicaBlockInfoVersions::V0_0_1(_info) => {
+                panic!("ReplicaBlockInfoVersions::V0_0_1 unsupported, please upgrade your Solana node.");
+            }
+            ReplicaBlockInfoVersions::V0_0_2(_info) => {
+                panic!("ReplicaBlockInfoVersions::V0_0_2 unsupported, please upgrade your Solana node.");
+            }
+            ReplicaBlockInfoVersions::V0_0_3(info) => info,
+        }
+    }
+
     fn unwrap_transaction(
         transaction: ReplicaTransactionInfoVersions,
     ) -> &ReplicaTransactionInfoV2 {
@@ -249,227 +1246,73 @@ impl KafkaPlugin {
         }
     }
 
-    fn build_compiled_instruction(
-        ix: &solana_program::instruction::CompiledInstruction,
-    ) -> CompiledInstruction {
-        CompiledInstruction {
-            program_id_index: ix.program_id_index as u32,
-            accounts: ix.clone().accounts.into_iter().map(|v| v as u32).collect(),
-            data: ix.data.clone(),
-        }
-    }
-
-    fn build_inner_instruction(
-        ix: &solana_transaction_status::InnerInstruction,
-    ) -> InnerInstruction {
-        InnerInstruction {
-            instruction: Some(Self::build_compiled_instruction(&ix.instruction)),
-            stack_height: ix.stack_height,
+    fn build_transaction_event(
+        metrics: &InstanceMetrics,
+        slot: u64,
+        fork_instance_id: u64,
+        info: &ReplicaTransactionInfoV2,
+        caps: TransactionCaps,
+    ) -> TransactionEvent {
+        TransactionEvent {
+            fork_instance_id,
+            ..build_transaction_event(
+                metrics,
+                slot,
+                info.signature,
+                info.is_vote,
+                info.transaction,
+                info.transaction_status_meta,
+                info.index,
+                caps,
+            )
         }
     }
 
-    fn build_message_header(header: &solana_program::message::MessageHeader) -> MessageHeader {
-        MessageHeader {
-            num_required_signatures: header.num_required_signatures as u32,
-            num_readonly_signed_accounts: header.num_readonly_signed_accounts as u32,
-            num_readonly_unsigned_accounts: header.num_readonly_unsigned_accounts as u32,
+    fn build_transaction_index_event(
+        slot: u64,
+        info: &ReplicaTransactionInfoV2,
+        filter: &Filter,
+    ) -> TransactionIndexEvent {
+        let message = info.transaction.message();
+        let account_keys = message.account_keys();
+        let matched_program = message
+            .instructions()
+            .iter()
+            .filter_map(|instruction| account_keys.get(instruction.program_id_index as usize))
+            .find(|program| filter.wants_program(program.as_ref()));
+        TransactionIndexEvent {
+            matched_program: matched_program
+                .map_or_else(Vec::new, |program| program.to_bytes().into()),
+            ..build_transaction_index_event(
+                slot,
+                info.signature,
+                info.transaction,
+                info.transaction_status_meta,
+                info.index,
+            )
         }
     }
 
-    fn build_transaction_token_balance(
-        transaction_token_account_balance: solana_transaction_status::TransactionTokenBalance,
-    ) -> TransactionTokenBalance {
-        TransactionTokenBalance {
-            account_index: transaction_token_account_balance.account_index as u32,
-            ui_token_account: Some(UiTokenAmount {
-                ui_amount: transaction_token_account_balance.ui_token_amount.ui_amount,
-                decimals: transaction_token_account_balance.ui_token_amount.decimals as u32,
-                amount: transaction_token_account_balance.ui_token_amount.amount,
-                ui_amount_string: transaction_token_account_balance
-                    .ui_token_amount
-                    .ui_amount_string,
-            }),
-            mint: transaction_token_account_balance.mint,
-            owner: transaction_token_account_balance.owner,
+    fn count_truncation(metrics: &InstanceMetrics, event: &TransactionEvent) {
+        if event.memos_truncated {
+            metrics
+                .transactions_truncated_total
+                .with_label_values(&["memos"])
+                .inc();
         }
-    }
-
-    fn build_transaction_event(
-        slot: u64,
-        ReplicaTransactionInfoV2 {
-            signature,
-            is_vote,
-            transaction,
-            transaction_status_meta,
-            index,
-        }: &ReplicaTransactionInfoV2,
-    ) -> TransactionEvent {
-        TransactionEvent {
-            is_vote: *is_vote,
-            slot,
-            index: *index as u64,
-            signature: signature.as_ref().into(),
-            transaction_status_meta: Some(TransactionStatusMeta {
-                is_status_err: transaction_status_meta.status.is_err(),
-                error_info: match &transaction_status_meta.status {
-                    Err(e) => e.to_string(),
-                    Ok(_) => "".to_owned(),
-                },
-                rewards: transaction_status_meta
-                    .rewards
-                    .clone()
-                    .unwrap()
-                    .into_iter()
-                    .map(|x| Reward {
-                        pubkey: x.pubkey,
-                        lamports: x.lamports,
-                        post_balance: x.post_balance,
-                        reward_type: match x.reward_type {
-                            Some(r) => r as i32,
-                            None => 0,
-                        },
-                        commission: match x.commission {
-                            Some(v) => v as u32,
-                            None => 0,
-                        },
-                    })
-                    .collect(),
-                fee: transaction_status_meta.fee,
-                log_messages: match &transaction_status_meta.log_messages {
-                    Some(v) => v.to_owned(),
-                    None => vec![],
-                },
-                inner_instructions: match &transaction_status_meta.inner_instructions {
-                    Some(inners) => inners
-                        .clone()
-                        .into_iter()
-                        .map(|inner| InnerInstructions {
-                            index: inner.index as u32,
-                            instructions: inner
-                                .instructions
-                                .iter()
-                                .map(Self::build_inner_instruction)
-                                .collect(),
-                        })
-                        .collect(),
-                    None => vec![],
-                },
-                pre_balances: transaction_status_meta.pre_balances.clone(),
-                post_balances: transaction_status_meta.post_balances.clone(),
-                pre_token_balances: match &transaction_status_meta.pre_token_balances {
-                    Some(v) => v
-                        .clone()
-                        .into_iter()
-                        .map(Self::build_transaction_token_balance)
-                        .collect(),
-                    None => vec![],
-                },
-                post_token_balances: match &transaction_status_meta.post_token_balances {
-                    Some(v) => v
-                        .clone()
-                        .into_iter()
-                        .map(Self::build_transaction_token_balance)
-                        .collect(),
-                    None => vec![],
-                },
-            }),
-            transaction: Some(SanitizedTransaction {
-                message_hash: transaction.message_hash().to_bytes().into(),
-                is_simple_vote_transaction: transaction.is_simple_vote_transaction(),
-                message: Some(SanitizedMessage {
-                    message_payload: Some(match transaction.message() {
-                        solana_program::message::SanitizedMessage::Legacy(lv) => {
-                            sanitized_message::MessagePayload::Legacy(LegacyLoadedMessage {
-                                message: Some(LegacyMessage {
-                                    header: Some(Self::build_message_header(&lv.message.header)),
-                                    account_keys: lv
-                                        .message
-                                        .account_keys
-                                        .clone()
-                                        .into_iter()
-                                        .map(|k| k.as_ref().into())
-                                        .collect(),
-                                    instructions: lv
-                                        .message
-                                        .instructions
-                                        .iter()
-                                        .map(Self::build_compiled_instruction)
-                                        .collect(),
-                                    recent_block_hash: lv.message.recent_blockhash.as_ref().into(),
-                                }),
-                                is_writable_account_cache: (0..(lv.account_keys().len() - 1))
-                                    .map(|i: usize| lv.is_writable(i))
-                                    .collect(),
-                            })
-                        }
-                        solana_program::message::SanitizedMessage::V0(v0) => {
-                            sanitized_message::MessagePayload::V0(V0LoadedMessage {
-                                message: Some(V0Message {
-                                    header: Some(Self::build_message_header(&v0.message.header)),
-                                    account_keys: v0
-                                        .message
-                                        .account_keys
-                                        .clone()
-                                        .into_iter()
-                                        .map(|k| k.as_ref().into())
-                                        .collect(),
-                                    recent_block_hash: v0.message.recent_blockhash.as_ref().into(),
-                                    instructions: v0
-                                        .message
-                                        .instructions
-                                        .iter()
-                                        .map(Self::build_compiled_instruction)
-                                        .collect(),
-                                    address_table_lookup: v0
-                                        .message
-                                        .address_table_lookups
-                                        .clone()
-                                        .into_iter()
-                                        .map(|vf| MessageAddressTableLookup {
-                                            account_key: vf.account_key.as_ref().into(),
-                                            writable_indexes: vf
-                                                .writable_indexes
-                                                .iter()
-                                                .map(|x| *x as u32)
-                                                .collect(),
-                                            readonly_indexes: vf
-                                                .readonly_indexes
-                                                .iter()
-                                                .map(|x| *x as u32)
-                                                .collect(),
-                                        })
-                                        .collect(),
-                                }),
-                                loaded_adresses: Some(LoadedAddresses {
-                                    writable: v0
-                                        .loaded_addresses
-                                        .writable
-                                        .clone()
-                                        .into_iter()
-                                        .map(|x| x.as_ref().into())
-                                        .collect(),
-                                    readonly: v0
-                                        .loaded_addresses
-                                        .readonly
-                                        .clone()
-                                        .into_iter()
-                                        .map(|x| x.as_ref().into())
-                                        .collect(),
-                                }),
-                                is_writable_account_cache: (0..(v0.account_keys().len() - 1))
-                                    .map(|i: usize| v0.is_writable(i))
-                                    .collect(),
-                            })
-                        }
-                    }),
-                }),
-                signatures: transaction
-                    .signatures()
-                    .iter()
-                    .copied()
-                    .map(|x| x.as_ref().into())
-                    .collect(),
-            }),
+        if let Some(meta) = &event.transaction_status_meta {
+            if meta.inner_instructions_truncated {
+                metrics
+                    .transactions_truncated_total
+                    .with_label_values(&["inner_instructions"])
+                    .inc();
+            }
+            if meta.log_messages_truncated {
+                metrics
+                    .transactions_truncated_total
+                    .with_label_values(&["log_messages"])
+                    .inc();
+            }
         }
     }
 
@@ -486,3 +1329,2326 @@ impl KafkaPlugin {
         }
     }
 }
+
+/// Whether `result` was sent, messages deliberately not sent aren't failures.
+fn sent(result: Result<(), PublishError>) -> Result<bool, PublishError> {
+    match result {
+        Ok(()) => Ok(true),
+        Err(error) if error.skip_status().is_some() => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+/// Whether a status unknown to this plugin, named `description`, is the `Dead` status of newer
+/// Geyser interfaces, notified for slots that won't be rooted.
+fn is_dead(description: &str) -> bool {
+    description.starts_with("Dead")
+}
+
+/// Whether a global logger was installed, the validator's or `solana_logger`.
+static LOGGER_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Install a global logger with `install`, returning false without touching the current
+/// one if a logger is already installed.
+fn install_logger(
+    install: impl FnOnce() -> Result<(), SetLoggerError>,
+    level: LevelFilter,
+) -> bool {
+    let installed = install().is_ok();
+    if installed {
+        log::set_max_level(level);
+        LOGGER_INSTALLED.store(true, Ordering::Relaxed);
+    }
+    installed
+}
+
+/// Whether the host installed a global logger. The `log` crate can't tell, but until a logger is
+/// installed logging is off and every record is discarded.
+fn logger_installed() -> bool {
+    log::max_level() != LevelFilter::Off
+        || log::logger().enabled(&Metadata::builder().level(Level::Error).build())
+}
+
+/// Set up `solana_logger` from `RUST_LOG`, defaulting to `info`, unless a logger is already
+/// installed. It would reset the level of any logger and reconfigure its own on every call.
+fn install_default_logger() -> bool {
+    if LOGGER_INSTALLED.swap(true, Ordering::Relaxed) || logger_installed() {
+        return false;
+    }
+    solana_logger::setup_with_default("info");
+    true
+}
+
+/// Failed sends of one notification, reported once every matching filter was attempted.
+struct PublishFailures<'a> {
+    metrics: &'a InstanceMetrics,
+    failed: Vec<String>,
+}
+
+impl<'a> PublishFailures<'a> {
+    fn new(metrics: &'a InstanceMetrics) -> Self {
+        Self {
+            metrics,
+            failed: Vec::new(),
+        }
+    }
+
+    /// Count a failed `result` of sending to `topic`, keeping it for the error unless
+    /// `fail_open`. Returns whether the message was sent, messages deliberately not sent
+    /// aren't failures.
+    fn check(&mut self, topic: &str, fail_open: bool, result: Result<(), PublishError>) -> bool {
+        let Err(error) = result else {
+            return true;
+        };
+        if error.skip_status().is_some() {
+            return false;
+        }
+        self.metrics
+            .publish_errors_total
+            .with_label_values(&[topic])
+            .inc();
+        if !fail_open {
+            self.failed.push(format!("{topic}: {error}"));
+        }
+        false
+    }
+
+    fn into_result(self, error: impl FnOnce(String) -> PluginError) -> PluginResult<()> {
+        if self.failed.is_empty() {
+            return Ok(());
+        }
+        Err(error(format!(
+            "failed to publish to {}",
+            self.failed.join(", ")
+        )))
+    }
+}
+
+/// Destinations one notification was sent to, so filters sharing a topic publish it once.
+struct Destinations<'a> {
+    metrics: &'a InstanceMetrics,
+    sent: Vec<(&'a str, bool)>,
+}
+
+impl<'a> Destinations<'a> {
+    fn new(metrics: &'a InstanceMetrics) -> Self {
+        Self {
+            metrics,
+            sent: Vec::new(),
+        }
+    }
+
+    /// Whether `topic` with `wrap` is new to this notification of `kind`, recording it.
+    /// Repeats are counted in `duplicate_sends_skipped_total`.
+    fn insert(&mut self, kind: &str, topic: &'a str, wrap: bool) -> bool {
+        if self.sent.contains(&(topic, wrap)) {
+            self.metrics
+                .duplicate_sends_skipped_total
+                .with_label_values(&[kind])
+                .inc();
+            return false;
+        }
+        self.sent.push((topic, wrap));
+        true
+    }
+}
+
+/// Run a Geyser callback, turning a panic into an error instead of unwinding into the validator.
+fn catch_panic<T>(
+    callback: &'static str,
+    context: impl Display,
+    f: impl FnOnce() -> PluginResult<T>,
+) -> PluginResult<T> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let msg = panic_message(payload.as_ref());
+        error!("Panic in {callback} ({context}): {msg}");
+        PLUGIN_PANICS_TOTAL.with_label_values(&[callback]).inc();
+        Err(PluginError::Custom(msg.into()))
+    })
+}
+
+pub(crate) fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        (*msg).to_owned()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            file_sink::FileSinkReader, leader_schedule::EpochLeaders,
+            outstanding::OutstandingBytes, BackpressurePolicy, BlockEvent, ConfigFilter,
+            MessageWrapper, SlotContentionEvent, UpdateAccountEvent,
+        },
+        prost::Message,
+        solana_sdk::{
+            compute_budget::ComputeBudgetInstruction,
+            instruction::{AccountMeta, Instruction},
+            signature::{Signature, Signer},
+            signer::keypair::Keypair,
+            transaction::{SanitizedTransaction as SdkSanitizedTransaction, Transaction},
+        },
+    };
+
+    /// Logger accepting and discarding every record.
+    struct NullLogger;
+
+    impl Log for NullLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+        fn log(&self, _: &log::Record) {}
+        fn flush(&self) {}
+    }
+
+    static NULL_LOGGER: NullLogger = NullLogger;
+
+    /// Update of an account holding one lamport, as the validator notifies it.
+    fn account_info<'a>(
+        pubkey: &'a Pubkey,
+        owner: &'a Pubkey,
+        data: &'a [u8],
+        txn: Option<&'a SdkSanitizedTransaction>,
+    ) -> ReplicaAccountInfoV3<'a> {
+        ReplicaAccountInfoV3 {
+            pubkey: pubkey.as_ref(),
+            lamports: 1,
+            owner: owner.as_ref(),
+            executable: false,
+            rent_epoch: 0,
+            data,
+            write_version: 1,
+            txn,
+        }
+    }
+
+    fn notify_account(
+        plugin: &KafkaPlugin,
+        pubkey: &Pubkey,
+        owner: &Pubkey,
+        data: &[u8],
+        slot: u64,
+        is_startup: bool,
+        txn: Option<&SdkSanitizedTransaction>,
+    ) {
+        let info = account_info(pubkey, owner, data, txn);
+        plugin
+            .update_account(ReplicaAccountInfoVersions::V0_0_3(&info), slot, is_startup)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_install_logger_keeps_existing() {
+        // Whichever test installs first, a logger is in place afterwards.
+        install_logger(|| log::set_logger(&NULL_LOGGER), log::max_level());
+        let level = log::max_level();
+        let mut called = false;
+        let installed = install_logger(
+            || {
+                called = true;
+                log::set_logger(&NULL_LOGGER)
+            },
+            LevelFilter::Trace,
+        );
+        assert!(called);
+        assert!(!installed);
+        assert_eq!(log::max_level(), level);
+        assert!(!install_default_logger());
+    }
+
+    #[test]
+    fn test_default_logger_keeps_host_logger() {
+        // Installed directly, as a host not calling setup_logger would.
+        let _ = log::set_logger(&NULL_LOGGER);
+        let level = log::max_level();
+        assert!(logger_installed());
+        assert!(!install_default_logger());
+        assert_eq!(log::max_level(), level);
+    }
+
+    #[test]
+    fn test_setup_logger_config() {
+        let parse = |extra: &str| {
+            serde_json::from_str::<Config>(&format!(
+                r#"{{"libpath": "", "kafka": {{}}, "filters": []{extra}}}"#
+            ))
+            .unwrap()
+        };
+        assert!(parse("").setup_logger);
+        assert!(parse(r#", "setup_logger": true"#).setup_logger);
+        assert!(!parse(r#", "setup_logger": false"#).setup_logger);
+        assert!(Config::default().setup_logger);
+    }
+
+    #[test]
+    fn test_catch_panic() {
+        let before = PLUGIN_PANICS_TOTAL.with_label_values(&["test"]).get();
+        let result: PluginResult<()> = catch_panic("test", "slot 1", || panic!("boom"));
+        match result {
+            Err(PluginError::Custom(error)) => assert_eq!(error.to_string(), "boom"),
+            other => panic!("unexpected result: {other:?}"),
+        }
+        assert_eq!(
+            PLUGIN_PANICS_TOTAL.with_label_values(&["test"]).get(),
+            before + 1
+        );
+
+        assert!(catch_panic("test", "", || Ok(1)).is_ok());
+    }
+
+    #[test]
+    fn test_unloaded_plugin_ignores_notifications() {
+        let ignored = || {
+            NOTIFICATIONS_UNLOADED_TOTAL
+                .with_label_values(&["update_slot_status"])
+                .get()
+        };
+        let before = ignored();
+        let plugin = KafkaPlugin::new();
+        assert!(plugin
+            .update_slot_status(1, None, PluginSlotStatus::Rooted)
+            .is_ok());
+        assert!(ignored() > before);
+        assert!(!plugin.account_data_notifications_enabled());
+    }
+
+    #[test]
+    fn test_unload_during_notifications() {
+        let mut config = Config::default();
+        config.filters = vec![ConfigFilter {
+            update_account_topic: Some("accounts".to_owned()),
+            slot_status_topic: Some("slots".to_owned()),
+            ..Default::default()
+        }];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        let pubkey = Pubkey::new_unique();
+        let panics = || {
+            PLUGIN_PANICS_TOTAL
+                .with_label_values(&["update_account"])
+                .get()
+        };
+        let panics_before = panics();
+
+        std::thread::scope(|scope| {
+            let notifiers: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        for slot in 0..2_000 {
+                            notify_account(&plugin, &pubkey, &pubkey, &[], slot, false, None);
+                            plugin
+                                .update_slot_status(slot, None, PluginSlotStatus::Processed)
+                                .unwrap();
+                        }
+                    })
+                })
+                .collect();
+            std::thread::sleep(Duration::from_millis(5));
+            plugin.unload();
+            for notifier in notifiers {
+                notifier.join().unwrap();
+            }
+        });
+        assert!(!plugin.is_loaded());
+        assert_eq!(panics(), panics_before);
+    }
+
+    #[test]
+    fn test_unload_with_blocked_callback() {
+        let mut config = Config::default();
+        config.shutdown_timeout_ms = 50;
+        config.prometheus = vec!["127.0.0.1:0".parse().unwrap()];
+        config.checkpoint_interval_ms = Some(10);
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        let metrics = plugin.metrics().unwrap();
+        let state = plugin.state.load_full().unwrap();
+        let prometheus = state
+            .workers
+            .lock()
+            .unwrap()
+            .prometheus
+            .as_ref()
+            .unwrap()
+            .tcp_addrs()[0];
+        // Over `max_outstanding_bytes` with `Block` and no `callback_deadline_ms`.
+        let outstanding = OutstandingBytes::new(Arc::default());
+        outstanding.enqueued(100);
+        let (entered, blocked) = std::sync::mpsc::channel();
+
+        std::thread::scope(|scope| {
+            let callback = scope.spawn(|| {
+                let _state = plugin.loaded("update_account").unwrap();
+                entered.send(()).unwrap();
+                outstanding.admit(100, BackpressurePolicy::Block, None)
+            });
+            blocked.recv().unwrap();
+            let started = Instant::now();
+            plugin.unload();
+            assert!(started.elapsed() < Duration::from_secs(5));
+            assert!(!plugin.is_loaded());
+            assert_eq!(metrics.plugin_loaded.get(), 0);
+            // Stopped without waiting for the callback.
+            let workers = state.workers.lock().unwrap();
+            assert!(workers.prometheus.is_none() && workers.checkpointer.is_none());
+            drop(workers);
+            assert!(std::net::TcpStream::connect(prometheus).is_err());
+
+            outstanding.delivered(100);
+            assert!(callback.join().unwrap().unwrap());
+        });
+    }
+
+    #[test]
+    fn test_unload_default_shutdown_timeout() {
+        let path = std::env::temp_dir().join(format!(
+            "kafka-plugin-default-shutdown-{}.json",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            r#"{
+                "libpath": "",
+                "kafka": {"bootstrap.servers": "localhost:9092"},
+                "filters": []
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            Config::read_from(path.to_str().unwrap())
+                .unwrap()
+                .shutdown_timeout_ms,
+            30_000
+        );
+        let mut plugin = KafkaPlugin::new();
+        plugin.on_load(path.to_str().unwrap(), false).unwrap();
+        let (entered, running) = std::sync::mpsc::channel();
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let _state = plugin.loaded("update_account").unwrap();
+                entered.send(()).unwrap();
+                std::thread::sleep(Duration::from_millis(200));
+            });
+            running.recv().unwrap();
+            // Waits for the callback rather than shutting down under it.
+            let started = Instant::now();
+            plugin.unload();
+            assert!(started.elapsed() >= Duration::from_millis(150));
+            assert!(!plugin.is_loaded());
+        });
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_reload_after_failed_load() {
+        let occupied = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let config_path = |name: &str, prometheus: &str| {
+            let path = std::env::temp_dir()
+                .join(format!("kafka-plugin-{name}-{}.json", std::process::id()));
+            std::fs::write(
+                &path,
+                format!(
+                    r#"{{
+                        "libpath": "",
+                        "kafka": {{"bootstrap.servers": "localhost:9092"}},
+                        "shutdown_timeout_ms": 100,
+                        "filters": [],
+                        "prometheus": {prometheus}
+                    }}"#
+                ),
+            )
+            .unwrap();
+            path
+        };
+
+        let mut plugin = KafkaPlugin::new();
+        let bad = config_path("bad", &format!("\"{}\"", occupied.local_addr().unwrap()));
+        assert!(plugin.on_load(bad.to_str().unwrap(), false).is_err());
+        assert!(!plugin.is_loaded());
+        plugin.on_unload();
+
+        let good = config_path("good", "null");
+        plugin.on_load(good.to_str().unwrap(), false).unwrap();
+        assert!(plugin.is_loaded());
+        assert!(plugin.on_load(good.to_str().unwrap(), false).is_err());
+
+        plugin.on_unload();
+        plugin.on_unload();
+        assert!(!plugin.is_loaded());
+
+        std::fs::remove_file(bad).unwrap();
+        std::fs::remove_file(good).unwrap();
+    }
+
+    #[test]
+    fn test_file_sink() {
+        let dir = std::env::temp_dir().join(format!("kafka-plugin-sink-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.with_extension("json");
+        std::fs::write(
+            &path,
+            serde_json::json!({
+                "libpath": "",
+                "sink": "file",
+                "file_sink_dir": dir,
+                "file_sink_compression": "zstd",
+                "filters": [{"slot_status_topic": "slots"}],
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let mut plugin = KafkaPlugin::new();
+        plugin.on_load(path.to_str().unwrap(), false).unwrap();
+        for slot in 1..=3 {
+            plugin
+                .update_slot_status(slot, None, PluginSlotStatus::Processed)
+                .unwrap();
+        }
+        // Unloading completes the file.
+        plugin.on_unload();
+
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let messages = FileSinkReader::open(&files[0].as_ref().unwrap().path())
+            .unwrap()
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+        let slots: Vec<u64> = messages
+            .iter()
+            .map(|message| {
+                assert_eq!(message.topic, "slots");
+                SlotStatusEvent::decode(message.payload.as_deref().unwrap())
+                    .unwrap()
+                    .slot
+            })
+            .collect();
+        assert_eq!(slots, [1, 2, 3]);
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_top_level_slot_status_topic() {
+        let topics = |config: &Config| {
+            let plugin = KafkaPlugin::dry_run(config).unwrap();
+            plugin
+                .update_slot_status(1, None, PluginSlotStatus::Rooted)
+                .unwrap();
+            let recorded = plugin.take_recorded();
+            for message in &recorded {
+                let event = SlotStatusEvent::decode(message.payload.as_deref().unwrap()).unwrap();
+                assert_eq!(event.status(), SlotStatus::Rooted);
+            }
+            recorded
+                .into_iter()
+                .map(|message| message.topic)
+                .collect::<Vec<_>>()
+        };
+        let filter = |slots: &str| ConfigFilter {
+            slot_status_topic: Some(slots.to_owned()),
+            block_events_topic: Some("blocks".to_owned()),
+            ..Default::default()
+        };
+
+        // Without any filter publishing statuses.
+        let mut config = Config::default();
+        config.filters = vec![ConfigFilter {
+            block_events_topic: Some("blocks".to_owned()),
+            ..Default::default()
+        }];
+        config.slot_status_topic = Some("slots".to_owned());
+        assert_eq!(topics(&config), ["slots"]);
+
+        config.slot_status_topic = None;
+        config.filters = vec![filter("filter-slots")];
+        assert_eq!(topics(&config), ["filter-slots"]);
+
+        config.slot_status_topic = Some("slots".to_owned());
+        config.filters = vec![filter("filter-slots"), filter("slots")];
+        assert_eq!(topics(&config), ["filter-slots", "slots"]);
+        assert!(config.topics().contains(&("slots".to_owned(), false)));
+
+        // With `rooted_only`, nothing goes out before the root, then the statuses follow the
+        // events of the slot.
+        config.rooted_only = true;
+        config.filters = vec![ConfigFilter {
+            update_account_topic: Some("accounts".to_owned()),
+            publish_all_accounts: true,
+            ..Default::default()
+        }];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        let pubkey = Pubkey::new_unique();
+        notify_account(&plugin, &pubkey, &pubkey, &[], 1, false, None);
+        for status in [PluginSlotStatus::Processed, PluginSlotStatus::Confirmed] {
+            plugin.update_slot_status(1, None, status).unwrap();
+        }
+        assert!(plugin.take_recorded().is_empty());
+        plugin
+            .update_slot_status(1, None, PluginSlotStatus::Rooted)
+            .unwrap();
+        let recorded = plugin
+            .take_recorded()
+            .into_iter()
+            .map(|message| message.topic)
+            .collect::<Vec<_>>();
+        assert_eq!(recorded, ["accounts", "slots"]);
+    }
+
+    #[test]
+    fn test_sequence_state() {
+        let path = std::env::temp_dir().join(format!(
+            "kafka-plugin-sequences-{}.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut config = Config::default();
+        config.sequence_state_path = Some(path.clone());
+        config.filters = vec![ConfigFilter {
+            slot_status_topic: Some("slots".to_owned()),
+            ..Default::default()
+        }];
+        let sequences = |plugin: &KafkaPlugin| {
+            for slot in 1..=2 {
+                plugin
+                    .update_slot_status(slot, None, PluginSlotStatus::Processed)
+                    .unwrap();
+            }
+            plugin
+                .take_recorded()
+                .iter()
+                .map(|message| {
+                    SlotStatusEvent::decode(message.payload.as_deref().unwrap())
+                        .unwrap()
+                        .sequence
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        assert_eq!(sequences(&plugin), [0, 1]);
+        // Dropping the publisher saves the state the next load continues from.
+        drop(plugin);
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        assert_eq!(sequences(&plugin), [2, 3]);
+        drop(plugin);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_expected_validator_version() {
+        let path = std::env::temp_dir().join(format!(
+            "kafka-plugin-validator-version-{}.json",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            r#"{
+                "libpath": "",
+                "kafka": {"bootstrap.servers": "localhost:9092"},
+                "filters": [],
+                "expected_validator_version": "0.1.0"
+            }"#,
+        )
+        .unwrap();
+        let mut plugin = KafkaPlugin::new();
+        let error = plugin.on_load(path.to_str().unwrap(), false).unwrap_err();
+        assert!(
+            error.to_string().contains("validator version 0.1.0"),
+            "{error}"
+        );
+        assert!(!plugin.is_loaded());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_require_causing_txn() {
+        let owner = Pubkey::new_unique();
+        let filter = |topic: &str, require_causing_txn| ConfigFilter {
+            update_account_topic: Some(topic.to_owned()),
+            program_filters: vec![owner.to_string()],
+            require_causing_txn,
+            ..Default::default()
+        };
+        let mut config = Config::default();
+        config.filters = vec![filter("any", false), filter("caused", true)];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+
+        let payer = Keypair::new();
+        let txn = SdkSanitizedTransaction::from_transaction_for_tests(Transaction::new_with_payer(
+            &[],
+            Some(&payer.pubkey()),
+        ));
+        let pubkey = Pubkey::new_unique();
+        let update = |txn| {
+            notify_account(&plugin, &pubkey, &owner, &[], 5, false, txn);
+            plugin
+                .take_recorded()
+                .into_iter()
+                .map(|message| message.topic)
+                .collect::<Vec<_>>()
+        };
+        let metrics = plugin.metrics().unwrap();
+
+        assert_eq!(update(Some(&txn)), ["any", "caused"]);
+        assert_eq!(update(None), ["any"]);
+        assert_eq!(
+            metrics
+                .account_updates_missing_txn_total
+                .with_label_values(&["false"])
+                .get(),
+            1
+        );
+        assert_eq!(metrics.account_updates_skipped_missing_txn_total.get(), 1);
+    }
+
+    #[test]
+    fn test_skip_slots_older_than() {
+        let mut config = Config::default();
+        config.skip_slots_older_than = Some(10);
+        config.filters = vec![ConfigFilter {
+            update_account_topic: Some("accounts".to_owned()),
+            slot_status_topic: Some("slots".to_owned()),
+            transaction_topic: Some("transactions".to_owned()),
+            ..Default::default()
+        }];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+
+        let payer = Keypair::new();
+        let txn = SdkSanitizedTransaction::from_transaction_for_tests(Transaction::new_with_payer(
+            &[],
+            Some(&payer.pubkey()),
+        ));
+        let pubkey = Pubkey::new_unique();
+        let topics = || {
+            plugin
+                .take_recorded()
+                .into_iter()
+                .map(|message| message.topic)
+                .collect::<Vec<_>>()
+        };
+        let account = |slot| {
+            notify_account(&plugin, &pubkey, &pubkey, &[], slot, false, None);
+            topics()
+        };
+        let meta = solana_transaction_status::TransactionStatusMeta {
+            rewards: Some(vec![]),
+            ..Default::default()
+        };
+        let transaction = |slot| {
+            let info = ReplicaTransactionInfoV2 {
+                signature: txn.signature(),
+                is_vote: false,
+                transaction: &txn,
+                transaction_status_meta: &meta,
+                index: 0,
+            };
+            plugin
+                .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&info), slot)
+                .unwrap();
+            topics()
+        };
+        let slot = |slot| {
+            plugin
+                .update_slot_status(slot, None, PluginSlotStatus::Processed)
+                .unwrap();
+            topics()
+        };
+
+        // Nothing is skipped before the first slot status.
+        assert_eq!(account(1), ["accounts"]);
+        assert_eq!(transaction(1), ["transactions"]);
+
+        // Catching up, far behind the highest slot.
+        assert_eq!(slot(1_000), ["slots"]);
+        assert_eq!(account(500), Vec::<String>::new());
+        assert_eq!(transaction(500), Vec::<String>::new());
+        assert_eq!(slot(501), Vec::<String>::new());
+
+        // Live again.
+        assert_eq!(account(995), ["accounts"]);
+        assert_eq!(transaction(1_000), ["transactions"]);
+        assert_eq!(slot(1_001), ["slots"]);
+    }
+
+    #[test]
+    fn test_publish_all_accounts_per_filter() {
+        let mut config = Config::default();
+        config.filters = vec![
+            ConfigFilter {
+                update_account_topic: Some("startup-accounts".to_owned()),
+                publish_all_accounts: true,
+                ..Default::default()
+            },
+            ConfigFilter {
+                update_account_topic: Some("live-accounts".to_owned()),
+                ..Default::default()
+            },
+        ];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        let pubkey = Pubkey::new_unique();
+        let update = |slot, is_startup| {
+            notify_account(&plugin, &pubkey, &pubkey, &[], slot, is_startup, None);
+            let mut topics = plugin
+                .take_recorded()
+                .into_iter()
+                .map(|message| message.topic)
+                .collect::<Vec<_>>();
+            topics.sort();
+            topics
+        };
+
+        // Only the filter opted in receives startup accounts.
+        assert_eq!(update(0, true), ["startup-accounts"]);
+        assert_eq!(update(5, false), ["live-accounts", "startup-accounts"]);
+    }
+
+    #[test]
+    fn test_account_bytes_per_slot() {
+        let mut config = Config::default();
+        config.max_account_bytes_per_slot = Some(250);
+        config.filters = vec![ConfigFilter {
+            update_account_topic: Some("accounts".to_owned()),
+            slot_status_topic: Some("slots".to_owned()),
+            publish_all_accounts: true,
+            ..Default::default()
+        }];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        let data = [0; 60];
+        let update = |plugin: &KafkaPlugin, slot, is_startup| {
+            let pubkey = Pubkey::new_unique();
+            notify_account(plugin, &pubkey, &pubkey, &data, slot, is_startup, None);
+            plugin
+                .take_recorded()
+                .iter()
+                .map(|message| message.payload.as_ref().unwrap().len())
+                .collect::<Vec<_>>()
+        };
+        let status = |plugin: &KafkaPlugin, slot, status| {
+            plugin.update_slot_status(slot, None, status).unwrap();
+            let recorded = plugin.take_recorded();
+            let event = SlotStatusEvent::decode(recorded[0].payload.as_deref().unwrap()).unwrap();
+            event.suppressed_account_updates
+        };
+
+        // Startup updates don't count.
+        assert_eq!(update(&plugin, 0, true).len(), 1);
+        assert_eq!(update(&plugin, 0, true).len(), 1);
+        // Charged with the encoded size rather than the account data size, the second update
+        // of a slot exceeds the budget, then the rest is suppressed.
+        let sent = update(&plugin, 5, false);
+        assert!(
+            sent.len() == 1 && sent[0] > 125 && sent[0] <= 250,
+            "{sent:?}"
+        );
+        assert_eq!(update(&plugin, 5, false).len(), 0);
+        assert_eq!(update(&plugin, 5, false).len(), 0);
+        assert_eq!(update(&plugin, 6, false).len(), 1);
+        assert_eq!(status(&plugin, 5, PluginSlotStatus::Processed), 2);
+        assert_eq!(status(&plugin, 6, PluginSlotStatus::Processed), 0);
+        assert_eq!(status(&plugin, 5, PluginSlotStatus::Rooted), 2);
+        // Forgotten once rooted.
+        assert_eq!(status(&plugin, 5, PluginSlotStatus::Confirmed), 0);
+
+        // Updates skipped rather than sent aren't charged.
+        config.filters[0].require_causing_txn = true;
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        for _ in 0..3 {
+            assert_eq!(update(&plugin, 7, false).len(), 0);
+        }
+        assert_eq!(status(&plugin, 7, PluginSlotStatus::Processed), 0);
+    }
+
+    #[test]
+    fn test_rooted_only() {
+        let mut config = Config::default();
+        config.rooted_only = true;
+        config.filters = vec![ConfigFilter {
+            update_account_topic: Some("rooted-accounts".to_owned()),
+            slot_status_topic: Some("rooted-slots".to_owned()),
+            publish_all_accounts: true,
+            ..Default::default()
+        }];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        // Updates are told apart by their single byte of data.
+        let update = |slot, is_startup, tag: u8| {
+            let pubkey = Pubkey::new_unique();
+            notify_account(&plugin, &pubkey, &pubkey, &[tag], slot, is_startup, None);
+        };
+        let recorded = || {
+            plugin
+                .take_recorded()
+                .into_iter()
+                .map(|message| match message.topic.as_str() {
+                    "rooted-accounts" => {
+                        let event = UpdateAccountEvent::decode(message.payload.as_deref().unwrap())
+                            .unwrap();
+                        (event.slot, event.data[0])
+                    }
+                    _ => {
+                        let event =
+                            SlotStatusEvent::decode(message.payload.as_deref().unwrap()).unwrap();
+                        (event.slot, 0)
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Startup accounts come from a rooted snapshot.
+        update(10, true, 1);
+        assert_eq!(recorded(), [(10, 1)]);
+        update(11, false, 2);
+        update(12, false, 3);
+        update(11, false, 4);
+        plugin
+            .update_slot_status(11, Some(10), PluginSlotStatus::Processed)
+            .unwrap();
+        plugin
+            .update_slot_status(11, Some(10), PluginSlotStatus::Confirmed)
+            .unwrap();
+        assert_eq!(recorded(), []);
+
+        // Flushed in order ahead of the rooted status.
+        plugin
+            .update_slot_status(11, Some(10), PluginSlotStatus::Rooted)
+            .unwrap();
+        assert_eq!(recorded(), [(11, 2), (11, 4), (11, 0)]);
+        update(11, false, 5);
+        assert_eq!(recorded(), [(11, 5)]);
+
+        // Slot 12 was on a fork that 13 didn't build on.
+        update(13, false, 6);
+        plugin
+            .update_slot_status(13, Some(11), PluginSlotStatus::Rooted)
+            .unwrap();
+        update(12, false, 7);
+        assert_eq!(recorded(), [(13, 6), (13, 0)]);
+
+        // Dead slots are dropped without waiting for a later root. Statuses of the interface
+        // built against can't be dead, newer ones are named in their description.
+        assert!(is_dead(r#"Dead("invalid block")"#));
+        assert!(!is_dead(""));
+        update(14, false, 8);
+        plugin.state.load_full().unwrap().publisher.drop_slot(14);
+        update(14, false, 9);
+        plugin
+            .update_slot_status(15, Some(13), PluginSlotStatus::Rooted)
+            .unwrap();
+        assert_eq!(recorded(), [(15, 0)]);
+    }
+
+    #[test]
+    fn test_fork_instance_ids() {
+        let mut config = Config::default();
+        config.fork_instance_ids = true;
+        config.filters = vec![ConfigFilter {
+            update_account_topic: Some("fork-accounts".to_owned()),
+            slot_status_topic: Some("fork-slots".to_owned()),
+            publish_all_accounts: true,
+            ..Default::default()
+        }];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        let pubkey = Pubkey::new_unique();
+        let update = |slot, is_startup| {
+            notify_account(&plugin, &pubkey, &pubkey, &[], slot, is_startup, None);
+        };
+        let processed = |slot| {
+            plugin
+                .update_slot_status(slot, Some(slot - 1), PluginSlotStatus::Processed)
+                .unwrap();
+        };
+        let ids = || {
+            plugin
+                .take_recorded()
+                .into_iter()
+                .map(|message| {
+                    let payload = message.payload.as_deref().unwrap();
+                    match message.topic.as_str() {
+                        "fork-accounts" => {
+                            UpdateAccountEvent::decode(payload)
+                                .unwrap()
+                                .fork_instance_id
+                        }
+                        _ => SlotStatusEvent::decode(payload).unwrap().fork_instance_id,
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+
+        update(4, true);
+        assert_eq!(ids(), [0]);
+
+        update(5, false);
+        update(5, false);
+        processed(5);
+        let first = ids();
+        assert_ne!(first[0], 0);
+        assert_eq!(first, [first[0]; 3]);
+
+        // Slot 5 died and is replayed on another fork.
+        update(5, false);
+        processed(5);
+        update(6, false);
+        let second = ids();
+        assert_ne!(second[0], first[0]);
+        assert_eq!(second[..2], [second[0]; 2]);
+        assert_ne!(second[2], second[0]);
+    }
+
+    #[test]
+    fn test_firehose_account_matches_filtered() {
+        let mut config = Config::default();
+        config.fork_instance_ids = true;
+        config.firehose_topic = "firehose".to_owned();
+        config.filters = vec![ConfigFilter {
+            update_account_topic: Some("accounts".to_owned()),
+            publish_all_accounts: true,
+            ..Default::default()
+        }];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        let pubkey = Pubkey::new_unique();
+        notify_account(&plugin, &pubkey, &pubkey, &[], 5, false, None);
+        let recorded = plugin.take_recorded();
+        assert_eq!(recorded.len(), 2);
+        let filtered = UpdateAccountEvent::decode(recorded[0].payload.as_deref().unwrap()).unwrap();
+        assert_ne!(filtered.fork_instance_id, 0);
+        let wrapper = MessageWrapper::decode(recorded[1].payload.as_deref().unwrap()).unwrap();
+        let Some(EventMessage::Account(firehose)) = wrapper.event_message else {
+            panic!("unexpected firehose message: {:?}", wrapper.event_message);
+        };
+        // Only numbered in different sequences.
+        assert_eq!(
+            UpdateAccountEvent {
+                sequence: 0,
+                ..firehose
+            },
+            UpdateAccountEvent {
+                sequence: 0,
+                ..filtered
+            }
+        );
+    }
+
+    #[test]
+    fn test_shared_topics() {
+        let filter = |name: &str, accounts: &str, slots: &str| ConfigFilter {
+            name: name.to_owned(),
+            update_account_topic: Some(accounts.to_owned()),
+            slot_status_topic: Some(slots.to_owned()),
+            publish_all_accounts: true,
+            ..Default::default()
+        };
+        let mut config = Config::default();
+        config.filters = vec![
+            filter("a", "shared-accounts", "shared-slots"),
+            filter("b", "shared-accounts", "shared-slots"),
+            filter("c", "other-accounts", "other-slots"),
+        ];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        let topics = || {
+            let mut topics: Vec<_> = plugin
+                .take_recorded()
+                .into_iter()
+                .map(|message| message.topic)
+                .collect();
+            topics.sort();
+            topics
+        };
+        let skipped = |kind| {
+            plugin
+                .metrics()
+                .unwrap()
+                .duplicate_sends_skipped_total
+                .with_label_values(&[kind])
+                .get()
+        };
+        let (slots_before, accounts_before) = (skipped("slot"), skipped("account"));
+
+        plugin
+            .update_slot_status(1, None, PluginSlotStatus::Processed)
+            .unwrap();
+        assert_eq!(topics(), ["other-slots", "shared-slots"]);
+        assert!(skipped("slot") > slots_before);
+
+        let pubkey = Pubkey::new_unique();
+        notify_account(&plugin, &pubkey, &pubkey, &[], 1, false, None);
+        assert_eq!(topics(), ["other-accounts", "shared-accounts"]);
+        assert!(skipped("account") > accounts_before);
+    }
+
+    #[test]
+    fn test_state_topic() {
+        let mut config = Config::default();
+        config.filters = vec![
+            ConfigFilter {
+                update_account_topic: Some("accounts".to_owned()),
+                state_topic: Some("state".to_owned()),
+                wrap_messages: true,
+                ..Default::default()
+            },
+            ConfigFilter {
+                state_topic: Some("seeded".to_owned()),
+                state_topic_seed_from_startup: true,
+                ..Default::default()
+            },
+        ];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+
+        let pubkey = Pubkey::new_unique();
+        let update = |lamports, is_startup| {
+            let info = ReplicaAccountInfoV3 {
+                lamports,
+                ..account_info(&pubkey, &pubkey, &[1, 2, 3], None)
+            };
+            plugin
+                .update_account(ReplicaAccountInfoVersions::V0_0_3(&info), 5, is_startup)
+                .unwrap();
+            plugin.take_recorded()
+        };
+        let topics = |recorded: &[RecordedMessage]| {
+            recorded
+                .iter()
+                .map(|message| message.topic.clone())
+                .collect::<Vec<_>>()
+        };
+
+        // Seeding only goes to the state topic that asks for it.
+        let recorded = update(10, true);
+        assert_eq!(topics(&recorded), ["seeded"]);
+        assert_eq!(recorded[0].key, pubkey.to_bytes());
+
+        // Live updates are keyed by the bare pubkey and never wrapped.
+        let recorded = update(10, false);
+        assert_eq!(topics(&recorded), ["state", "accounts", "seeded"]);
+        assert_eq!(recorded[0].key, pubkey.to_bytes());
+        let event = UpdateAccountEvent::decode(recorded[0].payload.as_deref().unwrap()).unwrap();
+        assert_eq!(event.lamports, 10);
+        assert_eq!(event.data, [1, 2, 3]);
+        assert_ne!(recorded[1].key, pubkey.to_bytes());
+
+        // Closed accounts get a tombstone.
+        let recorded = update(0, false);
+        assert_eq!(topics(&recorded), ["state", "accounts", "seeded"]);
+        assert_eq!(recorded[0].key, pubkey.to_bytes());
+        assert_eq!(recorded[0].payload, None);
+        assert_eq!(recorded[2].payload, None);
+        assert!(recorded[1].payload.is_some());
+
+        // A state topic alone takes account notifications too.
+        config.filters.remove(0);
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        assert!(plugin.account_data_notifications_enabled());
+    }
+
+    #[test]
+    fn test_filter_stats() {
+        let watched = Keypair::new();
+        let program = Pubkey::new_unique();
+        let unused = Pubkey::new_unique();
+        let mut config = Config::default();
+        config.filters = vec![ConfigFilter {
+            update_account_topic: Some("accounts".to_owned()),
+            transaction_topic: Some("transactions".to_owned()),
+            account_filters: vec![watched.pubkey().to_string(), unused.to_string()],
+            program_filters: vec![program.to_string()],
+            ..Default::default()
+        }];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+
+        let update = |pubkey: &Pubkey, owner: &Pubkey| {
+            notify_account(&plugin, pubkey, owner, &[], 1, false, None);
+        };
+        update(&Pubkey::new_unique(), &program);
+        update(&watched.pubkey(), &solana_program::system_program::id());
+        // Neither entry matches.
+        update(&Pubkey::new_unique(), &solana_program::system_program::id());
+
+        let txn = SdkSanitizedTransaction::from_transaction_for_tests(Transaction::new_with_payer(
+            &[],
+            Some(&watched.pubkey()),
+        ));
+        let meta = solana_transaction_status::TransactionStatusMeta {
+            rewards: Some(vec![]),
+            ..Default::default()
+        };
+        let info = ReplicaTransactionInfoV2 {
+            signature: txn.signature(),
+            is_vote: false,
+            transaction: &txn,
+            transaction_status_meta: &meta,
+            index: 0,
+        };
+        plugin
+            .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&info), 1)
+            .unwrap();
+        assert_eq!(plugin.take_recorded().len(), 3);
+
+        let json = plugin.state.load().as_ref().unwrap().filter_stats.to_json();
+        let accounts = &json[0]["account_filters"];
+        assert_eq!(accounts[watched.pubkey().to_string()]["matches"], 2);
+        assert!(accounts[watched.pubkey().to_string()]["last_matched_ms"].is_u64());
+        assert_eq!(
+            accounts[unused.to_string()],
+            serde_json::json!({"matches": 0, "last_matched_ms": null})
+        );
+        assert_eq!(
+            json[0]["program_filters"][program.to_string()]["matches"],
+            1
+        );
+    }
+
+    #[test]
+    fn test_raw_transaction() {
+        let mut config = Config::default();
+        config.filters = vec![
+            ConfigFilter {
+                transaction_topic: Some("raw".to_owned()),
+                include_raw_transaction: true,
+                ..Default::default()
+            },
+            ConfigFilter {
+                transaction_topic: Some("decoded".to_owned()),
+                ..Default::default()
+            },
+        ];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+
+        let payer = Keypair::new();
+        let transfer =
+            solana_sdk::system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 42);
+        let txn = SdkSanitizedTransaction::from_transaction_for_tests(
+            Transaction::new_signed_with_payer(
+                &[transfer],
+                Some(&payer.pubkey()),
+                &[&payer],
+                solana_program::hash::Hash::new_unique(),
+            ),
+        );
+        let meta = solana_transaction_status::TransactionStatusMeta {
+            rewards: Some(vec![]),
+            ..Default::default()
+        };
+        let info = ReplicaTransactionInfoV2 {
+            signature: txn.signature(),
+            is_vote: false,
+            transaction: &txn,
+            transaction_status_meta: &meta,
+            index: 0,
+        };
+        plugin
+            .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&info), 1)
+            .unwrap();
+        let events: Vec<TransactionEvent> = plugin
+            .take_recorded()
+            .iter()
+            .map(|message| TransactionEvent::decode(message.payload.as_deref().unwrap()).unwrap())
+            .collect();
+
+        let raw: solana_sdk::transaction::VersionedTransaction =
+            bincode::deserialize(&events[0].raw_transaction).unwrap();
+        assert_eq!(raw.signatures, txn.signatures());
+        assert_eq!(raw.message.hash(), *txn.message_hash());
+        assert!(raw.verify_with_results().into_iter().all(|ok| ok));
+        assert!(events[1].raw_transaction.is_empty());
+    }
+
+    #[test]
+    fn test_transaction_index() {
+        let program = Pubkey::new_unique();
+        let mut config = Config::default();
+        config.filters = vec![
+            ConfigFilter {
+                transaction_topic: Some("indexed-txs".to_owned()),
+                transaction_index_topic: Some("index-both".to_owned()),
+                ..Default::default()
+            },
+            ConfigFilter {
+                transaction_index_topic: Some("index-only".to_owned()),
+                program_filters: vec![program.to_string()],
+                ..Default::default()
+            },
+        ];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        assert!(plugin.transaction_notifications_enabled());
+
+        let payer = Keypair::new();
+        let other = Pubkey::new_unique();
+        let txn = SdkSanitizedTransaction::from_transaction_for_tests(Transaction::new_with_payer(
+            &[
+                Instruction::new_with_bytes(other, &[], vec![]),
+                Instruction::new_with_bytes(program, &[], vec![]),
+            ],
+            Some(&payer.pubkey()),
+        ));
+        let meta = solana_transaction_status::TransactionStatusMeta {
+            status: Err(solana_sdk::transaction::TransactionError::InsufficientFundsForFee),
+            rewards: Some(vec![]),
+            ..Default::default()
+        };
+        let info = ReplicaTransactionInfoV2 {
+            signature: txn.signature(),
+            is_vote: false,
+            transaction: &txn,
+            transaction_status_meta: &meta,
+            index: 7,
+        };
+        plugin
+            .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&info), 11)
+            .unwrap();
+
+        let recorded = plugin.take_recorded();
+        let topics: Vec<&str> = recorded.iter().map(|m| m.topic.as_str()).collect();
+        assert_eq!(topics, ["index-both", "indexed-txs", "index-only"]);
+        let index = |topic| {
+            let message = recorded.iter().find(|m| m.topic == topic).unwrap();
+            assert_eq!(message.key, txn.signature().as_ref());
+            TransactionIndexEvent::decode(message.payload.as_deref().unwrap()).unwrap()
+        };
+        let expected = TransactionIndexEvent {
+            slot: 11,
+            signature: txn.signature().as_ref().to_vec(),
+            index: 7,
+            is_status_err: true,
+            fee_payer: payer.pubkey().to_bytes().to_vec(),
+            // Without program filters the first program invoked.
+            matched_program: other.to_bytes().to_vec(),
+            sequence: 0,
+        };
+        assert_eq!(index("index-both"), expected);
+        assert_eq!(
+            index("index-only"),
+            TransactionIndexEvent {
+                matched_program: program.to_bytes().to_vec(),
+                ..expected
+            }
+        );
+    }
+
+    #[test]
+    fn test_transaction_caps() {
+        let mut config = Config::default();
+        config.filters = vec![
+            ConfigFilter {
+                transaction_topic: Some("capped".to_owned()),
+                max_inner_instructions: Some(3),
+                max_log_messages: Some(1),
+                ..Default::default()
+            },
+            ConfigFilter {
+                transaction_topic: Some("full".to_owned()),
+                ..Default::default()
+            },
+        ];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+
+        let payer = Keypair::new();
+        let txn = SdkSanitizedTransaction::from_transaction_for_tests(Transaction::new_with_payer(
+            &[],
+            Some(&payer.pubkey()),
+        ));
+        let inner = |index, count| solana_transaction_status::InnerInstructions {
+            index,
+            instructions: (0..count)
+                .map(|i| solana_transaction_status::InnerInstruction {
+                    instruction:
+                        solana_program::instruction::CompiledInstruction::new_from_raw_parts(
+                            0,
+                            vec![i],
+                            vec![],
+                        ),
+                    stack_height: Some(2),
+                })
+                .collect(),
+        };
+        let meta = solana_transaction_status::TransactionStatusMeta {
+            rewards: Some(vec![]),
+            inner_instructions: Some(vec![inner(0, 2), inner(1, 2), inner(2, 1)]),
+            log_messages: Some(vec!["a".to_owned(), "b".to_owned(), "c".to_owned()]),
+            ..Default::default()
+        };
+        let info = ReplicaTransactionInfoV2 {
+            signature: txn.signature(),
+            is_vote: false,
+            transaction: &txn,
+            transaction_status_meta: &meta,
+            index: 0,
+        };
+        let truncated = |field| {
+            plugin
+                .metrics()
+                .unwrap()
+                .transactions_truncated_total
+                .with_label_values(&[field])
+                .get()
+        };
+        let (inner_before, logs_before) =
+            (truncated("inner_instructions"), truncated("log_messages"));
+        plugin
+            .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&info), 1)
+            .unwrap();
+        let recorded = plugin.take_recorded();
+        let status = |topic| {
+            let message = recorded.iter().find(|m| m.topic == topic).unwrap();
+            TransactionEvent::decode(message.payload.as_deref().unwrap())
+                .unwrap()
+                .transaction_status_meta
+                .unwrap()
+        };
+
+        let capped = status("capped");
+        assert_eq!(capped.inner_instruction_count, 5);
+        assert!(capped.inner_instructions_truncated);
+        assert_eq!(
+            capped
+                .inner_instructions
+                .iter()
+                .map(|inner| (inner.index, inner.instructions.len()))
+                .collect::<Vec<_>>(),
+            [(0, 2), (1, 1)]
+        );
+        assert_eq!(capped.log_messages, ["a"]);
+        assert_eq!(capped.log_message_count, 3);
+        assert!(capped.log_messages_truncated);
+
+        let full = status("full");
+        assert_eq!(full.inner_instruction_count, 5);
+        assert!(!full.inner_instructions_truncated);
+        assert_eq!(full.inner_instructions.len(), 3);
+        assert_eq!(full.log_messages.len(), 3);
+        assert!(!full.log_messages_truncated);
+
+        assert_eq!(truncated("inner_instructions"), inner_before + 1);
+        assert_eq!(truncated("log_messages"), logs_before + 1);
+    }
+
+    #[test]
+    fn test_transaction_sample_rate() {
+        let mut config = Config::default();
+        config.filters = [("none", 0.0), ("half", 0.5), ("all", 1.0)]
+            .into_iter()
+            .map(|(name, rate)| ConfigFilter {
+                name: name.to_owned(),
+                transaction_topic: Some(name.to_owned()),
+                transaction_sample_rate: rate,
+                ..Default::default()
+            })
+            .collect();
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        assert!(plugin.transaction_notifications_enabled());
+
+        let txn = SdkSanitizedTransaction::from_transaction_for_tests(Transaction::new_with_payer(
+            &[],
+            Some(&Pubkey::new_unique()),
+        ));
+        let meta = solana_transaction_status::TransactionStatusMeta {
+            rewards: Some(vec![]),
+            ..Default::default()
+        };
+        let sampled_out = |filter| {
+            plugin
+                .metrics()
+                .unwrap()
+                .transactions_sampled_out_total
+                .with_label_values(&[filter])
+                .get()
+        };
+        let (none_before, half_before) = (sampled_out("none"), sampled_out("half"));
+        let mut topics = Vec::new();
+        // First 8 bytes below and above half of the u64 range.
+        for signature in [Signature::from([0x10; 64]), Signature::from([0xf0; 64])] {
+            let info = ReplicaTransactionInfoV2 {
+                signature: &signature,
+                is_vote: false,
+                transaction: &txn,
+                transaction_status_meta: &meta,
+                index: 0,
+            };
+            plugin
+                .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&info), 1)
+                .unwrap();
+            let mut recorded: Vec<String> = plugin
+                .take_recorded()
+                .into_iter()
+                .map(|m| m.topic)
+                .collect();
+            recorded.sort();
+            topics.push(recorded);
+        }
+        assert_eq!(topics, [vec!["all", "half"], vec!["all"]]);
+        // A rate of 0 is like having no topic, so nothing is counted.
+        assert_eq!(sampled_out("none"), none_before);
+        assert_eq!(sampled_out("half"), half_before + 1);
+
+        config.filters.retain(|filter| filter.name == "none");
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        assert!(!plugin.transaction_notifications_enabled());
+    }
+
+    #[test]
+    fn test_transactions_filtered() {
+        let mut config = Config::default();
+        config.filters = vec![
+            ConfigFilter {
+                name: "filtered-program".to_owned(),
+                transaction_topic: Some("program-txs".to_owned()),
+                program_filters: vec![Pubkey::new_unique().to_string()],
+                include_vote_transactions: false,
+                include_failed_transactions: false,
+                ..Default::default()
+            },
+            ConfigFilter {
+                name: "filtered-sampled".to_owned(),
+                transaction_topic: Some("sampled-txs".to_owned()),
+                transaction_sample_rate: 0.5,
+                ..Default::default()
+            },
+            ConfigFilter {
+                name: "filtered-index".to_owned(),
+                transaction_index_topic: Some("index-txs".to_owned()),
+                ..Default::default()
+            },
+            ConfigFilter {
+                name: "filtered-shared".to_owned(),
+                transaction_topic: Some("shared-txs".to_owned()),
+                ..Default::default()
+            },
+            ConfigFilter {
+                name: "filtered-shared-again".to_owned(),
+                transaction_topic: Some("shared-txs".to_owned()),
+                ..Default::default()
+            },
+            // Never evaluates transactions, so never counts them.
+            ConfigFilter {
+                name: "filtered-none".to_owned(),
+                slot_status_topic: Some("slots".to_owned()),
+                ..Default::default()
+            },
+        ];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+
+        let txn = SdkSanitizedTransaction::from_transaction_for_tests(Transaction::new_with_payer(
+            &[],
+            Some(&Pubkey::new_unique()),
+        ));
+        let failed = solana_transaction_status::TransactionStatusMeta {
+            status: Err(solana_sdk::transaction::TransactionError::AccountNotFound),
+            rewards: Some(vec![]),
+            ..Default::default()
+        };
+        let succeeded = solana_transaction_status::TransactionStatusMeta {
+            rewards: Some(vec![]),
+            ..Default::default()
+        };
+        let try_notify = |is_vote, meta, signature: [u8; 64]| {
+            let signature = Signature::from(signature);
+            let info = ReplicaTransactionInfoV2 {
+                signature: &signature,
+                is_vote,
+                transaction: &txn,
+                transaction_status_meta: meta,
+                index: 0,
+            };
+            plugin.notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&info), 1)
+        };
+        let notify = |is_vote, meta, signature| try_notify(is_vote, meta, signature).unwrap();
+        // A failed vote, a failed transaction, then two successful ones, the last sampled out.
+        notify(true, &failed, [0x10; 64]);
+        notify(false, &failed, [0x10; 64]);
+        notify(false, &succeeded, [0x10; 64]);
+        notify(false, &succeeded, [0xf0; 64]);
+
+        let count = |filter, reason| {
+            plugin
+                .metrics()
+                .unwrap()
+                .transactions_filtered_total
+                .with_label_values(&[filter, reason])
+                .get()
+        };
+        let counts = |filter| {
+            [
+                "vote",
+                "failed",
+                "no_key_match",
+                "sampled_out",
+                "published",
+                "send_failed",
+            ]
+            .map(|reason| count(filter, reason))
+        };
+        // A failed vote counts as a vote.
+        assert_eq!(counts("filtered-program"), [1, 1, 2, 0, 0, 0]);
+        assert_eq!(counts("filtered-sampled"), [0, 0, 0, 1, 3, 0]);
+        // Index entries and transactions sent by an earlier filter sharing the topic count as
+        // published.
+        assert_eq!(counts("filtered-index"), [0, 0, 0, 0, 4, 0]);
+        assert_eq!(counts("filtered-shared"), [0, 0, 0, 0, 4, 0]);
+        assert_eq!(counts("filtered-shared-again"), [0, 0, 0, 0, 4, 0]);
+        assert_eq!(counts("filtered-none"), [0; 6]);
+        assert_eq!(
+            plugin
+                .take_recorded()
+                .iter()
+                .filter(|message| message.topic == "shared-txs")
+                .count(),
+            4
+        );
+
+        // Transactions that fail to send are counted apart.
+        plugin
+            .state
+            .load_full()
+            .unwrap()
+            .publisher
+            .fail_recorded_topic("sampled-txs");
+        assert!(try_notify(false, &succeeded, [0x10; 64]).is_err());
+        assert_eq!(counts("filtered-sampled"), [0, 0, 0, 1, 3, 1]);
+        assert_eq!(counts("filtered-index"), [0, 0, 0, 0, 5, 0]);
+    }
+
+    #[test]
+    fn test_fee_market() {
+        let program = Pubkey::new_unique();
+        let mut config = Config::default();
+        config.filters = vec![ConfigFilter {
+            name: "fee-market".to_owned(),
+            transaction_topic: Some("fee-market-txs".to_owned()),
+            program_filters: vec![program.to_string()],
+            ..Default::default()
+        }];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        let meta = solana_transaction_status::TransactionStatusMeta {
+            compute_units_consumed: Some(30_000),
+            rewards: Some(vec![]),
+            ..Default::default()
+        };
+        let notify = |is_vote, price, program_id| {
+            let txn =
+                SdkSanitizedTransaction::from_transaction_for_tests(Transaction::new_with_payer(
+                    &[
+                        ComputeBudgetInstruction::set_compute_unit_price(price),
+                        Instruction::new_with_bytes(program_id, &[], vec![]),
+                    ],
+                    Some(&Pubkey::new_unique()),
+                ));
+            let info = ReplicaTransactionInfoV2 {
+                signature: &Signature::default(),
+                is_vote,
+                transaction: &txn,
+                transaction_status_meta: &meta,
+                index: 0,
+            };
+            plugin
+                .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&info), 1)
+                .unwrap();
+        };
+        let metrics = plugin.metrics().unwrap();
+        let count = |matched| {
+            metrics
+                .transaction_compute_units_consumed
+                .with_label_values(&[matched])
+                .get_sample_count()
+        };
+        let (matched_before, unmatched_before) = (count("true"), count("false"));
+
+        notify(false, 1_000, program);
+        notify(false, 2_000, program);
+        notify(false, 100, Pubkey::new_unique());
+        // Votes aren't part of the fee market.
+        notify(true, 1_000_000, program);
+
+        assert!(count("true") >= matched_before + 2);
+        assert!(count("false") > unmatched_before);
+        let fee_market = &plugin.state.load_full().unwrap().fee_market;
+        assert_eq!(fee_market.quantile(true, 0.5), Some(896));
+        assert_eq!(fee_market.quantile(true, 0.9), Some(1_792));
+        assert_eq!(fee_market.quantile(false, 0.9), Some(96));
+    }
+
+    #[test]
+    fn test_program_stats() {
+        let program = Pubkey::new_unique();
+        let meta = solana_transaction_status::TransactionStatusMeta {
+            compute_units_consumed: Some(5_000),
+            rewards: Some(vec![]),
+            ..Default::default()
+        };
+        let counted = |source, program_ids: &[Pubkey]| {
+            let mut config = Config::default();
+            config.filters = vec![ConfigFilter {
+                transaction_topic: Some("program-stats-txs".to_owned()),
+                program_filters: vec![program.to_string()],
+                ..Default::default()
+            }];
+            config.program_stats_topic = Some("program-stats".to_owned());
+            config.stats_interval_secs = 3600;
+            config.program_stats_source = source;
+            let plugin = KafkaPlugin::dry_run(&config).unwrap();
+            for program_id in program_ids {
+                let txn = SdkSanitizedTransaction::from_transaction_for_tests(
+                    Transaction::new_with_payer(
+                        &[Instruction::new_with_bytes(*program_id, &[], vec![])],
+                        Some(&Pubkey::new_unique()),
+                    ),
+                );
+                let info = ReplicaTransactionInfoV2 {
+                    signature: &Signature::default(),
+                    is_vote: false,
+                    transaction: &txn,
+                    transaction_status_meta: &meta,
+                    index: 0,
+                };
+                plugin
+                    .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&info), 1)
+                    .unwrap();
+            }
+            let state = plugin.state.load_full().unwrap();
+            let event = state.program_stats.as_ref().unwrap().take(u64::MAX);
+            event
+                .programs
+                .iter()
+                .map(|activity| (activity.tx_count, activity.cu_sum))
+                .collect::<Vec<_>>()
+        };
+
+        let unmatched = Pubkey::new_unique();
+        assert_eq!(
+            counted(
+                ProgramStatsSource::Published,
+                &[program, unmatched, program]
+            ),
+            [(2, 10_000)]
+        );
+        assert_eq!(
+            counted(ProgramStatsSource::Seen, &[program, unmatched, program]),
+            [(2, 10_000), (1, 5_000)]
+        );
+    }
+
+    #[test]
+    fn test_write_lock_contention() {
+        let mut config = Config::default();
+        config.write_lock_contention_topic = Some("contention".to_owned());
+        config.write_lock_contention_top_k = 1;
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        let (program, hot) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let meta = solana_transaction_status::TransactionStatusMeta {
+            rewards: Some(vec![]),
+            ..Default::default()
+        };
+        for accounts in [
+            vec![AccountMeta::new(hot, false)],
+            vec![AccountMeta::new(hot, false)],
+            vec![AccountMeta::new_readonly(hot, false)],
+        ] {
+            let txn =
+                SdkSanitizedTransaction::from_transaction_for_tests(Transaction::new_with_payer(
+                    &[Instruction::new_with_bytes(program, &[], accounts)],
+                    Some(&Pubkey::new_unique()),
+                ));
+            let info = ReplicaTransactionInfoV2 {
+                signature: &Signature::default(),
+                is_vote: false,
+                transaction: &txn,
+                transaction_status_meta: &meta,
+                index: 0,
+            };
+            plugin
+                .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&info), 7)
+                .unwrap();
+        }
+        assert!(plugin.take_recorded().is_empty());
+
+        plugin
+            .update_slot_status(7, Some(6), PluginSlotStatus::Processed)
+            .unwrap();
+        let recorded = plugin.take_recorded();
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(
+            (recorded[0].topic.as_str(), recorded[0].key.as_slice()),
+            ("contention", &7u64.to_le_bytes()[..])
+        );
+        let event = SlotContentionEvent::decode(recorded[0].payload.as_deref().unwrap()).unwrap();
+        assert_eq!(event.slot, 7);
+        assert_eq!(event.accounts.len(), 1);
+        assert_eq!(event.accounts[0].pubkey, hot.to_bytes());
+        assert_eq!(event.accounts[0].write_lock_count, 2);
+
+        // Later statuses of the slot don't publish it again.
+        plugin
+            .update_slot_status(7, Some(6), PluginSlotStatus::Confirmed)
+            .unwrap();
+        assert!(plugin.take_recorded().is_empty());
+    }
+
+    #[test]
+    fn test_events_in_any_order() {
+        let mut config = Config::default();
+        config.fork_instance_ids = true;
+        config.max_account_bytes_per_slot = Some(250);
+        config.write_lock_contention_topic = Some("contention".to_owned());
+        config.filters = vec![ConfigFilter {
+            update_account_topic: Some("accounts".to_owned()),
+            slot_status_topic: Some("slots".to_owned()),
+            transaction_topic: Some("txs".to_owned()),
+            block_events_topic: Some("blocks".to_owned()),
+            ..Default::default()
+        }];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        let (program, hot) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let txn = SdkSanitizedTransaction::from_transaction_for_tests(Transaction::new_with_payer(
+            &[Instruction::new_with_bytes(
+                program,
+                &[],
+                vec![AccountMeta::new(hot, false)],
+            )],
+            Some(&Pubkey::new_unique()),
+        ));
+        let meta = solana_transaction_status::TransactionStatusMeta {
+            rewards: Some(vec![]),
+            ..Default::default()
+        };
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        enum Event {
+            Txn,
+            Account,
+            Block,
+            Processed,
+        }
+        use Event::*;
+        let deliver = |slot: u64, event: Event| match event {
+            Txn => {
+                let info = ReplicaTransactionInfoV2 {
+                    signature: &Signature::default(),
+                    is_vote: false,
+                    transaction: &txn,
+                    transaction_status_meta: &meta,
+                    index: 0,
+                };
+                plugin
+                    .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&info), slot)
+                    .unwrap();
+            }
+            Account => {
+                notify_account(&plugin, &hot, &program, &[0; 60], slot, false, None);
+            }
+            Block => {
+                let info = ReplicaBlockInfoV3 {
+                    parent_slot: slot - 1,
+                    parent_blockhash: "8qbHbw2BbbTHBW1sbeqakYXVKRQM8Ne7pLK7m6CVfeR",
+                    slot,
+                    blockhash: "4vJ9JU1bJJE96FWSJKvHsmmFADCg4gpZQff4P3bkLKi",
+                    rewards: &[],
+                    block_time: Some(1_700_000_000),
+                    block_height: None,
+                    executed_transaction_count: 1,
+                    entry_count: 1,
+                };
+                plugin
+                    .notify_block_metadata(ReplicaBlockInfoVersions::V0_0_3(&info))
+                    .unwrap();
+            }
+            Processed => {
+                plugin
+                    .update_slot_status(slot, Some(slot - 1), PluginSlotStatus::Processed)
+                    .unwrap();
+            }
+        };
+        let metrics = plugin.metrics().unwrap();
+        let before_status = |event_type: &str| {
+            metrics
+                .events_before_slot_status_total
+                .with_label_values(&[event_type])
+                .get()
+        };
+
+        let orders = [
+            [Txn, Account, Account, Block, Processed],
+            [Account, Block, Txn, Account, Processed],
+            [Block, Account, Account, Txn, Processed],
+            [Txn, Account, Account, Processed, Block],
+        ];
+        for (slot, order) in (10..).zip(orders) {
+            let counted = ["account", "transaction", "block"].map(before_status);
+            for event in order {
+                deliver(slot, event);
+            }
+
+            let mut ids = Vec::new();
+            let mut suppressed = None;
+            let mut contention = None;
+            for message in plugin.take_recorded() {
+                let payload = message.payload.as_deref().unwrap();
+                match message.topic.as_str() {
+                    "accounts" => ids.push(
+                        UpdateAccountEvent::decode(payload)
+                            .unwrap()
+                            .fork_instance_id,
+                    ),
+                    "txs" => ids.push(TransactionEvent::decode(payload).unwrap().fork_instance_id),
+                    "blocks" => ids.push(BlockEvent::decode(payload).unwrap().fork_instance_id),
+                    "slots" => {
+                        let event = SlotStatusEvent::decode(payload).unwrap();
+                        ids.push(event.fork_instance_id);
+                        suppressed = Some(event.suppressed_account_updates);
+                    }
+                    _ => contention = Some(SlotContentionEvent::decode(payload).unwrap()),
+                }
+            }
+            // The second account update exceeds the slot's budget.
+            assert_eq!(ids.len(), 4, "{order:?}");
+            assert_ne!(ids[0], 0, "{order:?}");
+            assert_eq!(ids, [ids[0]; 4], "{order:?}");
+            assert_eq!(suppressed, Some(1), "{order:?}");
+            let contention = contention.unwrap();
+            assert_eq!(contention.slot, slot);
+            assert_eq!(contention.accounts[0].pubkey, hot.to_bytes());
+            assert_eq!(contention.accounts[0].write_lock_count, 1);
+
+            let status_at = order.iter().position(|event| *event == Processed).unwrap();
+            for (index, kind) in [Account, Txn, Block].into_iter().enumerate() {
+                let ahead = order[..status_at].iter().filter(|e| **e == kind).count();
+                let event_type = ["account", "transaction", "block"][index];
+                assert!(before_status(event_type) >= counted[index] + ahead as u64);
+            }
+        }
+    }
+
+    #[test]
+    fn test_publish_failure_isolation() {
+        let owner = Pubkey::new_unique();
+        let filter = |name: &str, fail_open| ConfigFilter {
+            name: name.to_owned(),
+            update_account_topic: Some(format!("{name}-accounts")),
+            transaction_topic: Some(format!("{name}-transactions")),
+            program_filters: vec![owner.to_string()],
+            fail_open,
+            ..Default::default()
+        };
+        let mut config = Config::default();
+        config.filters = vec![filter("failing", false), filter("healthy", false)];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        let fail_topics = |plugin: &KafkaPlugin| {
+            let state = plugin.state.load_full().unwrap();
+            state.publisher.fail_recorded_topic("failing-accounts");
+            state.publisher.fail_recorded_topic("failing-transactions");
+        };
+        fail_topics(&plugin);
+
+        let pubkey = Pubkey::new_unique();
+        let info = account_info(&pubkey, &owner, &[], None);
+        let txn = SdkSanitizedTransaction::from_transaction_for_tests(Transaction::new_with_payer(
+            &[],
+            Some(&owner),
+        ));
+        let meta = solana_transaction_status::TransactionStatusMeta {
+            rewards: Some(vec![]),
+            ..Default::default()
+        };
+        let transaction = ReplicaTransactionInfoV2 {
+            signature: txn.signature(),
+            is_vote: false,
+            transaction: &txn,
+            transaction_status_meta: &meta,
+            index: 0,
+        };
+        let errors = |plugin: &KafkaPlugin| {
+            plugin
+                .metrics()
+                .unwrap()
+                .publish_errors_total
+                .with_label_values(&["failing-accounts"])
+                .get()
+        };
+        let topics = |plugin: &KafkaPlugin| -> Vec<String> {
+            plugin
+                .take_recorded()
+                .into_iter()
+                .map(|m| m.topic)
+                .collect()
+        };
+
+        // The failing filter comes first, the healthy one is still published to.
+        let error = plugin
+            .update_account(ReplicaAccountInfoVersions::V0_0_3(&info), 5, false)
+            .unwrap_err();
+        assert!(matches!(
+            &error,
+            PluginError::AccountsUpdateError { msg } if msg.contains("failing-accounts")
+        ));
+        assert_eq!(topics(&plugin), ["healthy-accounts"]);
+        let error = plugin
+            .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&transaction), 5)
+            .unwrap_err();
+        assert!(matches!(
+            &error,
+            PluginError::TransactionUpdateError { msg } if msg.contains("failing-transactions")
+        ));
+        assert_eq!(topics(&plugin), ["healthy-transactions"]);
+        assert_eq!(errors(&plugin), 1);
+
+        config.filters = vec![filter("failing", true), filter("healthy", false)];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        fail_topics(&plugin);
+        plugin
+            .update_account(ReplicaAccountInfoVersions::V0_0_3(&info), 5, false)
+            .unwrap();
+        plugin
+            .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&transaction), 5)
+            .unwrap();
+        assert_eq!(
+            topics(&plugin),
+            ["healthy-accounts", "healthy-transactions"]
+        );
+        // Counted by the new instance alone.
+        assert_eq!(errors(&plugin), 1);
+    }
+
+    #[test]
+    fn test_callback_deadline() {
+        let owner = Pubkey::new_unique();
+        let filter = |name: &str| ConfigFilter {
+            name: name.to_owned(),
+            state_topic: Some(format!("{name}-state")),
+            update_account_topic: Some(format!("{name}-accounts")),
+            transaction_topic: Some(format!("{name}-transactions")),
+            program_filters: vec![owner.to_string()],
+            ..Default::default()
+        };
+        let mut config = Config::default();
+        config.filters = vec![filter("first"), filter("second")];
+        config.callback_deadline_ms = Some(30);
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        let publisher = Arc::clone(&plugin.state.load_full().unwrap().publisher);
+
+        let pubkey = Pubkey::new_unique();
+        let info = account_info(&pubkey, &owner, &[], None);
+        let txn = SdkSanitizedTransaction::from_transaction_for_tests(Transaction::new_with_payer(
+            &[],
+            Some(&owner),
+        ));
+        let meta = solana_transaction_status::TransactionStatusMeta::default();
+        let transaction = ReplicaTransactionInfoV2 {
+            signature: txn.signature(),
+            is_vote: false,
+            transaction: &txn,
+            transaction_status_meta: &meta,
+            index: 0,
+        };
+        let topics = || -> Vec<String> {
+            plugin
+                .take_recorded()
+                .into_iter()
+                .map(|m| m.topic)
+                .collect()
+        };
+        let metrics = plugin.metrics().unwrap();
+        let exceeded = |callback| {
+            metrics
+                .callback_deadline_exceeded_total
+                .with_label_values(&[callback])
+                .get()
+        };
+        let dropped = |topic| {
+            metrics
+                .callback_deadline_dropped_total
+                .with_label_values(&[topic])
+                .get()
+        };
+
+        // Within the deadline everything is published.
+        plugin
+            .update_account(ReplicaAccountInfoVersions::V0_0_3(&info), 5, false)
+            .unwrap();
+        assert_eq!(
+            topics(),
+            [
+                "first-state",
+                "first-accounts",
+                "second-state",
+                "second-accounts"
+            ]
+        );
+
+        // The first send takes past the deadline: the next is dropped, the next filter skipped.
+        publisher.delay_recorded_sends(Duration::from_millis(40));
+        let (accounts, transactions) = (exceeded("update_account"), exceeded("notify_transaction"));
+        let first_accounts = dropped("first-accounts");
+        plugin
+            .update_account(ReplicaAccountInfoVersions::V0_0_3(&info), 6, false)
+            .unwrap();
+        assert_eq!(topics(), ["first-state"]);
+        assert_eq!(dropped("first-accounts"), first_accounts);
+        assert!(exceeded("update_account") > accounts);
+
+        // Sends alone passing the deadline are counted too.
+        plugin
+            .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&transaction), 6)
+            .unwrap();
+        assert_eq!(topics(), ["first-transactions"]);
+        assert!(exceeded("notify_transaction") > transactions);
+
+        // Without the filter check in between, a send past the deadline is dropped.
+        config.filters = vec![ConfigFilter {
+            state_topic: None,
+            ..filter("only")
+        }];
+        config.firehose_topic = "firehose".to_owned();
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        let state = plugin.state.load_full().unwrap();
+        state
+            .publisher
+            .delay_recorded_sends(Duration::from_millis(40));
+        plugin
+            .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&transaction), 7)
+            .unwrap();
+        assert_eq!(plugin.take_recorded().len(), 1);
+        let dropped = state
+            .metrics
+            .callback_deadline_dropped_total
+            .with_label_values(&["firehose"])
+            .get();
+        assert_eq!(dropped, 1);
+        let firehose = |status| {
+            state
+                .metrics
+                .upload_firehose_total
+                .with_label_values(&["transaction", status])
+                .get()
+        };
+        assert_eq!((firehose("success"), firehose("dropped")), (0, 1));
+
+        // A filter's send dropped past the deadline isn't taken for published.
+        config.filters = vec![ConfigFilter {
+            transaction_index_topic: Some("only-index".to_owned()),
+            ..filter("only")
+        }];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        let state = plugin.state.load_full().unwrap();
+        state
+            .publisher
+            .delay_recorded_sends(Duration::from_millis(40));
+        plugin
+            .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&transaction), 8)
+            .unwrap();
+        let topics: Vec<_> = plugin
+            .take_recorded()
+            .into_iter()
+            .map(|m| m.topic)
+            .collect();
+        assert_eq!(topics, ["only-index"]);
+        let uploads = |status| {
+            state
+                .metrics
+                .upload_transactions_total
+                .with_label_values(&[status])
+                .get()
+        };
+        assert_eq!((uploads("success"), uploads("dropped")), (0, 1));
+        let filtered = |reason| {
+            state
+                .metrics
+                .transactions_filtered_total
+                .with_label_values(&["only", reason])
+                .get()
+        };
+        assert_eq!((filtered("published"), filtered("dropped")), (0, 1));
+        assert_eq!(
+            state
+                .metrics
+                .publish_errors_total
+                .with_label_values(&["only-transactions"])
+                .get(),
+            0
+        );
+        let dropped = state
+            .metrics
+            .callback_deadline_dropped_total
+            .with_label_values(&["only-transactions"])
+            .get();
+        assert_eq!(dropped, 1);
+        // Nor mirrored to the firehose.
+        let firehose = state
+            .metrics
+            .upload_firehose_total
+            .with_label_values(&["transaction", "dropped"])
+            .get();
+        assert_eq!(firehose, 0);
+    }
+
+    #[test]
+    fn test_block_leader() {
+        let mut config = Config::default();
+        config.filters = vec![ConfigFilter {
+            block_events_topic: Some("blocks".to_owned()),
+            ..Default::default()
+        }];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        let leader = Pubkey::new_unique();
+        plugin
+            .state
+            .load()
+            .as_ref()
+            .unwrap()
+            .leader_schedule
+            .insert(
+                EpochLeaders::from_rpc(
+                    1,
+                    8,
+                    &serde_json::json!({leader.to_string(): [0, 1, 2, 3]}),
+                )
+                .unwrap(),
+            );
+
+        let block = |slot| {
+            let info = ReplicaBlockInfoV3 {
+                parent_slot: slot - 1,
+                parent_blockhash: "8qbHbw2BbbTHBW1sbeqakYXVKRQM8Ne7pLK7m6CVfeR",
+                slot,
+                blockhash: "4vJ9JU1bJJE96FWSJKvHsmmFADCg4gpZQff4P3bkLKi",
+                rewards: &[],
+                block_time: Some(1_700_000_000),
+                block_height: None,
+                executed_transaction_count: 3,
+                entry_count: 2,
+            };
+            plugin
+                .notify_block_metadata(ReplicaBlockInfoVersions::V0_0_3(&info))
+                .unwrap();
+            let recorded = plugin.take_recorded();
+            assert_eq!(recorded.len(), 1);
+            assert_eq!(recorded[0].key, slot.to_le_bytes());
+            BlockEvent::decode(recorded[0].payload.as_deref().unwrap()).unwrap()
+        };
+
+        let event = block(9);
+        assert_eq!(event.leader_pubkey, leader.to_bytes());
+        assert_eq!(
+            event.blockhash,
+            "4vJ9JU1bJJE96FWSJKvHsmmFADCg4gpZQff4P3bkLKi"
+        );
+        assert!(!event.blockhash_invalid);
+        assert_eq!(event.block_time, Some(1_700_000_000));
+        assert_eq!(event.block_height, None);
+        // Outside the cached epoch.
+        assert!(block(12).leader_pubkey.is_empty());
+    }
+
+    #[test]
+    fn test_enrich_rooted_slot_with_block() {
+        let mut config = Config::default();
+        config.enrich_rooted_slot_with_block = true;
+        config.filters = vec![ConfigFilter {
+            slot_status_topic: Some("slots".to_owned()),
+            ..Default::default()
+        }];
+        let plugin = KafkaPlugin::dry_run(&config).unwrap();
+        let block = |slot: u64| {
+            let info = ReplicaBlockInfoV3 {
+                parent_slot: slot - 1,
+                parent_blockhash: "8qbHbw2BbbTHBW1sbeqakYXVKRQM8Ne7pLK7m6CVfeR",
+                slot,
+                blockhash: "4vJ9JU1bJJE96FWSJKvHsmmFADCg4gpZQff4P3bkLKi",
+                rewards: &[],
+                block_time: Some(1_700_000_000),
+                block_height: Some(slot),
+                executed_transaction_count: 3,
+                entry_count: 2,
+            };
+            plugin
+                .notify_block_metadata(ReplicaBlockInfoVersions::V0_0_3(&info))
+                .unwrap();
+        };
+        let status = |slot: u64, status: PluginSlotStatus| {
+            plugin
+                .update_slot_status(slot, Some(slot - 1), status)
+                .unwrap();
+            let recorded = plugin.take_recorded();
+            assert_eq!(recorded.len(), 1);
+            SlotStatusEvent::decode(recorded[0].payload.as_deref().unwrap()).unwrap()
+        };
+
+        // Block metadata before the rooted status is embedded in it, and only in it.
+        block(10);
+        assert_eq!(status(10, PluginSlotStatus::Processed).block, None);
+        let metrics = plugin.metrics().unwrap();
+        let without_block = metrics.rooted_slots_without_block_total.get();
+        let rooted = status(10, PluginSlotStatus::Rooted).block.unwrap();
+        assert_eq!(rooted.slot, 10);
+        assert_eq!(rooted.block_height, Some(10));
+        assert_eq!(rooted.executed_transaction_count, 3);
+
+        // Block metadata after the rooted status comes too late.
+        assert_eq!(status(11, PluginSlotStatus::Rooted).block, None);
+        block(11);
+        assert!(metrics.rooted_slots_without_block_total.get() > without_block);
+        // And is dropped with the next root, as blocks of dead forks are.
+        block(12);
+        assert_eq!(status(13, PluginSlotStatus::Rooted).block, None);
+        assert_eq!(status(12, PluginSlotStatus::Rooted).block, None);
+    }
+
+    #[test]
+    fn test_instances_count_independently() {
+        let instance = |name: &str| {
+            let mut config = Config::default();
+            config.instance_name = name.to_owned();
+            config.filters = vec![ConfigFilter {
+                update_account_topic: Some(format!("{name}-accounts")),
+                ..Default::default()
+            }];
+            KafkaPlugin::dry_run(&config).unwrap()
+        };
+        let (mut a, b) = (instance("a"), instance("b"));
+        let pubkey = Pubkey::new_unique();
+        let update = |plugin: &KafkaPlugin| {
+            notify_account(plugin, &pubkey, &pubkey, &[], 5, false, None);
+        };
+        update(&a);
+        update(&a);
+        update(&b);
+
+        let uploaded = |plugin: &KafkaPlugin| {
+            plugin
+                .metrics()
+                .unwrap()
+                .upload_accounts_total
+                .with_label_values(&["success"])
+                .get()
+        };
+        assert_eq!((uploaded(&a), uploaded(&b)), (2, 1));
+
+        // Each registry serves its own counters labelled with its instance, and the process
+        // wide metrics along.
+        let families = a.metrics().unwrap().registry().gather();
+        let family = |name: &str| families.iter().find(|family| family.name() == name);
+        let uploads = family("upload_accounts_total").unwrap();
+        assert_eq!(uploads.get_metric().len(), 1);
+        let mut labels: Vec<_> = uploads.get_metric()[0]
+            .get_label()
+            .iter()
+            .map(|label| (label.name(), label.value()))
+            .collect();
+        labels.sort();
+        assert_eq!(labels, [("instance", "a"), ("status", "success")]);
+        assert_eq!(uploads.get_metric()[0].get_counter().value(), 2.0);
+        assert!(family("version").is_some());
+
+        // Gauges too, unloading one instance leaves the other loaded.
+        let (a_metrics, b_metrics) = (a.metrics().unwrap(), b.metrics().unwrap());
+        assert_eq!(
+            (a_metrics.plugin_loaded.get(), b_metrics.plugin_loaded.get()),
+            (1, 1)
+        );
+        a.on_unload();
+        assert_eq!(
+            (a_metrics.plugin_loaded.get(), b_metrics.plugin_loaded.get()),
+            (0, 1)
+        );
+    }
+}
@@ -0,0 +1,284 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Producer tuning hints from heuristics over the librdkafka statistics of a period.
+
+use {
+    log::info,
+    rdkafka::statistics::Statistics,
+    serde::Serialize,
+    std::{
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+};
+
+/// Internal latency p99 in microseconds above which batches are worth growing.
+const HIGH_INT_LATENCY_US: i64 = 50_000;
+
+/// Average messages per batch below which batches count as small.
+const SMALL_BATCH_MESSAGES: f64 = 10.0;
+
+/// Fill of the producer queue, by messages or bytes, from which it counts as full.
+const FULL_QUEUE_RATIO: f64 = 0.8;
+
+/// Hint for a librdkafka setting.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Suggestion {
+    /// librdkafka setting to raise.
+    pub raise: &'static str,
+    pub reason: String,
+}
+
+/// Outcome of the heuristics over the statistics of one period.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Advisory {
+    /// Statistics received in the period.
+    pub samples: u32,
+    pub suggestions: Vec<Suggestion>,
+}
+
+/// Conditions seen in the statistics of the current period.
+#[derive(Debug, Default)]
+struct Period {
+    start: Option<Instant>,
+    samples: u32,
+    slow_small_batches: u32,
+    full_queue_messages: u32,
+    full_queue_bytes: u32,
+    req_timeouts: u64,
+}
+
+#[derive(Debug, Default)]
+struct AdvisorState {
+    period: Period,
+    /// Request timeouts of all brokers in the previous statistics, as librdkafka counts them
+    /// since the producer started.
+    last_req_timeouts: Option<u64>,
+    latest: Option<Advisory>,
+}
+
+/// Evaluates the statistics received every `interval`, logging an advisory each time.
+#[derive(Debug)]
+pub struct ProducerAdvisor {
+    interval: Duration,
+    state: Mutex<AdvisorState>,
+}
+
+impl ProducerAdvisor {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Account for `statistics` received at `now`, returning the advisory of the period if
+    /// `interval` passed since it started.
+    pub fn observe(&self, statistics: &Statistics, now: Instant) -> Option<Advisory> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let period = &mut state.period;
+        let start = *period.start.get_or_insert(now);
+        period.samples += 1;
+
+        let latency_p99 = statistics
+            .brokers
+            .values()
+            .filter_map(|broker| broker.int_latency.as_ref().map(|window| window.p99))
+            .max();
+        let (batches, batched_messages) =
+            statistics
+                .topics
+                .values()
+                .fold((0, 0.0), |(batches, messages), topic| {
+                    let window = &topic.batchcnt;
+                    (
+                        batches + window.cnt,
+                        messages + (window.avg * window.cnt) as f64,
+                    )
+                });
+        if latency_p99.is_some_and(|p99| p99 > HIGH_INT_LATENCY_US)
+            && batches > 0
+            && batched_messages / (batches as f64) < SMALL_BATCH_MESSAGES
+        {
+            period.slow_small_batches += 1;
+        }
+        let full = |used: u64, max: u64| max > 0 && used as f64 >= FULL_QUEUE_RATIO * max as f64;
+        if full(statistics.msg_cnt, statistics.msg_max) {
+            period.full_queue_messages += 1;
+        }
+        if full(statistics.msg_size, statistics.msg_size_max) {
+            period.full_queue_bytes += 1;
+        }
+        let req_timeouts = statistics
+            .brokers
+            .values()
+            .map(|broker| broker.req_timeouts)
+            .sum();
+        if let Some(last) = state.last_req_timeouts {
+            period.req_timeouts += u64::saturating_sub(req_timeouts, last);
+        }
+        state.last_req_timeouts = Some(req_timeouts);
+
+        if now.saturating_duration_since(start) < self.interval {
+            return None;
+        }
+        let advisory = Self::evaluate(&std::mem::take(&mut state.period));
+        info!(
+            "Producer advisory: {}",
+            serde_json::to_string(&advisory).unwrap()
+        );
+        state.latest = Some(advisory.clone());
+        Some(advisory)
+    }
+
+    fn evaluate(period: &Period) -> Advisory {
+        let samples = period.samples;
+        // Held in at least half of the period's statistics.
+        let persistent = |count: u32| count > 0 && 2 * count >= samples;
+        let mut suggestions = Vec::new();
+        if persistent(period.slow_small_batches) {
+            suggestions.push(Suggestion {
+                raise: "linger.ms",
+                reason: format!(
+                    "internal latency p99 above {} ms with under {SMALL_BATCH_MESSAGES} \
+                     messages per batch in {} of {samples} statistics",
+                    HIGH_INT_LATENCY_US / 1000,
+                    period.slow_small_batches
+                ),
+            });
+        }
+        for (count, raise, unit) in [
+            (
+                period.full_queue_messages,
+                "queue.buffering.max.messages",
+                "messages",
+            ),
+            (
+                period.full_queue_bytes,
+                "queue.buffering.max.kbytes",
+                "bytes",
+            ),
+        ] {
+            if persistent(count) {
+                suggestions.push(Suggestion {
+                    raise,
+                    reason: format!(
+                        "producer queue at least {}% full by {unit} in {count} of {samples} \
+                         statistics",
+                        FULL_QUEUE_RATIO * 100.0
+                    ),
+                });
+            }
+        }
+        if period.req_timeouts > 0 {
+            suggestions.push(Suggestion {
+                raise: "request.timeout.ms",
+                reason: format!("{} requests to brokers timed out", period.req_timeouts),
+            });
+        }
+        Advisory {
+            samples,
+            suggestions,
+        }
+    }
+
+    /// Advisory of the last completed period.
+    pub fn latest(&self) -> Option<Advisory> {
+        self.state.lock().unwrap().latest.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        rdkafka::statistics::{Broker, Topic, Window},
+    };
+
+    /// Statistics with an internal latency p99 in ms, messages per batch and queued messages
+    /// of at most 1000.
+    fn statistics(latency_ms: i64, batch_messages: i64, queued: u64) -> Statistics {
+        let broker = Broker {
+            int_latency: Some(Window {
+                p99: latency_ms * 1000,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let topic = Topic {
+            batchcnt: Window {
+                avg: batch_messages,
+                cnt: 20,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        Statistics {
+            msg_cnt: queued,
+            msg_max: 1000,
+            brokers: [("b:9092/1".to_owned(), broker)].into(),
+            topics: [("t".to_owned(), topic)].into(),
+            ..Default::default()
+        }
+    }
+
+    /// Feed `sequence` one second apart to an advisor over a period of its length.
+    fn advise(sequence: &[Statistics]) -> Vec<&'static str> {
+        let advisor = ProducerAdvisor::new(Duration::from_secs(sequence.len() as u64 - 1));
+        let start = Instant::now();
+        let mut advisories = sequence.iter().enumerate().filter_map(|(i, statistics)| {
+            advisor.observe(statistics, start + Duration::from_secs(i as u64))
+        });
+        let advisory = advisories.next().unwrap();
+        assert!(advisories.next().is_none());
+        assert_eq!(advisor.latest(), Some(advisory.clone()));
+        assert_eq!(advisory.samples, sequence.len() as u32);
+        advisory
+            .suggestions
+            .iter()
+            .map(|suggestion| suggestion.raise)
+            .collect()
+    }
+
+    #[test]
+    fn test_healthy() {
+        let advisor = ProducerAdvisor::new(Duration::from_secs(60));
+        assert_eq!(advisor.observe(&statistics(5, 2, 10), Instant::now()), None);
+        assert_eq!(advisor.latest(), None);
+        assert!(advise(&[statistics(5, 2, 10), statistics(80, 500, 10)]).is_empty());
+    }
+
+    #[test]
+    fn test_linger() {
+        let slow = statistics(80, 3, 10);
+        assert_eq!(
+            advise(&[slow.clone(), statistics(5, 3, 10), slow.clone()]),
+            ["linger.ms"]
+        );
+        // A single slow sample isn't persistent.
+        assert!(advise(&[slow, statistics(5, 3, 10), statistics(5, 3, 10)]).is_empty());
+    }
+
+    #[test]
+    fn test_queue_and_timeouts() {
+        let mut timed_out = statistics(5, 50, 900);
+        timed_out.brokers.get_mut("b:9092/1").unwrap().req_timeouts = 4;
+        assert_eq!(
+            advise(&[statistics(5, 50, 850), timed_out, statistics(5, 50, 10)]),
+            ["queue.buffering.max.messages", "request.timeout.ms"]
+        );
+    }
+}
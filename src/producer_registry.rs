@@ -0,0 +1,188 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `producer_registry_topic`, an inventory of the plugins producing to a Kafka deployment. Each
+//! plugin keeps one record on the compacted topic, produced on load and again on unload with
+//! its stop time, telling what build runs where with which config. Registering is best effort:
+//! failures are logged and never keep the plugin from loading.
+
+use {
+    crate::{
+        event_proto, identity::Identity, version::VERSION, wall_clock::unix_millis, Config,
+        ProducerRegistration, Publisher,
+    },
+    log::{error, info},
+    solana_program::hash::hash,
+};
+
+/// Last segment of registry keys.
+pub const PLUGIN_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// Key of the record of a plugin, `cluster_name/validator_identity/plugin`, the segments
+/// unset left empty.
+pub fn registry_key(identity: &Identity) -> String {
+    let validator = identity
+        .validator_identity
+        .map(|identity| identity.to_string())
+        .unwrap_or_default();
+    format!("{}/{validator}/{PLUGIN_NAME}", identity.cluster_name)
+}
+
+/// SHA-256 of the `event.proto` of this build, in base58.
+pub fn schema_hash() -> String {
+    hash(event_proto().as_bytes()).to_string()
+}
+
+/// Record of the loaded plugin on `producer_registry_topic`.
+#[derive(Debug)]
+pub struct ProducerRegistry {
+    topic: String,
+    key: String,
+    registration: ProducerRegistration,
+}
+
+impl ProducerRegistry {
+    /// Record of a plugin loaded now with `config`, whose filters hash to `config_hash`.
+    pub fn new(config: &Config, topic: String, config_hash: &str) -> Self {
+        let identity = Identity::new(config);
+        let registration = ProducerRegistration {
+            version: VERSION.version.to_owned(),
+            git_version: VERSION.git.to_owned(),
+            solana_version: VERSION.solana.to_owned(),
+            config_hash: config_hash.to_owned(),
+            schema_hash: schema_hash(),
+            topics: config
+                .access_topics()
+                .into_iter()
+                .map(|(topic, _)| topic)
+                .collect(),
+            cluster_name: identity.cluster_name.clone(),
+            validator_identity: identity
+                .validator_identity
+                .map(|identity| identity.to_bytes().to_vec())
+                .unwrap_or_default(),
+            start_time: unix_millis(),
+            stop_time: None,
+        };
+        Self {
+            topic,
+            key: registry_key(&identity),
+            registration,
+        }
+    }
+
+    /// Produce the record of the loaded plugin.
+    pub fn register(&self, publisher: &Publisher) {
+        self.publish(publisher, &self.registration);
+    }
+
+    /// Produce the record again with the stop time, as the plugin unloads.
+    pub fn deregister(&self, publisher: &Publisher) {
+        let registration = ProducerRegistration {
+            stop_time: Some(unix_millis()),
+            ..self.registration.clone()
+        };
+        self.publish(publisher, &registration);
+    }
+
+    fn publish(&self, publisher: &Publisher, registration: &ProducerRegistration) {
+        match publisher.publish_producer_registration(&self.topic, &self.key, registration) {
+            Ok(()) => info!(
+                "Registered producer {} on {} (stopped: {})",
+                self.key,
+                self.topic,
+                registration.stop_time.is_some()
+            ),
+            Err(error) => error!(
+                "Failed to register producer {} on {}: {error:?}",
+                self.key, self.topic
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{ConfigFilter, RecordedMessage},
+        prost::Message,
+        solana_sdk::pubkey::Pubkey,
+    };
+
+    #[test]
+    fn test_registry_key() {
+        assert_eq!(
+            registry_key(&Identity::default()),
+            format!("//{PLUGIN_NAME}")
+        );
+        let pubkey = Pubkey::new_unique();
+        let identity = Identity {
+            cluster_name: "mainnet".to_owned(),
+            validator_identity: Some(pubkey),
+        };
+        assert_eq!(
+            registry_key(&identity),
+            format!("mainnet/{pubkey}/solana-accountsdb-plugin-kafka")
+        );
+    }
+
+    #[test]
+    fn test_registration() {
+        let pubkey = Pubkey::new_unique();
+        let mut config = Config::default();
+        config.cluster_name = "testnet".to_owned();
+        config.validator_identity = Some(pubkey.to_string());
+        config.producer_registry_topic = Some("producers".to_owned());
+        config.filters = vec![ConfigFilter {
+            update_account_topic: Some("accounts".to_owned()),
+            transaction_topic: Some("transactions".to_owned()),
+            ..Default::default()
+        }];
+        let registry = ProducerRegistry::new(&config, "producers".to_owned(), "config-hash");
+        let publisher = Publisher::dry_run(&config);
+        registry.register(&publisher);
+        registry.deregister(&publisher);
+
+        let recorded = publisher.take_recorded();
+        assert_eq!(recorded.len(), 2);
+        let decode = |message: &RecordedMessage| {
+            assert_eq!(message.topic, "producers");
+            assert_eq!(
+                message.key,
+                format!("testnet/{pubkey}/{PLUGIN_NAME}").into_bytes()
+            );
+            ProducerRegistration::decode(message.payload.as_deref().unwrap()).unwrap()
+        };
+        let started = decode(&recorded[0]);
+        assert_eq!(started.version, VERSION.version);
+        assert_eq!(started.config_hash, "config-hash");
+        assert_eq!(started.schema_hash, schema_hash());
+        assert_eq!(started.topics, ["accounts", "transactions", "producers"]);
+        assert_eq!(started.cluster_name, "testnet");
+        assert_eq!(started.validator_identity, pubkey.to_bytes());
+        assert!(started.start_time > 0);
+        assert_eq!(started.stop_time, None);
+
+        let stopped = decode(&recorded[1]);
+        assert!(stopped.stop_time.unwrap() >= started.start_time);
+        assert_eq!(
+            ProducerRegistration {
+                stop_time: None,
+                ..stopped
+            },
+            started
+        );
+    }
+}
@@ -0,0 +1,276 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Transactions per program over fixed windows, published to `program_stats_topic`.
+
+use {
+    crate::{
+        checkpoint::CheckpointSchedule, resources::ThreadGuard, wall_clock::unix_millis,
+        ProgramActivity, ProgramActivityEvent, Publisher,
+    },
+    log::error,
+    solana_program::{message::SanitizedMessage, pubkey::Pubkey},
+    std::{
+        collections::HashMap,
+        sync::{
+            mpsc::{self, RecvTimeoutError, Sender},
+            Arc, Mutex,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    },
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Counts {
+    transactions: u64,
+    failed: u64,
+    units: u64,
+}
+
+impl Counts {
+    fn add(&mut self, other: Counts) {
+        self.transactions += other.transactions;
+        self.failed += other.failed;
+        self.units = self.units.saturating_add(other.units);
+    }
+}
+
+#[derive(Debug)]
+struct Window {
+    /// Milliseconds since the epoch.
+    start: u64,
+    programs: HashMap<Pubkey, Counts>,
+}
+
+/// Counts of the programs invoked by transactions in the current window.
+#[derive(Debug)]
+pub struct ProgramStats {
+    top_k: usize,
+    window: Mutex<Window>,
+}
+
+impl ProgramStats {
+    /// Stats keeping the `top_k` most active programs of each window, the first starting at
+    /// `now` in milliseconds since the epoch.
+    pub fn new(top_k: usize, now: u64) -> Self {
+        Self {
+            top_k,
+            window: Mutex::new(Window {
+                start: now,
+                programs: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Count a transaction of `message` for each program its instructions invoke, with the
+    /// compute `units` it consumed.
+    pub fn record(&self, message: &SanitizedMessage, failed: bool, units: Option<u64>) {
+        let mut programs: Vec<&Pubkey> = Vec::new();
+        for (program, _) in message.program_instructions_iter() {
+            if !programs.contains(&program) {
+                programs.push(program);
+            }
+        }
+        let counts = Counts {
+            transactions: 1,
+            failed: failed as u64,
+            units: units.unwrap_or_default(),
+        };
+        let mut window = self.window.lock().unwrap();
+        for program in programs {
+            window.programs.entry(*program).or_default().add(counts);
+        }
+    }
+
+    /// Close the window at `now`, returning its event and starting the next one.
+    pub fn take(&self, now: u64) -> ProgramActivityEvent {
+        let mut window = self.window.lock().unwrap();
+        let start = std::mem::replace(&mut window.start, now);
+        let mut programs: Vec<(Pubkey, Counts)> = window.programs.drain().collect();
+        drop(window);
+
+        programs.sort_unstable_by(|(a_program, a), (b_program, b)| {
+            b.transactions
+                .cmp(&a.transactions)
+                .then_with(|| a_program.cmp(b_program))
+        });
+        let activity = |program: &[u8], counts: Counts| ProgramActivity {
+            program: program.to_vec(),
+            tx_count: counts.transactions,
+            failed_count: counts.failed,
+            cu_sum: counts.units,
+        };
+        let rest = programs.split_off(self.top_k.min(programs.len()));
+        let other = (!rest.is_empty()).then(|| {
+            let mut total = Counts::default();
+            for (_, counts) in rest {
+                total.add(counts);
+            }
+            activity(&[], total)
+        });
+        ProgramActivityEvent {
+            window_start: start,
+            window_end: now,
+            programs: programs
+                .into_iter()
+                .map(|(program, counts)| activity(program.as_ref(), counts))
+                .collect(),
+            other,
+        }
+    }
+}
+
+/// Background thread publishing the program stats of every `stats_interval_secs` window, and of
+/// the last partial window when stopped.
+pub struct ProgramStatsPublisher {
+    stats: Arc<ProgramStats>,
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ProgramStatsPublisher {
+    pub fn spawn(
+        publisher: Arc<Publisher>,
+        top_k: usize,
+        topic: String,
+        interval: Duration,
+    ) -> Self {
+        let stats = Arc::new(ProgramStats::new(top_k, unix_millis()));
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("kafkaProgramStats".to_owned())
+            .spawn({
+                let stats = Arc::clone(&stats);
+                move || {
+                    let _thread = ThreadGuard::new(&publisher.metrics(), "background");
+                    let publish = || {
+                        let event = stats.take(unix_millis());
+                        if let Err(error) = publisher.publish_program_activity(&topic, &event) {
+                            error!("Failed to publish program stats to {topic:?}: {error}");
+                        }
+                    };
+                    let mut schedule = CheckpointSchedule::new(interval, Instant::now());
+                    while let Err(RecvTimeoutError::Timeout) =
+                        stopped.recv_timeout(schedule.until_next(Instant::now()))
+                    {
+                        if schedule.poll(Instant::now()) {
+                            publish();
+                        }
+                    }
+                    publish();
+                }
+            })
+            .expect("failed to spawn program stats thread");
+        Self {
+            stats,
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+
+    pub fn stats(&self) -> &Arc<ProgramStats> {
+        &self.stats
+    }
+}
+
+impl Drop for ProgramStatsPublisher {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        solana_sdk::{
+            instruction::Instruction,
+            transaction::{SanitizedTransaction, Transaction},
+        },
+    };
+
+    fn record(stats: &ProgramStats, programs: &[Pubkey], failed: bool, units: u64) {
+        let instructions: Vec<_> = programs
+            .iter()
+            .map(|program| Instruction::new_with_bytes(*program, &[], vec![]))
+            .collect();
+        let transaction = SanitizedTransaction::from_transaction_for_tests(
+            Transaction::new_with_payer(&instructions, Some(&Pubkey::new_unique())),
+        );
+        stats.record(transaction.message(), failed, Some(units));
+    }
+
+    fn activity(program: &[u8], tx_count: u64, failed_count: u64, cu_sum: u64) -> ProgramActivity {
+        ProgramActivity {
+            program: program.to_vec(),
+            tx_count,
+            failed_count,
+            cu_sum,
+        }
+    }
+
+    #[test]
+    fn test_windows() {
+        let stats = ProgramStats::new(10, 1_000);
+        let (a, b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        // Invoking a program twice counts the transaction once.
+        record(&stats, &[a, a, b], false, 300);
+        record(&stats, &[a], true, 50);
+
+        let event = stats.take(61_000);
+        assert_eq!((event.window_start, event.window_end), (1_000, 61_000));
+        assert_eq!(
+            event.programs,
+            [
+                activity(a.as_ref(), 2, 1, 350),
+                activity(b.as_ref(), 1, 0, 300)
+            ]
+        );
+        assert_eq!(event.other, None);
+
+        // Counts start over in the next window, which follows on the last.
+        record(&stats, &[b], false, 10);
+        let event = stats.take(121_000);
+        assert_eq!((event.window_start, event.window_end), (61_000, 121_000));
+        assert_eq!(event.programs, [activity(b.as_ref(), 1, 0, 10)]);
+        assert!(stats.take(181_000).programs.is_empty());
+    }
+
+    #[test]
+    fn test_top_k() {
+        let stats = ProgramStats::new(2, 0);
+        let mut programs: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+        programs.sort();
+        for (i, program) in programs.iter().enumerate() {
+            for _ in 0..[1, 3, 3, 2][i] {
+                record(&stats, &[*program], i == 0, 10);
+            }
+        }
+
+        let event = stats.take(1);
+        // Ties go to the lower program id.
+        assert_eq!(
+            event.programs,
+            [
+                activity(programs[1].as_ref(), 3, 0, 30),
+                activity(programs[2].as_ref(), 3, 0, 30),
+            ]
+        );
+        assert_eq!(event.other, Some(activity(&[], 3, 1, 30)));
+    }
+}
@@ -1,22 +1,64 @@
 use {
-    crate::version::VERSION as VERSION_INFO,
+    crate::{
+        checkpoint::SlotProgress,
+        config::PrometheusAddress,
+        event_proto, file_descriptor_set,
+        filter_stats::FilterStats,
+        instance_metrics::InstanceMetrics,
+        kafka_stats::KafkaStats,
+        metrics_json,
+        pause::TopicPauses,
+        rate::FilterRates,
+        resources::{sample_footprints, MemoryFootprints, ThreadGuard},
+        standby::Standby,
+        tail::Tail,
+        version::VERSION as VERSION_INFO,
+        zstd_dict::{ZstdDictionaries, ZSTD_DICT_ID_HEADER},
+        Filter,
+    },
     hyper::{
-        server::conn::AddrStream,
-        service::{make_service_fn, service_fn},
-        Body, Request, Response, Server, StatusCode,
+        server::conn::Http,
+        service::{service_fn, Service},
+        Body, Method, Request, Response, StatusCode,
     },
     log::*,
-    prometheus::{GaugeVec, IntCounterVec, Opts, Registry, TextEncoder},
+    prometheus::{Gauge, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder, TEXT_FORMAT},
     rdkafka::{
         client::ClientContext,
+        error::{KafkaError, RDKafkaErrorCode},
+        message::Message,
         producer::{DeliveryResult, ProducerContext},
-        statistics::Statistics,
     },
-    std::{io::Result as IoResult, net::SocketAddr, sync::Once, time::Duration},
-    tokio::runtime::Runtime,
+    serde_json::Value,
+    solana_program::pubkey::Pubkey,
+    std::{
+        collections::HashMap,
+        fs::{self, Permissions},
+        future::Future,
+        io::{Error as IoError, ErrorKind, Result as IoResult},
+        net::{SocketAddr, TcpListener as StdTcpListener},
+        os::unix::fs::{FileTypeExt, PermissionsExt},
+        path::{Path, PathBuf},
+        str::FromStr,
+        sync::{Arc, Once},
+        time::{Duration, Instant},
+    },
+    tokio::{
+        io::{AsyncRead, AsyncWrite},
+        net::{TcpListener, UnixListener},
+        runtime::{Builder as RuntimeBuilder, Runtime},
+        sync::Semaphore,
+    },
 };
 
+/// Events streamed by `/debug/tail` without a `limit`, and the most it streams.
+const DEFAULT_TAIL_LIMIT: usize = 100;
+const MAX_TAIL_LIMIT: usize = 10_000;
+
 lazy_static::lazy_static! {
+    /// Metrics shared by every instance of the plugin loaded in the process, which each
+    /// `InstanceMetrics` registry serves along its own. Anything derived from one instance's
+    /// state belongs in `InstanceMetrics` instead.
     pub static ref REGISTRY: Registry = Registry::new();
 
     static ref VERSION: IntCounterVec = IntCounterVec::new(
@@ -24,91 +66,584 @@ lazy_static::lazy_static! {
         &["key", "value"]
     ).unwrap();
 
-    pub static ref UPLOAD_ACCOUNTS_TOTAL: IntCounterVec = IntCounterVec::new(
-        Opts::new("upload_accounts_total", "Status of uploaded accounts"),
-        &["status"]
+    pub static ref NOTIFICATIONS_UNLOADED_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("notifications_unloaded_total", "Geyser callbacks ignored because the plugin is not loaded"),
+        &["callback"]
     ).unwrap();
 
-    pub static ref UPLOAD_SLOTS_TOTAL: IntCounterVec = IntCounterVec::new(
-        Opts::new("upload_slots_total", "Status of uploaded slots"),
-        &["status"]
+    pub static ref PLUGIN_PANICS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("plugin_panics_total", "Panics caught in Geyser callbacks"),
+        &["callback"]
     ).unwrap();
 
-    pub static ref UPLOAD_TRANSACTIONS_TOTAL: IntCounterVec = IntCounterVec::new(
-        Opts::new("upload_transactions_total", "Status of uploaded transactions"),
-        &["status"]
-    ).unwrap();
+    /// Reference point of delivery opaque timestamps.
+    static ref PROCESS_START: Instant = Instant::now();
 
-    static ref KAFKA_STATS: GaugeVec = GaugeVec::new(
-        Opts::new("kafka_stats", "librdkafka metrics"),
-        &["broker", "metric"]
+    pub static ref WALL_CLOCK_SKEW_SECONDS: Gauge = Gauge::new(
+        "wall_clock_skew_seconds", "System clock minus the wall clock derived from the monotonic clock at the last re-anchoring"
     ).unwrap();
 }
 
+/// State served by the HTTP endpoints next to `/metrics`.
+#[derive(Debug, Default)]
+pub struct HttpState {
+    /// Filters in config order.
+    pub filters: Vec<Filter>,
+    pub filter_rates: Arc<FilterRates>,
+    pub filter_stats: Arc<FilterStats>,
+    pub slot_progress: Arc<SlotProgress>,
+    pub pauses: Arc<TopicPauses>,
+    /// Statistics of the Kafka producer, if there is one.
+    pub kafka_stats: Option<Arc<KafkaStats>>,
+    /// Serve `POST /admin/pause`, `/admin/resume`, `/admin/activate` and `/admin/standby`.
+    pub admin_endpoints: bool,
+    pub standby: Arc<Standby>,
+    /// Summaries of published events, streamed on `/debug/tail`.
+    pub tail: Arc<Tail>,
+    /// Dictionaries of `payload_compression` `zstd-dict`.
+    pub zstd_dicts: Option<Arc<ZstdDictionaries>>,
+    /// Buffers and caches sampled into `plugin_memory_bytes`.
+    pub footprints: Arc<MemoryFootprints>,
+    /// Metrics of the instance, served on `/metrics` and `/metrics.json`.
+    pub metrics: Arc<InstanceMetrics>,
+}
+
+/// Registry adding `labels` to every metric it gathers.
+pub fn new_registry(labels: HashMap<String, String>) -> Registry {
+    Registry::new_custom(None, Some(labels)).expect("registry can't be created")
+}
+
+/// Register the process wide metrics with `REGISTRY`, once per process.
+pub fn register_metrics() {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| {
+        register_process_metrics(&REGISTRY);
+
+        for (key, value) in &[
+            ("version", VERSION_INFO.version),
+            ("solana", VERSION_INFO.solana),
+            ("geyser_interface", VERSION_INFO.geyser_interface),
+            ("git", VERSION_INFO.git),
+            ("rustc", VERSION_INFO.rustc),
+            ("buildts", VERSION_INFO.buildts),
+        ] {
+            VERSION.with_label_values(&[key, value]).inc()
+        }
+    });
+}
+
+/// Register the process wide metrics with `registry`.
+pub fn register_process_metrics(registry: &Registry) {
+    macro_rules! register {
+        ($collector:ident) => {
+            registry
+                .register(Box::new($collector.clone()))
+                .expect("collector can't be registered");
+        };
+    }
+    register!(VERSION);
+    register!(NOTIFICATIONS_UNLOADED_TOTAL);
+    register!(PLUGIN_PANICS_TOTAL);
+    register!(WALL_CLOCK_SKEW_SECONDS);
+}
+
+/// Limits of the HTTP endpoints, so misbehaving scrapers can't pile up connections in the
+/// validator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HttpLimits {
+    /// Connections served at once, further ones get a 503.
+    pub max_connections: usize,
+    /// Time for a client to send its request headers.
+    pub header_timeout: Duration,
+    /// Time to serve a request once its headers are read, including writing the response.
+    pub response_timeout: Duration,
+}
+
+impl Default for HttpLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: 16,
+            header_timeout: Duration::from_secs(5),
+            response_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct PrometheusService {
     runtime: Runtime,
+    tcp_addrs: Vec<SocketAddr>,
+    unix_paths: Vec<PathBuf>,
 }
 
 impl PrometheusService {
-    pub fn new(address: SocketAddr) -> IoResult<Self> {
-        static REGISTER: Once = Once::new();
-        REGISTER.call_once(|| {
-            macro_rules! register {
-                ($collector:ident) => {
-                    REGISTRY
-                        .register(Box::new($collector.clone()))
-                        .expect("collector can't be registered");
-                };
+    /// Serve on every one of `addresses`. If some can't be bound, this fails if
+    /// `require_all_binds` is set and otherwise serves on the rest, failing only if none could.
+    pub fn new(
+        addresses: &[PrometheusAddress],
+        require_all_binds: bool,
+        limits: HttpLimits,
+        state: Arc<HttpState>,
+    ) -> IoResult<Self> {
+        register_metrics();
+
+        let threads = state.metrics.plugin_threads.with_label_values(&["runtime"]);
+        let stopped = threads.clone();
+        let runtime = RuntimeBuilder::new_multi_thread()
+            .enable_all()
+            .on_thread_start(move || threads.inc())
+            .on_thread_stop(move || stopped.dec())
+            .build()?;
+        runtime.spawn(refresh_rates(Arc::clone(&state.filter_rates)));
+        runtime.spawn(sample_footprints(
+            Arc::clone(&state.footprints),
+            Arc::clone(&state.metrics),
+        ));
+        let mut service = PrometheusService {
+            runtime,
+            tcp_addrs: Vec::new(),
+            unix_paths: Vec::new(),
+        };
+        let mut last_error = None;
+        for address in addresses {
+            let listener = {
+                let _guard = service.runtime.enter();
+                service.bind(address)
+            };
+            match listener {
+                Ok(listener) => {
+                    service
+                        .runtime
+                        .spawn(serve(listener, limits, Arc::clone(&state)));
+                }
+                Err(error) if require_all_binds => {
+                    service.shutdown();
+                    return Err(error);
+                }
+                Err(error) => {
+                    warn!("failed to bind prometheus to {address:?}, skipping it: {error}");
+                    last_error = Some(error);
+                }
             }
-            register!(VERSION);
-            register!(UPLOAD_ACCOUNTS_TOTAL);
-            register!(UPLOAD_SLOTS_TOTAL);
-            register!(UPLOAD_TRANSACTIONS_TOTAL);
-            register!(KAFKA_STATS);
-
-            for (key, value) in &[
-                ("version", VERSION_INFO.version),
-                ("solana", VERSION_INFO.solana),
-                ("git", VERSION_INFO.git),
-                ("rustc", VERSION_INFO.rustc),
-                ("buildts", VERSION_INFO.buildts),
-            ] {
-                VERSION.with_label_values(&[key, value]).inc()
+        }
+        match last_error {
+            Some(error) if service.tcp_addrs.is_empty() && service.unix_paths.is_empty() => {
+                service.shutdown();
+                Err(error)
             }
-        });
+            _ => Ok(service),
+        }
+    }
 
-        let runtime = Runtime::new()?;
-        runtime.spawn(async move {
-            let make_service = make_service_fn(move |_: &AddrStream| async move {
-                Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| async move {
-                    let response = match req.uri().path() {
-                        "/metrics" => metrics_handler(),
-                        _ => not_found_handler(),
-                    };
-                    Ok::<_, hyper::Error>(response)
-                }))
-            });
-            if let Err(error) = Server::bind(&address).serve(make_service).await {
-                error!("prometheus service failed: {}", error);
+    fn bind(&mut self, address: &PrometheusAddress) -> IoResult<Listener> {
+        match address {
+            PrometheusAddress::Tcp(address) => {
+                let listener = StdTcpListener::bind(address)?;
+                listener.set_nonblocking(true)?;
+                self.tcp_addrs.push(listener.local_addr()?);
+                Ok(Listener::Tcp(TcpListener::from_std(listener)?))
             }
-        });
-        Ok(PrometheusService { runtime })
+            PrometheusAddress::Unix(path) => {
+                remove_stale_socket(path)?;
+                let listener = UnixListener::bind(path)?;
+                if let Err(error) = fs::set_permissions(path, Permissions::from_mode(0o660)) {
+                    let _ = fs::remove_file(path);
+                    return Err(error);
+                }
+                self.unix_paths.push(path.clone());
+                Ok(Listener::Unix(listener))
+            }
+        }
+    }
+
+    /// TCP addresses served on, with the ports picked for port 0.
+    pub fn tcp_addrs(&self) -> &[SocketAddr] {
+        &self.tcp_addrs
     }
 
     pub fn shutdown(self) {
         self.runtime.shutdown_timeout(Duration::from_secs(10));
+        for path in self.unix_paths {
+            if let Err(error) = fs::remove_file(&path) {
+                warn!("failed to remove prometheus socket {path:?}: {error}");
+            }
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+async fn serve(listener: Listener, limits: HttpLimits, state: Arc<HttpState>) {
+    let connections = Arc::new(Semaphore::new(limits.max_connections));
+    loop {
+        let accepted = match &listener {
+            Listener::Tcp(listener) => listener.accept().await.map(|(stream, _)| {
+                serve_connection(stream, limits, &connections, &state);
+            }),
+            Listener::Unix(listener) => listener.accept().await.map(|(stream, _)| {
+                serve_connection(stream, limits, &connections, &state);
+            }),
+        };
+        if let Err(error) = accepted {
+            error!("prometheus service failed to accept: {}", error);
+        }
     }
 }
 
-fn metrics_handler() -> Response<Body> {
+/// Serve a single request on `stream`, or a 503 if `max_connections` are already served.
+fn serve_connection<S>(
+    stream: S,
+    limits: HttpLimits,
+    connections: &Arc<Semaphore>,
+    state: &Arc<HttpState>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let permit = Arc::clone(connections).try_acquire_owned().ok();
+    if permit.is_none() {
+        state.metrics.http_connections_rejected_total.inc();
+    }
+    let state = Arc::clone(state);
+    tokio::spawn(async move {
+        let mut http = Http::new();
+        http.http1_keep_alive(false)
+            .http1_header_read_timeout(limits.header_timeout);
+        let connection = async {
+            match permit {
+                Some(_permit) => {
+                    let service = service(state, limits.response_timeout);
+                    http.serve_connection(stream, service).await
+                }
+                None => {
+                    let unavailable = service_fn(|_| async {
+                        Ok::<_, hyper::Error>(
+                            Response::builder()
+                                .status(StatusCode::SERVICE_UNAVAILABLE)
+                                .body(Body::from("too many connections"))
+                                .unwrap(),
+                        )
+                    });
+                    http.serve_connection(stream, unavailable).await
+                }
+            }
+        };
+        // Without keep-alive a connection serves one request, so this bounds reading its
+        // headers and writing the response.
+        let timeout = limits.header_timeout + limits.response_timeout;
+        match tokio::time::timeout(timeout, connection).await {
+            Ok(Ok(())) => {}
+            Ok(Err(error)) => debug!("prometheus connection failed: {}", error),
+            Err(_) => debug!("prometheus connection timed out"),
+        }
+    });
+}
+
+fn service(
+    state: Arc<HttpState>,
+    response_timeout: Duration,
+) -> impl Service<
+    Request<Body>,
+    Response = Response<Body>,
+    Error = hyper::Error,
+    Future = impl Future<Output = Result<Response<Body>, hyper::Error>> + Send,
+> + Send {
+    service_fn(move |req: Request<Body>| {
+        let state = Arc::clone(&state);
+        async move {
+            let response = if req.method() == Method::POST {
+                admin_route(req.uri().path(), req.uri().query(), &state)
+            } else if req.uri().path() == "/debug/explain" {
+                explain_route(req.uri().query(), &state)
+            } else if req.uri().path() == "/debug/tail" {
+                tail_route(req.uri().query(), &state, response_timeout)
+            } else {
+                route(req.uri().path(), &state)
+            };
+            Ok::<_, hyper::Error>(response)
+        }
+    })
+}
+
+/// Refresh the per-filter rate gauges every second.
+async fn refresh_rates(rates: Arc<FilterRates>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        rates.refresh_gauges();
+    }
+}
+
+/// Remove a socket left behind by a previous run, refusing to touch other files.
+fn remove_stale_socket(path: &Path) -> IoResult<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path),
+        Ok(_) => Err(IoError::new(
+            ErrorKind::AlreadyExists,
+            format!("{path:?} exists and is not a socket"),
+        )),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+/// Measures elapsed time into a histogram, without reading the clock when disabled.
+pub struct Timer {
+    start: Option<Instant>,
+}
+
+impl Timer {
+    pub fn start(enabled: bool) -> Self {
+        Self {
+            start: enabled.then(Instant::now),
+        }
+    }
+
+    pub fn observe(self, histogram: &HistogramVec, event: &str) {
+        if let Some(start) = self.start {
+            histogram
+                .with_label_values(&[event])
+                .observe(start.elapsed().as_secs_f64());
+        }
+    }
+}
+
+/// Accumulates the time until dropped into `callback_seconds_total`, like `Timer` without
+/// reading the clock when disabled.
+pub struct CallbackTimer<'a> {
+    metrics: &'a InstanceMetrics,
+    callback: &'static str,
+    timer: Timer,
+}
+
+impl<'a> CallbackTimer<'a> {
+    pub fn start(metrics: &'a InstanceMetrics, enabled: bool, callback: &'static str) -> Self {
+        Self {
+            metrics,
+            callback,
+            timer: Timer::start(enabled),
+        }
+    }
+}
+
+impl Drop for CallbackTimer<'_> {
+    fn drop(&mut self) {
+        if let Some(start) = self.timer.start {
+            self.metrics
+                .callback_seconds_total
+                .with_label_values(&[self.callback])
+                .inc_by(start.elapsed().as_secs_f64());
+        }
+    }
+}
+
+fn route(path: &str, state: &HttpState) -> Response<Body> {
+    if let Some(owner) = path.strip_prefix("/debug/zstd-dict/") {
+        return zstd_dict_route(owner, state);
+    }
+    match path {
+        "/metrics" => metrics_handler(state),
+        "/metrics.json" => {
+            json_handler(metrics_json::to_json(&state.metrics.registry().gather()).to_string())
+        }
+        "/version" => json_handler(serde_json::to_string(&VERSION_INFO).unwrap()),
+        "/checkpoint" => json_handler(state.slot_progress.to_json().to_string()),
+        "/debug/filters" => json_handler(debug_filters(state).to_string()),
+        "/debug/filter-stats" => json_handler(state.filter_stats.to_json().to_string()),
+        "/debug/pauses" => json_handler(state.pauses.to_json().to_string()),
+        "/debug/config" => json_handler(debug_config(state).to_string()),
+        "/debug/kafka-stats" => match state.kafka_stats.as_ref().and_then(|stats| stats.raw()) {
+            Some(raw) => json_handler(raw),
+            None => not_found_handler(),
+        },
+        "/debug/advisor" => match state
+            .kafka_stats
+            .as_ref()
+            .and_then(|stats| stats.advisory())
+        {
+            Some(advisory) => json_handler(serde_json::to_string(&advisory).unwrap()),
+            None => not_found_handler(),
+        },
+        "/debug/zstd-dict" => match &state.zstd_dicts {
+            Some(dictionaries) => json_handler(dictionaries.to_json().to_string()),
+            None => not_found_handler(),
+        },
+        "/schema/descriptor" => Response::builder()
+            .header("content-type", "application/octet-stream")
+            .body(Body::from(file_descriptor_set()))
+            .unwrap(),
+        "/schema/proto" => Response::builder()
+            .header("content-type", "text/plain; charset=utf-8")
+            .body(Body::from(event_proto()))
+            .unwrap(),
+        _ => not_found_handler(),
+    }
+}
+
+/// The trained zstd dictionary of accounts of `owner`.
+fn zstd_dict_route(owner: &str, state: &HttpState) -> Response<Body> {
+    let dictionary = state.zstd_dicts.as_ref().and_then(|dictionaries| {
+        let owner = Pubkey::from_str(owner).ok()?;
+        dictionaries.dictionary(owner.as_ref())
+    });
+    match dictionary {
+        Some(dictionary) => Response::builder()
+            .header("content-type", "application/octet-stream")
+            .header(ZSTD_DICT_ID_HEADER, dictionary.id)
+            .body(Body::from(dictionary.bytes.clone()))
+            .unwrap(),
+        None => not_found_handler(),
+    }
+}
+
+/// Runtime state that can differ from the config file.
+fn debug_config(state: &HttpState) -> Value {
+    let mut config = state.standby.to_json();
+    config["admin_endpoints"] = state.admin_endpoints.into();
+    config
+}
+
+/// Pause or resume the topic in the `topic` query parameter, or enter or leave standby, if
+/// `admin_endpoints` is set.
+fn admin_route(path: &str, query: Option<&str>, state: &HttpState) -> Response<Body> {
+    let pause = match path {
+        "/admin/pause" if state.admin_endpoints => true,
+        "/admin/resume" if state.admin_endpoints => false,
+        "/admin/activate" | "/admin/standby" if state.admin_endpoints => {
+            state.standby.set(path == "/admin/standby");
+            return json_handler(debug_config(state).to_string());
+        }
+        _ => return not_found_handler(),
+    };
+    let Some(topic) = query_param(query, "topic") else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from("missing topic"))
+            .unwrap();
+    };
+    let known = if pause {
+        state.pauses.pause(topic)
+    } else {
+        state.pauses.resume(topic)
+    };
+    if !known {
+        return not_found_handler();
+    }
+    info!("{} topic {topic}", if pause { "Paused" } else { "Resumed" });
+    json_handler(state.pauses.to_json().to_string())
+}
+
+/// Whether each filter would publish live updates of the account in the `pubkey` query
+/// parameter owned by `owner`, and why.
+fn explain_route(query: Option<&str>, state: &HttpState) -> Response<Body> {
+    let pubkey = |name| query_param(query, name).and_then(|key| Pubkey::from_str(key).ok());
+    let (Some(pubkey), Some(owner)) = (pubkey("pubkey"), pubkey("owner")) else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from("pubkey and owner must be base58 addresses"))
+            .unwrap();
+    };
+    let explained: Vec<Value> = state
+        .filters
+        .iter()
+        .map(|filter| filter.explain(&pubkey, &owner))
+        .collect();
+    json_handler(Value::from(explained).to_string())
+}
+
+/// Stream the summaries of the next `limit` events published to the `topic` query parameter as
+/// JSON lines, ending early once `timeout` elapses.
+fn tail_route(query: Option<&str>, state: &HttpState, timeout: Duration) -> Response<Body> {
+    let limit = match query_param(query, "limit").map(str::parse::<usize>) {
+        None => DEFAULT_TAIL_LIMIT,
+        Some(Ok(limit)) if limit <= MAX_TAIL_LIMIT => limit,
+        Some(_) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!(
+                    "limit must be at most {MAX_TAIL_LIMIT}"
+                )))
+                .unwrap()
+        }
+    };
+    let Some(topic) = query_param(query, "topic") else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from("missing topic"))
+            .unwrap();
+    };
+    let mut subscription = state.tail.subscribe(topic);
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let stream = async {
+            for _ in 0..limit {
+                let Some(summary) = subscription.next().await else {
+                    break;
+                };
+                if sender
+                    .send_data(summary.to_json_line().into())
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        };
+        let _ = tokio::time::timeout(timeout, stream).await;
+    });
+    Response::builder()
+        .header("content-type", "application/x-ndjson")
+        .body(body)
+        .unwrap()
+}
+
+fn query_param<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query?.split('&').find_map(|param| {
+        param
+            .strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix('='))
+    })
+}
+
+/// Canonical settings of the filters with their names and current rates merged in.
+fn debug_filters(state: &HttpState) -> Value {
+    state
+        .filters
+        .iter()
+        .enumerate()
+        .map(|(index, filter)| {
+            let mut json = filter.canonical();
+            json["name"] = filter.name().into();
+            if let (Some(json), Value::Object(rates)) =
+                (json.as_object_mut(), state.filter_rates.to_json(index))
+            {
+                json.extend(rates);
+            }
+            json
+        })
+        .collect()
+}
+
+fn metrics_handler(state: &HttpState) -> Response<Body> {
     let metrics = TextEncoder::new()
-        .encode_to_string(&REGISTRY.gather())
+        .encode_to_string(&state.metrics.registry().gather())
         .unwrap_or_else(|error| {
             error!("could not encode custom metrics: {}", error);
             String::new()
         });
-    Response::builder().body(Body::from(metrics)).unwrap()
+    Response::builder()
+        .header("content-type", TEXT_FORMAT)
+        .body(Body::from(metrics))
+        .unwrap()
+}
+
+fn json_handler(body: String) -> Response<Body> {
+    Response::builder()
+        .header("content-type", "application/json")
+        .body(Body::from(body))
+        .unwrap()
 }
 
 fn not_found_handler() -> Response<Body> {
@@ -118,67 +653,716 @@ fn not_found_handler() -> Response<Body> {
         .unwrap()
 }
 
-#[derive(Debug, Default, Clone, Copy)]
-pub struct StatsThreadedProducerContext;
+/// Timestamp of a message enqueued now: nanoseconds since the process started.
+pub fn enqueue_timestamp() -> usize {
+    PROCESS_START.elapsed().as_nanos() as usize
+}
 
-impl ClientContext for StatsThreadedProducerContext {
-    fn stats(&self, statistics: Statistics) {
-        for (name, broker) in statistics.brokers {
-            macro_rules! set_value {
-                ($name:expr, $value:expr) => {
-                    KAFKA_STATS
-                        .with_label_values(&[&name, $name])
-                        .set($value as f64);
-                };
-            }
+/// Delivery opaque of a produced message.
+#[derive(Debug)]
+pub struct Enqueued {
+    /// `enqueue_timestamp` of the message.
+    pub at: usize,
+    /// Slot the message is counted for by `rooted_barrier`.
+    pub slot: Option<u64>,
+}
 
-            set_value!("outbuf_cnt", broker.outbuf_cnt);
-            set_value!("outbuf_msg_cnt", broker.outbuf_msg_cnt);
-            set_value!("waitresp_cnt", broker.waitresp_cnt);
-            set_value!("waitresp_msg_cnt", broker.waitresp_msg_cnt);
-            set_value!("tx", broker.tx);
-            set_value!("txerrs", broker.txerrs);
-            set_value!("txretries", broker.txretries);
-            set_value!("req_timeouts", broker.req_timeouts);
-
-            if let Some(window) = broker.int_latency {
-                set_value!("int_latency.min", window.min);
-                set_value!("int_latency.max", window.max);
-                set_value!("int_latency.avg", window.avg);
-                set_value!("int_latency.sum", window.sum);
-                set_value!("int_latency.cnt", window.cnt);
-                set_value!("int_latency.stddev", window.stddev);
-                set_value!("int_latency.hdrsize", window.hdrsize);
-                set_value!("int_latency.p50", window.p50);
-                set_value!("int_latency.p75", window.p75);
-                set_value!("int_latency.p90", window.p90);
-                set_value!("int_latency.p95", window.p95);
-                set_value!("int_latency.p99", window.p99);
-                set_value!("int_latency.p99_99", window.p99_99);
-                set_value!("int_latency.outofrange", window.outofrange);
-            }
+#[derive(Debug, Clone)]
+pub struct StatsThreadedProducerContext {
+    /// Deliveries slower than this are counted per topic.
+    delivery_latency_slo: Option<Duration>,
+    /// Shared by the producers re-created on credential changes.
+    stats: Arc<KafkaStats>,
+    /// Poll thread of the producer owning the context, which lives as long as the producer.
+    _poll_thread: Arc<ThreadGuard>,
+}
 
-            if let Some(window) = broker.outbuf_latency {
-                set_value!("outbuf_latency.min", window.min);
-                set_value!("outbuf_latency.max", window.max);
-                set_value!("outbuf_latency.avg", window.avg);
-                set_value!("outbuf_latency.sum", window.sum);
-                set_value!("outbuf_latency.cnt", window.cnt);
-                set_value!("outbuf_latency.stddev", window.stddev);
-                set_value!("outbuf_latency.hdrsize", window.hdrsize);
-                set_value!("outbuf_latency.p50", window.p50);
-                set_value!("outbuf_latency.p75", window.p75);
-                set_value!("outbuf_latency.p90", window.p90);
-                set_value!("outbuf_latency.p95", window.p95);
-                set_value!("outbuf_latency.p99", window.p99);
-                set_value!("outbuf_latency.p99_99", window.p99_99);
-                set_value!("outbuf_latency.outofrange", window.outofrange);
+impl Default for StatsThreadedProducerContext {
+    fn default() -> Self {
+        Self::new(None, Arc::default())
+    }
+}
+
+impl StatsThreadedProducerContext {
+    pub fn new(delivery_latency_slo: Option<Duration>, stats: Arc<KafkaStats>) -> Self {
+        let poll_thread = ThreadGuard::new(stats.metrics(), "producer_poll");
+        Self {
+            delivery_latency_slo,
+            stats,
+            _poll_thread: Arc::new(poll_thread),
+        }
+    }
+
+    pub fn kafka_stats(&self) -> &Arc<KafkaStats> {
+        &self.stats
+    }
+
+    /// Record the delivery to `topic` of a message enqueued at `enqueued`, both timestamps
+    /// from `enqueue_timestamp`.
+    fn record_delivery(
+        &self,
+        topic: &str,
+        error: Option<&KafkaError>,
+        enqueued: usize,
+        delivered: usize,
+    ) {
+        let metrics = self.stats.metrics();
+        match error {
+            Some(error) => {
+                metrics.delivery_errors_total.inc();
+                if error.rdkafka_error_code() == Some(RDKafkaErrorCode::MessageTimedOut) {
+                    metrics
+                        .kafka_delivery_timeouts_total
+                        .with_label_values(&[topic])
+                        .inc();
+                }
+            }
+            None => {
+                let latency = Duration::from_nanos(delivered.saturating_sub(enqueued) as u64);
+                metrics
+                    .kafka_delivery_latency_seconds
+                    .with_label_values(&[topic])
+                    .observe(latency.as_secs_f64());
+                if self.delivery_latency_slo.is_some_and(|slo| latency > slo) {
+                    metrics
+                        .kafka_delivery_slo_exceeded_total
+                        .with_label_values(&[topic])
+                        .inc();
+                }
             }
         }
     }
 }
 
+impl ClientContext for StatsThreadedProducerContext {
+    fn stats_raw(&self, statistics: &[u8]) {
+        self.stats.export_raw(statistics, Instant::now());
+    }
+}
+
 impl ProducerContext for StatsThreadedProducerContext {
-    type DeliveryOpaque = ();
-    fn delivery(&self, _: &DeliveryResult<'_>, _: Self::DeliveryOpaque) {}
+    type DeliveryOpaque = Box<Enqueued>;
+    fn delivery(&self, result: &DeliveryResult<'_>, enqueued: Self::DeliveryOpaque) {
+        let delivered = enqueue_timestamp();
+        let (message, error) = match result {
+            Ok(message) => (message, None),
+            Err((error, message)) => (message, Some(error)),
+        };
+        self.stats.outstanding().delivered(
+            message.key().map_or(0, <[u8]>::len) + message.payload().map_or(0, <[u8]>::len),
+        );
+        if let Some(slot) = enqueued.slot {
+            self.stats.slot_deliveries().delivered(slot);
+        }
+        self.record_delivery(message.topic(), error, enqueued.at, delivered);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{identity::Identity, Config, ConfigFilter, SlotStatus},
+        serde_json::json,
+        std::{
+            collections::HashSet,
+            io::{Read, Write},
+            os::unix::net::{UnixListener as StdUnixListener, UnixStream},
+        },
+    };
+
+    #[test]
+    fn test_timer() {
+        let metrics = InstanceMetrics::default();
+        let count =
+            |histogram: &HistogramVec| histogram.with_label_values(&["test"]).get_sample_count();
+
+        Timer::start(true).observe(&metrics.event_build_seconds, "test");
+        assert_eq!(count(&metrics.event_build_seconds), 1);
+        assert_eq!(count(&metrics.event_send_seconds), 0);
+
+        let timer = Timer::start(false);
+        assert!(timer.start.is_none());
+        timer.observe(&metrics.event_send_seconds, "test");
+        assert_eq!(count(&metrics.event_send_seconds), 0);
+    }
+
+    #[test]
+    fn test_const_labels() {
+        let identity = Identity {
+            cluster_name: "testnet".to_owned(),
+            validator_identity: Some(Pubkey::new_unique()),
+        };
+        let registry = new_registry(identity.const_labels());
+        let sends = IntCounterVec::new(Opts::new("sends_total", "Sends"), &["topic"]).unwrap();
+        registry.register(Box::new(sends.clone())).unwrap();
+        sends.with_label_values(&["accounts"]).inc();
+
+        let families = registry.gather();
+        let mut labels: Vec<_> = families[0].get_metric()[0]
+            .get_label()
+            .iter()
+            .map(|label| (label.name(), label.value()))
+            .collect();
+        labels.sort();
+        let identity = identity.validator_identity.unwrap().to_string();
+        assert_eq!(
+            labels,
+            [
+                ("cluster", "testnet"),
+                ("topic", "accounts"),
+                ("validator_identity", identity.as_str()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_record_delivery() {
+        let context =
+            StatsThreadedProducerContext::new(Some(Duration::from_millis(100)), Arc::default());
+        let metrics = Arc::clone(context.kafka_stats().metrics());
+        let latency = || {
+            metrics
+                .kafka_delivery_latency_seconds
+                .with_label_values(&["delivery-test"])
+        };
+        let slo_exceeded = || {
+            metrics
+                .kafka_delivery_slo_exceeded_total
+                .with_label_values(&["delivery-test"])
+                .get()
+        };
+        let timeouts = || {
+            metrics
+                .kafka_delivery_timeouts_total
+                .with_label_values(&["delivery-test"])
+                .get()
+        };
+        let ms = |ms: usize| ms * 1_000_000;
+
+        // Enqueued at 1s, acknowledged 20ms and 250ms later.
+        context.record_delivery("delivery-test", None, ms(1_000), ms(1_020));
+        context.record_delivery("delivery-test", None, ms(1_000), ms(1_250));
+        assert_eq!(latency().get_sample_count(), 2);
+        assert!((latency().get_sample_sum() - 0.27).abs() < 1e-9);
+        assert_eq!(slo_exceeded(), 1);
+
+        // Failures don't count towards latency, timeouts are told apart.
+        let timed_out = KafkaError::MessageProduction(RDKafkaErrorCode::MessageTimedOut);
+        let refused = KafkaError::MessageProduction(RDKafkaErrorCode::TopicAuthorizationFailed);
+        context.record_delivery("delivery-test", Some(&timed_out), ms(0), ms(30_000));
+        context.record_delivery("delivery-test", Some(&refused), ms(0), ms(1));
+        assert_eq!(latency().get_sample_count(), 2);
+        assert_eq!(slo_exceeded(), 1);
+        assert_eq!(timeouts(), 1);
+        assert_eq!(metrics.delivery_errors_total.get(), 2);
+
+        // Without an SLO nothing is exceeded, clock skew clamps to zero.
+        let context = StatsThreadedProducerContext::new(None, Arc::clone(context.kafka_stats()));
+        context.record_delivery("delivery-test", None, ms(5_000), ms(10_000));
+        context.record_delivery("delivery-test", None, ms(2), ms(1));
+        assert_eq!(latency().get_sample_count(), 4);
+        assert_eq!(slo_exceeded(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_endpoint() {
+        let state = HttpState::default();
+        state.slot_progress.observe(12, SlotStatus::Processed);
+        state.slot_progress.observe(10, SlotStatus::Rooted);
+        state.slot_progress.observe(9, SlotStatus::Rooted);
+
+        let response = route("/checkpoint", &state);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["rooted_slot"], 10);
+        assert_eq!(json["processed_slot"], 12);
+        assert!(json["updated_at"].as_u64().unwrap() > 0);
+
+        assert_eq!(route("/unknown", &state).status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_schema_endpoints() {
+        let state = HttpState::default();
+        let response = route("/schema/descriptor", &state);
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, file_descriptor_set());
+
+        let response = route("/schema/proto", &state);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(std::str::from_utf8(&body)
+            .unwrap()
+            .contains("message UpdateAccountEvent"));
+    }
+
+    #[tokio::test]
+    async fn test_zstd_dict_endpoints() {
+        assert_eq!(
+            route("/debug/zstd-dict", &HttpState::default()).status(),
+            StatusCode::NOT_FOUND
+        );
+        let dictionaries = Arc::new(ZstdDictionaries::new(100, Arc::default()));
+        let owner = Pubkey::new_unique();
+        for i in 0..100u32 {
+            let payload = [&b"layout"[..], &i.to_le_bytes(), &[0; 64]].concat();
+            dictionaries.compress(owner.as_ref(), &payload);
+        }
+        let state = HttpState {
+            zstd_dicts: Some(Arc::clone(&dictionaries)),
+            ..Default::default()
+        };
+        let dictionary = (0..500)
+            .find_map(|_| {
+                std::thread::sleep(Duration::from_millis(10));
+                dictionaries.dictionary(owner.as_ref())
+            })
+            .unwrap();
+
+        let response = route(&format!("/debug/zstd-dict/{owner}"), &state);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[ZSTD_DICT_ID_HEADER],
+            dictionary.id.to_string()
+        );
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, dictionary.bytes);
+
+        let response = route("/debug/zstd-dict", &state);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let listed: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            listed,
+            json!([{"owner": owner.to_string(), "id": dictionary.id}])
+        );
+
+        let unknown = Pubkey::new_unique();
+        for path in [
+            format!("/debug/zstd-dict/{unknown}"),
+            "/debug/zstd-dict/x".to_owned(),
+        ] {
+            assert_eq!(route(&path, &state).status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_debug_explain() {
+        let account = Pubkey::new_unique();
+        let state = HttpState {
+            filters: vec![Filter::new(&ConfigFilter {
+                name: "explained".to_owned(),
+                update_account_topic: Some("accounts".to_owned()),
+                account_filters: vec![account.to_string()],
+                program_filters: vec![Pubkey::new_unique().to_string()],
+                ..Default::default()
+            })],
+            ..Default::default()
+        };
+        let explain = |query: &str| explain_route(Some(query), &state);
+
+        let owner = Pubkey::new_unique();
+        let response = explain(&format!("pubkey={account}&owner={owner}"));
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json[0]["decision"], "account");
+        assert_eq!(json[0]["published"], true);
+
+        let response = explain(&format!("owner={owner}&pubkey={owner}"));
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json[0]["decision"], "no_match");
+
+        for query in [format!("pubkey={account}"), "pubkey=x&owner=y".to_owned()] {
+            assert_eq!(explain(&query).status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_debug_filters() {
+        let filter = Filter::new(&ConfigFilter {
+            name: "0".to_owned(),
+            transaction_topic: Some("txs".to_owned()),
+            ..Default::default()
+        });
+        let mut expected = filter.canonical();
+        let state = HttpState {
+            filters: vec![filter],
+            filter_rates: Arc::new(FilterRates::new(vec!["0".to_owned()], Arc::default())),
+            ..Default::default()
+        };
+        state.filter_rates.record_transaction(0);
+
+        let response = route("/debug/filters", &state);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(expected["transaction_topic"], "txs");
+        expected["name"] = "0".into();
+        expected["account_rate_1m"] = 0.0.into();
+        expected["transaction_rate_1m"] = (1.0 / 60.0).into();
+        assert_eq!(json, json!([expected]));
+    }
+
+    #[tokio::test]
+    async fn test_admin_pause() {
+        let mut config = Config::default();
+        config.filters = vec![ConfigFilter {
+            transaction_topic: Some("admin-txs".to_owned()),
+            ..Default::default()
+        }];
+        let state = HttpState {
+            pauses: Arc::new(TopicPauses::new(&config, Arc::default())),
+            ..Default::default()
+        };
+        // Disabled unless `admin_endpoints` is set.
+        let response = admin_route("/admin/pause", Some("topic=admin-txs"), &state);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let state = HttpState {
+            admin_endpoints: true,
+            ..state
+        };
+        let response = admin_route("/admin/pause", Some("topic=admin-txs"), &state);
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = route("/debug/pauses", &state);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["topics"]["admin-txs"]["paused"], true);
+        assert_eq!(json["policy"], "drop");
+
+        let response = admin_route("/admin/resume", Some("x=1&topic=admin-txs"), &state);
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["topics"]["admin-txs"]["paused"], false);
+
+        let status = |path, query| admin_route(path, query, &state).status();
+        assert_eq!(status("/admin/pause", None), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            status("/admin/pause", Some("topic=unknown")),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status("/admin/other", Some("topic=admin-txs")),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_standby() {
+        let state = HttpState {
+            standby: Arc::new(Standby::new(true, Arc::default())),
+            ..Default::default()
+        };
+        assert_eq!(
+            admin_route("/admin/activate", None, &state).status(),
+            StatusCode::NOT_FOUND
+        );
+        assert!(state.standby.is_standby());
+
+        let state = HttpState {
+            admin_endpoints: true,
+            ..state
+        };
+        let config = |response: Response<Body>| async move {
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            serde_json::from_slice::<Value>(&body).unwrap()["standby"].clone()
+        };
+        assert_eq!(config(route("/debug/config", &state)).await, true);
+        assert_eq!(
+            config(admin_route("/admin/activate", None, &state)).await,
+            false
+        );
+        assert_eq!(config(route("/debug/config", &state)).await, false);
+        assert_eq!(
+            config(admin_route("/admin/standby", None, &state)).await,
+            true
+        );
+        assert!(state.standby.is_standby());
+    }
+
+    #[tokio::test]
+    async fn test_debug_tail() {
+        let state = HttpState::default();
+        let tail = |query| tail_route(Some(query), &state, Duration::from_secs(5));
+        assert_eq!(tail("limit=2").status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            tail("topic=accounts&limit=20000").status(),
+            StatusCode::BAD_REQUEST
+        );
+
+        let response = tail("topic=accounts&limit=2");
+        assert_eq!(response.headers()["content-type"], "application/x-ndjson");
+        for slot in 1..=3 {
+            state.tail.record("account", slot, "accounts", &[1], 4);
+        }
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let slots: Vec<u64> = String::from_utf8(body.to_vec())
+            .unwrap()
+            .lines()
+            .map(|line| {
+                serde_json::from_str::<Value>(line).unwrap()["slot"]
+                    .as_u64()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(slots, [1, 2]);
+
+        // Quiet topics end with the timeout.
+        let response = tail_route(Some("topic=slots"), &state, Duration::from_millis(50));
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_debug_filter_stats() {
+        let account = Pubkey::new_from_array([3; 32]);
+        let mut filter = Filter::new(&ConfigFilter {
+            account_filters: vec![account.to_string()],
+            ..Default::default()
+        });
+        filter.name = "accounts".to_owned();
+        let state = HttpState {
+            filter_stats: Arc::new(FilterStats::new(&[filter], &HashSet::new(), Arc::default())),
+            ..Default::default()
+        };
+        state.filter_stats.record_account(0, account.as_ref());
+
+        let response = route("/debug/filter-stats", &state);
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json[0]["name"], "accounts");
+        assert_eq!(json[0]["program_filters"], json!({}));
+        let entry = &json[0]["account_filters"][account.to_string()];
+        assert_eq!(entry["matches"], 1);
+        assert!(entry["last_matched_ms"].as_u64().unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_version() {
+        let response = route("/version", &HttpState::default());
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["version"], VERSION_INFO.version);
+        assert_eq!(json["geyser_interface"], VERSION_INFO.geyser_interface);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_json() {
+        let state = HttpState::default();
+        state
+            .metrics
+            .upload_blocks_total
+            .with_label_values(&["success"])
+            .inc();
+        let response = route("/metrics.json", &state);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let success = json["upload_blocks_total"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|series| series["labels"]["status"] == "success")
+            .unwrap();
+        assert_eq!(success["value"].as_f64(), Some(1.0));
+    }
+
+    #[tokio::test]
+    async fn test_kafka_stats_endpoint() {
+        let mut state = HttpState::default();
+        let status = |state: &HttpState| route("/debug/kafka-stats", state).status();
+        assert_eq!(status(&state), StatusCode::NOT_FOUND);
+        // No statistics received yet.
+        state.kafka_stats = Some(Arc::default());
+        assert_eq!(status(&state), StatusCode::NOT_FOUND);
+
+        let stats = state.kafka_stats.as_ref().unwrap();
+        stats.export_raw(br#"{"name": "raw"}"#, Instant::now());
+        let response = route("/debug/kafka-stats", &state);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], br#"{"name": "raw"}"#);
+    }
+
+    #[test]
+    fn test_prometheus_address() {
+        assert_eq!(
+            "127.0.0.1:9090".parse::<PrometheusAddress>().unwrap(),
+            PrometheusAddress::Tcp("127.0.0.1:9090".parse().unwrap())
+        );
+        assert_eq!(
+            "unix:/run/kafka-plugin/metrics.sock"
+                .parse::<PrometheusAddress>()
+                .unwrap(),
+            PrometheusAddress::Unix("/run/kafka-plugin/metrics.sock".into())
+        );
+        assert!("localhost".parse::<PrometheusAddress>().is_err());
+    }
+
+    #[test]
+    fn test_unix_socket() {
+        let path =
+            std::env::temp_dir().join(format!("kafka-plugin-metrics-{}.sock", std::process::id()));
+        // A socket left behind by a previous run is replaced.
+        drop(StdUnixListener::bind(&path).unwrap());
+
+        let address = PrometheusAddress::Unix(path.clone());
+        let service = PrometheusService::new(
+            std::slice::from_ref(&address),
+            true,
+            HttpLimits::default(),
+            Arc::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o660
+        );
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.contains("content-type: text/plain; version=0.0.4"));
+        assert!(response.contains("plugin_loaded"));
+
+        service.shutdown();
+        assert!(!path.exists());
+
+        // Other files at the socket path are left alone.
+        fs::write(&path, b"").unwrap();
+        assert!(PrometheusService::new(
+            std::slice::from_ref(&address),
+            true,
+            HttpLimits::default(),
+            Arc::default()
+        )
+        .is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_multiple_addresses() {
+        let get = |address: &SocketAddr| {
+            let mut stream = std::net::TcpStream::connect(address).unwrap();
+            stream
+                .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let loopback = || PrometheusAddress::Tcp("127.0.0.1:0".parse().unwrap());
+
+        let service = PrometheusService::new(
+            &[loopback(), loopback()],
+            true,
+            HttpLimits::default(),
+            Arc::default(),
+        )
+        .unwrap();
+        let addresses = service.tcp_addrs().to_vec();
+        assert_eq!(addresses.len(), 2);
+        assert_ne!(addresses[0], addresses[1]);
+        for address in &addresses {
+            let response = get(address);
+            assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+            assert!(response.contains("plugin_loaded"));
+        }
+        service.shutdown();
+        for address in &addresses {
+            assert!(std::net::TcpStream::connect(address).is_err());
+        }
+
+        // An address that can't be bound fails loading, unless not all are required.
+        let occupied = StdTcpListener::bind("127.0.0.1:0").unwrap();
+        let addresses = [
+            loopback(),
+            PrometheusAddress::Tcp(occupied.local_addr().unwrap()),
+        ];
+        assert!(
+            PrometheusService::new(&addresses, true, HttpLimits::default(), Arc::default())
+                .is_err()
+        );
+        let service =
+            PrometheusService::new(&addresses, false, HttpLimits::default(), Arc::default())
+                .unwrap();
+        assert_eq!(service.tcp_addrs().len(), 1);
+        assert!(get(&service.tcp_addrs()[0]).starts_with("HTTP/1.1 200 OK"));
+        service.shutdown();
+        assert!(PrometheusService::new(
+            &addresses[1..],
+            false,
+            HttpLimits::default(),
+            Arc::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        let path =
+            std::env::temp_dir().join(format!("kafka-plugin-limit-{}.sock", std::process::id()));
+        let limits = HttpLimits {
+            max_connections: 2,
+            header_timeout: Duration::from_millis(300),
+            response_timeout: Duration::from_secs(5),
+        };
+        let state: Arc<HttpState> = Arc::default();
+        let service = PrometheusService::new(
+            &[PrometheusAddress::Unix(path.clone())],
+            true,
+            limits,
+            Arc::clone(&state),
+        )
+        .unwrap();
+        let rejected = || state.metrics.http_connections_rejected_total.get();
+        let get = || {
+            let mut stream = UnixStream::connect(&path).unwrap();
+            stream
+                .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        // Two requests still sending their headers hold both connections.
+        let mut in_flight: Vec<UnixStream> = (0..2)
+            .map(|_| {
+                let mut stream = UnixStream::connect(&path).unwrap();
+                stream.write_all(b"GET /metrics HTTP/1.1\r\n").unwrap();
+                stream
+            })
+            .collect();
+        let response = get();
+        assert!(
+            response.starts_with("HTTP/1.1 503 Service Unavailable"),
+            "{response}"
+        );
+        assert_eq!(rejected(), 1);
+
+        // The header timeout closes them, freeing their connections.
+        for stream in &mut in_flight {
+            let mut response = String::new();
+            let _ = stream.read_to_string(&mut response);
+            assert!(!response.contains("200 OK"), "{response}");
+        }
+        let served = (0..50).any(|_| {
+            std::thread::sleep(Duration::from_millis(20));
+            get().starts_with("HTTP/1.1 200 OK")
+        });
+        assert!(served);
+
+        service.shutdown();
+    }
 }
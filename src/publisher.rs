@@ -14,118 +14,2349 @@
 
 use {
     crate::{
-        message_wrapper::EventMessage::{self, Account, Slot, Transaction},
-        prom::{
-            StatsThreadedProducerContext, UPLOAD_ACCOUNTS_TOTAL, UPLOAD_SLOTS_TOTAL,
-            UPLOAD_TRANSACTIONS_TOTAL,
+        checkpoint::SlotProgress,
+        checksum::{payload_checksum, PAYLOAD_CHECKSUM_HEADER},
+        chunk::split_account,
+        deadline,
+        file_sink::FileSink,
+        identity::Identity,
+        instance_metrics::InstanceMetrics,
+        kafka_stats::KafkaStats,
+        message_wrapper::EventMessage::{self, Account, Block, Checkpoint, Slot, Transaction},
+        ordering::{order_stamp, OrderingChecker},
+        partition::{
+            self, explicit_partition, sticky_partition, KafkaPartitionSource, PartitionCounts,
+            PartitionRefresher,
         },
-        Config, MessageWrapper, SlotStatusEvent, TransactionEvent, UpdateAccountEvent,
+        pause::TopicPauses,
+        prom::{enqueue_timestamp, Enqueued, Timer},
+        resources::MemoryFootprints,
+        rooted_barrier::{barrier_slot, take_barrier_slot, RootedBarrier, SlotDeliveries},
+        rooted_gate::RootedGate,
+        rotation::CredentialRefresher,
+        sequence::Sequences,
+        slot_budget::SlotBudget,
+        standby::Standby,
+        tail::Tail,
+        throttle::ThrottlePacer,
+        wall_clock::unix_millis,
+        zstd_dict::{ZstdDictionaries, ZSTD_DICT_ID_HEADER},
+        AltResolutionFailureEvent, BackpressurePolicy, BlockEvent, CheckpointEvent, Config,
+        KafkaProducer, MessageWrapper, Partitioning, PayloadCompression, ProducerRegistration,
+        ProgramActivityEvent, SchemaCompat, SerializationFailurePolicy, SlotContentionEvent,
+        SlotStatus, SlotStatusEvent, TransactionEvent, TransactionIndexEvent, TransactionKeySource,
+        UpdateAccountEvent,
     },
+    arc_swap::ArcSwap,
+    log::{debug, error, info, warn},
     prost::Message,
     rdkafka::{
-        error::KafkaError,
-        producer::{BaseRecord, Producer, ThreadedProducer},
+        error::{KafkaError, RDKafkaErrorCode},
+        message::{Header, OwnedHeaders},
+        producer::{BaseRecord, Producer},
+    },
+    std::{
+        cell::RefCell,
+        collections::HashSet,
+        error::Error,
+        fmt::{Display, Formatter},
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
     },
-    std::time::Duration,
 };
 
+/// Key of checkpoint markers, distinguishing them from events on the same topic.
+pub const CHECKPOINT_KEY: &[u8] = b"checkpoint";
+
+/// Key of the highest rooted slot on the checkpoint topic, for log compaction.
+pub const ROOTED_SLOT_KEY: &[u8] = b"rooted_slot";
+
+/// Key of program activity events on `program_stats_topic`.
+pub const PROGRAM_STATS_KEY: &[u8] = b"program_stats";
+
+/// Kafka header of account updates with `emit_phase_headers`, `startup` for accounts notified
+/// from the snapshot at startup and `live` otherwise.
+pub const PHASE_HEADER: &str = "phase";
+
+/// Kafka header of transactions with `emit_phase_headers`, `ok` or `failed`.
+pub const STATUS_HEADER: &str = "status";
+
+/// Kafka header of transactions with `emit_phase_headers`, `vote` or `nonvote`.
+pub const KIND_HEADER: &str = "kind";
+
+/// Timeout of the partition count fetched at load.
+const PARTITION_METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Kafka headers of a message, as name and value.
+pub type MessageHeaders = Vec<(String, Vec<u8>)>;
+
+/// Message as it would have been produced to Kafka.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedMessage {
+    pub topic: String,
+    pub key: Vec<u8>,
+    /// `None` for tombstones.
+    pub payload: Option<Vec<u8>>,
+    /// Explicitly assigned partition.
+    pub partition: Option<i32>,
+    pub headers: MessageHeaders,
+}
+
+impl Display for RecordedMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ", self.topic)?;
+        for byte in &self.key {
+            write!(f, "{byte:02x}")?;
+        }
+        write!(f, " ")?;
+        match &self.payload {
+            Some(payload) => {
+                for byte in payload {
+                    write!(f, "{byte:02x}")?;
+                }
+            }
+            None => write!(f, "null")?,
+        }
+        if let Some(partition) = self.partition {
+            write!(f, " {partition}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Destination of produced messages.
+enum Sink {
+    Kafka(Arc<ArcSwap<KafkaProducer>>),
+    /// Dry run, messages are kept in memory instead.
+    Recorder(Recorder),
+    File(FileSink),
+}
+
+/// Failure to publish an event.
+#[derive(Debug)]
+pub enum PublishError {
+    /// The event couldn't be serialized into a payload.
+    Serialization(String),
+    Kafka(KafkaError),
+    /// The account message didn't fit `max_account_bytes_per_slot` and wasn't sent.
+    OverBudget,
+    /// The message wasn't sent as the callback was past `callback_deadline_ms`.
+    DeadlineExceeded,
+    /// The message wasn't sent as the plugin is in standby.
+    Standby,
+}
+
+impl PublishError {
+    /// `status` label of a message deliberately not sent, `None` for failures.
+    pub fn skip_status(&self) -> Option<&'static str> {
+        match self {
+            Self::DeadlineExceeded => Some("dropped"),
+            Self::Standby => Some("held"),
+            Self::Serialization(_) | Self::Kafka(_) | Self::OverBudget => None,
+        }
+    }
+}
+
+impl Display for PublishError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Serialization(error) => write!(f, "serialization failed: {error}"),
+            Self::Kafka(error) => error.fmt(f),
+            Self::OverBudget => write!(f, "over max_account_bytes_per_slot"),
+            Self::DeadlineExceeded => write!(f, "past callback_deadline_ms"),
+            Self::Standby => write!(f, "held in standby"),
+        }
+    }
+}
+
+impl Error for PublishError {}
+
+impl From<KafkaError> for PublishError {
+    fn from(error: KafkaError) -> Self {
+        Self::Kafka(error)
+    }
+}
+
+/// `status` label of the upload metrics for the result of publishing an event.
+fn upload_status<T>(result: &Result<T, PublishError>) -> &'static str {
+    match result {
+        Ok(_) => "success",
+        Err(PublishError::Serialization(_)) => "serialization_failed",
+        Err(PublishError::Kafka(_)) => "failed",
+        Err(PublishError::OverBudget) => "suppressed",
+        Err(PublishError::DeadlineExceeded) => "dropped",
+        Err(PublishError::Standby) => "held",
+    }
+}
+
+/// The failure of a send, leaving out messages deliberately not sent.
+fn send_failure(result: Result<(), PublishError>) -> Result<(), KafkaError> {
+    match result {
+        Err(PublishError::Kafka(error)) => Err(error),
+        _ => Ok(()),
+    }
+}
+
+/// Serialization of events into payloads.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Encoder {
+    #[default]
+    Protobuf,
+    /// Fails every event, to exercise serialization failures.
+    #[cfg(test)]
+    Failing,
+}
+
+impl Encoder {
+    fn encode(self, message: &impl Message, buf: &mut Vec<u8>) -> Result<(), PublishError> {
+        match self {
+            Self::Protobuf => {
+                encode_into(message, buf);
+                Ok(())
+            }
+            #[cfg(test)]
+            Self::Failing => Err(PublishError::Serialization("failing encoder".to_owned())),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Recorder {
+    messages: Mutex<Vec<RecordedMessage>>,
+    /// Topics whose sends fail, to exercise error handling.
+    failing_topics: Mutex<HashSet<String>>,
+    /// Time every send takes, to exercise slow sinks.
+    send_delay: Mutex<Duration>,
+}
+
 pub struct Publisher {
-    producer: ThreadedProducer<StatsThreadedProducerContext>,
+    sink: Sink,
+    refresher: Option<CredentialRefresher>,
     shutdown_timeout: Duration,
+
+    timing_metrics: bool,
+
+    firehose_topic: String,
+    firehose_include: Vec<String>,
+
+    slot_progress: Arc<SlotProgress>,
+    sequences: Sequences,
+
+    partition_counts: Arc<PartitionCounts>,
+    partition_refresher: Option<PartitionRefresher>,
+
+    pauses: Arc<TopicPauses>,
+    /// Holds every message off the sink while set, toggled by the admin endpoints.
+    standby: Arc<Standby>,
+
+    zstd_dicts: Option<Arc<ZstdDictionaries>>,
+
+    /// Holds back events until their slot is rooted, with `rooted_only`.
+    rooted_gate: Option<Arc<RootedGate>>,
+
+    /// Caps the encoded bytes of live account messages sent per slot, with
+    /// `max_account_bytes_per_slot`.
+    slot_budget: Option<SlotBudget>,
+
+    /// Holds back rooted slot statuses until the messages of their slot are delivered, with
+    /// `rooted_barrier`.
+    rooted_barrier: Option<Arc<RootedBarrier>>,
+
+    phase_headers: bool,
+
+    /// Stamped into wrapped messages.
+    identity: Identity,
+
+    /// Added to every message, with `emit_identity_headers`.
+    identity_headers: Vec<(String, Vec<u8>)>,
+
+    /// Adds the CRC32C of payloads as produced, with `emit_payload_checksum`.
+    payload_checksum: bool,
+
+    /// Splits account updates encoded larger than this, with `chunk_threshold_bytes`.
+    chunk_threshold: Option<usize>,
+
+    /// Slows down account updates while brokers throttle, with `respect_broker_throttle`.
+    pacer: Option<Arc<ThrottlePacer>>,
+
+    /// Checks account updates are produced in order, with `strict_ordering_assertions`.
+    ordering: Option<OrderingChecker>,
+
+    /// Summaries of published events for `/debug/tail`.
+    tail: Arc<Tail>,
+
+    encoder: Encoder,
+    serialization_failure_policy: SerializationFailurePolicy,
+
+    /// Cap on the bytes outstanding in the Kafka producer, and what happens to messages beyond.
+    max_outstanding_bytes: Option<(u64, BackpressurePolicy)>,
+
+    /// Counters of the plugin instance publishing.
+    metrics: Arc<InstanceMetrics>,
 }
 
 impl Publisher {
-    pub fn new(producer: ThreadedProducer<StatsThreadedProducerContext>, config: &Config) -> Self {
+    /// Publisher producing with `producer`, recording in the metrics of its statistics.
+    pub fn new(producer: KafkaProducer, config: &Config) -> Self {
+        let stats = producer.context().kafka_stats();
+        let metrics = Arc::clone(stats.metrics());
+        let pacer = config.respect_broker_throttle.then(|| stats.pacer());
+        let slot_deliveries = Arc::clone(stats.slot_deliveries());
+        let producer = Arc::new(ArcSwap::from_pointee(producer));
+        let shutdown_timeout = Duration::from_millis(config.shutdown_timeout_ms);
+        let refresher = config.credential_refresh_interval_secs.map(|secs| {
+            CredentialRefresher::spawn(
+                config.clone(),
+                Arc::clone(&producer),
+                Duration::from_secs(secs),
+                shutdown_timeout,
+            )
+        });
+        let partition_counts = Arc::new(PartitionCounts::new(Arc::clone(&metrics)));
+        let partition_topics = config.explicit_partition_topics();
+        let partition_refresher = (!partition_topics.is_empty()).then(|| {
+            let source =
+                KafkaPartitionSource::new(Arc::clone(&producer), PARTITION_METADATA_TIMEOUT);
+            partition_counts.refresh(&source, &partition_topics);
+            PartitionRefresher::spawn(
+                Arc::clone(&partition_counts),
+                Box::new(source),
+                partition_topics,
+                Duration::from_secs(config.partition_refresh_interval_secs),
+            )
+        });
         Self {
-            producer,
+            sink: Sink::Kafka(producer),
+            refresher,
+            shutdown_timeout,
+            timing_metrics: config.timing_metrics,
+            firehose_topic: config.firehose_topic.clone(),
+            firehose_include: config.firehose_include.clone(),
+            slot_progress: Arc::new(SlotProgress::new(Arc::clone(&metrics))),
+            sequences: Sequences::new(&metrics),
+            partition_counts,
+            partition_refresher,
+            pauses: Arc::new(TopicPauses::new(config, Arc::clone(&metrics))),
+            standby: Arc::new(Standby::new(config.standby, Arc::clone(&metrics))),
+            zstd_dicts: Self::zstd_dicts(config, &metrics),
+            rooted_gate: Self::rooted_gate(config, &metrics),
+            slot_budget: Self::slot_budget(config, &metrics),
+            rooted_barrier: Self::rooted_barrier(config, slot_deliveries, &metrics),
+            phase_headers: config.emit_phase_headers,
+            identity: Identity::new(config),
+            identity_headers: Self::identity_headers(config),
+            payload_checksum: config.emit_payload_checksum,
+            chunk_threshold: config.chunk_threshold_bytes,
+            pacer,
+            ordering: Self::ordering(config, &metrics),
+            tail: Arc::default(),
+            encoder: Encoder::default(),
+            serialization_failure_policy: config.serialization_failure_policy,
+            max_outstanding_bytes: config
+                .max_outstanding_bytes
+                .map(|limit| (limit, config.backpressure_policy)),
+            metrics,
+        }
+    }
+
+    /// Publisher recording messages instead of producing them.
+    pub fn dry_run(config: &Config) -> Self {
+        Self::with_sink(Sink::Recorder(Recorder::default()), config)
+    }
+
+    /// Publisher writing messages to files instead of producing them.
+    pub fn to_files(sink: FileSink, config: &Config) -> Self {
+        Self::with_sink(Sink::File(sink), config)
+    }
+
+    fn with_sink(sink: Sink, config: &Config) -> Self {
+        let metrics = Arc::new(InstanceMetrics::new(config));
+        Self {
+            sink,
+            refresher: None,
             shutdown_timeout: Duration::from_millis(config.shutdown_timeout_ms),
+            timing_metrics: config.timing_metrics,
+            firehose_topic: config.firehose_topic.clone(),
+            firehose_include: config.firehose_include.clone(),
+            slot_progress: Arc::new(SlotProgress::new(Arc::clone(&metrics))),
+            sequences: Sequences::new(&metrics),
+            partition_counts: Arc::new(PartitionCounts::new(Arc::clone(&metrics))),
+            partition_refresher: None,
+            pauses: Arc::new(TopicPauses::new(config, Arc::clone(&metrics))),
+            standby: Arc::new(Standby::new(config.standby, Arc::clone(&metrics))),
+            zstd_dicts: Self::zstd_dicts(config, &metrics),
+            rooted_gate: Self::rooted_gate(config, &metrics),
+            slot_budget: Self::slot_budget(config, &metrics),
+            rooted_barrier: Self::rooted_barrier(config, Arc::default(), &metrics),
+            phase_headers: config.emit_phase_headers,
+            identity: Identity::new(config),
+            identity_headers: Self::identity_headers(config),
+            payload_checksum: config.emit_payload_checksum,
+            chunk_threshold: config.chunk_threshold_bytes,
+            pacer: None,
+            ordering: Self::ordering(config, &metrics),
+            tail: Arc::default(),
+            encoder: Encoder::default(),
+            serialization_failure_policy: config.serialization_failure_policy,
+            max_outstanding_bytes: config
+                .max_outstanding_bytes
+                .map(|limit| (limit, config.backpressure_policy)),
+            metrics,
+        }
+    }
+
+    fn identity_headers(config: &Config) -> Vec<(String, Vec<u8>)> {
+        if config.emit_identity_headers {
+            Identity::new(config).headers()
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn zstd_dicts(
+        config: &Config,
+        metrics: &Arc<InstanceMetrics>,
+    ) -> Option<Arc<ZstdDictionaries>> {
+        (config.payload_compression == PayloadCompression::ZstdDict).then(|| {
+            Arc::new(ZstdDictionaries::new(
+                config.zstd_dict_samples,
+                Arc::clone(metrics),
+            ))
+        })
+    }
+
+    fn rooted_gate(config: &Config, metrics: &Arc<InstanceMetrics>) -> Option<Arc<RootedGate>> {
+        config.rooted_only.then(|| {
+            Arc::new(RootedGate::new(
+                config.rooted_only_buffer_bytes,
+                Arc::clone(metrics),
+            ))
+        })
+    }
+
+    fn slot_budget(config: &Config, metrics: &Arc<InstanceMetrics>) -> Option<SlotBudget> {
+        config
+            .max_account_bytes_per_slot
+            .map(|max_bytes| SlotBudget::new(max_bytes, Arc::clone(metrics)))
+    }
+
+    fn rooted_barrier(
+        config: &Config,
+        deliveries: Arc<SlotDeliveries>,
+        metrics: &Arc<InstanceMetrics>,
+    ) -> Option<Arc<RootedBarrier>> {
+        config.rooted_barrier.then(|| {
+            Arc::new(RootedBarrier::new(
+                deliveries,
+                Duration::from_millis(config.rooted_barrier_timeout_ms),
+                Arc::clone(metrics),
+            ))
+        })
+    }
+
+    fn ordering(config: &Config, metrics: &Arc<InstanceMetrics>) -> Option<OrderingChecker> {
+        config
+            .strict_ordering_assertions
+            .then(|| OrderingChecker::new(Arc::clone(metrics)))
+    }
+
+    /// Account bytes sent per slot, with `max_account_bytes_per_slot`.
+    pub fn account_budget(&self) -> Option<&SlotBudget> {
+        self.slot_budget.as_ref()
+    }
+
+    /// Charge a live account message of `bytes` to the budget of `slot`, failing with
+    /// `OverBudget` if it doesn't fit.
+    fn admit_account(&self, slot: u64, bytes: usize, is_startup: bool) -> Result<(), PublishError> {
+        match &self.slot_budget {
+            Some(budget) if !is_startup && !budget.admit(slot, bytes as u64) => {
+                Err(PublishError::OverBudget)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Dictionaries account update payloads are compressed with, if enabled.
+    pub fn zstd_dictionaries(&self) -> Option<Arc<ZstdDictionaries>> {
+        self.zstd_dicts.clone()
+    }
+
+    /// Continue from `sequences` instead of starting every topic at 0.
+    pub fn with_sequences(mut self, sequences: Sequences) -> Self {
+        self.sequences = sequences;
+        self
+    }
+
+    /// Partition counts used for explicit partitioning.
+    pub fn partition_counts(&self) -> &PartitionCounts {
+        &self.partition_counts
+    }
+
+    /// Metrics of the plugin instance, shared with the plugin and the HTTP endpoints.
+    pub fn metrics(&self) -> Arc<InstanceMetrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// Pause state of the configured topics.
+    pub fn pauses(&self) -> Arc<TopicPauses> {
+        Arc::clone(&self.pauses)
+    }
+
+    /// Add the buffers holding messages back to `footprints`.
+    pub fn register_footprints(&self, footprints: &MemoryFootprints) {
+        footprints.register("pause_buffer", self.pauses());
+        if let Some(gate) = &self.rooted_gate {
+            footprints.register("rooted_only_buffer", Arc::clone(gate) as _);
+        }
+        if let Some(barrier) = &self.rooted_barrier {
+            footprints.register("rooted_barrier", Arc::clone(barrier) as _);
+        }
+    }
+
+    pub fn standby(&self) -> Arc<Standby> {
+        Arc::clone(&self.standby)
+    }
+
+    pub fn tail(&self) -> Arc<Tail> {
+        Arc::clone(&self.tail)
+    }
+
+    /// Statistics of the Kafka producer, shared by its replacements.
+    pub fn kafka_stats(&self) -> Option<Arc<KafkaStats>> {
+        match &self.sink {
+            Sink::Kafka(producer) => Some(Arc::clone(producer.load().context().kafka_stats())),
+            Sink::Recorder(_) | Sink::File(_) => None,
+        }
+    }
+
+    /// Wait up to `shutdown_timeout_ms` for the messages sent so far to be delivered.
+    pub fn flush(&self) {
+        match &self.sink {
+            Sink::Kafka(producer) => {
+                let _ = producer.load().flush(self.shutdown_timeout);
+            }
+            Sink::File(sink) => {
+                if let Err(error) = sink.flush() {
+                    error!("Failed to flush file sink: {error}");
+                }
+            }
+            Sink::Recorder(_) => {}
+        }
+    }
+
+    /// Take the messages recorded so far by a dry run publisher.
+    pub fn take_recorded(&self) -> Vec<RecordedMessage> {
+        match &self.sink {
+            Sink::Recorder(recorder) => std::mem::take(&mut *recorder.messages.lock().unwrap()),
+            Sink::Kafka(_) | Sink::File(_) => Vec::new(),
+        }
+    }
+
+    /// Make every later send of a dry run publisher take `delay`.
+    pub fn delay_recorded_sends(&self, delay: Duration) {
+        if let Sink::Recorder(recorder) = &self.sink {
+            *recorder.send_delay.lock().unwrap() = delay;
+        }
+    }
+
+    /// Make a dry run publisher fail every later send to `topic`.
+    pub fn fail_recorded_topic(&self, topic: &str) {
+        if let Sink::Recorder(recorder) = &self.sink {
+            recorder
+                .failing_topics
+                .lock()
+                .unwrap()
+                .insert(topic.to_owned());
+        }
+    }
+
+    fn send(&self, topic: &str, key: &[u8], payload: &[u8]) -> Result<(), PublishError> {
+        self.send_to(topic, key, Some(payload), None, &[])
+    }
+
+    /// Send to `partition` if set, otherwise leave it to the producer's partitioner.
+    /// A `None` payload is sent as a tombstone. Sends to paused topics are held back.
+    fn send_to(
+        &self,
+        topic: &str,
+        key: &[u8],
+        payload: Option<&[u8]>,
+        partition: Option<i32>,
+        headers: &[(String, Vec<u8>)],
+    ) -> Result<(), PublishError> {
+        // Buffered messages flushed ahead and not sent are dropped, the outcome is the message's.
+        let mut skipped = None;
+        let result = self.pauses.send(
+            topic,
+            key,
+            payload,
+            partition,
+            headers,
+            |key, payload, partition, headers| match self
+                .produce(topic, key, payload, partition, headers)
+            {
+                Err(error) if error.skip_status().is_some() => {
+                    skipped = Some(error);
+                    Ok(())
+                }
+                result => {
+                    skipped = None;
+                    result
+                }
+            },
+        );
+        match skipped {
+            Some(error) if result.is_ok() => Err(error),
+            _ => result,
+        }
+    }
+
+    /// Send an event of `slot`, held back until the slot is rooted with `rooted_only`.
+    fn send_at(
+        &self,
+        slot: u64,
+        topic: &str,
+        key: &[u8],
+        payload: Option<&[u8]>,
+        partition: Option<i32>,
+        headers: &[(String, Vec<u8>)],
+    ) -> Result<(), PublishError> {
+        let stamped;
+        let headers = match &self.rooted_barrier {
+            Some(_) => {
+                stamped = [headers, &[barrier_slot(slot)]].concat();
+                stamped.as_slice()
+            }
+            None => headers,
+        };
+        match &self.rooted_gate {
+            Some(gate) => gate.send(
+                slot,
+                topic,
+                key,
+                payload,
+                partition,
+                headers,
+                |key, payload, partition, headers| {
+                    self.send_to(topic, key, payload, partition, headers)
+                },
+            ),
+            None => self.send_to(topic, key, payload, partition, headers),
+        }
+    }
+
+    /// Whether events are held back until their slot is rooted.
+    pub fn is_rooted_only(&self) -> bool {
+        self.rooted_gate.is_some()
+    }
+
+    /// Send the events held back for `slot`, now rooted, dropping those of earlier slots it
+    /// didn't build on.
+    pub fn root_slot(&self, slot: u64) -> Result<(), KafkaError> {
+        let Some(gate) = &self.rooted_gate else {
+            return Ok(());
+        };
+        gate.root(slot, |topic, message| {
+            send_failure(self.send_to(
+                topic,
+                &message.key,
+                message.payload.as_deref(),
+                message.partition,
+                &message.headers,
+            ))
+        })
+    }
+
+    /// Drop the events held back for `slot`, notified dead, and those sent later.
+    pub fn drop_slot(&self, slot: u64) {
+        if let Some(gate) = &self.rooted_gate {
+            gate.drop_slot(slot);
+        }
+    }
+
+    /// Record the slot of startup accounts, whose events aren't held back.
+    pub fn observe_startup_slot(&self, slot: u64) {
+        if let Some(gate) = &self.rooted_gate {
+            gate.observe_startup_slot(slot);
+        }
+    }
+
+    fn produce(
+        &self,
+        topic: &str,
+        key: &[u8],
+        payload: Option<&[u8]>,
+        partition: Option<i32>,
+        headers: &[(String, Vec<u8>)],
+    ) -> Result<(), PublishError> {
+        // Before the ordering check, which would take a dropped or held message for produced.
+        if deadline::expired() {
+            self.metrics
+                .callback_deadline_dropped_total
+                .with_label_values(&[topic])
+                .inc();
+            return Err(PublishError::DeadlineExceeded);
+        }
+        if self.standby.holds(topic) {
+            return Err(PublishError::Standby);
+        }
+        let checked;
+        let headers = match &self.ordering {
+            Some(ordering) => {
+                (checked, _) = ordering.check(topic, key, headers);
+                checked.as_slice()
+            }
+            None => headers,
+        };
+        let (unstamped, barrier_slot);
+        let headers = match &self.rooted_barrier {
+            Some(_) => {
+                (unstamped, barrier_slot) = take_barrier_slot(headers);
+                unstamped.as_slice()
+            }
+            None => {
+                barrier_slot = None;
+                headers
+            }
+        };
+        // Over the payload as produced, after compression.
+        let identified;
+        let headers = if self.identity_headers.is_empty() {
+            headers
+        } else {
+            identified = [headers, &self.identity_headers].concat();
+            identified.as_slice()
+        };
+        let checksummed;
+        let headers = match payload.filter(|_| self.payload_checksum) {
+            Some(payload) => {
+                let checksum = (
+                    PAYLOAD_CHECKSUM_HEADER.to_owned(),
+                    payload_checksum(payload),
+                );
+                checksummed = [headers, &[checksum]].concat();
+                checksummed.as_slice()
+            }
+            None => headers,
+        };
+        match &self.sink {
+            Sink::Kafka(producer) => {
+                let producer = producer.load();
+                let outstanding = producer.context().kafka_stats().outstanding();
+                match self.max_outstanding_bytes {
+                    Some((limit, policy)) if outstanding.bytes() >= limit => {
+                        let action = match policy {
+                            BackpressurePolicy::Block => "blocked",
+                            BackpressurePolicy::Error => "failed",
+                            BackpressurePolicy::Drop => "dropped",
+                        };
+                        self.metrics
+                            .outstanding_bytes_exceeded_total
+                            .with_label_values(&[topic, action])
+                            .inc();
+                        if !outstanding.admit(limit, policy, deadline::current())? {
+                            if deadline::expired() {
+                                self.metrics
+                                    .callback_deadline_dropped_total
+                                    .with_label_values(&[topic])
+                                    .inc();
+                                return Err(PublishError::DeadlineExceeded);
+                            }
+                            return Ok(());
+                        }
+                    }
+                    _ => {}
+                }
+                let size = key.len() + payload.map_or(0, <[u8]>::len);
+                let enqueued = Box::new(Enqueued {
+                    at: enqueue_timestamp(),
+                    slot: barrier_slot,
+                });
+                let mut record =
+                    BaseRecord::<[u8], [u8], Box<Enqueued>>::with_opaque_to(topic, enqueued)
+                        .key(key);
+                if let Some(payload) = payload {
+                    record = record.payload(payload);
+                }
+                if let Some(partition) = partition {
+                    record = record.partition(partition);
+                }
+                if !headers.is_empty() {
+                    let headers = headers.iter().fold(
+                        OwnedHeaders::new_with_capacity(headers.len()),
+                        |kafka_headers, (name, value)| {
+                            kafka_headers.insert(Header {
+                                key: name,
+                                value: Some(value),
+                            })
+                        },
+                    );
+                    record = record.headers(headers);
+                }
+                // Counted first, the delivery report may arrive before `send` returns.
+                let deliveries = self
+                    .rooted_barrier
+                    .as_ref()
+                    .map(|barrier| barrier.deliveries());
+                if let Some((deliveries, slot)) = deliveries.zip(barrier_slot) {
+                    deliveries.enqueued(slot);
+                }
+                outstanding.enqueued(size);
+                producer.send(record).map_err(|(e, _)| {
+                    if let Some((deliveries, slot)) = deliveries.zip(barrier_slot) {
+                        deliveries.delivered(slot);
+                    }
+                    outstanding.delivered(size);
+                    e
+                })?;
+                Ok(())
+            }
+            Sink::Recorder(recorder) => {
+                let delay = *recorder.send_delay.lock().unwrap();
+                if !delay.is_zero() {
+                    thread::sleep(delay);
+                }
+                if recorder.failing_topics.lock().unwrap().contains(topic) {
+                    return Err(
+                        KafkaError::MessageProduction(RDKafkaErrorCode::UnknownTopic).into(),
+                    );
+                }
+                recorder.messages.lock().unwrap().push(RecordedMessage {
+                    topic: topic.to_owned(),
+                    key: key.to_vec(),
+                    payload: payload.map(<[u8]>::to_vec),
+                    partition,
+                    headers: headers.to_vec(),
+                });
+                Ok(())
+            }
+            Sink::File(sink) => {
+                sink.write(topic, key, payload, partition, headers)
+                    .map_err(|error| {
+                        error!("Failed to write message to file sink: {error}");
+                        KafkaError::MessageProduction(RDKafkaErrorCode::Fail).into()
+                    })
+            }
+        }
+    }
+
+    /// Wait before an account update in proportion to the broker throttle times.
+    fn pace(&self) {
+        let Some(pacer) = &self.pacer else {
+            return;
+        };
+        let delay = deadline::bounded(pacer.delay(Instant::now()));
+        if !delay.is_zero() {
+            thread::sleep(delay);
+            self.metrics
+                .account_pacing_delay_seconds_total
+                .inc_by(delay.as_secs_f64());
         }
     }
 
     pub fn update_account(
         &self,
-        ev: UpdateAccountEvent,
+        mut ev: UpdateAccountEvent,
         wrap_messages: bool,
+        partitioning: Partitioning,
+        schema: SchemaCompat,
+        is_startup: bool,
         topic: &str,
-    ) -> Result<(), KafkaError> {
-        let temp_key;
-        let (key, buf) = if wrap_messages {
-            temp_key = self.copy_and_prepend(ev.pubkey.as_slice(), 65u8);
-            (&temp_key, Self::encode_with_wrapper(Account(Box::new(ev))))
-        } else {
-            (&ev.pubkey, ev.encode_to_vec())
+    ) -> Result<(), PublishError> {
+        self.pace();
+        let timer = Timer::start(self.timing_metrics);
+        ev.sequence = self.sequences.next(topic);
+        ev.project(schema);
+        let partition = match partitioning {
+            Partitioning::KeyHash => None,
+            Partitioning::Explicit | Partitioning::Sticky => {
+                let partition = self.partition_counts.get(topic).and_then(|count| {
+                    if partitioning == Partitioning::Sticky {
+                        sticky_partition(&ev.pubkey, count)
+                    } else {
+                        explicit_partition(&ev.pubkey, count)
+                    }
+                });
+                partition::count_send(&self.metrics, partition);
+                partition
+            }
         };
-        let record = BaseRecord::<Vec<u8>, _>::to(topic).key(key).payload(&buf);
-        let result = self.producer.send(record).map(|_| ()).map_err(|(e, _)| e);
-        UPLOAD_ACCOUNTS_TOTAL
-            .with_label_values(&[if result.is_ok() { "success" } else { "failed" }])
+        let slot = ev.slot;
+        // Kept for the owner's dictionary, as wrapping takes the event.
+        let owner = self.zstd_dicts.as_ref().map(|_| ev.owner.clone());
+        let mut headers = MessageHeaders::new();
+        if self.phase_headers {
+            let phase = if is_startup { "startup" } else { "live" };
+            headers.push((PHASE_HEADER.to_owned(), phase.into()));
+        }
+        if self.ordering.is_some() {
+            headers.push(order_stamp(ev.slot, ev.write_version));
+        }
+        let chunks = match self.chunk_threshold {
+            Some(threshold) => split_account(ev, threshold),
+            None => vec![ev],
+        };
+        if chunks.len() > 1 {
+            self.metrics
+                .account_chunks_total
+                .inc_by(chunks.len() as u64);
+        }
+        // Chunks share the key, and so the partition.
+        let result = chunks.into_iter().enumerate().try_for_each(|(index, ev)| {
+            with_buffers(|key, buf| {
+                if wrap_messages {
+                    key.push(65u8);
+                    key.extend_from_slice(&ev.pubkey);
+                    self.serialize_wrapped(Account(ev), buf)?;
+                } else {
+                    key.extend_from_slice(&ev.pubkey);
+                    self.serialize(&ev, buf)?;
+                }
+                let compressed = self
+                    .zstd_dicts
+                    .as_ref()
+                    .zip(owner.as_deref())
+                    .and_then(|(dictionaries, owner)| dictionaries.compress(owner, buf));
+                let bytes = compressed
+                    .as_ref()
+                    .map_or(buf.len(), |(_, payload)| payload.len());
+                // Once the first chunk is sent, the update is sent whole.
+                match (&self.slot_budget, index) {
+                    (_, 0) => self.admit_account(slot, bytes, is_startup)?,
+                    (Some(budget), _) if !is_startup => budget.charge(slot, bytes as u64),
+                    _ => {}
+                }
+                self.tail.record("account", slot, topic, key, buf.len());
+                match compressed {
+                    Some((id, payload)) => {
+                        let mut headers = headers.clone();
+                        headers.push((ZSTD_DICT_ID_HEADER.to_owned(), id.to_string().into_bytes()));
+                        self.send_at(slot, topic, key, Some(&payload), partition, &headers)
+                    }
+                    None => self.send_at(slot, topic, key, Some(buf), partition, &headers),
+                }
+            })
+        });
+        timer.observe(&self.metrics.event_send_seconds, "account");
+        self.metrics
+            .upload_accounts_total
+            .with_label_values(&[upload_status(&result)])
             .inc();
+        self.apply_serialization_policy(topic, result)
+    }
+
+    /// Latest state of an account on a compacted state topic, keyed by the bare pubkey.
+    pub fn update_account_state(
+        &self,
+        mut ev: UpdateAccountEvent,
+        schema: SchemaCompat,
+        is_startup: bool,
+        topic: &str,
+    ) -> Result<(), PublishError> {
+        self.pace();
+        ev.sequence = self.sequences.next(topic);
+        ev.project(schema);
+        let headers: MessageHeaders = self
+            .ordering
+            .iter()
+            .map(|_| order_stamp(ev.slot, ev.write_version))
+            .collect();
+        let result = with_buffers(|_, buf| {
+            self.serialize(&ev, buf)?;
+            self.admit_account(ev.slot, buf.len(), is_startup)?;
+            self.tail
+                .record("account_state", ev.slot, topic, &ev.pubkey, buf.len());
+            self.send_at(ev.slot, topic, &ev.pubkey, Some(buf), None, &headers)
+        });
+        self.count_account_state("update", &result);
+        self.apply_serialization_policy(topic, result)
+    }
+
+    /// Tombstone removing a closed account from a compacted state topic.
+    pub fn delete_account_state(
+        &self,
+        slot: u64,
+        pubkey: &[u8],
+        is_startup: bool,
+        topic: &str,
+    ) -> Result<(), PublishError> {
+        let result = self
+            .admit_account(slot, 0, is_startup)
+            .and_then(|()| self.send_at(slot, topic, pubkey, None, None, &[]));
+        self.count_account_state("tombstone", &result);
         result
     }
 
+    fn count_account_state(&self, kind: &str, result: &Result<(), PublishError>) {
+        self.metrics
+            .upload_account_states_total
+            .with_label_values(&[kind, upload_status(result)])
+            .inc();
+    }
+
+    /// Publish a slot status. Rooted statuses are held back with `rooted_barrier` while messages
+    /// of their slot are outstanding, and published by `release_rooted_statuses`.
     pub fn update_slot_status(
         &self,
         ev: SlotStatusEvent,
         wrap_messages: bool,
         topic: &str,
-    ) -> Result<(), KafkaError> {
-        let temp_key;
-        let (key, buf) = if wrap_messages {
-            temp_key = self.copy_and_prepend(&ev.slot.to_le_bytes(), 83u8);
-            (&temp_key, Self::encode_with_wrapper(Slot(Box::new(ev))))
-        } else {
-            temp_key = ev.slot.to_le_bytes().to_vec();
-            (&temp_key, ev.encode_to_vec())
+    ) -> Result<(), PublishError> {
+        if let Some(barrier) = &self.rooted_barrier {
+            if ev.status() == SlotStatus::Rooted
+                && barrier.defer(ev.clone(), wrap_messages, topic, Instant::now())
+            {
+                return Ok(());
+            }
+        }
+        self.publish_slot_status(ev, wrap_messages, topic)
+    }
+
+    /// Whether rooted statuses are held back until the messages of their slot are delivered.
+    pub fn has_rooted_barrier(&self) -> bool {
+        self.rooted_barrier.is_some()
+    }
+
+    /// Publish the rooted statuses held back by `rooted_barrier` that are due at `now`, or all
+    /// of them with `flush`.
+    pub fn release_rooted_statuses(&self, now: Instant, flush: bool) {
+        let Some(barrier) = &self.rooted_barrier else {
+            return;
         };
-        let record = BaseRecord::<Vec<u8>, _>::to(topic).key(key).payload(&buf);
-        let result = self.producer.send(record).map(|_| ()).map_err(|(e, _)| e);
-        UPLOAD_SLOTS_TOTAL
-            .with_label_values(&[if result.is_ok() { "success" } else { "failed" }])
+        for status in barrier.release(now, flush) {
+            let slot = status.event.slot;
+            if status.event.barrier_timeout {
+                warn!("Published rooted status of slot {slot} with messages still undelivered");
+            }
+            match self.publish_slot_status(status.event, status.wrap_messages, &status.topic) {
+                Err(error) if error.skip_status().is_none() => error!(
+                    "Failed to publish rooted status of slot {slot} to {:?}: {error}",
+                    status.topic
+                ),
+                _ => {}
+            }
+        }
+    }
+
+    fn publish_slot_status(
+        &self,
+        mut ev: SlotStatusEvent,
+        wrap_messages: bool,
+        topic: &str,
+    ) -> Result<(), PublishError> {
+        let timer = Timer::start(self.timing_metrics);
+        ev.sequence = self.sequences.next(topic);
+        let slot = ev.slot;
+        let result = with_buffers(|key, buf| {
+            if wrap_messages {
+                key.push(83u8);
+                key.extend_from_slice(&ev.slot.to_le_bytes());
+                self.serialize_wrapped(Slot(ev), buf)?;
+            } else {
+                key.extend_from_slice(&ev.slot.to_le_bytes());
+                self.serialize(&ev, buf)?;
+            }
+            self.tail.record("slot", slot, topic, key, buf.len());
+            self.send(topic, key, buf)
+        });
+        timer.observe(&self.metrics.event_send_seconds, "slot");
+        self.metrics
+            .upload_slots_total
+            .with_label_values(&[upload_status(&result)])
             .inc();
-        result
+        self.apply_serialization_policy(topic, result)
     }
 
+    /// Messages with equal keys are enqueued in call order, as sends are synchronous
+    /// on a single producer. Any asynchronous send path must keep that guarantee.
     pub fn update_transaction(
         &self,
-        ev: TransactionEvent,
+        mut ev: TransactionEvent,
         wrap_messages: bool,
+        key_source: TransactionKeySource,
         topic: &str,
-    ) -> Result<(), KafkaError> {
-        let temp_key;
-        let (key, buf) = if wrap_messages {
-            temp_key = self.copy_and_prepend(ev.signature.as_slice(), 84u8);
-            (
-                &temp_key,
-                Self::encode_with_wrapper(Transaction(Box::new(ev))),
-            )
-        } else {
-            (&ev.signature, ev.encode_to_vec())
+    ) -> Result<(), PublishError> {
+        let timer = Timer::start(self.timing_metrics);
+        ev.sequence = self.sequences.next(topic);
+        let slot = ev.slot;
+        let mut headers = MessageHeaders::new();
+        if self.phase_headers {
+            let failed = ev
+                .transaction_status_meta
+                .as_ref()
+                .is_some_and(|meta| meta.is_status_err);
+            let status = if failed { "failed" } else { "ok" };
+            let kind = if ev.is_vote { "vote" } else { "nonvote" };
+            headers.push((STATUS_HEADER.to_owned(), status.into()));
+            headers.push((KIND_HEADER.to_owned(), kind.into()));
+        }
+        let result = with_buffers(|key, buf| {
+            if wrap_messages {
+                key.push(84u8);
+            }
+            // Keys differ by transaction, so the slot alone picks the partition.
+            let partition = match key_source {
+                TransactionKeySource::Signature => {
+                    key.extend_from_slice(&ev.signature);
+                    None
+                }
+                TransactionKeySource::SlotIndex => {
+                    key.extend_from_slice(&ev.slot.to_le_bytes());
+                    key.extend_from_slice(&ev.index.to_be_bytes());
+                    self.partition_counts
+                        .get(topic)
+                        .and_then(|count| explicit_partition(&slot.to_le_bytes(), count))
+                }
+            };
+            if wrap_messages {
+                self.serialize_wrapped(Transaction(ev), buf)?;
+            } else {
+                self.serialize(&ev, buf)?;
+            }
+            self.tail.record("transaction", slot, topic, key, buf.len());
+            self.send_at(slot, topic, key, Some(buf), partition, &headers)
+        });
+        timer.observe(&self.metrics.event_send_seconds, "transaction");
+        self.metrics
+            .upload_transactions_total
+            .with_label_values(&[upload_status(&result)])
+            .inc();
+        self.apply_serialization_policy(topic, result)
+    }
+
+    /// Publish the index entry of a transaction, keyed by its signature and never wrapped.
+    pub fn update_transaction_index(
+        &self,
+        mut ev: TransactionIndexEvent,
+        topic: &str,
+    ) -> Result<(), PublishError> {
+        ev.sequence = self.sequences.next(topic);
+        let result = with_buffers(|_, buf| {
+            self.serialize(&ev, buf)?;
+            self.tail.record(
+                "transaction_index",
+                ev.slot,
+                topic,
+                &ev.signature,
+                buf.len(),
+            );
+            self.send_at(ev.slot, topic, &ev.signature, Some(buf), None, &[])
+        });
+        self.metrics
+            .upload_transaction_index_total
+            .with_label_values(&[upload_status(&result)])
+            .inc();
+        self.apply_serialization_policy(topic, result)
+    }
+
+    pub fn update_block(
+        &self,
+        mut ev: BlockEvent,
+        wrap_messages: bool,
+        topic: &str,
+    ) -> Result<(), PublishError> {
+        let timer = Timer::start(self.timing_metrics);
+        ev.sequence = self.sequences.next(topic);
+        let slot = ev.slot;
+        let result = with_buffers(|key, buf| {
+            if wrap_messages {
+                key.push(66u8);
+                key.extend_from_slice(&ev.slot.to_le_bytes());
+                self.serialize_wrapped(Block(ev), buf)?;
+            } else {
+                key.extend_from_slice(&ev.slot.to_le_bytes());
+                self.serialize(&ev, buf)?;
+            }
+            self.tail.record("block", slot, topic, key, buf.len());
+            self.send_at(slot, topic, key, Some(buf), None, &[])
+        });
+        timer.observe(&self.metrics.event_send_seconds, "block");
+        self.metrics
+            .upload_blocks_total
+            .with_label_values(&[upload_status(&result)])
+            .inc();
+        self.apply_serialization_policy(topic, result)
+    }
+
+    pub fn firehose_topic(&self) -> &str {
+        &self.firehose_topic
+    }
+
+    pub fn wants_firehose(&self, kind: &str) -> bool {
+        !self.firehose_topic.is_empty()
+            && (self.firehose_include.is_empty() || self.firehose_include.iter().any(|k| k == kind))
+    }
+
+    /// Mirror an already published event to the firehose topic, numbered in its sequence.
+    pub fn update_firehose(
+        &self,
+        mut message: EventMessage,
+        slot: u64,
+    ) -> Result<(), PublishError> {
+        let kind = message.kind();
+        message.set_sequence(self.sequences.next(&self.firehose_topic));
+        let result = with_buffers(|_, buf| {
+            let key = self.firehose_record(message, slot, buf)?;
+            self.tail
+                .record(kind, slot, &self.firehose_topic, &key, buf.len());
+            self.send_at(slot, &self.firehose_topic, &key, Some(buf), None, &[])
+        });
+        self.metrics
+            .upload_firehose_total
+            .with_label_values(&[kind, upload_status(&result)])
+            .inc();
+        self.apply_serialization_policy(&self.firehose_topic, result)
+    }
+
+    /// Serialize a firehose payload into `buf`, returning its key.
+    fn firehose_record(
+        &self,
+        message: EventMessage,
+        slot: u64,
+        buf: &mut Vec<u8>,
+    ) -> Result<[u8; 8], PublishError> {
+        self.serialize_wrapped(message, buf)?;
+        Ok(slot.to_le_bytes())
+    }
+
+    fn serialize(&self, message: &impl Message, buf: &mut Vec<u8>) -> Result<(), PublishError> {
+        self.encoder.encode(message, buf)
+    }
+
+    fn serialize_wrapped(
+        &self,
+        message: EventMessage,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), PublishError> {
+        self.serialize(&self.wrap(message), buf)
+    }
+
+    /// `message` in a `MessageWrapper` stamped with the identity of the plugin.
+    fn wrap(&self, message: EventMessage) -> MessageWrapper {
+        let mut wrapper = MessageWrapper {
+            event_message: Some(message),
+            ..Default::default()
+        };
+        self.identity.stamp(&mut wrapper);
+        wrapper
+    }
+
+    /// Turn a serialization failure into success with `serialization_failure_policy` `drop`,
+    /// so the event is skipped instead of failing the notification.
+    fn apply_serialization_policy(
+        &self,
+        topic: &str,
+        result: Result<(), PublishError>,
+    ) -> Result<(), PublishError> {
+        match result {
+            Err(PublishError::Serialization(error))
+                if self.serialization_failure_policy == SerializationFailurePolicy::Drop =>
+            {
+                debug!("Dropped an event for {topic:?}: serialization failed: {error}");
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Record a slot status, reported in checkpoint markers and on the checkpoint topic.
+    pub fn observe_slot(&self, slot: u64, status: SlotStatus) {
+        self.slot_progress.observe(slot, status);
+    }
+
+    pub fn slot_progress(&self) -> Arc<SlotProgress> {
+        Arc::clone(&self.slot_progress)
+    }
+
+    /// Highest rooted slot seen in slot status updates.
+    pub fn rooted_slot(&self) -> u64 {
+        self.slot_progress.rooted_slot()
+    }
+
+    /// Publish the highest rooted slot as little endian bytes under `ROOTED_SLOT_KEY`.
+    pub fn publish_rooted_slot(&self, topic: &str) -> Result<(), KafkaError> {
+        let slot = self.slot_progress.rooted_slot();
+        let result = self.send(topic, ROOTED_SLOT_KEY, &slot.to_le_bytes());
+        self.metrics
+            .upload_checkpoints_total
+            .with_label_values(&[upload_status(&result)])
+            .inc();
+        send_failure(result)
+    }
+
+    pub fn publish_checkpoint(&self, topic: &str, wrap_messages: bool) -> Result<(), KafkaError> {
+        let event = CheckpointEvent {
+            sequence: self.sequences.next(topic),
+            timestamp: unix_millis(),
+            last_slot: self.slot_progress.last_slot(),
         };
-        let record = BaseRecord::<Vec<u8>, _>::to(topic).key(key).payload(&buf);
-        let result = self.producer.send(record).map(|_| ()).map_err(|(e, _)| e);
-        UPLOAD_TRANSACTIONS_TOTAL
-            .with_label_values(&[if result.is_ok() { "success" } else { "failed" }])
+        let buf = self.encode_checkpoint(event, wrap_messages);
+        let result = self.send(topic, CHECKPOINT_KEY, &buf);
+        self.metrics
+            .upload_checkpoints_total
+            .with_label_values(&[upload_status(&result)])
+            .inc();
+        send_failure(result)
+    }
+
+    /// Publish a record of the plugin to the producer registry under `key`.
+    pub fn publish_producer_registration(
+        &self,
+        topic: &str,
+        key: &str,
+        registration: &ProducerRegistration,
+    ) -> Result<(), KafkaError> {
+        let result = self.send(topic, key.as_bytes(), &registration.encode_to_vec());
+        self.metrics
+            .upload_producer_registrations_total
+            .with_label_values(&[upload_status(&result)])
+            .inc();
+        send_failure(result)
+    }
+
+    /// Publish the program stats of a window under `PROGRAM_STATS_KEY`.
+    pub fn publish_program_activity(
+        &self,
+        topic: &str,
+        event: &ProgramActivityEvent,
+    ) -> Result<(), KafkaError> {
+        let result = self.send(topic, PROGRAM_STATS_KEY, &event.encode_to_vec());
+        self.metrics
+            .upload_program_stats_total
+            .with_label_values(&[upload_status(&result)])
+            .inc();
+        send_failure(result)
+    }
+
+    /// Publish an address lookup table resolution failure, keyed by the transaction signature.
+    pub fn publish_alt_failure(
+        &self,
+        topic: &str,
+        event: &AltResolutionFailureEvent,
+    ) -> Result<(), KafkaError> {
+        let payload = event.encode_to_vec();
+        let result = self.send_at(
+            event.slot,
+            topic,
+            &event.signature,
+            Some(&payload),
+            None,
+            &[],
+        );
+        self.metrics
+            .upload_alt_failures_total
+            .with_label_values(&[upload_status(&result)])
+            .inc();
+        send_failure(result)
+    }
+
+    /// Publish the most write locked accounts of a slot, keyed by the slot as little endian
+    /// bytes. Held back until the slot is rooted with `rooted_only`.
+    pub fn publish_slot_contention(
+        &self,
+        topic: &str,
+        event: &SlotContentionEvent,
+    ) -> Result<(), KafkaError> {
+        let key = event.slot.to_le_bytes();
+        let payload = event.encode_to_vec();
+        let result = self.send_at(event.slot, topic, &key, Some(&payload), None, &[]);
+        self.metrics
+            .upload_slot_contention_total
+            .with_label_values(&[upload_status(&result)])
             .inc();
+        send_failure(result)
+    }
+
+    fn encode_checkpoint(&self, event: CheckpointEvent, wrap_messages: bool) -> Vec<u8> {
+        if wrap_messages {
+            self.wrap(Checkpoint(event)).encode_to_vec()
+        } else {
+            event.encode_to_vec()
+        }
+    }
+}
+
+thread_local! {
+    /// Key and payload buffers reused by every send on a thread.
+    static BUFFERS: RefCell<(Vec<u8>, Vec<u8>)> = const { RefCell::new((Vec::new(), Vec::new())) };
+}
+
+/// Buffers grown beyond this by a large event are released rather than kept per thread.
+const MAX_RETAINED_BUFFER: usize = 1 << 20;
+
+/// Run `f` with this thread's cleared key and payload buffers.
+/// rdkafka copies borrowed keys and payloads on send, so no owned copy is needed.
+fn with_buffers<R>(f: impl FnOnce(&mut Vec<u8>, &mut Vec<u8>) -> R) -> R {
+    BUFFERS.with(|buffers| {
+        let (key, buf) = &mut *buffers.borrow_mut();
+        key.clear();
+        buf.clear();
+        let result = f(key, buf);
+        if buf.capacity() > MAX_RETAINED_BUFFER {
+            *buf = Vec::new();
+        }
         result
+    })
+}
+
+fn encode_into(message: &impl Message, buf: &mut Vec<u8>) {
+    buf.reserve(message.encoded_len());
+    message
+        .encode(buf)
+        .expect("encoding into a reserved Vec can't fail");
+}
+
+impl Drop for Publisher {
+    fn drop(&mut self) {
+        drop(self.refresher.take());
+        drop(self.partition_refresher.take());
+        if let Some(gate) = &self.rooted_gate {
+            gate.discard();
+        }
+        let drained = self.pauses.drain();
+        if !drained.is_empty() {
+            info!(
+                "Sending {} messages buffered for paused topics",
+                drained.len()
+            );
+        }
+        for (topic, message) in drained {
+            if let Err(error) = send_failure(self.produce(
+                &topic,
+                &message.key,
+                message.payload.as_deref(),
+                message.partition,
+                &message.headers,
+            )) {
+                warn!("Failed to send message buffered for paused topic {topic}: {error}");
+            }
+        }
+        self.pauses.remove_gauges();
+        if let Err(error) = self.sequences.save() {
+            error!("Failed to save sequence state: {error}");
+        }
+        self.sequences.remove_gauges();
+        self.flush();
     }
+}
 
-    fn encode_with_wrapper(message: EventMessage) -> Vec<u8> {
+#[cfg(test)]
+mod tests {
+    use {
+        super::{Encoder, PublishError},
+        crate::{
+            message_wrapper::EventMessage,
+            partition::{sticky_partition, PartitionSource},
+            reassemble_account, verify_payload_checksum,
+            zstd_dict::{decompress_payload, ZSTD_DICT_ID_HEADER},
+            BlockEvent, CheckpointEvent, Config, ConfigFilter, MessageWrapper, Partitioning,
+            PausePolicy, PayloadCompression, Publisher, RecordedMessage, SchemaCompat,
+            SerializationFailurePolicy, SlotStatus, SlotStatusEvent, TransactionEvent,
+            TransactionKeySource, TransactionStatusMeta, UpdateAccountEvent, CLUSTER_HEADER,
+            KIND_HEADER, PAYLOAD_CHECKSUM_HEADER, PHASE_HEADER, STATUS_HEADER,
+            VALIDATOR_IDENTITY_HEADER,
+        },
+        prost::Message,
+        rdkafka::error::KafkaError,
+        solana_sdk::pubkey::Pubkey,
+        std::{
+            panic::{self, AssertUnwindSafe},
+            time::{Duration, Instant},
+        },
+    };
+
+    fn wrapped(message: EventMessage) -> Vec<u8> {
         MessageWrapper {
             event_message: Some(message),
+            ..Default::default()
         }
         .encode_to_vec()
     }
 
-    fn copy_and_prepend(&self, data: &[u8], prefix: u8) -> Vec<u8> {
-        let mut temp_key = Vec::with_capacity(data.len() + 1);
-        temp_key.push(prefix);
-        temp_key.extend_from_slice(data);
-        temp_key
+    #[test]
+    fn test_emitted_bytes() {
+        let publisher = Publisher::dry_run(&Config::default());
+        let account = UpdateAccountEvent {
+            slot: 9,
+            pubkey: vec![1; 32],
+            owner: vec![2; 32],
+            data: vec![3; 300],
+            ..Default::default()
+        };
+        let slot = SlotStatusEvent {
+            slot: 9,
+            parent: 8,
+            status: 1,
+            ..Default::default()
+        };
+        let transaction = TransactionEvent {
+            signature: vec![4; 64],
+            slot: 9,
+            ..Default::default()
+        };
+        for wrap in [false, true] {
+            publisher
+                .update_account(
+                    account.clone(),
+                    wrap,
+                    Partitioning::KeyHash,
+                    SchemaCompat::Latest,
+                    false,
+                    "a",
+                )
+                .unwrap();
+            publisher
+                .update_slot_status(slot.clone(), wrap, "s")
+                .unwrap();
+            publisher
+                .update_transaction(
+                    transaction.clone(),
+                    wrap,
+                    TransactionKeySource::Signature,
+                    "t",
+                )
+                .unwrap();
+        }
+        let message = |topic: &str, key: Vec<u8>, payload: Vec<u8>| RecordedMessage {
+            topic: topic.to_owned(),
+            key,
+            payload: Some(payload),
+            partition: None,
+            headers: Vec::new(),
+        };
+        let prefixed = |prefix: u8, key: &[u8]| [&[prefix], key].concat();
+        // The wrapped events are second on each topic.
+        let (mut wrapped_account, mut wrapped_slot, mut wrapped_transaction) =
+            (account.clone(), slot.clone(), transaction.clone());
+        wrapped_account.sequence = 1;
+        wrapped_slot.sequence = 1;
+        wrapped_transaction.sequence = 1;
+        assert_eq!(
+            publisher.take_recorded(),
+            vec![
+                message("a", vec![1; 32], account.encode_to_vec()),
+                message("s", 9u64.to_le_bytes().to_vec(), slot.encode_to_vec()),
+                message("t", vec![4; 64], transaction.encode_to_vec()),
+                message(
+                    "a",
+                    prefixed(b'A', &[1; 32]),
+                    wrapped(EventMessage::Account(wrapped_account))
+                ),
+                message(
+                    "s",
+                    prefixed(b'S', &9u64.to_le_bytes()),
+                    wrapped(EventMessage::Slot(wrapped_slot))
+                ),
+                message(
+                    "t",
+                    prefixed(b'T', &[4; 64]),
+                    wrapped(EventMessage::Transaction(wrapped_transaction))
+                ),
+            ]
+        );
+        // Unwrapped slot status, spelled out to catch encoding changes.
+        assert_eq!(slot.encode_to_vec(), [0x08, 9, 0x10, 8, 0x18, 1]);
     }
-}
 
-impl Drop for Publisher {
-    fn drop(&mut self) {
-        let _ = self.producer.flush(self.shutdown_timeout);
+    #[test]
+    fn test_wrapped_bytes_golden() {
+        let publisher = Publisher::dry_run(&Config::default());
+        let account = UpdateAccountEvent {
+            slot: 9,
+            pubkey: vec![1; 2],
+            data: vec![3; 3],
+            ..Default::default()
+        };
+        let slot = SlotStatusEvent {
+            slot: 9,
+            parent: 8,
+            status: 1,
+            ..Default::default()
+        };
+        let transaction = TransactionEvent {
+            signature: vec![4; 2],
+            slot: 9,
+            ..Default::default()
+        };
+        publisher
+            .update_account(
+                account,
+                true,
+                Partitioning::KeyHash,
+                SchemaCompat::Latest,
+                false,
+                "a",
+            )
+            .unwrap();
+        publisher.update_slot_status(slot, true, "s").unwrap();
+        publisher
+            .update_transaction(transaction, true, TransactionKeySource::Signature, "t")
+            .unwrap();
+        let payloads: Vec<_> = publisher
+            .take_recorded()
+            .into_iter()
+            .map(|message| message.payload.unwrap())
+            .collect();
+        // The field of the event in the wrapper, its length and the event, as encoded
+        // while the wrapped event was boxed.
+        assert_eq!(
+            payloads,
+            [
+                vec![0x0a, 11, 0x08, 9, 0x12, 2, 1, 1, 0x3a, 3, 3, 3, 3],
+                vec![0x12, 6, 0x08, 9, 0x10, 8, 0x18, 1],
+                vec![0x1a, 6, 0x0a, 2, 4, 4, 0x28, 9],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_firehose_record_matches_filter_payload() {
+        let event = UpdateAccountEvent {
+            slot: 42,
+            pubkey: vec![1; 32],
+            lamports: 100,
+            owner: vec![2; 32],
+            data: vec![3; 16],
+            write_version: 7,
+            ..Default::default()
+        };
+        let filter_payload = event.encode_to_vec();
+
+        let mut payload = Vec::new();
+        let key = Publisher::dry_run(&Config::default())
+            .firehose_record(EventMessage::Account(event), 42, &mut payload)
+            .unwrap();
+        assert_eq!(key, 42u64.to_le_bytes());
+        let wrapper = MessageWrapper::decode(payload.as_slice()).unwrap();
+        match wrapper.event_message {
+            Some(EventMessage::Account(inner)) => assert_eq!(inner.encode_to_vec(), filter_payload),
+            other => panic!("unexpected firehose message: {other:?}"),
+        }
+    }
+
+    /// Partition source with the same count for every topic.
+    struct Fixed(u32);
+
+    impl PartitionSource for Fixed {
+        fn partition_count(&self, _: &str) -> Result<u32, KafkaError> {
+            Ok(self.0)
+        }
+    }
+
+    #[test]
+    fn test_slot_index_key() {
+        let publisher = Publisher::dry_run(&Config::default());
+        for index in [2u64, 0, 1, 256] {
+            let transaction = TransactionEvent {
+                signature: vec![index as u8; 64],
+                slot: 0x0102,
+                index,
+                ..Default::default()
+            };
+            publisher
+                .update_transaction(
+                    transaction,
+                    index == 256,
+                    TransactionKeySource::SlotIndex,
+                    "t",
+                )
+                .unwrap();
+        }
+        let keys: Vec<_> = publisher
+            .take_recorded()
+            .into_iter()
+            .map(|message| message.key)
+            .collect();
+        let slot = [2, 1, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            keys,
+            [
+                [&slot[..], &[0, 0, 0, 0, 0, 0, 0, 2]].concat(),
+                [&slot[..], &[0, 0, 0, 0, 0, 0, 0, 0]].concat(),
+                [&slot[..], &[0, 0, 0, 0, 0, 0, 0, 1]].concat(),
+                [b"T", &slot[..], &[0, 0, 0, 0, 0, 0, 1, 0]].concat(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_slot_index_partition() {
+        let publisher = Publisher::dry_run(&Config::default());
+        publisher
+            .partition_counts()
+            .refresh(&Fixed(4), &["t".to_owned()]);
+        let send = |slot, index, key_source| {
+            let transaction = TransactionEvent {
+                signature: vec![index as u8; 64],
+                slot,
+                index,
+                ..Default::default()
+            };
+            publisher
+                .update_transaction(transaction, false, key_source, "t")
+                .unwrap();
+            publisher.take_recorded()[0].partition
+        };
+        // Every transaction of a slot lands on the partition of the slot.
+        let partition = send(0x0102, 0, TransactionKeySource::SlotIndex);
+        assert_eq!(partition, Some(2));
+        assert_eq!(send(0x0102, 7, TransactionKeySource::SlotIndex), partition);
+        assert_eq!(send(0x0103, 0, TransactionKeySource::SlotIndex), Some(3));
+        assert_eq!(send(0x0102, 0, TransactionKeySource::Signature), None);
+    }
+
+    #[test]
+    fn test_explicit_partitioning() {
+        let publisher = Publisher::dry_run(&Config::default());
+        let account = |first: u8| UpdateAccountEvent {
+            pubkey: [&[first, 0, 0, 0][..], &[9; 28]].concat(),
+            ..Default::default()
+        };
+        let send_wrapped = |first, partitioning, wrap| {
+            publisher
+                .update_account(
+                    account(first),
+                    wrap,
+                    partitioning,
+                    SchemaCompat::Latest,
+                    false,
+                    "a",
+                )
+                .unwrap();
+            publisher.take_recorded()[0].partition
+        };
+        let send = |first, partitioning| send_wrapped(first, partitioning, false);
+
+        // Unknown partition count falls back to the partitioner.
+        assert_eq!(send(5, Partitioning::Explicit), None);
+
+        let topics = ["a".to_owned()];
+        publisher.partition_counts().refresh(&Fixed(4), &topics);
+        assert_eq!(send(5, Partitioning::Explicit), Some(1));
+        assert_eq!(send(5, Partitioning::KeyHash), None);
+        publisher.partition_counts().refresh(&Fixed(3), &topics);
+        assert_eq!(send(5, Partitioning::Explicit), Some(2));
+
+        // Sticky partitions only depend on the pubkey, not on how the message is keyed or wrapped.
+        let sticky = sticky_partition(&account(5).pubkey, 3);
+        assert!(sticky.is_some());
+        assert_eq!(send(5, Partitioning::Sticky), sticky);
+        assert_eq!(send_wrapped(5, Partitioning::Sticky, true), sticky);
+    }
+
+    #[test]
+    fn test_phase_headers() {
+        let headers = |emit_phase_headers, is_startup, is_vote, is_status_err| {
+            let mut config = Config::default();
+            config.emit_phase_headers = emit_phase_headers;
+            let publisher = Publisher::dry_run(&config);
+            publisher
+                .update_account(
+                    UpdateAccountEvent::default(),
+                    false,
+                    Partitioning::KeyHash,
+                    SchemaCompat::Latest,
+                    is_startup,
+                    "a",
+                )
+                .unwrap();
+            let transaction = TransactionEvent {
+                is_vote,
+                transaction_status_meta: Some(TransactionStatusMeta {
+                    is_status_err,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            publisher
+                .update_transaction(transaction, true, TransactionKeySource::Signature, "t")
+                .unwrap();
+            let recorded = publisher.take_recorded();
+            let values = |message: &RecordedMessage| {
+                message
+                    .headers
+                    .iter()
+                    .map(|(name, value)| format!("{name}={}", String::from_utf8_lossy(value)))
+                    .collect::<Vec<_>>()
+            };
+            (values(&recorded[0]), values(&recorded[1]))
+        };
+
+        assert_eq!(
+            headers(false, true, true, true),
+            (vec![], vec![]),
+            "disabled by default"
+        );
+        let phase = |value: &str| vec![format!("{PHASE_HEADER}={value}")];
+        let transaction = |status: &str, kind: &str| {
+            vec![
+                format!("{STATUS_HEADER}={status}"),
+                format!("{KIND_HEADER}={kind}"),
+            ]
+        };
+        assert_eq!(
+            headers(true, true, false, false),
+            (phase("startup"), transaction("ok", "nonvote"))
+        );
+        assert_eq!(
+            headers(true, false, true, true),
+            (phase("live"), transaction("failed", "vote"))
+        );
+    }
+
+    #[test]
+    fn test_payload_checksum() {
+        let mut config = Config::default();
+        config.emit_payload_checksum = true;
+        let publisher = Publisher::dry_run(&config);
+        publisher
+            .update_account(
+                UpdateAccountEvent {
+                    pubkey: vec![1; 32],
+                    data: vec![2; 64],
+                    ..Default::default()
+                },
+                false,
+                Partitioning::KeyHash,
+                SchemaCompat::Latest,
+                false,
+                "checksum-accounts",
+            )
+            .unwrap();
+        publisher
+            .update_slot_status(SlotStatusEvent::default(), true, "checksum-slots")
+            .unwrap();
+        publisher
+            .update_transaction(
+                TransactionEvent {
+                    signature: vec![4; 64],
+                    ..Default::default()
+                },
+                false,
+                TransactionKeySource::Signature,
+                "checksum-txs",
+            )
+            .unwrap();
+        publisher
+            .update_block(BlockEvent::default(), true, "checksum-blocks")
+            .unwrap();
+        publisher
+            .delete_account_state(1, &[1; 32], false, "checksum-states")
+            .unwrap();
+
+        let recorded = publisher.take_recorded();
+        assert_eq!(recorded.len(), 5);
+        for message in &recorded[..4] {
+            let [(name, checksum)] = message.headers.as_slice() else {
+                panic!("one header expected on {}", message.topic);
+            };
+            assert_eq!(name, PAYLOAD_CHECKSUM_HEADER);
+            let mut payload = message.payload.clone().unwrap();
+            assert!(verify_payload_checksum(&payload, checksum));
+            let last = payload.len() - 1;
+            payload[last] ^= 1;
+            assert!(!verify_payload_checksum(&payload, checksum));
+        }
+        // Tombstones have no payload to check.
+        assert!(recorded[4].headers.is_empty());
+    }
+
+    #[test]
+    fn test_identity() {
+        let identity = Pubkey::new_unique();
+        let mut config = Config::default();
+        config.cluster_name = "testnet".to_owned();
+        config.validator_identity = Some(identity.to_string());
+        let slot = SlotStatusEvent::default();
+
+        // Only wrapped messages carry the identity without `emit_identity_headers`.
+        let publisher = Publisher::dry_run(&config);
+        publisher
+            .update_slot_status(slot.clone(), false, "s")
+            .unwrap();
+        publisher
+            .update_slot_status(slot.clone(), true, "s")
+            .unwrap();
+        let recorded = publisher.take_recorded();
+        assert!(recorded.iter().all(|message| message.headers.is_empty()));
+        assert_eq!(recorded[0].payload, Some(slot.encode_to_vec()));
+        let wrapper = MessageWrapper::decode(recorded[1].payload.as_deref().unwrap()).unwrap();
+        assert_eq!(wrapper.cluster_name, "testnet");
+        assert_eq!(wrapper.validator_identity, identity.to_bytes());
+
+        config.emit_identity_headers = true;
+        let publisher = Publisher::dry_run(&config);
+        publisher.update_slot_status(slot, false, "s").unwrap();
+        publisher
+            .delete_account_state(1, &[1; 32], false, "states")
+            .unwrap();
+        for message in publisher.take_recorded() {
+            assert_eq!(
+                message.headers,
+                [
+                    (CLUSTER_HEADER.to_owned(), b"testnet".to_vec()),
+                    (
+                        VALIDATOR_IDENTITY_HEADER.to_owned(),
+                        identity.to_string().into_bytes()
+                    ),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_chunked_accounts() {
+        let mut config = Config::default();
+        config.chunk_threshold_bytes = Some(1024);
+        config.strict_ordering_assertions = true;
+        let publisher = Publisher::dry_run(&config);
+        let account = |data_len: usize| UpdateAccountEvent {
+            slot: 5,
+            pubkey: vec![1; 32],
+            owner: vec![2; 32],
+            data: (0..data_len).map(|i| (i % 256) as u8).collect(),
+            write_version: 3,
+            ..Default::default()
+        };
+        let publish = |ev: UpdateAccountEvent, wrap_messages: bool| {
+            publisher
+                .update_account(
+                    ev,
+                    wrap_messages,
+                    Partitioning::KeyHash,
+                    SchemaCompat::Latest,
+                    false,
+                    "chunked-accounts",
+                )
+                .unwrap();
+            publisher.take_recorded()
+        };
+        let recorded = publish(account(5_000), false);
+        assert!(recorded.len() >= 5);
+        let mut chunks = Vec::new();
+        for message in &recorded {
+            assert_eq!(message.key, recorded[0].key);
+            let payload = message.payload.as_deref().unwrap();
+            assert!(payload.len() <= 1024);
+            chunks.push(UpdateAccountEvent::decode(payload).unwrap());
+        }
+        // Every chunk carries the sequence of the update.
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.sequence == chunks[0].sequence));
+        chunks.reverse();
+        let expected = UpdateAccountEvent {
+            sequence: chunks[0].sequence,
+            ..account(5_000)
+        };
+        assert_eq!(reassemble_account(chunks), Ok(expected));
+
+        let recorded = publish(account(5_000), true);
+        assert!(recorded.len() >= 5);
+        let chunks = recorded.iter().map(|message| {
+            let wrapper = MessageWrapper::decode(message.payload.as_deref().unwrap()).unwrap();
+            match wrapper.event_message {
+                Some(EventMessage::Account(ev)) => ev,
+                other => panic!("unexpected {other:?}"),
+            }
+        });
+        assert_eq!(
+            reassemble_account(chunks).unwrap().data,
+            account(5_000).data
+        );
+
+        // Updates that fit are left alone.
+        let recorded = publish(account(100), false);
+        let [message] = recorded.as_slice() else {
+            panic!("one message expected");
+        };
+        let ev = UpdateAccountEvent::decode(message.payload.as_deref().unwrap()).unwrap();
+        assert!(ev.chunk.is_none());
+        // Chunks of an update stamp the same slot and write version.
+        assert_eq!(publisher.metrics().ordering_violations_total.get(), 0);
+    }
+
+    #[test]
+    fn test_zstd_dict_compression() {
+        let mut config = Config::default();
+        config.payload_compression = PayloadCompression::ZstdDict;
+        config.zstd_dict_samples = 100;
+        let publisher = Publisher::dry_run(&config);
+        let account = |i: u64| UpdateAccountEvent {
+            pubkey: vec![(i % 251) as u8; 32],
+            owner: vec![3; 32],
+            lamports: 1_000_000 + i,
+            data: [&b"layout-v1"[..], &i.to_le_bytes(), &[0; 96], b"authority"].concat(),
+            ..Default::default()
+        };
+        let send = |i| {
+            publisher
+                .update_account(
+                    account(i),
+                    false,
+                    Partitioning::KeyHash,
+                    SchemaCompat::Latest,
+                    false,
+                    "accounts",
+                )
+                .unwrap();
+            publisher.take_recorded().remove(0)
+        };
+
+        // Sampled payloads go out as they are.
+        for i in 0..100 {
+            let message = send(i);
+            assert!(message.headers.is_empty());
+            UpdateAccountEvent::decode(message.payload.as_deref().unwrap()).unwrap();
+        }
+        let dictionaries = publisher.zstd_dictionaries().unwrap();
+        let dictionary = (0..500)
+            .find_map(|_| {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                dictionaries.dictionary(&[3; 32])
+            })
+            .unwrap();
+
+        let message = send(1000);
+        assert_eq!(
+            message.headers,
+            [(
+                ZSTD_DICT_ID_HEADER.to_owned(),
+                dictionary.id.to_string().into_bytes()
+            )]
+        );
+        let payload =
+            decompress_payload(message.payload.as_deref().unwrap(), &dictionary.bytes).unwrap();
+        let event = UpdateAccountEvent::decode(payload.as_slice()).unwrap();
+        assert_eq!(event.lamports, 1_001_000);
+        assert_eq!(event.data, account(1000).data);
+    }
+
+    #[test]
+    fn test_checkpoint_payload() {
+        let event = CheckpointEvent {
+            sequence: 3,
+            timestamp: 1_700_000_000_000,
+            last_slot: 250_000_000,
+        };
+
+        let publisher = Publisher::dry_run(&Config::default());
+        let payload = publisher.encode_checkpoint(event, false);
+        assert_eq!(CheckpointEvent::decode(payload.as_slice()).unwrap(), event);
+
+        let payload = publisher.encode_checkpoint(event, true);
+        let wrapper = MessageWrapper::decode(payload.as_slice()).unwrap();
+        assert_eq!(wrapper.event_message, Some(EventMessage::Checkpoint(event)));
+    }
+
+    #[test]
+    fn test_paused_topic() {
+        let mut config = Config::default();
+        config.filters = vec![ConfigFilter {
+            update_account_topic: Some("paused-accounts".to_owned()),
+            transaction_topic: Some("paused-txs".to_owned()),
+            ..Default::default()
+        }];
+        config.pause_policy = PausePolicy::Buffer;
+        let publisher = Publisher::dry_run(&config);
+        let transaction = |index| {
+            publisher
+                .update_transaction(
+                    TransactionEvent {
+                        slot: 1,
+                        index,
+                        ..Default::default()
+                    },
+                    false,
+                    TransactionKeySource::SlotIndex,
+                    "paused-txs",
+                )
+                .unwrap()
+        };
+        let recorded = || {
+            publisher
+                .take_recorded()
+                .into_iter()
+                .map(|message| message.topic)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(publisher.pauses().pause("paused-txs"));
+        transaction(0);
+        transaction(1);
+        publisher
+            .update_account(
+                UpdateAccountEvent::default(),
+                false,
+                Partitioning::KeyHash,
+                SchemaCompat::Latest,
+                false,
+                "paused-accounts",
+            )
+            .unwrap();
+        assert_eq!(recorded(), ["paused-accounts"]);
+
+        // Buffered transactions go out in order ahead of the next one.
+        assert!(publisher.pauses().resume("paused-txs"));
+        assert!(recorded().is_empty());
+        transaction(2);
+        let indexes: Vec<u8> = publisher
+            .take_recorded()
+            .into_iter()
+            .map(|message| message.key[15])
+            .collect();
+        assert_eq!(indexes, [0, 1, 2]);
+    }
+
+    #[test]
+    fn test_standby() {
+        let mut config = Config::default();
+        config.standby = true;
+        let publisher = Publisher::dry_run(&config);
+        let send = || {
+            let account = publisher.update_account(
+                UpdateAccountEvent::default(),
+                false,
+                Partitioning::KeyHash,
+                SchemaCompat::Latest,
+                false,
+                "standby-accounts",
+            );
+            let slot =
+                publisher.update_slot_status(SlotStatusEvent::default(), false, "standby-slots");
+            (account, slot)
+        };
+        let held = |topic| {
+            publisher
+                .metrics()
+                .standby_messages_total
+                .with_label_values(&[topic])
+                .get()
+        };
+        let uploads = |status| {
+            let metrics = publisher.metrics();
+            (
+                metrics
+                    .upload_accounts_total
+                    .with_label_values(&[status])
+                    .get(),
+                metrics
+                    .upload_slots_total
+                    .with_label_values(&[status])
+                    .get(),
+            )
+        };
+
+        let (account, slot) = send();
+        assert!(matches!(account, Err(PublishError::Standby)), "{account:?}");
+        assert!(matches!(slot, Err(PublishError::Standby)), "{slot:?}");
+        assert!(publisher.take_recorded().is_empty());
+        assert_eq!((held("standby-accounts"), held("standby-slots")), (1, 1));
+        assert_eq!(uploads("success"), (0, 0));
+        assert_eq!(uploads("held"), (1, 1));
+
+        assert!(publisher.standby().set(false));
+        let (account, slot) = send();
+        account.unwrap();
+        slot.unwrap();
+        let topics: Vec<String> = publisher
+            .take_recorded()
+            .into_iter()
+            .map(|message| message.topic)
+            .collect();
+        assert_eq!(topics, ["standby-accounts", "standby-slots"]);
+        assert_eq!(held("standby-accounts"), 1);
+        assert_eq!(uploads("success"), (1, 1));
+        assert_eq!(uploads("held"), (1, 1));
+
+        // Held messages aren't taken for produced by the ordering check.
+        config.strict_ordering_assertions = true;
+        let publisher = Publisher::dry_run(&config);
+        let update = |write_version| {
+            let event = UpdateAccountEvent {
+                slot: 7,
+                pubkey: vec![1; 32],
+                write_version,
+                ..Default::default()
+            };
+            let (partitioning, schema) = (Partitioning::KeyHash, SchemaCompat::Latest);
+            publisher.update_account(
+                event,
+                false,
+                partitioning,
+                schema,
+                false,
+                "standby-accounts",
+            )
+        };
+        assert!(matches!(update(2), Err(PublishError::Standby)));
+        assert!(publisher.standby().set(false));
+        update(1).unwrap();
+        assert_eq!(publisher.metrics().ordering_violations_total.get(), 0);
+    }
+
+    #[test]
+    fn test_serialization_failures() {
+        let serialization_failed = |publisher: &Publisher| {
+            publisher
+                .metrics
+                .upload_transactions_total
+                .with_label_values(&["serialization_failed"])
+                .get()
+        };
+        let send = |publisher: &Publisher| {
+            publisher.update_transaction(
+                TransactionEvent::default(),
+                true,
+                TransactionKeySource::Signature,
+                "serialize-txs",
+            )
+        };
+        let mut config = Config::default();
+        let mut publisher = Publisher::dry_run(&config);
+        publisher.encoder = Encoder::Failing;
+        let error = send(&publisher).unwrap_err();
+        assert!(matches!(error, PublishError::Serialization(_)), "{error:?}");
+        assert_eq!(error.to_string(), "serialization failed: failing encoder");
+        assert_eq!(serialization_failed(&publisher), 1);
+        assert!(publisher.take_recorded().is_empty());
+
+        config.serialization_failure_policy = SerializationFailurePolicy::Drop;
+        let mut publisher = Publisher::dry_run(&config);
+        publisher.encoder = Encoder::Failing;
+        send(&publisher).unwrap();
+        assert_eq!(serialization_failed(&publisher), 1);
+        assert!(publisher.take_recorded().is_empty());
+
+        // Send failures aren't dropped.
+        publisher.encoder = Encoder::Protobuf;
+        publisher.fail_recorded_topic("serialize-txs");
+        assert!(matches!(send(&publisher), Err(PublishError::Kafka(_))));
+    }
+
+    #[test]
+    fn test_strict_ordering() {
+        let mut config = Config::default();
+        config.strict_ordering_assertions = true;
+        config.filters = vec![ConfigFilter {
+            update_account_topic: Some("accounts".to_owned()),
+            ..Default::default()
+        }];
+        config.pause_policy = PausePolicy::Buffer;
+        let publisher = Publisher::dry_run(&config);
+        let update = |write_version| {
+            let event = UpdateAccountEvent {
+                slot: 7,
+                pubkey: vec![1; 32],
+                write_version,
+                ..Default::default()
+            };
+            let partitioning = Partitioning::KeyHash;
+            let schema = SchemaCompat::Latest;
+            panic::catch_unwind(AssertUnwindSafe(|| {
+                publisher.update_account(event, false, partitioning, schema, false, "accounts")
+            }))
+        };
+        let write_versions = || {
+            publisher
+                .take_recorded()
+                .into_iter()
+                .map(|message| {
+                    assert!(message.headers.is_empty(), "order stamp is internal");
+                    UpdateAccountEvent::decode(message.payload.unwrap().as_slice())
+                        .unwrap()
+                        .write_version
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Updates buffered while paused go out in order.
+        assert!(publisher.pauses().pause("accounts"));
+        update(1).unwrap().unwrap();
+        update(2).unwrap().unwrap();
+        assert!(publisher.pauses().resume("accounts"));
+        update(3).unwrap().unwrap();
+        assert_eq!(write_versions(), [1, 2, 3]);
+
+        assert_eq!(publisher.metrics().ordering_violations_total.get(), 0);
+        match update(2) {
+            Ok(result) => result.unwrap(),
+            Err(_) if cfg!(feature = "abort-on-ordering-violation") => {}
+            Err(panic) => panic::resume_unwind(panic),
+        }
+        assert_eq!(publisher.metrics().ordering_violations_total.get(), 1);
+    }
+
+    #[test]
+    fn test_rooted_barrier() {
+        let mut config = Config::default();
+        config.rooted_barrier = true;
+        config.rooted_barrier_timeout_ms = 1_000;
+        let publisher = Publisher::dry_run(&config);
+        let deliveries = publisher.rooted_barrier.as_ref().unwrap().deliveries();
+        let status = |slot, status: SlotStatus| {
+            let event = SlotStatusEvent {
+                slot,
+                status: status.into(),
+                ..Default::default()
+            };
+            publisher
+                .update_slot_status(event, false, "barrier-slots")
+                .unwrap();
+        };
+        let published = || {
+            publisher
+                .take_recorded()
+                .into_iter()
+                .map(|message| {
+                    let event =
+                        SlotStatusEvent::decode(message.payload.unwrap().as_slice()).unwrap();
+                    (event.slot, event.status(), event.barrier_timeout)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The slot is internal to the publisher.
+        publisher
+            .update_transaction(
+                TransactionEvent::default(),
+                false,
+                TransactionKeySource::Signature,
+                "barrier-txs",
+            )
+            .unwrap();
+        assert!(publisher.take_recorded()[0].headers.is_empty());
+
+        // A delivery of slot 5 is delayed, holding back its root and the next one.
+        deliveries.enqueued(5);
+        status(5, SlotStatus::Processed);
+        status(5, SlotStatus::Rooted);
+        status(6, SlotStatus::Rooted);
+        assert_eq!(published(), [(5, SlotStatus::Processed, false)]);
+        let now = Instant::now();
+        publisher.release_rooted_statuses(now, false);
+        assert!(published().is_empty());
+
+        deliveries.delivered(5);
+        publisher.release_rooted_statuses(now, false);
+        assert_eq!(
+            published(),
+            [
+                (5, SlotStatus::Rooted, false),
+                (6, SlotStatus::Rooted, false)
+            ]
+        );
+        // Sequences follow the order statuses were published in.
+        status(7, SlotStatus::Rooted);
+        assert_eq!(published(), [(7, SlotStatus::Rooted, false)]);
+
+        // Never delivered.
+        deliveries.enqueued(8);
+        status(8, SlotStatus::Rooted);
+        publisher.release_rooted_statuses(Instant::now(), false);
+        assert!(published().is_empty());
+        publisher.release_rooted_statuses(Instant::now() + Duration::from_secs(1), false);
+        assert_eq!(published(), [(8, SlotStatus::Rooted, true)]);
     }
 }
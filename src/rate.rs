@@ -0,0 +1,296 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::instance_metrics::InstanceMetrics,
+    serde_json::{json, Map, Value},
+    std::{
+        fmt::{Debug, Formatter},
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Instant,
+    },
+};
+
+/// Length of the sliding window, in seconds.
+const WINDOW_SECS: u64 = 60;
+
+const COUNT_MASK: u64 = u32::MAX as u64;
+
+/// Source of whole seconds for rate tracking.
+pub trait Clock: Send + Sync {
+    fn now_secs(&self) -> u64;
+}
+
+/// Seconds elapsed since creation.
+pub struct MonotonicClock(Instant);
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self(Instant::now())
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now_secs(&self) -> u64 {
+        self.0.elapsed().as_secs()
+    }
+}
+
+/// Events per second over the last minute, from a ring of per-second buckets.
+/// Each bucket packs the second it counts (upper 32 bits) and the count (lower 32 bits)
+/// into one atomic, so recording is a single compare-and-swap without locks.
+pub struct RateTracker {
+    buckets: [AtomicU64; WINDOW_SECS as usize],
+}
+
+impl Default for RateTracker {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+}
+
+impl RateTracker {
+    pub fn record(&self, now: u64) {
+        let bucket = &self.buckets[(now % WINDOW_SECS) as usize];
+        let _ = bucket.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |packed| {
+            let (second, count) = unpack(packed);
+            let count = if second == now { count + 1 } else { 1 };
+            Some(pack(now, count))
+        });
+    }
+
+    /// Average events per second over the window ending at `now`, inclusive.
+    pub fn rate(&self, now: u64) -> f64 {
+        let total: u64 = self
+            .buckets
+            .iter()
+            .map(|bucket| unpack(bucket.load(Ordering::Relaxed)))
+            .filter(|(second, _)| *second <= now && now - second < WINDOW_SECS)
+            .map(|(_, count)| count)
+            .sum();
+        total as f64 / WINDOW_SECS as f64
+    }
+}
+
+fn pack(second: u64, count: u64) -> u64 {
+    (second & COUNT_MASK) << 32 | count.min(COUNT_MASK)
+}
+
+fn unpack(packed: u64) -> (u64, u64) {
+    (packed >> 32, packed & COUNT_MASK)
+}
+
+struct FilterRate {
+    name: String,
+    accounts: RateTracker,
+    transactions: RateTracker,
+}
+
+/// Account and transaction publish rates of each filter, by filter index.
+pub struct FilterRates {
+    clock: Arc<dyn Clock>,
+    filters: Vec<FilterRate>,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl Debug for FilterRates {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.filters.iter().map(|filter| &filter.name))
+            .finish()
+    }
+}
+
+impl Default for FilterRates {
+    fn default() -> Self {
+        Self::new(Vec::new(), Arc::default())
+    }
+}
+
+impl FilterRates {
+    pub fn new(names: Vec<String>, metrics: Arc<InstanceMetrics>) -> Self {
+        Self::with_clock(names, Arc::new(MonotonicClock::default()), metrics)
+    }
+
+    pub fn with_clock(
+        names: Vec<String>,
+        clock: Arc<dyn Clock>,
+        metrics: Arc<InstanceMetrics>,
+    ) -> Self {
+        let filters = names
+            .into_iter()
+            .map(|name| FilterRate {
+                name,
+                accounts: Default::default(),
+                transactions: Default::default(),
+            })
+            .collect();
+        Self {
+            clock,
+            filters,
+            metrics,
+        }
+    }
+
+    pub fn record_account(&self, index: usize) {
+        if let Some(filter) = self.filters.get(index) {
+            filter.accounts.record(self.clock.now_secs());
+        }
+    }
+
+    pub fn record_transaction(&self, index: usize) {
+        if let Some(filter) = self.filters.get(index) {
+            filter.transactions.record(self.clock.now_secs());
+        }
+    }
+
+    /// Name, account rate and transaction rate of each filter.
+    pub fn rates(&self) -> impl Iterator<Item = (&str, f64, f64)> {
+        let now = self.clock.now_secs();
+        self.filters.iter().map(move |filter| {
+            (
+                filter.name.as_str(),
+                filter.accounts.rate(now),
+                filter.transactions.rate(now),
+            )
+        })
+    }
+
+    /// Rates of the filter at `index` as JSON.
+    pub fn to_json(&self, index: usize) -> Value {
+        self.rates()
+            .nth(index)
+            .map(|(_, accounts, transactions)| {
+                json!({
+                    "account_rate_1m": accounts,
+                    "transaction_rate_1m": transactions,
+                })
+            })
+            .unwrap_or_else(|| Value::Object(Map::new()))
+    }
+
+    /// Set the `filter_account_rate_1m` and `filter_transaction_rate_1m` gauges.
+    pub fn refresh_gauges(&self) {
+        for (name, accounts, transactions) in self.rates() {
+            self.metrics
+                .filter_account_rate_1m
+                .with_label_values(&[name])
+                .set(accounts);
+            self.metrics
+                .filter_transaction_rate_1m
+                .with_label_values(&[name])
+                .set(transactions);
+        }
+    }
+
+    /// Remove this filter set's gauges, so renamed filters don't linger after a reload.
+    pub fn remove_gauges(&self) {
+        for filter in &self.filters {
+            let metrics = &self.metrics;
+            let _ = metrics
+                .filter_account_rate_1m
+                .remove_label_values(&[&filter.name]);
+            let _ = metrics
+                .filter_transaction_rate_1m
+                .remove_label_values(&[&filter.name]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct MockClock(AtomicU64);
+
+    impl MockClock {
+        fn set(&self, secs: u64) {
+            self.0.store(secs, Ordering::Relaxed);
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now_secs(&self) -> u64 {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    #[test]
+    fn test_window() {
+        let tracker = RateTracker::default();
+        assert_eq!(tracker.rate(0), 0.0);
+
+        // 60 events in each of the first two seconds.
+        for _ in 0..60 {
+            tracker.record(100);
+            tracker.record(101);
+        }
+        assert_eq!(tracker.rate(101), 2.0);
+        assert_eq!(tracker.rate(159), 2.0);
+        // Second 100 falls out of the window, then 101.
+        assert_eq!(tracker.rate(160), 1.0);
+        assert_eq!(tracker.rate(161), 0.0);
+
+        // A bucket reused a minute later starts counting from scratch.
+        tracker.record(160);
+        assert_eq!(tracker.rate(160), 61.0 / 60.0);
+        assert_eq!(tracker.rate(161), 1.0 / 60.0);
+        assert_eq!(tracker.rate(219), 1.0 / 60.0);
+        assert_eq!(tracker.rate(220), 0.0);
+    }
+
+    #[test]
+    fn test_filter_rates() {
+        let clock = Arc::new(MockClock::default());
+        let rates = FilterRates::with_clock(
+            vec!["rate-accounts".to_owned(), "rate-transactions".to_owned()],
+            Arc::clone(&clock) as Arc<dyn Clock>,
+            Arc::default(),
+        );
+        for second in 0..30 {
+            clock.set(second);
+            rates.record_account(0);
+            rates.record_account(0);
+            rates.record_transaction(1);
+        }
+        rates.record_account(5);
+
+        clock.set(45);
+        assert_eq!(
+            rates.rates().collect::<Vec<_>>(),
+            vec![("rate-accounts", 1.0, 0.0), ("rate-transactions", 0.0, 0.5)]
+        );
+        assert_eq!(
+            rates.to_json(1),
+            json!({"account_rate_1m": 0.0, "transaction_rate_1m": 0.5})
+        );
+
+        rates.refresh_gauges();
+        let rates_1m = &rates.metrics.filter_account_rate_1m;
+        let gauge = || rates_1m.with_label_values(&["rate-accounts"]);
+        assert_eq!(gauge().get(), 1.0);
+
+        clock.set(90);
+        rates.refresh_gauges();
+        assert_eq!(gauge().get(), 0.0);
+        rates.remove_gauges();
+        assert!(rates_1m.remove_label_values(&["rate-accounts"]).is_err());
+    }
+}
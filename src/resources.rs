@@ -0,0 +1,167 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Resources used by the plugin within the validator process: memory held by its buffers and
+//! caches, sampled from the components registered in `MemoryFootprints`, and the threads it
+//! owns, counted while they run.
+
+use {
+    crate::instance_metrics::InstanceMetrics,
+    prometheus::IntGauge,
+    std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        fmt,
+        mem::size_of,
+        sync::{Arc, Mutex},
+        time::Duration,
+    },
+};
+
+/// Period of sampling the registered components.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Component holding memory that grows with the data flowing through the plugin.
+pub trait MemoryFootprint: Send + Sync {
+    /// Approximate bytes held on the heap. Cheap enough to compute every `SAMPLE_INTERVAL`.
+    fn memory_footprint(&self) -> usize;
+}
+
+/// Heap bytes of the buckets of `set`, with a control byte each.
+pub fn hash_set_bytes<T>(set: &HashSet<T>) -> usize {
+    set.capacity() * (size_of::<T>() + 1)
+}
+
+/// Heap bytes of the buckets of `map`, with a control byte each.
+pub fn hash_map_bytes<K, V>(map: &HashMap<K, V>) -> usize {
+    map.capacity() * (size_of::<(K, V)>() + 1)
+}
+
+/// Components of a loaded plugin exported in `plugin_memory_bytes`, by component name.
+#[derive(Default)]
+pub struct MemoryFootprints {
+    components: Mutex<Vec<(&'static str, Arc<dyn MemoryFootprint>)>>,
+}
+
+impl fmt::Debug for MemoryFootprints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let components = self.components.lock().unwrap();
+        f.debug_list()
+            .entries(components.iter().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+impl MemoryFootprints {
+    /// Add `component`, summed with the others registered as `name`.
+    pub fn register(&self, name: &'static str, component: Arc<dyn MemoryFootprint>) {
+        self.components.lock().unwrap().push((name, component));
+    }
+
+    /// Bytes held by each component name.
+    pub fn sample(&self) -> BTreeMap<&'static str, usize> {
+        let mut sampled = BTreeMap::new();
+        for (name, component) in self.components.lock().unwrap().iter() {
+            *sampled.entry(*name).or_default() += component.memory_footprint();
+        }
+        sampled
+    }
+
+    /// Set `plugin_memory_bytes` of every component name in `metrics`.
+    pub fn export(&self, metrics: &InstanceMetrics) {
+        for (name, bytes) in self.sample() {
+            metrics
+                .plugin_memory_bytes
+                .with_label_values(&[name])
+                .set(bytes as i64);
+        }
+    }
+}
+
+/// Sample the memory of `footprints` every `SAMPLE_INTERVAL`, on the Prometheus runtime.
+pub async fn sample_footprints(footprints: Arc<MemoryFootprints>, metrics: Arc<InstanceMetrics>) {
+    let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+    loop {
+        interval.tick().await;
+        footprints.export(&metrics);
+    }
+}
+
+/// Counts a plugin owned thread of `kind` in `plugin_threads` until dropped.
+#[derive(Debug)]
+pub struct ThreadGuard {
+    threads: IntGauge,
+}
+
+impl ThreadGuard {
+    pub fn new(metrics: &InstanceMetrics, kind: &str) -> Self {
+        let threads = metrics.plugin_threads.with_label_values(&[kind]);
+        threads.inc();
+        Self { threads }
+    }
+}
+
+impl Drop for ThreadGuard {
+    fn drop(&mut self) {
+        self.threads.dec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    struct Buffer(AtomicUsize);
+
+    impl MemoryFootprint for Buffer {
+        fn memory_footprint(&self) -> usize {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    #[test]
+    fn test_memory_footprints() {
+        let footprints = MemoryFootprints::default();
+        let buffer = Arc::new(Buffer(AtomicUsize::new(100)));
+        footprints.register("buffers", buffer.clone());
+        footprints.register("buffers", Arc::new(Buffer(AtomicUsize::new(20))));
+        footprints.register("cache", Arc::new(Buffer(AtomicUsize::new(7))));
+        assert_eq!(
+            footprints.sample().into_iter().collect::<Vec<_>>(),
+            [("buffers", 120), ("cache", 7)]
+        );
+
+        // Sampled again on every export.
+        buffer.0.store(300, Ordering::Relaxed);
+        let metrics = InstanceMetrics::default();
+        footprints.export(&metrics);
+        let gauge = metrics.plugin_memory_bytes.with_label_values(&["buffers"]);
+        assert_eq!(gauge.get(), 320);
+    }
+
+    #[test]
+    fn test_thread_guard() {
+        let metrics = InstanceMetrics::default();
+        let threads = metrics.plugin_threads.with_label_values(&["background"]);
+        let guard = ThreadGuard::new(&metrics, "background");
+        let other = ThreadGuard::new(&metrics, "background");
+        assert_eq!(threads.get(), 2);
+        drop(guard);
+        assert_eq!(threads.get(), 1);
+        drop(other);
+        assert_eq!(threads.get(), 0);
+    }
+}
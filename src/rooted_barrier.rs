@@ -0,0 +1,323 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `rooted_barrier`, holding back the rooted status of a slot until the messages of the slot
+//! enqueued so far are delivered, or `rooted_barrier_timeout_ms` passes.
+//!
+//! Messages of a slot carry it in an internal header through pausing and `rooted_only`
+//! buffering, and are counted from the moment they are handed to the producer until their
+//! delivery report through the delivery opaque.
+
+use {
+    crate::{
+        instance_metrics::InstanceMetrics,
+        resources::{hash_map_bytes, MemoryFootprint, ThreadGuard},
+        MessageHeaders, Publisher, SlotStatusEvent,
+    },
+    std::{
+        collections::{HashMap, VecDeque},
+        mem::size_of,
+        sync::{
+            mpsc::{self, RecvTimeoutError, Sender},
+            Arc, Mutex,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    },
+};
+
+/// Internal header of the slot of a message counted by the barrier, never produced.
+pub const BARRIER_SLOT_HEADER: &str = "x-plugin-barrier-slot";
+
+/// Period of the thread publishing released rooted statuses.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Header carrying `slot`, big endian.
+pub fn barrier_slot(slot: u64) -> (String, Vec<u8>) {
+    (BARRIER_SLOT_HEADER.to_owned(), slot.to_be_bytes().to_vec())
+}
+
+/// `headers` without the barrier slot header, and the slot it carried.
+pub fn take_barrier_slot(headers: &[(String, Vec<u8>)]) -> (MessageHeaders, Option<u64>) {
+    let mut stripped = MessageHeaders::with_capacity(headers.len());
+    let mut slot = None;
+    for (name, value) in headers {
+        match <[u8; 8]>::try_from(value.as_slice()) {
+            Ok(value) if name == BARRIER_SLOT_HEADER => slot = Some(u64::from_be_bytes(value)),
+            _ => stripped.push((name.clone(), value.clone())),
+        }
+    }
+    (stripped, slot)
+}
+
+/// Messages enqueued and not yet reported delivered or failed, by slot. Shared by the producers
+/// replacing each other.
+#[derive(Debug, Default)]
+pub struct SlotDeliveries {
+    outstanding: Mutex<HashMap<u64, u64>>,
+}
+
+impl SlotDeliveries {
+    /// Count a message of `slot`, before it is handed to the producer so its delivery report
+    /// can't come first.
+    pub fn enqueued(&self, slot: u64) {
+        *self.outstanding.lock().unwrap().entry(slot).or_default() += 1;
+    }
+
+    /// Account for the delivery report of a message of `slot`, or for it failing to enqueue.
+    pub fn delivered(&self, slot: u64) {
+        let mut outstanding = self.outstanding.lock().unwrap();
+        if let Some(count) = outstanding.get_mut(&slot) {
+            *count -= 1;
+            if *count == 0 {
+                outstanding.remove(&slot);
+            }
+        }
+    }
+
+    pub fn outstanding(&self, slot: u64) -> u64 {
+        self.outstanding
+            .lock()
+            .unwrap()
+            .get(&slot)
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+/// Rooted slot status held back by the barrier.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeferredStatus {
+    pub event: SlotStatusEvent,
+    pub wrap_messages: bool,
+    pub topic: String,
+    until: Instant,
+}
+
+/// Rooted slot statuses waiting for the deliveries of their slot, in the order they arrived.
+#[derive(Debug)]
+pub struct RootedBarrier {
+    deliveries: Arc<SlotDeliveries>,
+    timeout: Duration,
+    deferred: Mutex<VecDeque<DeferredStatus>>,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl RootedBarrier {
+    pub fn new(
+        deliveries: Arc<SlotDeliveries>,
+        timeout: Duration,
+        metrics: Arc<InstanceMetrics>,
+    ) -> Self {
+        Self {
+            deliveries,
+            timeout,
+            deferred: Mutex::default(),
+            metrics,
+        }
+    }
+
+    pub fn deliveries(&self) -> &SlotDeliveries {
+        &self.deliveries
+    }
+
+    /// Hold back the rooted status `event` if messages of its slot are outstanding at `now`, or
+    /// earlier statuses are held back, keeping rooted statuses in order. Returns whether it was.
+    pub fn defer(
+        &self,
+        event: SlotStatusEvent,
+        wrap_messages: bool,
+        topic: &str,
+        now: Instant,
+    ) -> bool {
+        let mut deferred = self.deferred.lock().unwrap();
+        if deferred.is_empty() && self.deliveries.outstanding(event.slot) == 0 {
+            return false;
+        }
+        deferred.push_back(DeferredStatus {
+            event,
+            wrap_messages,
+            topic: topic.to_owned(),
+            until: now + self.timeout,
+        });
+        self.metrics
+            .rooted_barrier_statuses_total
+            .with_label_values(&["deferred"])
+            .inc();
+        true
+    }
+
+    /// Statuses to publish at `now`, in order: those whose slot has no message outstanding, and
+    /// those whose timeout passed with `barrier_timeout` set. Every held back status with
+    /// `flush`, as on unload.
+    pub fn release(&self, now: Instant, flush: bool) -> Vec<DeferredStatus> {
+        let mut deferred = self.deferred.lock().unwrap();
+        let mut released = Vec::new();
+        while let Some(front) = deferred.front() {
+            let delivered = self.deliveries.outstanding(front.event.slot) == 0;
+            if !delivered && !flush && now < front.until {
+                break;
+            }
+            let mut status = deferred.pop_front().unwrap();
+            status.event.barrier_timeout = !delivered;
+            let action = if delivered { "released" } else { "timed_out" };
+            self.metrics
+                .rooted_barrier_statuses_total
+                .with_label_values(&[action])
+                .inc();
+            released.push(status);
+        }
+        released
+    }
+}
+
+/// Statuses held back, with the messages outstanding by slot.
+impl MemoryFootprint for RootedBarrier {
+    fn memory_footprint(&self) -> usize {
+        let deferred = self.deferred.lock().unwrap();
+        let topics: usize = deferred.iter().map(|status| status.topic.capacity()).sum();
+        let outstanding = hash_map_bytes(&self.deliveries.outstanding.lock().unwrap());
+        deferred.capacity() * size_of::<DeferredStatus>() + topics + outstanding
+    }
+}
+
+/// Background thread publishing the rooted statuses released by the barrier, and those still
+/// held back when dropped.
+pub struct RootedBarrierPoller {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl RootedBarrierPoller {
+    pub fn spawn(publisher: Arc<Publisher>) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("kafkaRootedBarrier".to_owned())
+            .spawn(move || {
+                let _thread = ThreadGuard::new(&publisher.metrics(), "background");
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(POLL_INTERVAL) {
+                    publisher.release_rooted_statuses(Instant::now(), false);
+                }
+                publisher.release_rooted_statuses(Instant::now(), true);
+            })
+            .expect("failed to spawn rooted barrier thread");
+        Self {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for RootedBarrierPoller {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::SlotStatus};
+
+    fn rooted(slot: u64) -> SlotStatusEvent {
+        SlotStatusEvent {
+            slot,
+            status: SlotStatus::Rooted.into(),
+            ..Default::default()
+        }
+    }
+
+    fn slots(released: &[DeferredStatus]) -> Vec<(u64, bool)> {
+        released
+            .iter()
+            .map(|status| (status.event.slot, status.event.barrier_timeout))
+            .collect()
+    }
+
+    #[test]
+    fn test_barrier_slot_header() {
+        let headers = [("phase".to_owned(), b"live".to_vec()), barrier_slot(300)];
+        let (stripped, slot) = take_barrier_slot(&headers);
+        assert_eq!(stripped, [headers[0].clone()]);
+        assert_eq!(slot, Some(300));
+        assert_eq!(take_barrier_slot(&headers[..1]), (stripped, None));
+    }
+
+    #[test]
+    fn test_delayed_deliveries() {
+        let deliveries = Arc::new(SlotDeliveries::default());
+        let barrier = RootedBarrier::new(
+            Arc::clone(&deliveries),
+            Duration::from_secs(5),
+            Arc::default(),
+        );
+        let now = Instant::now();
+
+        // Nothing outstanding, published right away.
+        assert!(!barrier.defer(rooted(9), false, "slots", now));
+
+        deliveries.enqueued(10);
+        deliveries.enqueued(10);
+        deliveries.enqueued(11);
+        assert!(barrier.defer(rooted(10), false, "slots", now));
+        // Held back behind slot 10 although its own messages are delivered.
+        assert!(barrier.defer(rooted(12), true, "slots", now));
+        assert!(barrier.release(now, false).is_empty());
+
+        deliveries.delivered(10);
+        assert_eq!(deliveries.outstanding(10), 1);
+        assert!(barrier.release(now, false).is_empty());
+        deliveries.delivered(10);
+        let released = barrier.release(now, false);
+        assert_eq!(slots(&released), [(10, false), (12, false)]);
+        assert!(released[1].wrap_messages);
+
+        // Reports of messages not counted are ignored.
+        deliveries.delivered(12);
+        assert_eq!(deliveries.outstanding(11), 1);
+        assert_eq!(deliveries.outstanding(12), 0);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let deliveries = Arc::new(SlotDeliveries::default());
+        let barrier = RootedBarrier::new(
+            Arc::clone(&deliveries),
+            Duration::from_secs(5),
+            Arc::default(),
+        );
+        let now = Instant::now();
+        deliveries.enqueued(20);
+        deliveries.enqueued(21);
+        assert!(barrier.defer(rooted(20), false, "slots", now));
+        assert!(barrier.defer(rooted(21), false, "slots", now + Duration::from_secs(1)));
+
+        assert!(barrier
+            .release(now + Duration::from_millis(4999), false)
+            .is_empty());
+        let released = barrier.release(now + Duration::from_secs(5), false);
+        assert_eq!(slots(&released), [(20, true)]);
+
+        // Delivered before its own timeout.
+        deliveries.delivered(21);
+        let released = barrier.release(now + Duration::from_secs(5), false);
+        assert_eq!(slots(&released), [(21, false)]);
+
+        deliveries.enqueued(22);
+        assert!(barrier.defer(rooted(22), false, "slots", now));
+        assert_eq!(slots(&barrier.release(now, true)), [(22, true)]);
+    }
+}
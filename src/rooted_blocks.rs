@@ -0,0 +1,92 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `enrich_rooted_slot_with_block`, block metadata held until the slot is rooted to be
+//! embedded in its rooted status, so one topic carries both.
+
+use {
+    crate::{instance_metrics::InstanceMetrics, slot_tracker::SlotTracker, BlockEvent},
+    std::sync::{Arc, Mutex},
+};
+
+/// Blocks held at once, the least recently notified are dropped first if none get rooted.
+const MAX_TRACKED_SLOTS: usize = 512;
+
+/// Block events by slot, until the slot is rooted.
+#[derive(Debug)]
+pub struct RootedBlocks {
+    blocks: Mutex<SlotTracker<BlockEvent>>,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl RootedBlocks {
+    pub fn new(metrics: Arc<InstanceMetrics>) -> Self {
+        let blocks = SlotTracker::new("rooted_blocks", MAX_TRACKED_SLOTS, &metrics);
+        Self {
+            blocks: Mutex::new(blocks),
+            metrics,
+        }
+    }
+
+    /// Hold the block of `slot`, replacing any notified before.
+    pub fn insert(&self, slot: u64, block: BlockEvent) {
+        let mut blocks = self.blocks.lock().unwrap();
+        *blocks.get_or_insert_with(slot, BlockEvent::default).0 = block;
+    }
+
+    /// Block of the rooted `slot`, counted in `rooted_slots_without_block_total` if missing.
+    /// Blocks of lower slots are dropped, they were left behind on forks that died.
+    pub fn take_rooted(&self, slot: u64) -> Option<BlockEvent> {
+        let block = self
+            .blocks
+            .lock()
+            .unwrap()
+            .remove_through(slot)
+            .pop()
+            .filter(|(block_slot, _)| *block_slot == slot)
+            .map(|(_, block)| block);
+        if block.is_none() {
+            self.metrics.rooted_slots_without_block_total.inc();
+        }
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(slot: u64) -> BlockEvent {
+        BlockEvent {
+            slot,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_take_rooted() {
+        let blocks = RootedBlocks::new(Arc::default());
+        blocks.insert(5, block(5));
+        blocks.insert(6, block(6));
+        blocks.insert(8, block(8));
+
+        assert_eq!(blocks.take_rooted(6), Some(block(6)));
+        // The block of 5 was dropped with the root at 6.
+        assert_eq!(blocks.take_rooted(5), None);
+        assert_eq!(blocks.take_rooted(7), None);
+        assert_eq!(blocks.metrics.rooted_slots_without_block_total.get(), 2);
+        assert_eq!(blocks.take_rooted(8), Some(block(8)));
+        assert_eq!(blocks.take_rooted(8), None);
+    }
+}
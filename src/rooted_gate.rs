@@ -0,0 +1,347 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{
+        instance_metrics::InstanceMetrics, pause::SpilledMessage, resources::MemoryFootprint,
+        slot_tracker::SlotTracker,
+    },
+    log::warn,
+    std::{
+        collections::BTreeSet,
+        mem::size_of,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+    },
+};
+
+/// Rooted slots remembered, slots older than all of them are taken as rooted.
+const MAX_ROOTED_SLOTS: usize = 1024;
+
+/// Slots with messages held back at once, the least recently sent to are dropped beyond it.
+const MAX_PENDING_SLOTS: usize = 1024;
+
+#[derive(Debug)]
+struct GateState {
+    rooted: BTreeSet<u64>,
+    /// Slots notified dead, that won't be rooted.
+    dead: BTreeSet<u64>,
+    /// Messages of slots not rooted yet, by slot, with their topic.
+    pending: SlotTracker<Vec<(String, SpilledMessage)>>,
+    bytes: usize,
+}
+
+impl GateState {
+    fn is_rooted(&self, slot: u64) -> bool {
+        self.rooted.contains(&slot)
+            || (self.rooted.len() >= MAX_ROOTED_SLOTS
+                && self.rooted.first().is_some_and(|first| slot < *first))
+    }
+
+    /// Whether `slot` can't be rooted anymore, as it died or a later slot is rooted and it isn't.
+    fn is_abandoned(&self, slot: u64) -> bool {
+        self.dead.contains(&slot)
+            || (self.rooted.last().is_some_and(|last| slot < *last) && !self.is_rooted(slot))
+    }
+}
+
+/// Holds back messages of slots until they are rooted, for `rooted_only`. Messages of slots
+/// that died or were passed over by a later root are on abandoned forks and dropped.
+#[derive(Debug)]
+pub struct RootedGate {
+    max_bytes: usize,
+    /// Slot of the snapshot startup accounts are notified from, which is rooted.
+    startup_slot: AtomicU64,
+    state: Mutex<GateState>,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl RootedGate {
+    pub fn new(max_bytes: usize, metrics: Arc<InstanceMetrics>) -> Self {
+        Self {
+            max_bytes,
+            startup_slot: AtomicU64::default(),
+            state: Mutex::new(GateState {
+                rooted: BTreeSet::new(),
+                dead: BTreeSet::new(),
+                pending: SlotTracker::new("rooted_only", MAX_PENDING_SLOTS, &metrics),
+                bytes: 0,
+            }),
+            metrics,
+        }
+    }
+
+    /// Let through messages of `slot` and earlier, as notified at startup.
+    pub fn observe_startup_slot(&self, slot: u64) {
+        self.startup_slot.fetch_max(slot, Ordering::Relaxed);
+    }
+
+    /// Send a message of `slot` with `produce` if the slot is rooted, otherwise buffer it up to
+    /// `max_bytes` or drop it if its slot was abandoned.
+    #[allow(clippy::too_many_arguments)]
+    pub fn send<E>(
+        &self,
+        slot: u64,
+        topic: &str,
+        key: &[u8],
+        payload: Option<&[u8]>,
+        partition: Option<i32>,
+        headers: &[(String, Vec<u8>)],
+        produce: impl FnOnce(&[u8], Option<&[u8]>, Option<i32>, &[(String, Vec<u8>)]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if slot <= self.startup_slot.load(Ordering::Relaxed) {
+            return produce(key, payload, partition, headers);
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.is_rooted(slot) {
+            // Only after the lock, so messages flushed on rooting go out first.
+            drop(state);
+            return produce(key, payload, partition, headers);
+        }
+        let action = if state.is_abandoned(slot) {
+            "dropped_abandoned"
+        } else {
+            let message = SpilledMessage {
+                key: key.to_vec(),
+                payload: payload.map(<[u8]>::to_vec),
+                partition,
+                headers: headers.to_vec(),
+            };
+            if state.bytes + message.len() <= self.max_bytes {
+                state.bytes += message.len();
+                let (messages, evicted) = state.pending.get_or_insert_with(slot, Vec::new);
+                messages.push((topic.to_owned(), message));
+                if let Some((_, evicted)) = evicted {
+                    for (_, message) in &evicted {
+                        state.bytes -= message.len();
+                    }
+                    self.metrics
+                        .rooted_only_messages_total
+                        .with_label_values(&["dropped_evicted"])
+                        .inc_by(evicted.len() as u64);
+                }
+                self.metrics
+                    .rooted_only_buffered_bytes
+                    .set(state.bytes as i64);
+                "buffered"
+            } else {
+                "dropped_full"
+            }
+        };
+        self.metrics
+            .rooted_only_messages_total
+            .with_label_values(&[action])
+            .inc();
+        Ok(())
+    }
+
+    /// Record `slot` as rooted, sending its buffered messages in order with `produce` and
+    /// dropping those of earlier slots that weren't rooted. Every message is attempted, the
+    /// first error is returned.
+    pub fn root<E>(
+        &self,
+        slot: u64,
+        mut produce: impl FnMut(&str, &SpilledMessage) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let mut state = self.state.lock().unwrap();
+        state.rooted.insert(slot);
+        while state.rooted.len() > MAX_ROOTED_SLOTS {
+            state.rooted.pop_first();
+        }
+        // Slots up to the root are abandoned by it unless rooted, dead or not.
+        state.dead = state.dead.split_off(&slot);
+        let settled = state.pending.remove_through(slot);
+        let mut result = Ok(());
+        for (pending_slot, messages) in settled {
+            for (topic, message) in messages {
+                state.bytes -= message.len();
+                let action = if pending_slot != slot {
+                    "dropped_abandoned"
+                } else {
+                    match produce(&topic, &message) {
+                        Ok(()) => "flushed",
+                        Err(error) => {
+                            if result.is_ok() {
+                                result = Err(error);
+                            }
+                            "failed"
+                        }
+                    }
+                };
+                self.metrics
+                    .rooted_only_messages_total
+                    .with_label_values(&[action])
+                    .inc();
+            }
+        }
+        self.metrics
+            .rooted_only_buffered_bytes
+            .set(state.bytes as i64);
+        result
+    }
+
+    /// Drop the buffered messages of `slot`, notified dead, along with any arriving later.
+    pub fn drop_slot(&self, slot: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.is_rooted(slot) {
+            return;
+        }
+        state.dead.insert(slot);
+        while state.dead.len() > MAX_ROOTED_SLOTS {
+            state.dead.pop_first();
+        }
+        let messages = state.pending.remove(slot).unwrap_or_default();
+        for (_, message) in &messages {
+            state.bytes -= message.len();
+        }
+        self.metrics
+            .rooted_only_messages_total
+            .with_label_values(&["dropped_dead"])
+            .inc_by(messages.len() as u64);
+        self.metrics
+            .rooted_only_buffered_bytes
+            .set(state.bytes as i64);
+    }
+
+    /// Drop what is still buffered, returning the number of messages.
+    pub fn discard(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let discarded = state.pending.values().map(Vec::len).sum();
+        if discarded > 0 {
+            warn!("Dropping {discarded} messages of slots that weren't rooted");
+        }
+        state.pending.drain();
+        state.bytes = 0;
+        self.metrics.rooted_only_buffered_bytes.set(0);
+        discarded
+    }
+}
+
+/// Messages held back, with the rooted slots remembered.
+impl MemoryFootprint for RootedGate {
+    fn memory_footprint(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        let messages: usize = state
+            .pending
+            .values()
+            .map(|messages| messages.capacity() * size_of::<(String, SpilledMessage)>())
+            .sum();
+        state.bytes + messages + state.rooted.len() * size_of::<u64>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(gate: &RootedGate, slot: u64, key: u8, sent: &mut Vec<u8>) {
+        gate.send::<()>(
+            slot,
+            "txs",
+            &[key],
+            Some(&[0; 4]),
+            None,
+            &[],
+            |key, _, _, _| {
+                sent.push(key[0]);
+                Ok(())
+            },
+        )
+        .unwrap();
+    }
+
+    fn root(gate: &RootedGate, slot: u64, sent: &mut Vec<u8>) {
+        gate.root::<()>(slot, |_, message| {
+            sent.push(message.key[0]);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_buffer_until_rooted() {
+        let gate = RootedGate::new(1024, Arc::default());
+        let mut sent = Vec::new();
+        send(&gate, 10, 1, &mut sent);
+        send(&gate, 11, 2, &mut sent);
+        send(&gate, 10, 3, &mut sent);
+        assert!(sent.is_empty());
+
+        root(&gate, 10, &mut sent);
+        assert_eq!(sent, [1, 3]);
+        // Later messages of a rooted slot go out right away.
+        send(&gate, 10, 4, &mut sent);
+        assert_eq!(sent, [1, 3, 4]);
+        root(&gate, 11, &mut sent);
+        assert_eq!(sent, [1, 3, 4, 2]);
+        assert_eq!(gate.discard(), 0);
+    }
+
+    #[test]
+    fn test_drop_abandoned() {
+        let gate = RootedGate::new(1024, Arc::default());
+        let mut sent = Vec::new();
+        send(&gate, 20, 1, &mut sent);
+        send(&gate, 21, 2, &mut sent);
+        send(&gate, 22, 3, &mut sent);
+        // Slot 21 was on a fork that 22 didn't build on.
+        root(&gate, 20, &mut sent);
+        root(&gate, 22, &mut sent);
+        assert_eq!(sent, [1, 3]);
+        // Late messages of an abandoned slot are dropped too.
+        send(&gate, 21, 4, &mut sent);
+        assert_eq!(sent, [1, 3]);
+        assert_eq!(gate.state.lock().unwrap().bytes, 0);
+    }
+
+    #[test]
+    fn test_drop_on_dead() {
+        let gate = RootedGate::new(1024, Arc::default());
+        let mut sent = Vec::new();
+        send(&gate, 40, 1, &mut sent);
+        send(&gate, 41, 2, &mut sent);
+        gate.drop_slot(41);
+        assert_eq!(gate.state.lock().unwrap().bytes, 5);
+        // Late messages of a dead slot are dropped too, without waiting for a later root.
+        send(&gate, 41, 3, &mut sent);
+        assert_eq!(gate.state.lock().unwrap().bytes, 5);
+        root(&gate, 40, &mut sent);
+        assert_eq!(sent, [1]);
+        assert_eq!(gate.state.lock().unwrap().bytes, 0);
+        // A rooted slot isn't taken back.
+        gate.drop_slot(40);
+        send(&gate, 40, 4, &mut sent);
+        assert_eq!(sent, [1, 4]);
+    }
+
+    #[test]
+    fn test_budget_and_startup() {
+        // Room for two messages of five bytes.
+        let gate = RootedGate::new(12, Arc::default());
+        let mut sent = Vec::new();
+        for key in 1..=3 {
+            send(&gate, 30, key, &mut sent);
+        }
+        root(&gate, 30, &mut sent);
+        assert_eq!(sent, [1, 2]);
+
+        gate.observe_startup_slot(100);
+        send(&gate, 100, 4, &mut sent);
+        assert_eq!(sent, [1, 2, 4]);
+        send(&gate, 101, 5, &mut sent);
+        assert_eq!(gate.discard(), 1);
+    }
+}
@@ -0,0 +1,249 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{instance_metrics::InstanceMetrics, resources::ThreadGuard, Config, KafkaProducer},
+    arc_swap::ArcSwap,
+    log::{error, info, warn},
+    rdkafka::producer::Producer,
+    std::{
+        fmt::Display,
+        fs,
+        path::{Path, PathBuf},
+        sync::{
+            mpsc::{self, RecvTimeoutError, Sender},
+            Arc,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, Instant, SystemTime},
+    },
+};
+
+/// Tracks modification times of credential files.
+pub struct CredentialWatcher {
+    /// Files with the modification times the producer was last created with.
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    /// Modification times seen by the last `poll_changed`, until committed.
+    seen: Vec<Option<SystemTime>>,
+}
+
+impl CredentialWatcher {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        let files = paths
+            .into_iter()
+            .map(|path| {
+                let mtime = Self::mtime(&path);
+                (path, mtime)
+            })
+            .collect();
+        Self {
+            files,
+            seen: Vec::new(),
+        }
+    }
+
+    /// Returns true if any watched file changed since the last `commit`, so a rotation that
+    /// failed is retried on the next poll.
+    pub fn poll_changed(&mut self) -> bool {
+        self.seen = self
+            .files
+            .iter()
+            .map(|(path, _)| Self::mtime(path))
+            .collect();
+        let mut changed = false;
+        for ((path, mtime), current) in self.files.iter().zip(&self.seen) {
+            if current != mtime {
+                info!("Credential file {path:?} changed");
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Take the modification times seen by the last `poll_changed` as those of the current
+    /// producer, once it was created with the changed files.
+    pub fn commit(&mut self) {
+        for ((_, mtime), seen) in self.files.iter_mut().zip(&self.seen) {
+            *mtime = *seen;
+        }
+    }
+
+    fn mtime(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|meta| meta.modified()).ok()
+    }
+}
+
+/// Swap in a producer created by `factory`, returning the previous one.
+/// On failure the current producer is kept.
+pub fn swap_producer<P, E: Display>(
+    metrics: &InstanceMetrics,
+    current: &ArcSwap<P>,
+    factory: impl FnOnce() -> Result<P, E>,
+) -> Option<Arc<P>> {
+    match factory() {
+        Ok(producer) => {
+            metrics
+                .credential_rotations_total
+                .with_label_values(&["success"])
+                .inc();
+            Some(current.swap(Arc::new(producer)))
+        }
+        Err(error) => {
+            error!("Failed to create producer with refreshed credentials: {error}");
+            metrics
+                .credential_rotations_total
+                .with_label_values(&["failed"])
+                .inc();
+            None
+        }
+    }
+}
+
+/// Wait for in-flight users of a replaced producer to finish, then drain it.
+pub fn retire_producer<P>(mut old: Arc<P>, timeout: Duration, drain: impl FnOnce(&P)) {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match Arc::try_unwrap(old) {
+            Ok(producer) => return drain(&producer),
+            Err(shared) if Instant::now() >= deadline => {
+                warn!("Retiring producer still in use after {timeout:?}");
+                return drain(&shared);
+            }
+            Err(shared) => {
+                old = shared;
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+}
+
+/// Background thread re-creating the producer when credential files change.
+pub struct CredentialRefresher {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl CredentialRefresher {
+    pub fn spawn(
+        config: Config,
+        producer: Arc<ArcSwap<KafkaProducer>>,
+        interval: Duration,
+        shutdown_timeout: Duration,
+    ) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let mut watcher = CredentialWatcher::new(config.credential_paths());
+        let stats = Arc::clone(producer.load().context().kafka_stats());
+        let handle = thread::Builder::new()
+            .name("kafkaCredRefresh".to_owned())
+            .spawn(move || {
+                let metrics = stats.metrics();
+                let _thread = ThreadGuard::new(metrics, "background");
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    if !watcher.poll_changed() {
+                        continue;
+                    }
+                    if let Some(old) = swap_producer(metrics, &producer, || {
+                        config.producer_with_stats(Arc::clone(&stats))
+                    }) {
+                        info!("Swapped producer after credential change");
+                        watcher.commit();
+                        retire_producer(old, shutdown_timeout, |old| {
+                            let _ = old.flush(shutdown_timeout);
+                        });
+                    }
+                }
+            })
+            .expect("failed to spawn credential refresh thread");
+        Self {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for CredentialRefresher {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{fs::File, io::Write},
+    };
+
+    #[test]
+    fn test_swap_producer() {
+        let metrics = InstanceMetrics::default();
+        let current = ArcSwap::from_pointee(1u32);
+
+        let old = swap_producer(&metrics, &current, || Ok::<_, String>(2u32));
+        assert_eq!(old.as_deref(), Some(&1));
+        assert_eq!(**current.load(), 2);
+
+        let old = swap_producer(&metrics, &current, || Err::<u32, _>("bad cert".to_owned()));
+        assert!(old.is_none());
+        assert_eq!(**current.load(), 2);
+        let rotations = |status| {
+            metrics
+                .credential_rotations_total
+                .with_label_values(&[status])
+                .get()
+        };
+        assert_eq!((rotations("success"), rotations("failed")), (1, 1));
+    }
+
+    #[test]
+    fn test_retire_producer_waits_for_users() {
+        let old = Arc::new(7u32);
+        let user = Arc::clone(&old);
+        let released = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            let released = Instant::now();
+            drop(user);
+            released
+        });
+        let mut drained_at = None;
+        retire_producer(old, Duration::from_secs(5), |value| {
+            assert_eq!(*value, 7);
+            drained_at = Some(Instant::now());
+        });
+        assert!(drained_at.unwrap() >= released.join().unwrap());
+    }
+
+    #[test]
+    fn test_watcher_detects_change() {
+        let path = std::env::temp_dir().join(format!("kafka-plugin-cred-{}", std::process::id()));
+        File::create(&path).unwrap().write_all(b"a").unwrap();
+        let mut watcher = CredentialWatcher::new(vec![path.clone()]);
+        assert!(!watcher.poll_changed());
+
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        assert!(watcher.poll_changed());
+        // Until a producer was created with the change, as after a failed rotation.
+        assert!(watcher.poll_changed());
+        watcher.commit();
+        assert!(!watcher.poll_changed());
+
+        fs::remove_file(&path).unwrap();
+        assert!(watcher.poll_changed());
+    }
+}
@@ -0,0 +1,238 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::instance_metrics::InstanceMetrics,
+    log::{error, info},
+    prometheus::IntGaugeVec,
+    std::{
+        collections::{BTreeMap, HashMap},
+        fs,
+        io::{Error as IoError, ErrorKind, Result as IoResult},
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Mutex, RwLock,
+        },
+    },
+};
+
+/// Sequence numbers saved ahead of use, so a run that ends without saving is continued after
+/// them rather than from an older state.
+const RESERVED_SEQUENCES: u64 = 1 << 20;
+
+#[derive(Debug, Default)]
+struct TopicSequence {
+    next: AtomicU64,
+    /// Numbers below this are covered by the saved state.
+    reserved: AtomicU64,
+}
+
+/// Per topic sequence numbers of published events, optionally persisted across restarts.
+#[derive(Debug)]
+pub struct Sequences {
+    topics: RwLock<HashMap<String, TopicSequence>>,
+    /// `sequence_state_path`.
+    path: Option<PathBuf>,
+    /// Held while reserving, so each exhausted reservation is saved once.
+    reserving: Mutex<()>,
+    /// `topic_sequence` of the instance.
+    gauges: IntGaugeVec,
+}
+
+impl Sequences {
+    /// Sequences starting at 0, not persisted.
+    pub fn new(metrics: &InstanceMetrics) -> Self {
+        Self {
+            topics: RwLock::default(),
+            path: None,
+            reserving: Mutex::default(),
+            gauges: metrics.topic_sequence.clone(),
+        }
+    }
+
+    /// Sequences continuing from the state saved at `path`, or starting at 0 if there is none.
+    pub fn load(path: &Path, metrics: &InstanceMetrics) -> IoResult<Self> {
+        let saved: BTreeMap<String, u64> = match fs::read(path) {
+            Ok(json) => serde_json::from_slice(&json)
+                .map_err(|error| IoError::new(ErrorKind::InvalidData, error))?,
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                info!("No sequence state at {path:?}, sequences start at 0");
+                BTreeMap::new()
+            }
+            Err(error) => return Err(error),
+        };
+        let topics = saved
+            .into_iter()
+            .map(|(topic, next)| {
+                let sequence = TopicSequence {
+                    next: AtomicU64::new(next),
+                    reserved: AtomicU64::new(next),
+                };
+                (topic, sequence)
+            })
+            .collect();
+        Ok(Self {
+            topics: RwLock::new(topics),
+            path: Some(path.to_owned()),
+            reserving: Mutex::default(),
+            gauges: metrics.topic_sequence.clone(),
+        })
+    }
+
+    /// Assign the next sequence number of `topic`.
+    pub fn next(&self, topic: &str) -> u64 {
+        let (sequence, reserved) = self.assign(topic);
+        if self.path.is_some() && sequence >= reserved {
+            self.reserve(topic, sequence);
+        }
+        self.gauges.with_label_values(&[topic]).set(sequence as i64);
+        sequence
+    }
+
+    /// Take the next number of `topic`, returning it with the topic's reservation.
+    fn assign(&self, topic: &str) -> (u64, u64) {
+        let take = |sequence: &TopicSequence| {
+            let next = sequence.next.fetch_add(1, Ordering::Relaxed);
+            (next, sequence.reserved.load(Ordering::Relaxed))
+        };
+        if let Some(sequence) = self.topics.read().unwrap().get(topic) {
+            return take(sequence);
+        }
+        take(
+            self.topics
+                .write()
+                .unwrap()
+                .entry(topic.to_owned())
+                .or_default(),
+        )
+    }
+
+    /// Save a reservation of `topic` past `sequence`, unless another caller just did.
+    fn reserve(&self, topic: &str, sequence: u64) {
+        let _reserving = self.reserving.lock().unwrap();
+        if let Some(reserved) = self.topics.read().unwrap().get(topic).map(|s| &s.reserved) {
+            if sequence < reserved.load(Ordering::Relaxed) {
+                return;
+            }
+            reserved.store(sequence + RESERVED_SEQUENCES, Ordering::Relaxed);
+        }
+        let saved = self.write(|sequence| {
+            sequence
+                .reserved
+                .load(Ordering::Relaxed)
+                .max(sequence.next.load(Ordering::Relaxed))
+        });
+        if let Err(error) = saved {
+            error!("Failed to save sequence state: {error}");
+        }
+    }
+
+    /// Save the next sequence number of every topic to `sequence_state_path`, if set.
+    pub fn save(&self) -> IoResult<()> {
+        let _reserving = self.reserving.lock().unwrap();
+        self.write(|sequence| sequence.next.load(Ordering::Relaxed))
+    }
+
+    /// Write the state, through a temporary file so a failed write leaves the previous one intact.
+    fn write(&self, next: impl Fn(&TopicSequence) -> u64) -> IoResult<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let topics = self.topics.read().unwrap();
+        let saved: BTreeMap<&str, u64> = topics
+            .iter()
+            .map(|(topic, sequence)| (topic.as_str(), next(sequence)))
+            .collect();
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, serde_json::to_vec(&saved)?)?;
+        fs::rename(&temporary, path)
+    }
+
+    pub fn remove_gauges(&self) {
+        for topic in self.topics.read().unwrap().keys() {
+            let _ = self.gauges.remove_label_values(&[topic]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::env};
+
+    fn state_path(test: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("sequence-{test}-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_assignment() {
+        let metrics = InstanceMetrics::default();
+        let sequences = Sequences::new(&metrics);
+        assert_eq!(sequences.next("seq-a"), 0);
+        assert_eq!(sequences.next("seq-a"), 1);
+        assert_eq!(sequences.next("seq-b"), 0);
+        assert_eq!(sequences.next("seq-a"), 2);
+        assert_eq!(
+            metrics.topic_sequence.with_label_values(&["seq-a"]).get(),
+            2
+        );
+        // Nothing to save to.
+        sequences.save().unwrap();
+    }
+
+    #[test]
+    fn test_persistence() {
+        // Fresh start.
+        let path = state_path("persistence");
+        let sequences = Sequences::load(&path, &InstanceMetrics::default()).unwrap();
+        assert_eq!(sequences.next("a"), 0);
+        assert_eq!(sequences.next("a"), 1);
+        assert_eq!(sequences.next("b"), 0);
+        sequences.save().unwrap();
+
+        let sequences = Sequences::load(&path, &InstanceMetrics::default()).unwrap();
+        assert_eq!(sequences.next("a"), 2);
+        assert_eq!(sequences.next("b"), 1);
+        assert_eq!(sequences.next("c"), 0);
+        sequences.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), r#"{"a":3,"b":2,"c":1}"#);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_unsaved_run() {
+        let path = state_path("unsaved");
+        let sequences = Sequences::load(&path, &InstanceMetrics::default()).unwrap();
+        assert_eq!(sequences.next("a"), 0);
+        assert_eq!(sequences.next("a"), 1);
+        drop(sequences);
+
+        // Continued after the reservation rather than from 0.
+        let sequences = Sequences::load(&path, &InstanceMetrics::default()).unwrap();
+        assert_eq!(sequences.next("a"), RESERVED_SEQUENCES);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_state() {
+        let path = state_path("invalid");
+        fs::write(&path, "[1, 2]").unwrap();
+        let error = Sequences::load(&path, &InstanceMetrics::default()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{instance_metrics::InstanceMetrics, slot_tracker::SlotTracker},
+    log::warn,
+    std::sync::{Arc, Mutex},
+};
+
+/// Slots tracked at once, the least recently charged are forgotten first if none get rooted.
+const MAX_TRACKED_SLOTS: usize = 1024;
+
+#[derive(Debug, Default)]
+struct SlotUsage {
+    bytes: u64,
+    suppressed: u64,
+}
+
+/// Encoded bytes of the account messages sent per slot, limited to `max_account_bytes_per_slot`.
+#[derive(Debug)]
+pub struct SlotBudget {
+    max_bytes: u64,
+    slots: Mutex<SlotTracker<SlotUsage>>,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl SlotBudget {
+    pub fn new(max_bytes: u64, metrics: Arc<InstanceMetrics>) -> Self {
+        let slots = SlotTracker::new("slot_budget", MAX_TRACKED_SLOTS, &metrics);
+        Self {
+            max_bytes,
+            slots: Mutex::new(slots),
+            metrics,
+        }
+    }
+
+    /// Charge an account message of `bytes` in `slot`, returning whether it fits the budget.
+    /// Once an update doesn't, every later one of the slot is suppressed.
+    pub fn admit(&self, slot: u64, bytes: u64) -> bool {
+        let mut slots = self.slots.lock().unwrap();
+        let (usage, _) = slots.get_or_insert_with(slot, SlotUsage::default);
+        if usage.suppressed == 0 && usage.bytes + bytes <= self.max_bytes {
+            usage.bytes += bytes;
+            return true;
+        }
+        if usage.suppressed == 0 {
+            warn!(
+                "Account updates of slot {slot} exceed {} bytes, suppressing the rest",
+                self.max_bytes
+            );
+            self.metrics.slots_over_account_budget_total.inc();
+        }
+        usage.suppressed += 1;
+        self.metrics.account_updates_suppressed_budget_total.inc();
+        false
+    }
+
+    /// Charge `bytes` in `slot` whether they fit or not, for the rest of an admitted update.
+    pub fn charge(&self, slot: u64, bytes: u64) {
+        let mut slots = self.slots.lock().unwrap();
+        slots.get_or_insert_with(slot, SlotUsage::default).0.bytes += bytes;
+    }
+
+    /// Account updates of `slot` suppressed so far.
+    pub fn suppressed(&self, slot: u64) -> u64 {
+        let slots = self.slots.lock().unwrap();
+        slots.peek(slot).map_or(0, |usage| usage.suppressed)
+    }
+
+    /// Forget the slots up to the rooted `slot`.
+    pub fn root(&self, slot: u64) {
+        self.slots.lock().unwrap().remove_through(slot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget() {
+        let budget = SlotBudget::new(100, Arc::default());
+        assert!(budget.admit(1, 60));
+        assert!(budget.admit(1, 40));
+        assert!(!budget.admit(1, 1));
+        // Smaller updates that would fit are suppressed too, the slot is incomplete anyway.
+        assert!(!budget.admit(1, 0));
+        assert_eq!(budget.suppressed(1), 2);
+        let metrics = &budget.metrics;
+        assert_eq!(metrics.account_updates_suppressed_budget_total.get(), 2);
+        assert_eq!(metrics.slots_over_account_budget_total.get(), 1);
+
+        // Every slot has its own budget.
+        assert!(budget.admit(2, 100));
+        assert_eq!(budget.suppressed(2), 0);
+
+        budget.root(1);
+        assert_eq!(budget.suppressed(1), 0);
+        assert!(!budget.admit(2, 1));
+        assert_eq!(budget.suppressed(2), 1);
+
+        // The rest of an admitted update is charged past the budget, suppressing what follows.
+        assert!(budget.admit(3, 90));
+        budget.charge(3, 20);
+        assert_eq!(budget.suppressed(3), 0);
+        assert!(!budget.admit(3, 0));
+        assert_eq!(budget.suppressed(3), 1);
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Events arriving before any status of their slot.
+//!
+//! Account updates and transactions are notified while a slot is replayed, before its first
+//! status, and block metadata may come on either side of it. Per slot state is therefore
+//! created by whichever event of a slot comes first, never by its statuses, and the events
+//! seen ahead of them are counted in `events_before_slot_status_total`.
+
+use {
+    crate::{instance_metrics::InstanceMetrics, slot_tracker::SlotTracker},
+    std::sync::{Arc, RwLock},
+};
+
+/// Slots remembered as having a status, the least recently notified are forgotten first.
+const MAX_TRACKED_SLOTS: usize = 1024;
+
+/// Slots with a status notified.
+#[derive(Debug)]
+pub struct SlotStatusesSeen {
+    slots: RwLock<SlotTracker<()>>,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl SlotStatusesSeen {
+    pub fn new(metrics: Arc<InstanceMetrics>) -> Self {
+        let slots = SlotTracker::new("slot_status_seen", MAX_TRACKED_SLOTS, &metrics);
+        Self {
+            slots: RwLock::new(slots),
+            metrics,
+        }
+    }
+
+    pub fn observe_status(&self, slot: u64) {
+        self.slots.write().unwrap().get_or_insert_with(slot, || ());
+    }
+
+    /// Count an event of `event_type` in `slot` if no status of the slot was notified yet,
+    /// returning whether it came first.
+    pub fn observe_event(&self, slot: u64, event_type: &str) -> bool {
+        let first = self.slots.read().unwrap().peek(slot).is_none();
+        if first {
+            self.metrics
+                .events_before_slot_status_total
+                .with_label_values(&[event_type])
+                .inc();
+        }
+        first
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_before_status() {
+        let seen = SlotStatusesSeen::new(Arc::default());
+        let counted = || {
+            seen.metrics
+                .events_before_slot_status_total
+                .with_label_values(&["slot-order-test"])
+                .get()
+        };
+        assert!(seen.observe_event(5, "slot-order-test"));
+        assert!(seen.observe_event(5, "slot-order-test"));
+        assert_eq!(counted(), 2);
+
+        seen.observe_status(5);
+        assert!(!seen.observe_event(5, "slot-order-test"));
+        // Other slots have statuses of their own.
+        assert!(seen.observe_event(6, "slot-order-test"));
+        assert_eq!(counted(), 3);
+    }
+}
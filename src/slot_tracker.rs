@@ -0,0 +1,204 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per slot state bounded by a capacity rather than by slot statuses arriving, which stop during
+//! some validator failures while accounts and transactions keep coming.
+
+use {
+    crate::instance_metrics::InstanceMetrics,
+    prometheus::{IntCounter, IntGauge},
+    std::collections::BTreeMap,
+};
+
+/// Values by slot, evicting the least recently used slot beyond `capacity`. Entries of all
+/// trackers of a name are exported as `slot_tracker_entries` and their evictions counted in
+/// `slot_tracker_evictions_total`.
+#[derive(Debug)]
+pub struct SlotTracker<V> {
+    capacity: usize,
+    /// Value and last use of each slot.
+    entries: BTreeMap<u64, (V, u64)>,
+    /// Slots by last use.
+    recency: BTreeMap<u64, u64>,
+    uses: u64,
+    entries_gauge: IntGauge,
+    evictions: IntCounter,
+}
+
+impl<V> SlotTracker<V> {
+    /// Tracker of at most `capacity` slots, at least one, exported as `name` in `metrics`.
+    pub fn new(name: &str, capacity: usize, metrics: &InstanceMetrics) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: BTreeMap::new(),
+            recency: BTreeMap::new(),
+            uses: 0,
+            entries_gauge: metrics.slot_tracker_entries.with_label_values(&[name]),
+            evictions: metrics
+                .slot_tracker_evictions_total
+                .with_label_values(&[name]),
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Value of `slot`, without counting as a use.
+    pub fn peek(&self, slot: u64) -> Option<&V> {
+        self.entries.get(&slot).map(|(value, _)| value)
+    }
+
+    /// Value of `slot`, inserted with `default` if missing. A slot inserted at capacity first
+    /// evicts the least recently used one, which is returned.
+    pub fn get_or_insert_with(
+        &mut self,
+        slot: u64,
+        default: impl FnOnce() -> V,
+    ) -> (&mut V, Option<(u64, V)>) {
+        self.uses += 1;
+        let used = self.uses;
+        let mut evicted = None;
+        if let Some((_, last_use)) = self.entries.get_mut(&slot) {
+            self.recency.remove(last_use);
+            *last_use = used;
+        } else {
+            if self.entries.len() >= self.capacity {
+                if let Some((_, lru)) = self.recency.pop_first() {
+                    let (value, _) = self.entries.remove(&lru).unwrap();
+                    self.evictions.inc();
+                    self.entries_gauge.dec();
+                    evicted = Some((lru, value));
+                }
+            }
+            self.entries.insert(slot, (default(), used));
+            self.entries_gauge.inc();
+        }
+        self.recency.insert(used, slot);
+        let (value, _) = self.entries.get_mut(&slot).unwrap();
+        (value, evicted)
+    }
+
+    /// Remove `slot`, returning its value.
+    pub fn remove(&mut self, slot: u64) -> Option<V> {
+        let (value, last_use) = self.entries.remove(&slot)?;
+        self.recency.remove(&last_use);
+        self.entries_gauge.dec();
+        Some(value)
+    }
+
+    /// Remove the slots up to and including `slot`, returning them in slot order.
+    pub fn remove_through(&mut self, slot: u64) -> Vec<(u64, V)> {
+        let later = match slot.checked_add(1) {
+            Some(next) => self.entries.split_off(&next),
+            None => BTreeMap::new(),
+        };
+        let removed = std::mem::replace(&mut self.entries, later);
+        self.entries_gauge.sub(removed.len() as i64);
+        removed
+            .into_iter()
+            .map(|(slot, (value, last_use))| {
+                self.recency.remove(&last_use);
+                (slot, value)
+            })
+            .collect()
+    }
+
+    /// Remove every slot, returning them in slot order.
+    pub fn drain(&mut self) -> Vec<(u64, V)> {
+        self.remove_through(u64::MAX)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.values().map(|(value, _)| value)
+    }
+}
+
+impl<V> Drop for SlotTracker<V> {
+    fn drop(&mut self) {
+        self.entries_gauge.sub(self.entries.len() as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru() {
+        let metrics = InstanceMetrics::default();
+        let mut tracker = SlotTracker::new("test-lru", 2, &metrics);
+        *tracker.get_or_insert_with(1, || 0).0 += 1;
+        tracker.get_or_insert_with(2, || 0);
+        // Slot 1 was used last, so 2 goes.
+        assert_eq!(*tracker.get_or_insert_with(1, || 0).0, 1);
+        let (value, evicted) = tracker.get_or_insert_with(3, || 7);
+        assert_eq!((*value, evicted), (7, Some((2, 0))));
+        assert_eq!(tracker.peek(2), None);
+        assert_eq!(tracker.len(), 2);
+
+        assert_eq!(tracker.remove_through(1), [(1, 1)]);
+        assert_eq!(tracker.drain(), [(3, 7)]);
+        assert_eq!(tracker.len(), 0);
+        assert_eq!(
+            metrics
+                .slot_tracker_entries
+                .with_label_values(&["test-lru"])
+                .get(),
+            0
+        );
+        assert_eq!(
+            metrics
+                .slot_tracker_evictions_total
+                .with_label_values(&["test-lru"])
+                .get(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_bounded() {
+        const CAPACITY: usize = 1000;
+        let metrics = InstanceMetrics::default();
+        let mut tracker = SlotTracker::new("test-bounded", CAPACITY, &metrics);
+        let gauge = metrics
+            .slot_tracker_entries
+            .with_label_values(&["test-bounded"]);
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut evictions = 0;
+        for i in 0..2_000_000u64 {
+            // Mostly ascending slots, with older ones revisited and rare roots, as when slot
+            // statuses stop flowing.
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let slot = match state % 16 {
+                0 => i.saturating_sub(state % 5000),
+                _ => i,
+            };
+            let (_, evicted) = tracker.get_or_insert_with(slot, || i);
+            evictions += evicted.is_some() as u64;
+            if state.trailing_zeros() >= 17 {
+                tracker.remove_through(slot / 2);
+            }
+            assert!(tracker.len() <= CAPACITY);
+            assert_eq!(tracker.recency.len(), tracker.len());
+        }
+        assert_eq!(gauge.get(), tracker.len() as i64);
+        assert!(evictions >= 1_000_000);
+        drop(tracker);
+        assert_eq!(gauge.get(), 0);
+    }
+}
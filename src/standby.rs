@@ -0,0 +1,109 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Standby mode, where everything runs but nothing is produced, for blue/green deployments.
+
+use {
+    crate::instance_metrics::InstanceMetrics,
+    log::info,
+    serde_json::{json, Value},
+    std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+#[derive(Debug, Default)]
+pub struct Standby {
+    standby: AtomicBool,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl Standby {
+    pub fn new(standby: bool, metrics: Arc<InstanceMetrics>) -> Self {
+        metrics.plugin_standby.set(standby as i64);
+        Self {
+            standby: AtomicBool::new(standby),
+            metrics,
+        }
+    }
+
+    pub fn is_standby(&self) -> bool {
+        self.standby.load(Ordering::Acquire)
+    }
+
+    /// Enter or leave standby, returning whether the state changed.
+    pub fn set(&self, standby: bool) -> bool {
+        let changed = self.standby.swap(standby, Ordering::AcqRel) != standby;
+        if changed {
+            self.metrics.plugin_standby.set(standby as i64);
+            info!(
+                "{}",
+                if standby {
+                    "Entered standby"
+                } else {
+                    "Activated"
+                }
+            );
+        }
+        changed
+    }
+
+    /// Whether a message for `topic` is held off the sink, counting it if so.
+    pub fn holds(&self, topic: &str) -> bool {
+        let standby = self.is_standby();
+        if standby {
+            self.metrics
+                .standby_messages_total
+                .with_label_values(&[topic])
+                .inc();
+        }
+        standby
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({ "standby": self.is_standby() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transitions() {
+        let standby = Standby::new(true, Arc::default());
+        let held = || {
+            standby
+                .metrics
+                .standby_messages_total
+                .with_label_values(&["standby-test"])
+                .get()
+        };
+        assert_eq!(standby.metrics.plugin_standby.get(), 1);
+        assert!(standby.holds("standby-test"));
+        assert_eq!(held(), 1);
+        assert!(!standby.set(true));
+
+        assert!(standby.set(false));
+        assert!(!standby.holds("standby-test"));
+        assert_eq!(held(), 1);
+        assert!(!standby.set(false));
+        assert_eq!(standby.metrics.plugin_standby.get(), 0);
+        assert_eq!(standby.to_json(), json!({ "standby": false }));
+
+        assert!(standby.set(true));
+        assert!(standby.is_standby());
+    }
+}
@@ -0,0 +1,179 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Summaries of published events for `/debug/tail`, only built while someone is tailing.
+
+use {
+    serde_json::json,
+    solana_sdk::bs58,
+    std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    tokio::sync::broadcast::{self, error::RecvError},
+};
+
+/// Summaries buffered per subscriber, the oldest are dropped for subscribers falling behind.
+const CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TailSummary {
+    pub topic: String,
+    pub kind: &'static str,
+    pub slot: u64,
+    pub key: Vec<u8>,
+    /// Serialized event size in bytes.
+    pub size: usize,
+}
+
+impl TailSummary {
+    /// The summary as a line of JSON, with the key in base58.
+    pub fn to_json_line(&self) -> String {
+        let mut line = json!({
+            "topic": self.topic,
+            "type": self.kind,
+            "slot": self.slot,
+            "key": bs58::encode(&self.key).into_string(),
+            "size": self.size,
+        })
+        .to_string();
+        line.push('\n');
+        line
+    }
+}
+
+#[derive(Debug)]
+pub struct Tail {
+    subscribers: Arc<AtomicUsize>,
+    sender: broadcast::Sender<Arc<TailSummary>>,
+}
+
+impl Default for Tail {
+    fn default() -> Self {
+        Self::new(CAPACITY)
+    }
+}
+
+impl Tail {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            subscribers: Arc::default(),
+            sender: broadcast::channel(capacity).0,
+        }
+    }
+
+    /// Offer a published event to subscribers. A single atomic load if there are none.
+    pub fn record(&self, kind: &'static str, slot: u64, topic: &str, key: &[u8], size: usize) {
+        if self.subscribers.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let _ = self.sender.send(Arc::new(TailSummary {
+            topic: topic.to_owned(),
+            kind,
+            slot,
+            key: key.to_vec(),
+            size,
+        }));
+    }
+
+    /// Receive the summaries of events published to `topic` from now on.
+    pub fn subscribe(&self, topic: &str) -> TailSubscription {
+        // Counted before subscribing so no event published after this returns is missed.
+        self.subscribers.fetch_add(1, Ordering::Relaxed);
+        TailSubscription {
+            subscribers: Arc::clone(&self.subscribers),
+            receiver: self.sender.subscribe(),
+            topic: topic.to_owned(),
+            dropped: 0,
+        }
+    }
+}
+
+pub struct TailSubscription {
+    subscribers: Arc<AtomicUsize>,
+    receiver: broadcast::Receiver<Arc<TailSummary>>,
+    topic: String,
+    /// Summaries of any topic dropped as this subscriber fell behind.
+    dropped: u64,
+}
+
+impl TailSubscription {
+    /// Next summary of the topic, `None` once the publisher is gone.
+    pub async fn next(&mut self) -> Option<Arc<TailSummary>> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(summary) if summary.topic == self.topic => return Some(summary),
+                Ok(_) => {}
+                Err(RecvError::Lagged(dropped)) => self.dropped += dropped,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+impl Drop for TailSubscription {
+    fn drop(&mut self) {
+        self.subscribers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(tail: &Tail, topic: &str, slot: u64) {
+        tail.record("account", slot, topic, &[1, 2], 10);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe() {
+        let tail = Tail::new(8);
+        record(&tail, "accounts", 1);
+        assert_eq!(tail.sender.len(), 0, "nothing is sent without subscribers");
+
+        let mut subscription = tail.subscribe("accounts");
+        record(&tail, "slots", 2);
+        record(&tail, "accounts", 3);
+        let summary = subscription.next().await.unwrap();
+        assert_eq!(summary.slot, 3);
+        assert_eq!(
+            summary.to_json_line(),
+            "{\"key\":\"5T\",\"size\":10,\"slot\":3,\"topic\":\"accounts\",\"type\":\"account\"}\n"
+        );
+
+        drop(subscription);
+        assert_eq!(tail.subscribers.load(Ordering::Relaxed), 0);
+        record(&tail, "accounts", 4);
+        assert_eq!(tail.sender.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest() {
+        let tail = Tail::new(2);
+        let mut subscription = tail.subscribe("accounts");
+        for slot in 1..=5 {
+            record(&tail, "accounts", slot);
+        }
+        assert_eq!(subscription.next().await.unwrap().slot, 4);
+        assert_eq!(subscription.next().await.unwrap().slot, 5);
+        assert_eq!(subscription.dropped(), 3);
+
+        drop(tail);
+        assert_eq!(subscription.next().await, None);
+    }
+}
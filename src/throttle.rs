@@ -0,0 +1,105 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pacing of account updates while brokers throttle the producer for exceeding a quota.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Time for the throttle level to halve once brokers stop throttling.
+const HALF_LIFE: Duration = Duration::from_secs(2);
+
+/// Delay per account update for each millisecond of throttle time.
+const DELAY_PER_THROTTLE_MS: Duration = Duration::from_micros(2);
+
+const MAX_DELAY: Duration = Duration::from_millis(2);
+
+/// Throttle level below which updates go out at full speed.
+const MIN_LEVEL_MS: f64 = 1.0;
+
+#[derive(Debug, Default)]
+pub struct ThrottlePacer {
+    /// Throttle time in milliseconds and when it was last updated.
+    level: Mutex<Option<(f64, Instant)>>,
+}
+
+impl ThrottlePacer {
+    fn decayed((level, at): (f64, Instant), now: Instant) -> f64 {
+        let half_lives = now.saturating_duration_since(at).as_secs_f64() / HALF_LIFE.as_secs_f64();
+        level * 0.5f64.powf(half_lives)
+    }
+
+    /// Account for the highest broker throttle time reported at `now`, in milliseconds.
+    pub fn observe(&self, throttle_ms: u64, now: Instant) {
+        let mut level = self.level.lock().unwrap();
+        let decayed = level.map_or(0.0, |level| Self::decayed(level, now));
+        *level = Some((decayed.max(throttle_ms as f64), now));
+    }
+
+    /// Delay to insert before an account update sent at `now`, in proportion to the throttle
+    /// level.
+    pub fn delay(&self, now: Instant) -> Duration {
+        let level = self
+            .level
+            .lock()
+            .unwrap()
+            .map_or(0.0, |level| Self::decayed(level, now));
+        if level < MIN_LEVEL_MS {
+            return Duration::ZERO;
+        }
+        DELAY_PER_THROTTLE_MS.mul_f64(level).min(MAX_DELAY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proportional() {
+        let pacer = ThrottlePacer::default();
+        let start = Instant::now();
+        assert_eq!(pacer.delay(start), Duration::ZERO);
+        pacer.observe(0, start);
+        assert_eq!(pacer.delay(start), Duration::ZERO);
+
+        pacer.observe(100, start);
+        assert_eq!(pacer.delay(start), Duration::from_micros(200));
+        // Lower throttle times don't cut the pace short.
+        pacer.observe(50, start);
+        assert_eq!(pacer.delay(start), Duration::from_micros(200));
+        pacer.observe(5_000, start);
+        assert_eq!(pacer.delay(start), MAX_DELAY);
+    }
+
+    #[test]
+    fn test_decay() {
+        let pacer = ThrottlePacer::default();
+        let start = Instant::now();
+        pacer.observe(400, start);
+        let at = |secs| start + Duration::from_secs(secs);
+        assert_eq!(pacer.delay(at(2)), Duration::from_micros(400));
+        assert_eq!(pacer.delay(at(4)), Duration::from_micros(200));
+        // Throttling stopped, later statistics report none.
+        pacer.observe(0, at(4));
+        assert_eq!(pacer.delay(at(6)), Duration::from_micros(100));
+        assert_eq!(pacer.delay(at(20)), Duration::ZERO);
+
+        // Throttling again raises the level right away.
+        pacer.observe(300, at(20));
+        assert_eq!(pacer.delay(at(20)), Duration::from_micros(600));
+    }
+}
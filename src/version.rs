@@ -4,6 +4,7 @@ use {serde::Serialize, std::env};
 pub struct Version {
     pub version: &'static str,
     pub solana: &'static str,
+    pub geyser_interface: &'static str,
     pub git: &'static str,
     pub rustc: &'static str,
     pub buildts: &'static str,
@@ -12,7 +13,80 @@ pub struct Version {
 pub const VERSION: Version = Version {
     version: env!("CARGO_PKG_VERSION"),
     solana: env!("SOLANA_SDK_VERSION"),
+    geyser_interface: env!("GEYSER_INTERFACE_VERSION"),
     git: env!("GIT_VERSION"),
     rustc: env!("VERGEN_RUSTC_SEMVER"),
     buildts: env!("VERGEN_BUILD_TIMESTAMP"),
 };
+
+/// Major and minor version, ignoring a leading `v`, the patch version and any pre-release or
+/// build suffix.
+fn major_minor(version: &str) -> Option<(u64, u64)> {
+    let version = version.trim().trim_start_matches('v');
+    let version = version.split(['-', '+']).next()?;
+    let mut parts = version.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+/// Whether a validator at `validator` can load a plugin built against the Geyser interface
+/// versions in `interface`, comma separated as extracted from `Cargo.lock`. The interface
+/// only changes between minor versions, so patch versions and pre-releases don't matter.
+pub fn interface_matches(interface: &str, validator: &str) -> Result<bool, String> {
+    let validator =
+        major_minor(validator).ok_or_else(|| format!("invalid validator version {validator:?}"))?;
+    Ok(interface
+        .split(',')
+        .any(|version| major_minor(version) == Some(validator)))
+}
+
+/// Fail unless the validator at version `validator` runs the Geyser interface this plugin was
+/// built against.
+pub fn check_validator_version(validator: &str) -> Result<(), String> {
+    if interface_matches(VERSION.geyser_interface, validator)? {
+        Ok(())
+    } else {
+        Err(format!(
+            "plugin built against solana-geyser-plugin-interface {} cannot be loaded by \
+             validator version {validator}, rebuild it against the validator's version",
+            VERSION.geyser_interface
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interface_matches() {
+        for (interface, validator, expected) in [
+            ("1.18.23", "1.18.23", true),
+            ("1.18.23", "1.18.26", true),
+            ("1.18.23", "v1.18.0", true),
+            ("1.18.23", "1.18.24-rc.1", true),
+            ("1.18.0-beta", "1.18.22+build.5", true),
+            ("1.18.23", "1.17.31", false),
+            ("1.18.23", "2.0.14", false),
+            ("1.18.23", "1.180.0", false),
+            ("1.17.31,1.18.23", "1.18.2", true),
+            ("", "1.18.23", false),
+        ] {
+            assert_eq!(
+                interface_matches(interface, validator),
+                Ok(expected),
+                "{interface} {validator}"
+            );
+        }
+        assert!(interface_matches("1.18.23", "1").is_err());
+        assert!(interface_matches("1.18.23", "latest").is_err());
+    }
+
+    #[test]
+    fn test_check_validator_version() {
+        assert!(check_validator_version(VERSION.geyser_interface).is_ok());
+        let error = check_validator_version("0.1.0").unwrap_err();
+        assert!(error.contains(VERSION.geyser_interface), "{error}");
+    }
+}
@@ -0,0 +1,185 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Wall clock times stamped by the plugin, derived from the monotonic clock and a wall clock
+//! anchor taken on load and every `RESYNC_INTERVAL`, so that steps of the system clock such as
+//! by NTP never make them go backwards. The difference between the system clock and the derived
+//! time at each re-anchoring is exported as `wall_clock_skew_seconds`.
+
+use {
+    crate::prom::WALL_CLOCK_SKEW_SECONDS,
+    std::{
+        sync::atomic::{AtomicI64, AtomicU64, Ordering},
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// Period after which the next timestamp re-anchors to the system clock.
+const RESYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static::lazy_static! {
+    static ref WALL_CLOCK: WallClock = WallClock::new(RESYNC_INTERVAL);
+}
+
+/// Milliseconds since the epoch, never less than earlier ones.
+pub fn unix_millis() -> u64 {
+    WALL_CLOCK.now_millis()
+}
+
+/// Seconds since the epoch, never less than earlier ones.
+pub fn unix_secs() -> u64 {
+    unix_millis() / 1000
+}
+
+/// Take a new anchor right away, as on load.
+pub fn reanchor() {
+    WALL_CLOCK.reanchor_at(Instant::now(), system_time());
+}
+
+fn system_time() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// Wall clock derived from `Instant`s, lock free as it is read on the hot path.
+#[derive(Debug)]
+pub struct WallClock {
+    resync_interval: Duration,
+    /// Origin of the nanoseconds below.
+    base: Instant,
+    /// Nanoseconds since the epoch at `base`, per the latest anchor.
+    offset_nanos: AtomicU64,
+    /// Nanoseconds after `base` of the latest anchor.
+    anchored_nanos: AtomicU64,
+    /// Latest milliseconds handed out, which later ones never go below.
+    last_millis: AtomicU64,
+    /// System clock minus the derived time at the latest anchor, in nanoseconds.
+    skew_nanos: AtomicI64,
+}
+
+impl WallClock {
+    pub fn new(resync_interval: Duration) -> Self {
+        Self::anchored(Instant::now(), system_time(), resync_interval)
+    }
+
+    fn anchored(base: Instant, unix: Duration, resync_interval: Duration) -> Self {
+        Self {
+            resync_interval,
+            base,
+            offset_nanos: AtomicU64::new(unix.as_nanos() as u64),
+            anchored_nanos: AtomicU64::default(),
+            last_millis: AtomicU64::default(),
+            skew_nanos: AtomicI64::default(),
+        }
+    }
+
+    pub fn now_millis(&self) -> u64 {
+        self.millis_at(Instant::now(), system_time)
+    }
+
+    /// Milliseconds since the epoch at `now`, re-anchoring to the system time `actual` if due.
+    fn millis_at(&self, now: Instant, actual: impl FnOnce() -> Duration) -> u64 {
+        let elapsed = now.saturating_duration_since(self.base).as_nanos() as u64;
+        let anchored = self.anchored_nanos.load(Ordering::Relaxed);
+        let due = elapsed.saturating_sub(anchored) >= self.resync_interval.as_nanos() as u64;
+        // Only the thread claiming the anchor reads the system clock.
+        if due
+            && self
+                .anchored_nanos
+                .compare_exchange(anchored, elapsed, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            self.reanchor_at(now, actual());
+        }
+        let derived = self.offset_nanos.load(Ordering::Relaxed) + elapsed;
+        let millis = derived / 1_000_000;
+        self.last_millis
+            .fetch_max(millis, Ordering::Relaxed)
+            .max(millis)
+    }
+
+    /// Anchor the system time `unix` to `now`.
+    fn reanchor_at(&self, now: Instant, unix: Duration) {
+        let elapsed = now.saturating_duration_since(self.base).as_nanos() as u64;
+        let derived = self.offset_nanos.load(Ordering::Relaxed) + elapsed;
+        let actual = unix.as_nanos() as u64;
+        let skew = actual as i64 - derived as i64;
+        self.skew_nanos.store(skew, Ordering::Relaxed);
+        WALL_CLOCK_SKEW_SECONDS.set(skew as f64 / 1e9);
+        self.offset_nanos
+            .store(actual.saturating_sub(elapsed), Ordering::Relaxed);
+        self.anchored_nanos.fetch_max(elapsed, Ordering::Relaxed);
+    }
+
+    /// System clock minus the derived time at the latest anchor.
+    #[cfg(test)]
+    fn skew_secs(&self) -> f64 {
+        self.skew_nanos.load(Ordering::Relaxed) as f64 / 1e9
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_derived_from_monotonic_clock() {
+        let base = Instant::now();
+        let clock = WallClock::anchored(base, Duration::from_secs(1_000), MINUTE);
+        let unreachable = || panic!("re-anchored early");
+        assert_eq!(clock.millis_at(base, unreachable), 1_000_000);
+        assert_eq!(
+            clock.millis_at(base + Duration::from_millis(30_500), unreachable),
+            1_030_500
+        );
+    }
+
+    #[test]
+    fn test_resync_keeps_monotonic() {
+        let base = Instant::now();
+        let clock = WallClock::anchored(base, Duration::from_secs(1_000), MINUTE);
+        // Milliseconds after the anchor, and the system clock then in milliseconds.
+        let at = |millis: u64, actual: u64| {
+            clock.millis_at(base + Duration::from_millis(millis), || {
+                Duration::from_millis(actual)
+            })
+        };
+        assert_eq!(at(59_900, 0), 1_059_900);
+
+        // The system clock was stepped back 10 seconds: times hold until they catch up.
+        assert_eq!(at(60_000, 1_050_000), 1_059_900);
+        assert_eq!(clock.skew_secs(), -10.0);
+        assert_eq!(at(65_000, 0), 1_059_900);
+        assert_eq!(at(70_000, 0), 1_060_000);
+        assert_eq!(at(71_000, 0), 1_061_000);
+
+        // Stepped forward 5 seconds on the next re-anchoring, after which it runs on.
+        assert_eq!(at(120_000, 1_115_000), 1_115_000);
+        assert_eq!(clock.skew_secs(), 5.0);
+        assert_eq!(at(121_000, 0), 1_116_000);
+
+        let mut previous = 0;
+        for tick in 0..1_000 {
+            let millis = 121_000 + tick * 250;
+            // Steps back and forth of the system clock at each re-anchoring.
+            let actual = millis + 995_000 + (tick % 3) * 4_000 - 4_000;
+            let stamped = at(millis, actual);
+            assert!(stamped >= previous, "{stamped} < {previous} at {millis}");
+            previous = stamped;
+        }
+    }
+}
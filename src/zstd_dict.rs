@@ -0,0 +1,317 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Zstandard dictionaries trained per account owner on the first account payloads published,
+//! then used to compress the later ones.
+
+use {
+    crate::{instance_metrics::InstanceMetrics, resources::ThreadGuard},
+    log::{info, warn},
+    serde_json::{json, Value},
+    solana_program::pubkey::Pubkey,
+    std::{
+        collections::HashMap,
+        fmt::{Debug, Formatter},
+        io::{Read, Result as IoResult},
+        sync::{Arc, Mutex},
+        thread,
+    },
+    zstd::{
+        bulk::Compressor,
+        dict::{self, EncoderDictionary},
+        zstd_safe, Decoder,
+    },
+};
+
+/// Kafka header of payloads compressed with a dictionary, holding its id in decimal.
+pub const ZSTD_DICT_ID_HEADER: &str = "zstd-dict-id";
+
+/// Size of trained dictionaries, a fraction of the samples as zstd recommends.
+const DICT_MAX_BYTES: usize = 16 * 1024;
+
+/// Sampled bytes per owner, training starts once reached even with fewer samples.
+const OWNER_SAMPLE_MAX_BYTES: usize = 1 << 20;
+
+/// Sampled bytes of all owners, further payloads aren't sampled until some are trained.
+const TOTAL_SAMPLE_MAX_BYTES: usize = 64 << 20;
+
+/// Owners tracked at once, payloads of further owners are left uncompressed.
+const MAX_OWNERS: usize = 1024;
+
+const ZSTD_LEVEL: i32 = 3;
+
+pub struct TrainedDictionary {
+    pub id: u32,
+    pub bytes: Vec<u8>,
+    prepared: EncoderDictionary<'static>,
+}
+
+enum OwnerDictionary {
+    Sampling { samples: Vec<Vec<u8>>, bytes: usize },
+    Training,
+    Trained(Arc<TrainedDictionary>),
+    Failed,
+}
+
+#[derive(Default)]
+struct Owners {
+    dictionaries: HashMap<[u8; 32], OwnerDictionary>,
+    sampled_bytes: usize,
+}
+
+/// Dictionaries of account owners, each trained on a background thread once `samples`
+/// payloads of the owner were seen.
+pub struct ZstdDictionaries {
+    samples: usize,
+    owners: Mutex<Owners>,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl Debug for ZstdDictionaries {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ZstdDictionaries")
+            .field("samples", &self.samples)
+            .field("owners", &self.owners.lock().unwrap().dictionaries.len())
+            .finish()
+    }
+}
+
+impl ZstdDictionaries {
+    pub fn new(samples: usize, metrics: Arc<InstanceMetrics>) -> Self {
+        Self {
+            samples,
+            owners: Mutex::default(),
+            metrics,
+        }
+    }
+
+    /// Compress `payload` of an account of `owner` with the owner's dictionary, returning the
+    /// dictionary id and compressed payload. Until the dictionary is trained, the payload is
+    /// sampled instead and `None` returned.
+    pub fn compress(self: &Arc<Self>, owner: &[u8], payload: &[u8]) -> Option<(u32, Vec<u8>)> {
+        let owner: [u8; 32] = owner.try_into().ok()?;
+        let mut guard = self.owners.lock().unwrap();
+        let owners = &mut *guard;
+        if !owners.dictionaries.contains_key(&owner) {
+            if owners.dictionaries.len() >= MAX_OWNERS {
+                return None;
+            }
+            owners.dictionaries.insert(
+                owner,
+                OwnerDictionary::Sampling {
+                    samples: Vec::new(),
+                    bytes: 0,
+                },
+            );
+        }
+        let dictionary = owners.dictionaries.get_mut(&owner).unwrap();
+        let (samples, bytes) = match dictionary {
+            OwnerDictionary::Trained(trained) => {
+                let trained = Arc::clone(trained);
+                drop(guard);
+                return match Compressor::with_prepared_dictionary(&trained.prepared)
+                    .and_then(|mut compressor| compressor.compress(payload))
+                {
+                    Ok(compressed) => Some((trained.id, compressed)),
+                    Err(error) => {
+                        warn!("Failed to compress account payload: {error}");
+                        None
+                    }
+                };
+            }
+            OwnerDictionary::Training | OwnerDictionary::Failed => return None,
+            OwnerDictionary::Sampling { samples, bytes } => (samples, bytes),
+        };
+        if owners.sampled_bytes + payload.len() > TOTAL_SAMPLE_MAX_BYTES {
+            return None;
+        }
+        samples.push(payload.to_vec());
+        *bytes += payload.len();
+        owners.sampled_bytes += payload.len();
+        if samples.len() < self.samples && *bytes < OWNER_SAMPLE_MAX_BYTES {
+            return None;
+        }
+
+        let samples = std::mem::take(samples);
+        owners.sampled_bytes -= *bytes;
+        *dictionary = OwnerDictionary::Training;
+        let this = Arc::clone(self);
+        thread::Builder::new()
+            .name("kafkaZstdDict".to_owned())
+            .spawn(move || {
+                let _thread = ThreadGuard::new(&this.metrics, "background");
+                this.train(owner, samples)
+            })
+            .map_err(|error| warn!("Failed to spawn dictionary training: {error}"))
+            .ok();
+        None
+    }
+
+    fn train(&self, owner: [u8; 32], samples: Vec<Vec<u8>>) {
+        let owner_key = Pubkey::new_from_array(owner);
+        let dictionary = match dict::from_samples(&samples, DICT_MAX_BYTES) {
+            Ok(bytes) => {
+                let id = zstd_safe::get_dict_id_from_dict(&bytes);
+                info!(
+                    "Trained zstd dictionary {id} of {} bytes for accounts of {owner_key}",
+                    bytes.len()
+                );
+                self.metrics
+                    .zstd_dictionaries_total
+                    .with_label_values(&["trained"])
+                    .inc();
+                OwnerDictionary::Trained(Arc::new(TrainedDictionary {
+                    id,
+                    prepared: EncoderDictionary::copy(&bytes, ZSTD_LEVEL),
+                    bytes,
+                }))
+            }
+            Err(error) => {
+                warn!(
+                    "Failed to train zstd dictionary for accounts of {owner_key} on {} samples, \
+                     leaving them uncompressed: {error}",
+                    samples.len()
+                );
+                self.metrics
+                    .zstd_dictionaries_total
+                    .with_label_values(&["failed"])
+                    .inc();
+                OwnerDictionary::Failed
+            }
+        };
+        self.owners
+            .lock()
+            .unwrap()
+            .dictionaries
+            .insert(owner, dictionary);
+    }
+
+    /// Trained dictionary of `owner`.
+    pub fn dictionary(&self, owner: &[u8]) -> Option<Arc<TrainedDictionary>> {
+        let owner: [u8; 32] = owner.try_into().ok()?;
+        match self.owners.lock().unwrap().dictionaries.get(&owner) {
+            Some(OwnerDictionary::Trained(trained)) => Some(Arc::clone(trained)),
+            _ => None,
+        }
+    }
+
+    /// Owners and ids of the trained dictionaries.
+    pub fn to_json(&self) -> Value {
+        let owners = self.owners.lock().unwrap();
+        let mut trained: Vec<(String, u32)> = owners
+            .dictionaries
+            .iter()
+            .filter_map(|(owner, dictionary)| match dictionary {
+                OwnerDictionary::Trained(trained) => {
+                    Some((Pubkey::new_from_array(*owner).to_string(), trained.id))
+                }
+                _ => None,
+            })
+            .collect();
+        trained.sort();
+        trained
+            .into_iter()
+            .map(|(owner, id)| json!({"owner": owner, "id": id}))
+            .collect()
+    }
+}
+
+/// Decompress a payload published with the `zstd-dict-id` header, given the dictionary of that
+/// id as served on `/debug/zstd-dict/<owner>`.
+pub fn decompress_payload(payload: &[u8], dictionary: &[u8]) -> IoResult<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    Decoder::with_dictionary(payload, dictionary)?.read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::time::Duration};
+
+    /// Account data of a made up program layout, alike but for the fields that vary.
+    fn payload(i: u32) -> Vec<u8> {
+        let mut payload = b"discriminator-v1".to_vec();
+        payload.extend_from_slice(&i.to_le_bytes());
+        payload.extend_from_slice(&[0; 64]);
+        payload.extend_from_slice(&(i % 7).to_le_bytes());
+        payload.extend_from_slice(b"authority-pubkey-0123456789abcdef");
+        payload.extend_from_slice(&(i * 31).to_le_bytes());
+        payload
+    }
+
+    fn wait_trained(dictionaries: &ZstdDictionaries, owner: &[u8]) -> Arc<TrainedDictionary> {
+        for _ in 0..500 {
+            if let Some(trained) = dictionaries.dictionary(owner) {
+                return trained;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("dictionary not trained");
+    }
+
+    #[test]
+    fn test_train_and_compress() {
+        let dictionaries = Arc::new(ZstdDictionaries::new(200, Arc::default()));
+        let owner = [7; 32];
+        for i in 0..200 {
+            assert_eq!(dictionaries.compress(&owner, &payload(i)), None);
+        }
+        let trained = wait_trained(&dictionaries, &owner);
+        assert_ne!(trained.id, 0);
+        assert_eq!(
+            dictionaries.to_json(),
+            json!([{"owner": Pubkey::new_from_array(owner).to_string(), "id": trained.id}])
+        );
+
+        let original = payload(1000);
+        let (id, compressed) = dictionaries.compress(&owner, &original).unwrap();
+        assert_eq!(id, trained.id);
+        let plain = zstd::bulk::compress(&original, ZSTD_LEVEL).unwrap();
+        assert!(compressed.len() < plain.len(), "{compressed:?}");
+        assert_eq!(
+            decompress_payload(&compressed, &trained.bytes).unwrap(),
+            original
+        );
+        assert!(decompress_payload(&compressed, b"").is_err());
+
+        // Other owners sample their own payloads.
+        assert_eq!(dictionaries.compress(&[8; 32], &original), None);
+        assert!(dictionaries.dictionary(&[8; 32]).is_none());
+    }
+
+    #[test]
+    fn test_training_failure() {
+        let dictionaries = Arc::new(ZstdDictionaries::new(2, Arc::default()));
+        let owner = [9; 32];
+        let failed = || {
+            dictionaries
+                .metrics
+                .zstd_dictionaries_total
+                .with_label_values(&["failed"])
+                .get()
+        };
+        // Too few samples to train on.
+        dictionaries.compress(&owner, b"a");
+        dictionaries.compress(&owner, b"b");
+        for _ in 0..500 {
+            if failed() > 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(failed(), 1);
+        assert_eq!(dictionaries.compress(&owner, b"c"), None);
+        assert!(dictionaries.dictionary(&owner).is_none());
+    }
+}
@@ -0,0 +1,278 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Topics, keys and payloads published for fixed notifications, compared byte for byte against
+//! `fixtures/contract`. A change here changes the bytes consumers see on the wire.
+//!
+//! After a deliberate schema change, run `cargo test --features regenerate-fixtures`, or the
+//! tests with `UPDATE_FIXTURES=1`, to rewrite the outdated fixtures, then review and commit them.
+
+use {
+    solana_accountsdb_plugin_kafka::{Config, ConfigFilter, KafkaPlugin},
+    solana_geyser_plugin_interface::geyser_plugin_interface::{
+        GeyserPlugin, ReplicaAccountInfoV3, ReplicaAccountInfoVersions, ReplicaBlockInfoV3,
+        ReplicaBlockInfoVersions, ReplicaTransactionInfoV2, ReplicaTransactionInfoVersions,
+        SlotStatus,
+    },
+    solana_sdk::{
+        hash::Hash,
+        instruction::CompiledInstruction,
+        message::{
+            legacy,
+            v0::{self, LoadedAddresses, MessageAddressTableLookup},
+            MessageHeader, SimpleAddressLoader, VersionedMessage,
+        },
+        pubkey::Pubkey,
+        signature::Signature,
+        transaction::{SanitizedTransaction, TransactionError, VersionedTransaction},
+    },
+    solana_transaction_status::{
+        InnerInstruction, InnerInstructions, Reward, RewardType, TransactionStatusMeta,
+    },
+    std::{fs, path::Path},
+};
+
+fn key(byte: u8) -> Pubkey {
+    Pubkey::new_from_array([byte; 32])
+}
+
+/// Program every transaction invokes, and the account filtered on.
+const PROGRAM: u8 = 2;
+const ACCOUNT: u8 = 11;
+
+fn legacy_transaction() -> SanitizedTransaction {
+    let message = legacy::Message {
+        header: MessageHeader {
+            num_required_signatures: 1,
+            num_readonly_signed_accounts: 0,
+            num_readonly_unsigned_accounts: 1,
+        },
+        account_keys: vec![key(1), key(ACCOUNT), key(PROGRAM)],
+        recent_blockhash: Hash::new_from_array([9; 32]),
+        instructions: vec![CompiledInstruction::new_from_raw_parts(
+            2,
+            vec![1, 2, 3],
+            vec![0, 1],
+        )],
+    };
+    sanitize(
+        VersionedMessage::Legacy(message),
+        20,
+        LoadedAddresses::default(),
+    )
+}
+
+fn v0_transaction() -> SanitizedTransaction {
+    let message = v0::Message {
+        header: MessageHeader {
+            num_required_signatures: 1,
+            num_readonly_signed_accounts: 0,
+            num_readonly_unsigned_accounts: 1,
+        },
+        account_keys: vec![key(1), key(PROGRAM)],
+        recent_blockhash: Hash::new_from_array([9; 32]),
+        instructions: vec![CompiledInstruction::new_from_raw_parts(
+            1,
+            vec![4],
+            vec![0, 2, 3],
+        )],
+        address_table_lookups: vec![MessageAddressTableLookup {
+            account_key: key(8),
+            writable_indexes: vec![0],
+            readonly_indexes: vec![1],
+        }],
+    };
+    let loaded = LoadedAddresses {
+        writable: vec![key(ACCOUNT)],
+        readonly: vec![key(5)],
+    };
+    sanitize(VersionedMessage::V0(message), 21, loaded)
+}
+
+fn sanitize(
+    message: VersionedMessage,
+    signature: u8,
+    loaded: LoadedAddresses,
+) -> SanitizedTransaction {
+    SanitizedTransaction::try_create(
+        VersionedTransaction {
+            signatures: vec![Signature::from([signature; 64])],
+            message,
+        },
+        Hash::new_from_array([signature; 32]),
+        Some(false),
+        SimpleAddressLoader::Enabled(loaded),
+    )
+    .unwrap()
+}
+
+fn meta(status: Result<(), TransactionError>, loaded: LoadedAddresses) -> TransactionStatusMeta {
+    TransactionStatusMeta {
+        status,
+        fee: 5_000,
+        pre_balances: vec![10_000, 1, 1],
+        post_balances: vec![5_000, 1, 1],
+        inner_instructions: Some(vec![InnerInstructions {
+            index: 0,
+            instructions: vec![InnerInstruction {
+                instruction: CompiledInstruction::new_from_raw_parts(1, vec![7], vec![0]),
+                stack_height: Some(2),
+            }],
+        }]),
+        log_messages: Some(vec!["Program log: contract".to_owned()]),
+        pre_token_balances: Some(vec![]),
+        post_token_balances: Some(vec![]),
+        rewards: Some(vec![]),
+        loaded_addresses: loaded,
+        return_data: None,
+        compute_units_consumed: Some(1_500),
+    }
+}
+
+/// Notify `plugin` of an account update, transactions of both message versions, slot
+/// statuses and block metadata of slot 100.
+fn notify(plugin: &KafkaPlugin) {
+    let legacy = legacy_transaction();
+    let (pubkey, owner) = (key(ACCOUNT), key(PROGRAM));
+    let account = ReplicaAccountInfoV3 {
+        pubkey: pubkey.as_ref(),
+        lamports: 5_000,
+        owner: owner.as_ref(),
+        executable: false,
+        rent_epoch: u64::MAX,
+        data: &[7; 16],
+        write_version: 3,
+        txn: Some(&legacy),
+    };
+    plugin
+        .update_account(ReplicaAccountInfoVersions::V0_0_3(&account), 100, false)
+        .unwrap();
+
+    let v0 = v0_transaction();
+    let transactions = [
+        (&legacy, meta(Ok(()), LoadedAddresses::default()), 4),
+        (
+            &v0,
+            meta(
+                Err(TransactionError::InsufficientFundsForFee),
+                v0.get_loaded_addresses(),
+            ),
+            5,
+        ),
+    ];
+    for (transaction, meta, index) in &transactions {
+        let info = ReplicaTransactionInfoV2 {
+            signature: transaction.signature(),
+            is_vote: false,
+            transaction,
+            transaction_status_meta: meta,
+            index: *index,
+        };
+        plugin
+            .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&info), 100)
+            .unwrap();
+    }
+
+    let rewards = [Reward {
+        pubkey: key(1).to_string(),
+        lamports: 2_500,
+        post_balance: 12_500,
+        reward_type: Some(RewardType::Fee),
+        commission: None,
+    }];
+    let (blockhash, parent_blockhash) = (
+        Hash::new_from_array([100; 32]).to_string(),
+        Hash::new_from_array([99; 32]).to_string(),
+    );
+    let block = ReplicaBlockInfoV3 {
+        parent_slot: 99,
+        parent_blockhash: &parent_blockhash,
+        slot: 100,
+        blockhash: &blockhash,
+        rewards: &rewards,
+        block_time: Some(1_700_000_000),
+        block_height: Some(90),
+        executed_transaction_count: 2,
+        entry_count: 6,
+    };
+    plugin
+        .notify_block_metadata(ReplicaBlockInfoVersions::V0_0_3(&block))
+        .unwrap();
+
+    for status in [
+        SlotStatus::Processed,
+        SlotStatus::Confirmed,
+        SlotStatus::Rooted,
+    ] {
+        plugin.update_slot_status(100, Some(99), status).unwrap();
+    }
+}
+
+/// Published messages, one per line as topic, key and payload in hex.
+fn published(wrap_messages: bool) -> String {
+    let mut config = Config::default();
+    config.filters = vec![ConfigFilter {
+        update_account_topic: Some("accounts".to_owned()),
+        slot_status_topic: Some("slots".to_owned()),
+        transaction_topic: Some("transactions".to_owned()),
+        block_events_topic: Some("blocks".to_owned()),
+        program_filters: vec![key(PROGRAM).to_string()],
+        account_filters: vec![key(ACCOUNT).to_string()],
+        include_failed_transactions: true,
+        wrap_messages,
+        ..Default::default()
+    }];
+    let plugin = KafkaPlugin::dry_run(&config).unwrap();
+    notify(&plugin);
+    plugin
+        .take_recorded()
+        .iter()
+        .map(|message| format!("{message}\n"))
+        .collect()
+}
+
+fn check_fixture(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures")
+        .join("contract")
+        .join(name);
+    let expected = fs::read_to_string(&path).unwrap_or_default();
+    if expected == actual {
+        return;
+    }
+    if cfg!(feature = "regenerate-fixtures") || std::env::var_os("UPDATE_FIXTURES").is_some() {
+        fs::write(&path, actual).unwrap();
+        panic!("rewrote {path:?}, review the change and run the tests again");
+    }
+    panic!(
+        "published bytes differ from {path:?}, run `cargo test --features regenerate-fixtures` \
+         if the change is deliberate\nexpected:\n{expected}\nactual:\n{actual}"
+    );
+}
+
+#[test]
+fn test_unwrapped_contract() {
+    check_fixture("unwrapped.golden", &published(false));
+}
+
+#[test]
+fn test_wrapped_contract() {
+    check_fixture("wrapped.golden", &published(true));
+}
+
+#[test]
+fn test_deterministic() {
+    assert_eq!(published(false), published(false));
+    assert_eq!(published(true), published(true));
+}
@@ -0,0 +1,396 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Round trips through a real Kafka broker: the plugin is loaded from a config file, notified of
+//! synthetic events, and the topics are consumed back to check keys, headers and payloads as the
+//! broker stores them.
+//!
+//! Built with the `kafka-integration` feature and skipped unless `KAFKA_BROKERS` is set, such as
+//! against the broker of `ci/docker-compose.kafka.yml`:
+//!
+//! ```shell
+//! docker compose -f ci/docker-compose.kafka.yml up -d
+//! KAFKA_BROKERS=localhost:9092 cargo test --features kafka-integration --test kafka_integration
+//! ```
+//!
+//! Every run creates its own topics and deletes them when done.
+
+#![cfg(feature = "kafka-integration")]
+
+use {
+    prost::Message as _,
+    rdkafka::{
+        admin::{AdminClient, AdminOptions, NewTopic, TopicReplication},
+        client::DefaultClientContext,
+        consumer::{BaseConsumer, Consumer},
+        message::{Headers, Message},
+        ClientConfig, Offset, TopicPartitionList,
+    },
+    serde_json::json,
+    solana_accountsdb_plugin_kafka::{
+        message_wrapper::EventMessage, KafkaPlugin, MessageWrapper, SlotStatus as EventSlotStatus,
+        SlotStatusEvent, TransactionEvent, UpdateAccountEvent, KIND_HEADER, PHASE_HEADER,
+        STATUS_HEADER,
+    },
+    solana_geyser_plugin_interface::geyser_plugin_interface::{
+        GeyserPlugin, ReplicaAccountInfoV3, ReplicaAccountInfoVersions, ReplicaTransactionInfoV2,
+        ReplicaTransactionInfoVersions, SlotStatus,
+    },
+    solana_sdk::{
+        instruction::{AccountMeta, Instruction},
+        message::v0::LoadedAddresses,
+        pubkey::Pubkey,
+        transaction::{SanitizedTransaction, Transaction},
+    },
+    solana_transaction_status::TransactionStatusMeta,
+    std::{
+        env, fs,
+        path::PathBuf,
+        process,
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+    tokio::runtime::Runtime,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn brokers() -> Option<String> {
+    let brokers = env::var("KAFKA_BROKERS").ok().filter(|b| !b.is_empty());
+    if brokers.is_none() {
+        eprintln!("KAFKA_BROKERS is not set, skipping");
+    }
+    brokers
+}
+
+/// Single partition topics named uniquely for a run, deleted on drop.
+struct TestTopics {
+    runtime: Runtime,
+    admin: AdminClient<DefaultClientContext>,
+    names: Vec<String>,
+}
+
+impl TestTopics {
+    fn create(brokers: &str, suffixes: &[&str]) -> Self {
+        let runtime = Runtime::new().unwrap();
+        let admin: AdminClient<DefaultClientContext> = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .create()
+            .unwrap();
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let names: Vec<String> = suffixes
+            .iter()
+            .map(|suffix| format!("plugin-test-{}-{nanos}-{suffix}", process::id()))
+            .collect();
+        let topics: Vec<NewTopic> = names
+            .iter()
+            .map(|name| NewTopic::new(name, 1, TopicReplication::Fixed(1)))
+            .collect();
+        let options = AdminOptions::new().operation_timeout(Some(TIMEOUT));
+        for result in runtime
+            .block_on(admin.create_topics(&topics, &options))
+            .unwrap()
+        {
+            result.unwrap_or_else(|(topic, error)| panic!("failed to create {topic}: {error}"));
+        }
+        Self {
+            runtime,
+            admin,
+            names,
+        }
+    }
+
+    fn name(&self, index: usize) -> &str {
+        &self.names[index]
+    }
+}
+
+impl Drop for TestTopics {
+    fn drop(&mut self) {
+        let names: Vec<&str> = self.names.iter().map(String::as_str).collect();
+        let options = AdminOptions::new().operation_timeout(Some(TIMEOUT));
+        if let Err(error) = self
+            .runtime
+            .block_on(self.admin.delete_topics(&names, &options))
+        {
+            eprintln!("failed to delete test topics {names:?}: {error}");
+        }
+    }
+}
+
+/// A message as consumed back from the broker.
+#[derive(Debug)]
+struct Consumed {
+    key: Vec<u8>,
+    headers: Vec<(String, Vec<u8>)>,
+    payload: Vec<u8>,
+}
+
+/// Read `count` messages of `topic` from the start.
+fn consume(brokers: &str, topic: &str, count: usize) -> Vec<Consumed> {
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", brokers)
+        .set("group.id", format!("{topic}-reader"))
+        .set("enable.auto.commit", "false")
+        .create()
+        .unwrap();
+    let mut partitions = TopicPartitionList::new();
+    partitions
+        .add_partition_offset(topic, 0, Offset::Beginning)
+        .unwrap();
+    consumer.assign(&partitions).unwrap();
+
+    let deadline = Instant::now() + TIMEOUT;
+    let mut consumed = Vec::new();
+    while consumed.len() < count {
+        assert!(
+            Instant::now() < deadline,
+            "consumed {} of {count} messages of {topic}",
+            consumed.len()
+        );
+        let Some(message) = consumer.poll(Duration::from_millis(100)) else {
+            continue;
+        };
+        let message = message.unwrap();
+        let headers = message
+            .headers()
+            .map(|headers| {
+                headers
+                    .iter()
+                    .map(|header| {
+                        let value = header.value.unwrap_or_default().to_vec();
+                        (header.key.to_owned(), value)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        consumed.push(Consumed {
+            key: message.key().unwrap_or_default().to_vec(),
+            headers,
+            payload: message.payload().unwrap_or_default().to_vec(),
+        });
+    }
+    consumed
+}
+
+/// Load the plugin from a config file of `filter` producing to `brokers`.
+fn load(brokers: &str, filter: serde_json::Value) -> (KafkaPlugin, PathBuf) {
+    let config = json!({
+        "libpath": "",
+        "kafka": {
+            "bootstrap.servers": brokers,
+            "request.required.acks": "all",
+            "compression.type": "lz4",
+            "partitioner": "murmur2_random",
+        },
+        "shutdown_timeout_ms": 30_000,
+        "setup_logger": false,
+        "emit_phase_headers": true,
+        "filters": [filter],
+    });
+    let path = env::temp_dir().join(format!(
+        "plugin-test-{}-{}.json",
+        process::id(),
+        filter["update_account_topic"].as_str().unwrap()
+    ));
+    fs::write(&path, config.to_string()).unwrap();
+    let mut plugin = KafkaPlugin::new();
+    plugin.on_load(path.to_str().unwrap(), false).unwrap();
+    (plugin, path)
+}
+
+/// Publish an account update, a transaction and a rooted slot of slot 100 through Kafka, then
+/// consume the account, transaction and slot topics back.
+fn round_trip(brokers: &str, wrap_messages: bool) -> [Vec<Consumed>; 3] {
+    let mode = if wrap_messages {
+        "wrapped"
+    } else {
+        "unwrapped"
+    };
+    let topics = TestTopics::create(
+        brokers,
+        &[
+            &format!("{mode}-accounts"),
+            &format!("{mode}-transactions"),
+            &format!("{mode}-slots"),
+        ],
+    );
+    let (program, account) = (program(), account());
+    let (mut plugin, config_path) = load(
+        brokers,
+        json!({
+            "update_account_topic": topics.name(0),
+            "transaction_topic": topics.name(1),
+            "slot_status_topic": topics.name(2),
+            "program_filters": [program.to_string()],
+            "account_filters": [account.to_string()],
+            "wrap_messages": wrap_messages,
+        }),
+    );
+
+    let transaction =
+        SanitizedTransaction::from_transaction_for_tests(Transaction::new_with_payer(
+            &[Instruction::new_with_bytes(
+                program,
+                &[1, 2, 3],
+                vec![AccountMeta::new(account, false)],
+            )],
+            Some(&Pubkey::new_from_array([1; 32])),
+        ));
+    let info = ReplicaAccountInfoV3 {
+        pubkey: account.as_ref(),
+        lamports: 5_000,
+        owner: program.as_ref(),
+        executable: false,
+        rent_epoch: u64::MAX,
+        data: &[7; 16],
+        write_version: 3,
+        txn: None,
+    };
+    plugin
+        .update_account(ReplicaAccountInfoVersions::V0_0_3(&info), 100, false)
+        .unwrap();
+    let meta = TransactionStatusMeta {
+        status: Ok(()),
+        fee: 5_000,
+        pre_balances: vec![10_000, 1, 1],
+        post_balances: vec![5_000, 1, 1],
+        inner_instructions: None,
+        log_messages: None,
+        pre_token_balances: None,
+        post_token_balances: None,
+        rewards: None,
+        loaded_addresses: LoadedAddresses::default(),
+        return_data: None,
+        compute_units_consumed: Some(1_500),
+    };
+    let info = ReplicaTransactionInfoV2 {
+        signature: transaction.signature(),
+        is_vote: false,
+        transaction: &transaction,
+        transaction_status_meta: &meta,
+        index: 4,
+    };
+    plugin
+        .notify_transaction(ReplicaTransactionInfoVersions::V0_0_2(&info), 100)
+        .unwrap();
+    plugin
+        .update_slot_status(100, Some(99), SlotStatus::Rooted)
+        .unwrap();
+    // Flushes the producer.
+    plugin.on_unload();
+    fs::remove_file(config_path).unwrap();
+
+    [0, 1, 2].map(|index| consume(brokers, topics.name(index), 1))
+}
+
+fn program() -> Pubkey {
+    Pubkey::new_from_array([2; 32])
+}
+
+fn account() -> Pubkey {
+    Pubkey::new_from_array([11; 32])
+}
+
+fn header<'a>(message: &'a Consumed, name: &str) -> Option<&'a [u8]> {
+    message
+        .headers
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_slice())
+}
+
+fn check_account(event: &UpdateAccountEvent) {
+    assert_eq!(event.slot, 100);
+    assert_eq!(event.pubkey, account().to_bytes());
+    assert_eq!(event.owner, program().to_bytes());
+    assert_eq!((event.lamports, event.write_version), (5_000, 3));
+    assert_eq!(event.data, [7; 16]);
+}
+
+fn check_transaction(event: &TransactionEvent) {
+    assert_eq!((event.slot, event.index), (100, 4));
+    assert!(!event.is_vote);
+    let meta = event.transaction_status_meta.as_ref().unwrap();
+    assert!(!meta.is_status_err);
+    assert_eq!(meta.fee, 5_000);
+}
+
+fn check_slot(event: &SlotStatusEvent) {
+    assert_eq!((event.slot, event.parent), (100, 99));
+    assert_eq!(event.status(), EventSlotStatus::Rooted);
+}
+
+fn check_headers(accounts: &Consumed, transactions: &Consumed) {
+    assert_eq!(header(accounts, PHASE_HEADER), Some(&b"live"[..]));
+    assert_eq!(header(transactions, STATUS_HEADER), Some(&b"ok"[..]));
+    assert_eq!(header(transactions, KIND_HEADER), Some(&b"nonvote"[..]));
+}
+
+#[test]
+fn test_unwrapped() {
+    let Some(brokers) = brokers() else {
+        return;
+    };
+    let [accounts, transactions, slots] = round_trip(&brokers, false);
+    check_headers(&accounts[0], &transactions[0]);
+
+    assert_eq!(accounts[0].key, account().to_bytes());
+    check_account(&UpdateAccountEvent::decode(accounts[0].payload.as_slice()).unwrap());
+
+    let event = TransactionEvent::decode(transactions[0].payload.as_slice()).unwrap();
+    assert_eq!(transactions[0].key, event.signature);
+    check_transaction(&event);
+
+    assert_eq!(slots[0].key, 100u64.to_le_bytes());
+    check_slot(&SlotStatusEvent::decode(slots[0].payload.as_slice()).unwrap());
+}
+
+#[test]
+fn test_wrapped() {
+    let Some(brokers) = brokers() else {
+        return;
+    };
+    let [accounts, transactions, slots] = round_trip(&brokers, true);
+    check_headers(&accounts[0], &transactions[0]);
+    let decode = |message: &Consumed| {
+        MessageWrapper::decode(message.payload.as_slice())
+            .unwrap()
+            .event_message
+            .unwrap()
+    };
+
+    assert_eq!(accounts[0].key[0], b'A');
+    assert_eq!(accounts[0].key[1..], account().to_bytes());
+    let EventMessage::Account(event) = decode(&accounts[0]) else {
+        panic!("not an account update");
+    };
+    check_account(&event);
+
+    assert_eq!(transactions[0].key[0], b'T');
+    let EventMessage::Transaction(event) = decode(&transactions[0]) else {
+        panic!("not a transaction");
+    };
+    assert_eq!(transactions[0].key[1..], event.signature);
+    check_transaction(&event);
+
+    assert_eq!(slots[0].key[0], b'S');
+    assert_eq!(slots[0].key[1..], 100u64.to_le_bytes());
+    let EventMessage::Slot(event) = decode(&slots[0]) else {
+        panic!("not a slot status");
+    };
+    check_slot(&event);
+}